@@ -0,0 +1,8 @@
+// Example user compositor mode. Files in this directory are appended to fullscreen.hlsl and show up in the
+// compositor mode list, they're recompiled when saved.
+
+float4 UserPShader(VSOutput input) : SV_Target {
+    float3 color = PShader(input).rgb;
+    float luminance = dot(color, float3(0.2126, 0.7152, 0.0722));
+    return float4(luminance.xxx, 1.0);
+}
@@ -0,0 +1,40 @@
+cbuffer DebugColor : register(b0) {
+    float4 color;
+};
+
+struct PSOutput {
+    float4 rt0 : SV_Target0;
+    float4 rt1 : SV_Target1;
+    float4 rt2 : SV_Target2;
+};
+
+// No inputs, so this shader can be combined with any of the game's vertex shaders
+PSOutput PSColor() {
+    PSOutput output;
+
+    output.rt0 = float4(color.rgb, 1.0);
+    output.rt1 = float4(0.5, 0.5, 1.0, 0.0);
+    output.rt2 = float4(0.0, 0.5, 0.0, 0.0);
+
+    return output;
+}
+
+static const float UV_CHECKER_SCALE = 8.0;
+
+// Reads TEXCOORD0 from the first input register (v0). DebugShaders::bind_uv_checker only uses this shader with
+// vertex shaders whose output signature puts TEXCOORD0 in o0
+PSOutput PSUvChecker(float4 uv : TEXCOORD0) {
+    float2 cell = floor(uv.xy * UV_CHECKER_SCALE);
+    float checker = fmod(abs(cell.x + cell.y), 2.0);
+
+    // Tint by the UV quadrant so flipped or offset coordinates stand out
+    float3 tint = float3(frac(uv.xy), 0.5);
+
+    PSOutput output;
+
+    output.rt0 = float4(lerp(tint * 0.4, tint, checker), 1.0);
+    output.rt1 = float4(0.5, 0.5, 1.0, 0.0);
+    output.rt2 = float4(0.0, 0.5, 0.0, 0.0);
+
+    return output;
+}
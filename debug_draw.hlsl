@@ -0,0 +1,40 @@
+cbuffer DebugDraw : register(b0) {
+    row_major float4x4 projViewMatrix;
+    // xy = render target size, z = 1 to test against the scene depth
+    float4 targetSize;
+};
+
+Texture2D DepthTarget : register(t0);
+
+struct VSInput {
+    float3 position : POSITION;
+    float4 color : COLOR;
+};
+
+struct VSOutput {
+    float4 position : SV_POSITION;
+    float4 color : COLOR;
+};
+
+VSOutput VSDebugDraw(VSInput input) {
+    VSOutput output;
+    output.position = mul(float4(input.position, 1.0), projViewMatrix);
+    output.color = input.color;
+
+    return output;
+}
+
+float4 PSDebugDraw(VSOutput input) : SV_Target {
+    if(targetSize.z > 0.0) {
+        // The depth target is scaled by the render scale, the render target is not
+        uint2 depthSize;
+        DepthTarget.GetDimensions(depthSize.x, depthSize.y);
+        uint2 depthPos = uint2(input.position.xy / targetSize.xy * depthSize);
+        float sceneDepth = DepthTarget.Load(int3(depthPos, 0)).r;
+
+        // Reverse-Z, with a small bias so lines on surfaces stay visible
+        clip(input.position.z * 1.001 - sceneDepth);
+    }
+
+    return input.color;
+}
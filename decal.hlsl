@@ -0,0 +1,63 @@
+cbuffer DecalView : register(b0) {
+    row_major float4x4 projViewMatrix;
+    row_major float4x4 projViewMatrixInv;
+    // xy = render target size
+    float4 targetSize;
+};
+
+cbuffer Decal : register(b1) {
+    row_major float4x4 decalToWorld;
+    row_major float4x4 worldToDecal;
+};
+
+Texture2D DecalTexture : register(t0);
+Texture2D DepthTarget : register(t10);
+SamplerState DecalSampler : register(s0);
+
+// Triangle list for a cube spanning -1..1, wound counter-clockwise when viewed from outside
+static const uint cubeIndices[36] = {
+    4, 6, 2, 4, 2, 0,
+    1, 3, 7, 1, 7, 5,
+    0, 1, 5, 0, 5, 4,
+    6, 7, 3, 6, 3, 2,
+    2, 3, 1, 2, 1, 0,
+    4, 5, 7, 4, 7, 6,
+};
+
+struct VSOutput {
+    float4 position : SV_POSITION;
+};
+
+VSOutput VSDecal(uint vertexID : SV_VertexID) {
+    uint corner = cubeIndices[vertexID];
+    float3 local = float3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) * 2.0 - 1.0;
+
+    VSOutput output;
+    float4 world = mul(float4(local, 1.0), decalToWorld);
+    output.position = mul(world, projViewMatrix);
+
+    return output;
+}
+
+float3 WorldPosFromDepth(float depth, float2 uv) {
+    float4 clipSpacePos = float4(uv * 2.0 - 1.0, depth, 1.0);
+    clipSpacePos.y *= -1.0;
+
+    float4 worldSpacePos = mul(clipSpacePos, projViewMatrixInv);
+    return worldSpacePos.xyz / worldSpacePos.w;
+}
+
+// Projects the decal texture along the local Z axis onto the geometry inside the decal box, writing albedo only
+float4 PSDecal(VSOutput input) : SV_Target0 {
+    float depth = DepthTarget.Load(int3(input.position.xy, 0)).r;
+    float3 world = WorldPosFromDepth(depth, input.position.xy / targetSize.xy);
+    float3 local = mul(float4(world, 1.0), worldToDecal).xyz;
+
+    clip(1.0 - abs(local));
+
+    float2 uv = float2(local.x, -local.y) * 0.5 + 0.5;
+    float4 color = DecalTexture.Sample(DecalSampler, uv);
+    clip(color.a - 0.01);
+
+    return color;
+}
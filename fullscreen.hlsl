@@ -4,10 +4,33 @@ cbuffer CompositeOptions : register(b0) {
     float4 cameraDir;
     uint tex_i;
     uint lightCount;
+    uint lightTilesX;
 };
 
-cbuffer Lights : register(b1) {
-    float4 lights[1024];
+// Must match LIGHT_TILE_SIZE in render/lights.rs
+#define LIGHT_TILE_SIZE 16
+
+struct Light {
+    // xyz = world position, w = radius
+    float4 positionRadius;
+    // rgb = color * intensity, w = 1 for spot lights
+    float4 color;
+    // xyz = spot direction, w = cosine of the outer half angle
+    float4 direction;
+    // x = cosine of the inner half angle
+    float4 params;
+};
+
+// Must match MAX_CUBEMAP_VOLUMES in render/cubemaps.rs
+#define MAX_CUBEMAP_VOLUMES 4
+
+// Sorted by priority, the first volume is used where no other volume applies
+cbuffer CubemapVolumes : register(b1) {
+    row_major float4x4 worldToVolume[MAX_CUBEMAP_VOLUMES];
+    row_major float4x4 volumeToWorld[MAX_CUBEMAP_VOLUMES];
+    // x = edge blend width in volume space
+    float4 volumeParams[MAX_CUBEMAP_VOLUMES];
+    uint cubemapCount;
 };
 
 struct VSOutput {
@@ -41,7 +64,18 @@ Texture2D RenderTarget1 : register(t1);
 Texture2D RenderTarget2 : register(t2);
 Texture2D DepthTarget : register(t3);
 Texture2D Matcap : register(t4);
+// Transparent geometry, premultiplied alpha
+Texture2D ForwardTarget : register(t5);
+StructuredBuffer<Light> Lights : register(t6);
+// Per tile (offset, count) into LightIndices
+StructuredBuffer<uint2> LightTileRanges : register(t7);
+StructuredBuffer<uint> LightIndices : register(t8);
+TextureCube Cubemap0 : register(t9);
+TextureCube Cubemap1 : register(t10);
+TextureCube Cubemap2 : register(t11);
+TextureCube Cubemap3 : register(t12);
 SamplerState SampleType : register(s0);
+SamplerState CubemapSampler : register(s1);
 
 float3 GammaCorrect(float3 c) {
     return pow(abs(c), (1.0/2.2).xxx);
@@ -127,7 +161,86 @@ float3 DecodeNormal(float3 n) {
     return n * 2.0 - 1.0;
 }
 
-float4 PeanutButterRasputin(float4 rt0, float4 rt1, float4 rt2, float depth, float2 uv) {
+// Smooth falloff reaching zero at the light radius
+float LightAttenuation(Light light, float3 L, float distance) {
+    float radius = light.positionRadius.w;
+    float window = saturate(1.0 - pow(distance / radius, 4.0));
+    float attenuation = window * window / (distance * distance + 1.0);
+
+    if(light.color.w > 0.0) {
+        float cosOuter = light.direction.w;
+        float cosInner = light.params.x;
+        attenuation *= smoothstep(cosOuter, max(cosInner, cosOuter + 0.001), dot(-L, light.direction.xyz));
+    }
+
+    return attenuation;
+}
+
+// lod is relative to the mip chain (0 = full resolution, 1 = smallest mip)
+float3 SampleCubemapLevel(TextureCube cube, float3 dir, float lod) {
+    uint width, height, levels;
+    cube.GetDimensions(0, width, height, levels);
+    return cube.SampleLevel(CubemapSampler, dir, lod * (levels - 1)).rgb;
+}
+
+float3 SampleCubemap(uint i, float3 dir, float lod) {
+    // Textures can't be indexed dynamically
+    [branch] switch(i) {
+        case 0: return SampleCubemapLevel(Cubemap0, dir, lod);
+        case 1: return SampleCubemapLevel(Cubemap1, dir, lod);
+        case 2: return SampleCubemapLevel(Cubemap2, dir, lod);
+        default: return SampleCubemapLevel(Cubemap3, dir, lod);
+    }
+}
+
+// Parallax corrects dir by intersecting it with the volume box
+float3 BoxProjectedDirection(uint i, float3 worldPos, float3 dir) {
+    float3 localPos = mul(float4(worldPos, 1.0), worldToVolume[i]).xyz;
+    float3 localDir = mul(float4(dir, 0.0), worldToVolume[i]).xyz;
+    localDir = abs(localDir) < 0.00001 ? 0.00001 : localDir;
+
+    float3 exitPlanes = localDir >= 0.0 ? 1.0 : -1.0;
+    float3 t = (exitPlanes - localPos) / localDir;
+    float3 hit = localPos + localDir * min(min(t.x, t.y), t.z);
+
+    return mul(float4(hit, 1.0), volumeToWorld[i]).xyz - volumeToWorld[i][3].xyz;
+}
+
+// Blends the cubemap volumes containing worldPos, in priority order
+void SampleCubemapVolumes(float3 worldPos, float3 N, float3 R, float roughness, out float3 specular, out float3 irradiance) {
+    specular = 0.0;
+    irradiance = 0.0;
+
+    float remaining = 1.0;
+    for(uint i = 0; i < cubemapCount && remaining > 0.0; ++i) {
+        float3 localPos = abs(mul(float4(worldPos, 1.0), worldToVolume[i]).xyz);
+        float edge = 1.0 - max(max(localPos.x, localPos.y), localPos.z);
+        float weight = saturate(edge / volumeParams[i].x) * remaining;
+        if(weight <= 0.0) {
+            continue;
+        }
+
+        specular += SampleCubemap(i, BoxProjectedDirection(i, worldPos, R), roughness) * weight;
+        irradiance += SampleCubemap(i, N, 1.0) * weight;
+        remaining -= weight;
+    }
+
+    if(remaining > 0.0 && cubemapCount > 0) {
+        specular += SampleCubemap(0, R, roughness) * remaining;
+        irradiance += SampleCubemap(0, N, 1.0) * remaining;
+    }
+}
+
+// Analytical approximation of the split-sum environment BRDF (Karis 2014)
+float2 EnvBRDFApprox(float NdotV, float roughness) {
+    const float4 c0 = float4(-1.0, -0.0275, -0.572, 0.022);
+    const float4 c1 = float4(1.0, 0.0425, 1.04, -0.04);
+    float4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * NdotV)) * r.x + r.y;
+    return float2(-1.04, 1.04) * a004 + r.zw;
+}
+
+float4 PeanutButterRasputin(float4 rt0, float4 rt1, float4 rt2, float depth, float2 uv, float2 pixel) {
     float3 albedo = rt0.xyz;
     float3 normal = DecodeNormal(rt1.xyz);
 
@@ -148,26 +261,22 @@ float4 PeanutButterRasputin(float4 rt0, float4 rt1, float4 rt2, float depth, flo
 
     // reflectance equation
     float3 Lo = float3(0.0, 0.0, 0.0);
-    float3 LIGHT_COL = float3(1.0, 1.0, 1.0) * 3.0;
-    for(uint i = 0; i < lightCount; ++i)
+    uint2 tile = uint2(pixel) / LIGHT_TILE_SIZE;
+    uint2 tileRange = LightTileRanges[tile.y * lightTilesX + tile.x];
+    for(uint t = 0; t < tileRange.y; ++t)
     {
-        float3 light_pos = lights[i].xyz;
-        if(i == 0) {
-            light_pos = cameraPos.xyz;
-        }
+        Light light = Lights[LightIndices[tileRange.x + t]];
 
-        float distance = length(light_pos - worldPos);
-        if(distance > 16.0) {
+        float distance = length(light.positionRadius.xyz - worldPos);
+        if(distance > light.positionRadius.w) {
             continue;
         }
 
         // calculate per-light radiance
-        float3 L = normalize(light_pos - worldPos);
+        float3 L = normalize(light.positionRadius.xyz - worldPos);
         float3 H = normalize(V + L);
-        // float distance    = length(lights[i].xyz - worldPos);
-        float attenuation = 1.0 / (distance * distance);
-        //float attenuation = 10.0 / (distance);
-		float3 radiance     = LIGHT_COL.xyz * attenuation;
+        float attenuation = LightAttenuation(light, L, distance);
+		float3 radiance     = light.color.rgb * attenuation;
 
         // cook-torrance brdf
         float NDF = DistributionGGX(N, H, roughness);
@@ -193,19 +302,16 @@ float4 PeanutButterRasputin(float4 rt0, float4 rt1, float4 rt2, float depth, flo
 	float3 kD = 1.0 - kS;
 	kD *= 1.0 - metallic;
 
-	// float3 irradiance = irradianceMap.Sample(textureSampler, N).rgb;
-	// float3 diffuse = albedo;
-	// float3 diffuse = irradiance * albedo;
+    float3 ambient = float3(0.03, 0.03, 0.03) * albedo;
+    if(cubemapCount > 0) {
+        float3 prefilteredColor, irradiance;
+        SampleCubemapVolumes(worldPos, N, R, roughness, prefilteredColor, irradiance);
 
-	// const float MAX_REFLECTION_LOD = 4.0;
-	// float3 prefilteredColor = preFilterMap.SampleLevel(textureSampler, R, roughness * MAX_REFLECTION_LOD).rgb;
-	// float2 envBRDF = brdfLUT.Sample(textureSampler, float2(max(dot(N, V), 0.0), roughness)).rg;
-	// float3 specular = prefilteredColor * (F * envBRDF.x + envBRDF.y);
+        float2 envBRDF = EnvBRDFApprox(max(dot(N, V), 0.0), roughness);
+        float3 specular = prefilteredColor * (F0 * envBRDF.x + envBRDF.y);
 
-	// float3 ambient = (kD * diffuse /*+ specular*/) * ao;
-    // float3 ambient = 1.0;
-    // float3 ambient = kD * diffuse;
-    float3 ambient = float3(0.03, 0.03, 0.03) * albedo;
+        ambient = (kD * irradiance * albedo + specular) * ao;
+    }
 
     float3 color = ambient + Lo;
 
@@ -252,15 +358,31 @@ float4 PShader(VSOutput input) : SV_Target {
         case 10: { // Iridescence
             return float4(albedo.aaa, 1.0);
         }
+        case 11: { // Matcap
+            float2 muv = 0.5 * rt1.xy + float2(0.5, 0.5);
+            float4 matcap = Matcap.Sample(SampleType, float2(muv.x, 1.0-muv.y));
+            return float4(GammaCorrect(albedo.xyz * matcap.x), 1.0);
+        }
+        case 12: { // Overdraw (every layer adds 1/32 to RT0.r)
+            float layers = albedo.r * 32.0;
+            float t = saturate((layers - 1.0) / 15.0);
+            float3 heat = lerp(float3(0.0, 0.0, 0.5), float3(0.0, 1.0, 0.0), saturate(t * 3.0));
+            heat = lerp(heat, float3(1.0, 1.0, 0.0), saturate(t * 3.0 - 1.0));
+            heat = lerp(heat, float3(1.0, 0.0, 0.0), saturate(t * 3.0 - 2.0));
+            return float4(layers < 0.5 ? float3(0.0, 0.0, 0.0) : heat, 1.0);
+        }
         default: { // Combined
+            float4 c;
             if(lightCount == 0) {
                 float2 muv = 0.5 * rt1.xy + float2(0.5, 0.5);
                 float4 matcap = Matcap.Sample(SampleType, float2(muv.x, 1.0-muv.y));
-                return float4(GammaCorrect(albedo.xyz * matcap.x) * (rt2.y * 2.0), 1.0);
+                c = float4(GammaCorrect(albedo.xyz * matcap.x) * (rt2.y * 2.0), 1.0);
             } else {
-                float4 c = PeanutButterRasputin(albedo, rt1, rt2, depth, input.uv);
-                return c;
+                c = PeanutButterRasputin(albedo, rt1, rt2, depth, input.uv, input.position.xy);
             }
+
+            float4 forward = ForwardTarget.Sample(SampleType, input.uv);
+            return float4(GammaCorrect(forward.rgb) + c.rgb * (1.0 - forward.a), 1.0);
 //             return float4((WorldPosFromDepth(depth, input.uv) % 100.0) / 100.0, 1.0);
 //             float3 t = WorldPosFromDepth(depth, input.uv) / 100.0;
 //             float3 t = WorldPosFromDepth(depth, input.uv);
@@ -0,0 +1,313 @@
+use glam::{Mat4, Vec3};
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    /// Returns an empty (inverted) box that any point will extend
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vec3>>(points: I) -> Self {
+        let mut aabb = Self::EMPTY;
+        for p in points {
+            aabb.extend(*p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn extend(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        [
+            Vec3::new(self.min.x, self.min.y, self.min.z),
+            Vec3::new(self.max.x, self.min.y, self.min.z),
+            Vec3::new(self.min.x, self.max.y, self.min.z),
+            Vec3::new(self.max.x, self.max.y, self.min.z),
+            Vec3::new(self.min.x, self.min.y, self.max.z),
+            Vec3::new(self.max.x, self.min.y, self.max.z),
+            Vec3::new(self.min.x, self.max.y, self.max.z),
+            Vec3::new(self.max.x, self.max.y, self.max.z),
+        ]
+    }
+
+    /// Returns the axis-aligned box enclosing this box after transformation
+    pub fn transform(&self, m: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        Aabb::from_points(self.corners().map(|c| m.transform_point3(c)).iter())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub const EMPTY: BoundingSphere = BoundingSphere {
+        center: Vec3::ZERO,
+        radius: 0.0,
+    };
+
+    /// Sphere enclosing the given box. Slightly larger than necessary, but cheap to compute
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        if aabb.is_empty() {
+            return Self::EMPTY;
+        }
+
+        Self {
+            center: aabb.center(),
+            radius: aabb.extents().length() * 0.5,
+        }
+    }
+
+    /// Approximate minimal bounding sphere using Ritter's algorithm
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let Some(&first) = points.first() else {
+            return Self::EMPTY;
+        };
+
+        let farthest_from = |p: Vec3| {
+            points
+                .iter()
+                .copied()
+                .max_by(|a, b| a.distance_squared(p).total_cmp(&b.distance_squared(p)))
+                .unwrap_or(p)
+        };
+
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+        let mut center = (a + b) * 0.5;
+        let mut radius = a.distance(b) * 0.5;
+
+        for &p in points {
+            let distance = p.distance(center);
+            if distance > radius {
+                let new_radius = (radius + distance) * 0.5;
+                center += (p - center) * ((new_radius - radius) / distance);
+                radius = new_radius;
+            }
+        }
+
+        Self { center, radius }
+    }
+
+    /// Transforms the sphere, scaling the radius by the largest axis scale of the matrix
+    pub fn transform(&self, m: &Mat4) -> Self {
+        let max_scale = m
+            .x_axis
+            .truncate()
+            .length()
+            .max(m.y_axis.truncate().length())
+            .max(m.z_axis.truncate().length());
+
+        Self {
+            center: m.transform_point3(self.center),
+            radius: self.radius * max_scale,
+        }
+    }
+}
+
+/// Largest per-component difference between two boxes, relative to the size of `reference`.
+/// Used to compare computed bounds against unidentified tag fields that might be boxes
+pub fn aabb_relative_difference(reference: &Aabb, candidate: &Aabb) -> f32 {
+    let size = reference.extents().max_element().max(f32::EPSILON);
+    let difference = (reference.min - candidate.min)
+        .abs()
+        .max((reference.max - candidate.max).abs())
+        .max_element();
+
+    difference / size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: Vec3::splat(-1.0),
+            max: Vec3::splat(1.0),
+        }
+    }
+
+    fn assert_encloses(sphere: &BoundingSphere, points: &[Vec3]) {
+        for p in points {
+            assert!(
+                p.distance(sphere.center) <= sphere.radius + 1e-4,
+                "{p} is outside of {sphere:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn sphere_from_no_points() {
+        assert_eq!(BoundingSphere::from_points(&[]), BoundingSphere::EMPTY);
+        assert_eq!(
+            BoundingSphere::from_aabb(&Aabb::EMPTY),
+            BoundingSphere::EMPTY
+        );
+
+        let single = BoundingSphere::from_points(&[Vec3::new(1.0, 2.0, 3.0)]);
+        assert_eq!(single.center, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(single.radius, 0.0);
+    }
+
+    #[test]
+    fn sphere_from_axis_points_is_minimal() {
+        let points = [
+            Vec3::X,
+            Vec3::NEG_X,
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::Z,
+            Vec3::NEG_Z,
+        ];
+        let sphere = BoundingSphere::from_points(&points);
+
+        assert!(sphere.center.abs_diff_eq(Vec3::ZERO, 1e-6));
+        assert!((sphere.radius - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sphere_grows_to_enclose_outliers() {
+        // The initial diameter between the two farthest points misses the apex of the tetrahedron
+        let points = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-0.5, 0.866, 0.0),
+            Vec3::new(-0.5, -0.866, 0.0),
+            Vec3::new(0.0, 0.0, 1.5),
+            Vec3::new(0.1, 0.2, 0.3),
+        ];
+        let sphere = BoundingSphere::from_points(&points);
+        assert_encloses(&sphere, &points);
+
+        // Ritter's algorithm is not exact, but shouldn't do worse than the sphere around the box.
+        // The minimal sphere is centered at z = 5/12 with a radius of 13/12
+        let box_sphere = BoundingSphere::from_aabb(&Aabb::from_points(&points));
+        assert!(sphere.radius >= 13.0 / 12.0 - 1e-4, "{sphere:?}");
+        assert!(sphere.radius <= box_sphere.radius, "{sphere:?}");
+    }
+
+    #[test]
+    fn sphere_from_box_encloses_corners() {
+        let aabb = Aabb {
+            min: Vec3::new(-1.0, 2.0, 0.0),
+            max: Vec3::new(3.0, 4.0, 0.5),
+        };
+        let sphere = BoundingSphere::from_aabb(&aabb);
+        assert_eq!(sphere.center, Vec3::new(1.0, 3.0, 0.25));
+        assert_encloses(&sphere, &aabb.corners());
+    }
+
+    #[test]
+    fn sphere_transform_uses_largest_scale() {
+        let sphere = BoundingSphere {
+            center: Vec3::X,
+            radius: 2.0,
+        };
+        let m = Mat4::from_scale_rotation_translation(
+            Vec3::new(1.0, 3.0, 0.5),
+            glam::Quat::from_rotation_z(1.0),
+            Vec3::new(0.0, 0.0, 10.0),
+        );
+        let transformed = sphere.transform(&m);
+
+        assert!(transformed
+            .center
+            .abs_diff_eq(m.transform_point3(Vec3::X), 1e-5));
+        assert!((transformed.radius - 6.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn aabb_transform_translation_and_scale() {
+        let m = Mat4::from_scale_rotation_translation(
+            Vec3::new(2.0, 1.0, 0.5),
+            glam::Quat::IDENTITY,
+            Vec3::new(10.0, 0.0, -1.0),
+        );
+        let transformed = unit_box().transform(&m);
+
+        assert_eq!(transformed.min, Vec3::new(8.0, -1.0, -1.5));
+        assert_eq!(transformed.max, Vec3::new(12.0, 1.0, -0.5));
+    }
+
+    #[test]
+    fn aabb_transform_rotation() {
+        let aabb = Aabb {
+            min: Vec3::new(0.0, 0.0, 0.0),
+            max: Vec3::new(2.0, 1.0, 1.0),
+        };
+
+        // A quarter turn swaps the X and Y extents
+        let quarter = aabb.transform(&Mat4::from_rotation_z(90f32.to_radians()));
+        assert!(quarter.min.abs_diff_eq(Vec3::new(-1.0, 0.0, 0.0), 1e-6));
+        assert!(quarter.max.abs_diff_eq(Vec3::new(0.0, 2.0, 1.0), 1e-6));
+
+        // An eighth turn makes the box larger, as it has to enclose the rotated corners
+        let eighth = unit_box().transform(&Mat4::from_rotation_z(45f32.to_radians()));
+        let half_diagonal = 2f32.sqrt();
+        assert!(eighth
+            .max
+            .abs_diff_eq(Vec3::new(half_diagonal, half_diagonal, 1.0), 1e-6));
+        assert!(eighth
+            .min
+            .abs_diff_eq(Vec3::new(-half_diagonal, -half_diagonal, -1.0), 1e-6));
+    }
+
+    #[test]
+    fn aabb_transform_keeps_empty_boxes_empty() {
+        let transformed = Aabb::EMPTY.transform(&Mat4::from_translation(Vec3::ONE));
+        assert!(transformed.is_empty());
+    }
+
+    #[test]
+    fn relative_difference() {
+        assert_eq!(aabb_relative_difference(&unit_box(), &unit_box()), 0.0);
+
+        // Off by 0.5 on a box that is 2 units wide
+        let shifted = Aabb {
+            min: Vec3::new(-1.0, -0.5, -1.0),
+            max: Vec3::ONE,
+        };
+        assert_eq!(aabb_relative_difference(&unit_box(), &shifted), 0.25);
+    }
+}
@@ -1,8 +1,68 @@
-use glam::{Mat4, Vec2, Vec3};
+use glam::{Mat4, Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use strum::{EnumVariantNames, FromRepr};
 use winit::event::VirtualKeyCode;
 
 use crate::input::InputState;
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, EnumVariantNames, FromRepr)]
+#[repr(usize)]
+pub enum ProjectionMode {
+    Perspective,
+    Orthographic,
+}
+
+/// Depth range covered by orthographic projections, in both directions from the camera
+const ORTHOGRAPHIC_DEPTH: f32 = 100000.0;
+
+/// Camera projection settings, shared by everything that needs to (un)project points on screen
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Projection {
+    pub mode: ProjectionMode,
+    /// Vertical field of view, in degrees
+    pub fov: f32,
+    pub near: f32,
+    /// Vertical extent of the orthographic view, in world units
+    pub orthographic_height: f32,
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self {
+            mode: ProjectionMode::Perspective,
+            fov: 90.0,
+            near: 0.0001,
+            orthographic_height: 100.0,
+        }
+    }
+}
+
+impl Projection {
+    /// Builds a reverse-Z projection matrix (depth 1.0 at the near plane)
+    pub fn matrix(&self, aspect_ratio: f32) -> Mat4 {
+        match self.mode {
+            ProjectionMode::Perspective => Mat4::perspective_infinite_reverse_rh(
+                self.fov.to_radians(),
+                aspect_ratio,
+                self.near,
+            ),
+            ProjectionMode::Orthographic => {
+                let half_height = self.orthographic_height / 2.0;
+                let half_width = half_height * aspect_ratio;
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    ORTHOGRAPHIC_DEPTH,
+                    -ORTHOGRAPHIC_DEPTH,
+                )
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FpsCamera {
     orientation: Vec2,
@@ -10,6 +70,7 @@ pub struct FpsCamera {
     pub right: Vec3,
     pub position: Vec3,
     pub speed_mul: f32,
+    pub projection: Projection,
 }
 
 impl Default for FpsCamera {
@@ -20,6 +81,7 @@ impl Default for FpsCamera {
             position: Vec3::ZERO,
             orientation: Vec2::ZERO,
             speed_mul: 1.0,
+            projection: Projection::default(),
         }
     }
 }
@@ -84,12 +146,38 @@ impl FpsCamera {
         self.update_vectors();
     }
 
-    pub fn calculate_matrix(&mut self) -> Mat4 {
+    pub fn calculate_matrix(&self) -> Mat4 {
         Mat4::look_at_rh(self.position, self.position + self.front, Vec3::Z)
     }
 
-    // pub fn rotation(&self) -> Quat {
-    //     Quat::from_rotation_y(self.orientation.y.to_radians())
-    //         * Quat::from_rotation_x(self.orientation.x.to_radians())
-    // }
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        self.projection.matrix(aspect_ratio)
+    }
+
+    /// Combined projection * view matrix
+    pub fn projection_view_matrix(&self, aspect_ratio: f32) -> Mat4 {
+        self.projection_matrix(aspect_ratio) * self.calculate_matrix()
+    }
+
+    /// Points the camera (almost) straight down, for use with orthographic projection
+    pub fn look_top_down(&mut self) {
+        self.orientation.x = 89.9;
+        self.update_vectors();
+    }
+
+    /// Rotation that transforms +Y (the default forward vector) into `front`
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_z(-self.orientation.y.to_radians())
+            * Quat::from_rotation_x(-self.orientation.x.to_radians())
+    }
+
+    /// Sets the camera orientation from a rotation created by [`FpsCamera::rotation`]
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        let front = (rotation * Vec3::Y).normalize();
+        self.orientation = Vec2::new(
+            (-front.z).clamp(-1.0, 1.0).asin().to_degrees(),
+            front.x.atan2(front.y).to_degrees(),
+        );
+        self.update_vectors();
+    }
 }
@@ -0,0 +1,356 @@
+use std::path::PathBuf;
+
+use destiny_pkg::TagHash;
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::FpsCamera;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    /// Time of this keyframe on the path, in seconds
+    pub time: f32,
+    pub position: Vec3,
+    pub rotation: Quat,
+    /// Vertical field of view, in degrees
+    pub fov: f32,
+}
+
+impl CameraKeyframe {
+    pub fn from_camera(camera: &FpsCamera, time: f32) -> Self {
+        Self {
+            time,
+            position: camera.position,
+            rotation: camera.rotation(),
+            fov: camera.projection.fov,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut FpsCamera) {
+        camera.position = self.position;
+        camera.projection.fov = self.fov;
+        camera.set_rotation(self.rotation);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct CameraPath {
+    /// Keyframes, sorted by time
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Total length of the path, in seconds
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or_default()
+    }
+
+    /// Inserts a keyframe, keeping the keyframes sorted by time
+    pub fn insert(&mut self, keyframe: CameraKeyframe) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<CameraKeyframe> {
+        (index < self.keyframes.len()).then(|| self.keyframes.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    /// Samples the path at the given time. Positions and FOV use a Catmull-Rom spline, rotations are slerped.
+    /// Times outside of the path are clamped to the first/last keyframe
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if time <= first.time {
+            return Some(CameraKeyframe { time, ..*first });
+        }
+        if time >= last.time {
+            return Some(CameraKeyframe { time, ..*last });
+        }
+
+        // Index of the keyframe that starts the segment containing `time`
+        let i = self.keyframes.partition_point(|k| k.time <= time) - 1;
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        let k0 = &self.keyframes[i.saturating_sub(1)];
+        let k3 = &self.keyframes[(i + 2).min(self.keyframes.len() - 1)];
+
+        let segment_length = k2.time - k1.time;
+        let t = if segment_length > f32::EPSILON {
+            (time - k1.time) / segment_length
+        } else {
+            0.0
+        };
+
+        Some(CameraKeyframe {
+            time,
+            position: catmull_rom(k0.position, k1.position, k2.position, k3.position, t),
+            rotation: k1.rotation.slerp(k2.rotation, t).normalize(),
+            fov: catmull_rom_scalar(k0.fov, k1.fov, k2.fov, k3.fov, t),
+        })
+    }
+
+    fn path_for_map(map: TagHash) -> PathBuf {
+        PathBuf::from("camera_paths").join(format!("{:08x}.yml", map.0))
+    }
+
+    pub fn load(map: TagHash) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read_to_string(Self::path_for_map(map))?)
+    }
+
+    fn parse(data: &str) -> anyhow::Result<Self> {
+        let mut path: CameraPath = serde_yaml::from_str(data)?;
+        path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(path)
+    }
+
+    pub fn save(&self, map: TagHash) -> anyhow::Result<()> {
+        let path = Self::path_for_map(map);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Uniform Catmull-Rom interpolation between `p1` and `p2`
+pub fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    0.5 * ((2.0 * p1)
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+pub fn catmull_rom_scalar(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    catmull_rom(
+        Vec3::splat(p0),
+        Vec3::splat(p1),
+        Vec3::splat(p2),
+        Vec3::splat(p3),
+        t,
+    )
+    .x
+}
+
+/// Plays back a camera path. When `fixed_timestep` is set, every frame advances the path by exactly that amount,
+/// making playback independent of the actual framerate
+pub struct CameraPathPlayer {
+    pub time: f32,
+    pub playing: bool,
+    pub looping: bool,
+    pub fixed_timestep: Option<f32>,
+}
+
+impl Default for CameraPathPlayer {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            playing: false,
+            looping: false,
+            fixed_timestep: Some(1.0 / 60.0),
+        }
+    }
+}
+
+impl CameraPathPlayer {
+    pub fn play(&mut self) {
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Advances playback and returns the sampled keyframe, or None if playback is stopped
+    pub fn advance(&mut self, path: &CameraPath, frame_delta: f32) -> Option<CameraKeyframe> {
+        if !self.playing || path.is_empty() {
+            self.playing = false;
+            return None;
+        }
+
+        let duration = path.duration();
+        let sample = path.sample(self.time);
+        if self.time >= duration {
+            if self.looping && duration > 0.0 {
+                self.time = 0.0;
+            } else {
+                self.playing = false;
+            }
+            return sample;
+        }
+
+        self.time = (self.time + self.fixed_timestep.unwrap_or(frame_delta)).min(duration);
+
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, rotation: Quat) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            position: Vec3::new(x, 0.0, 0.0),
+            rotation,
+            fov: 60.0 + x * 10.0,
+        }
+    }
+
+    /// Evenly spaced keyframes along the X axis, rotating 90 degrees around Z between the middle two
+    fn test_path() -> CameraPath {
+        let mut path = CameraPath::default();
+        let turned = Quat::from_rotation_z(90f32.to_radians());
+        // Inserted out of order on purpose
+        path.insert(keyframe(2.0, 2.0, turned));
+        path.insert(keyframe(0.0, 0.0, Quat::IDENTITY));
+        path.insert(keyframe(3.0, 3.0, turned));
+        path.insert(keyframe(1.0, 1.0, Quat::IDENTITY));
+        path
+    }
+
+    #[test]
+    fn insert_keeps_keyframes_sorted() {
+        let path = test_path();
+        let times: Vec<f32> = path.keyframes().iter().map(|k| k.time).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(path.duration(), 3.0);
+    }
+
+    #[test]
+    fn sample_hits_keyframes() {
+        let path = test_path();
+        for k in path.keyframes() {
+            let s = path.sample(k.time).unwrap();
+            assert!(s.position.abs_diff_eq(k.position, 1e-5));
+            assert!(s.rotation.abs_diff_eq(k.rotation, 1e-5));
+            assert!((s.fov - k.fov).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sample_interpolates_between_keyframes() {
+        let path = test_path();
+        let s = path.sample(1.5).unwrap();
+
+        // Catmull-Rom reproduces evenly spaced collinear points exactly
+        assert!(s.position.abs_diff_eq(Vec3::new(1.5, 0.0, 0.0), 1e-5));
+        assert!((s.fov - 75.0).abs() < 1e-4);
+        assert!(s
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_z(45f32.to_radians()), 1e-5));
+    }
+
+    #[test]
+    fn sample_clamps_outside_of_path() {
+        let path = test_path();
+        let before = path.sample(-1.0).unwrap();
+        assert_eq!(before.time, -1.0);
+        assert_eq!(before.position, Vec3::ZERO);
+
+        let after = path.sample(10.0).unwrap();
+        assert_eq!(after.position, Vec3::new(3.0, 0.0, 0.0));
+
+        assert!(CameraPath::default().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn fixed_step_playback_ignores_frame_delta() {
+        let path = test_path();
+        let mut player = CameraPathPlayer {
+            fixed_timestep: Some(1.0),
+            ..Default::default()
+        };
+        player.play();
+
+        let mut sampled = vec![];
+        while let Some(k) = player.advance(&path, 0.123) {
+            sampled.push(k.time);
+        }
+
+        assert_eq!(sampled, [0.0, 1.0, 2.0, 3.0]);
+        assert!(!player.playing);
+    }
+
+    #[test]
+    fn fixed_step_playback_stops_at_the_end() {
+        let path = test_path();
+        let mut player = CameraPathPlayer {
+            fixed_timestep: Some(2.0),
+            ..Default::default()
+        };
+        player.play();
+
+        let mut sampled = vec![];
+        while let Some(k) = player.advance(&path, 0.0) {
+            sampled.push(k.time);
+        }
+
+        // The last step is shortened so the final keyframe is always reached
+        assert_eq!(sampled, [0.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn variable_step_playback_uses_frame_delta() {
+        let path = test_path();
+        let mut player = CameraPathPlayer {
+            fixed_timestep: None,
+            ..Default::default()
+        };
+        player.play();
+
+        assert_eq!(player.advance(&path, 0.5).unwrap().time, 0.0);
+        assert_eq!(player.advance(&path, 0.5).unwrap().time, 0.5);
+        assert_eq!(player.time, 1.0);
+    }
+
+    #[test]
+    fn looping_playback_restarts() {
+        let path = test_path();
+        let mut player = CameraPathPlayer {
+            fixed_timestep: Some(3.0),
+            looping: true,
+            ..Default::default()
+        };
+        player.play();
+
+        let times: Vec<f32> = (0..4)
+            .map(|_| player.advance(&path, 0.0).unwrap().time)
+            .collect();
+        assert_eq!(times, [0.0, 3.0, 0.0, 3.0]);
+        assert!(player.playing);
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let path = test_path();
+        let loaded = CameraPath::parse(&serde_yaml::to_string(&path).unwrap()).unwrap();
+        assert_eq!(loaded.keyframes(), path.keyframes());
+    }
+
+    #[test]
+    fn load_sorts_keyframes() {
+        let mut path = test_path();
+        path.keyframes.reverse();
+        let loaded = CameraPath::parse(&serde_yaml::to_string(&path).unwrap()).unwrap();
+        assert_eq!(loaded.keyframes(), test_path().keyframes());
+    }
+}
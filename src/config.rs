@@ -3,6 +3,8 @@ use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
+use crate::camera::Projection;
+
 lazy_static! {
     pub static ref CONFIGURATION: RwLock<Config> = RwLock::new(Config::default());
 }
@@ -40,6 +42,8 @@ macro_rules! config {
 #[derive(Serialize, Deserialize, Default)]
 pub struct Config {
     pub window: WindowConfig,
+    #[serde(default)]
+    pub projection: Projection,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,282 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::lod::LOD_LEVELS;
+
+/// View frustum planes extracted from a (reverse-Z) projection-view matrix. Plane normals point inwards
+///
+/// Replaces `frustum_query`, so culling and the resource labels share one implementation that handles the 0..w
+/// depth range and box tests
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    pub fn from_projection_view(m: &Mat4) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+
+        // Clip space is -w <= x,y <= w and 0 <= z <= w
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|p| {
+            let length = p.truncate().length();
+            // Infinite projections have a degenerate far plane, which never culls anything
+            if length > f32::EPSILON {
+                p / length
+            } else {
+                Vec4::ZERO
+            }
+        });
+
+        Self { planes }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.truncate().dot(point) + p.w >= 0.0)
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.truncate().dot(sphere.center) + p.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|p| {
+            let normal = p.truncate();
+            // Corner of the box furthest along the plane normal
+            let positive = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(positive) + p.w >= 0.0
+        })
+    }
+}
+
+pub struct CullingSettings {
+    pub frustum_culling: bool,
+    pub distance_culling: bool,
+    pub max_distance: f32,
+}
+
+impl Default for CullingSettings {
+    fn default() -> Self {
+        Self {
+            frustum_culling: true,
+            distance_culling: false,
+            max_distance: 2000.0,
+        }
+    }
+}
+
+/// Decides which bounding volumes are visible from a given view
+pub struct ViewCuller {
+    frustum: Option<Frustum>,
+    camera_position: Vec3,
+    max_distance: Option<f32>,
+}
+
+impl ViewCuller {
+    pub fn new(settings: &CullingSettings, proj_view: &Mat4, camera_position: Vec3) -> Self {
+        Self {
+            frustum: settings
+                .frustum_culling
+                .then(|| Frustum::from_projection_view(proj_view)),
+            camera_position,
+            max_distance: settings.distance_culling.then_some(settings.max_distance),
+        }
+    }
+
+    pub fn is_sphere_visible(&self, sphere: &BoundingSphere) -> bool {
+        if let Some(max_distance) = self.max_distance {
+            if sphere.center.distance(self.camera_position) - sphere.radius > max_distance {
+                return false;
+            }
+        }
+
+        self.frustum
+            .as_ref()
+            .map_or(true, |f| f.intersects_sphere(sphere))
+    }
+
+    pub fn is_aabb_visible(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        if let Some(max_distance) = self.max_distance {
+            let closest = self.camera_position.clamp(aabb.min, aabb.max);
+            if closest.distance(self.camera_position) > max_distance {
+                return false;
+            }
+        }
+
+        self.frustum
+            .as_ref()
+            .map_or(true, |f| f.intersects_aabb(aabb))
+    }
+}
+
+/// Collects the indices of all visible items into `out`, in order
+pub fn visible_indices(count: usize, visible: impl Fn(usize) -> bool, out: &mut Vec<u32>) {
+    out.clear();
+    out.extend((0..count).filter(|&i| visible(i)).map(|i| i as u32));
+}
+
+/// Copies the items referenced by `indices` into `out`, so that visible instances are packed at the start of the instance buffer
+pub fn compact<T: Copy>(items: &[T], indices: &[u32], out: &mut Vec<T>) {
+    out.clear();
+    out.extend(indices.iter().map(|&i| items[i as usize]));
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct LayerStats {
+    pub total: usize,
+    pub visible: usize,
+    /// Visible items per LOD level
+    pub lods: [usize; LOD_LEVELS],
+}
+
+impl LayerStats {
+    pub fn add(&mut self, other: LayerStats) {
+        self.total += other.total;
+        self.visible += other.visible;
+        for (l, o) in self.lods.iter_mut().zip(other.lods) {
+            *l += o;
+        }
+    }
+}
+
+/// Culling results of the last frame
+#[derive(Default, Clone, Copy)]
+pub struct CullingStats {
+    pub statics: LayerStats,
+    pub terrain: LayerStats,
+    pub entities: LayerStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEAR: f32 = 0.1;
+
+    /// Camera at the origin looking down +Y with Z up, 90 degree FOV and an infinite far plane
+    fn test_proj_view() -> Mat4 {
+        let proj = Mat4::perspective_infinite_reverse_rh(90f32.to_radians(), 1.0, NEAR);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::Y, Vec3::Z);
+        proj * view
+    }
+
+    fn aabb(center: Vec3, half_size: f32) -> Aabb {
+        Aabb {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    #[test]
+    fn plane_extraction() {
+        let frustum = Frustum::from_projection_view(&test_proj_view());
+        let d = std::f32::consts::FRAC_1_SQRT_2;
+        let expected = [
+            Vec4::new(d, d, 0.0, 0.0),
+            Vec4::new(-d, d, 0.0, 0.0),
+            Vec4::new(0.0, d, d, 0.0),
+            Vec4::new(0.0, d, -d, 0.0),
+            // Degenerate far plane of the infinite projection
+            Vec4::ZERO,
+            Vec4::new(0.0, 1.0, 0.0, -NEAR),
+        ];
+
+        for (plane, expected) in frustum.planes.iter().zip(expected) {
+            assert!(plane.abs_diff_eq(expected, 1e-5), "{plane} != {expected}");
+        }
+    }
+
+    #[test]
+    fn point_classification() {
+        let frustum = Frustum::from_projection_view(&test_proj_view());
+        assert!(frustum.contains_point(Vec3::new(0.0, 10.0, 0.0)));
+        assert!(frustum.contains_point(Vec3::new(9.0, 10.0, -9.0)));
+        assert!(frustum.contains_point(Vec3::new(0.0, 1.0e6, 0.0)));
+
+        assert!(!frustum.contains_point(Vec3::new(0.0, -10.0, 0.0)));
+        assert!(!frustum.contains_point(Vec3::new(11.0, 10.0, 0.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 10.0, 11.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, NEAR * 0.5, 0.0)));
+    }
+
+    #[test]
+    fn aabb_classification() {
+        let frustum = Frustum::from_projection_view(&test_proj_view());
+
+        // Fully inside
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(0.0, 10.0, 0.0), 1.0)));
+        // Straddling the right plane
+        assert!(frustum.intersects_aabb(&aabb(Vec3::new(10.5, 10.0, 0.0), 1.0)));
+        // Containing the camera
+        assert!(frustum.intersects_aabb(&aabb(Vec3::ZERO, 1.0)));
+        // Outside of a single plane
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(14.0, 10.0, 0.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, 10.0, -14.0), 1.0)));
+        // Behind the camera
+        assert!(!frustum.intersects_aabb(&aabb(Vec3::new(0.0, -10.0, 0.0), 1.0)));
+    }
+
+    #[test]
+    fn sphere_classification() {
+        let frustum = Frustum::from_projection_view(&test_proj_view());
+        let sphere = |center: Vec3, radius: f32| BoundingSphere { center, radius };
+
+        assert!(frustum.intersects_sphere(&sphere(Vec3::new(0.0, 10.0, 0.0), 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(Vec3::new(0.0, -0.5, 0.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(Vec3::new(0.0, -10.0, 0.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(Vec3::new(20.0, 10.0, 0.0), 1.0)));
+    }
+
+    #[test]
+    fn distance_culling() {
+        let settings = CullingSettings {
+            frustum_culling: false,
+            distance_culling: true,
+            max_distance: 100.0,
+        };
+        let culler = ViewCuller::new(&settings, &test_proj_view(), Vec3::ZERO);
+
+        // Distance is measured to the closest point of the box, so large boxes stay visible
+        assert!(culler.is_aabb_visible(&aabb(Vec3::new(0.0, -105.0, 0.0), 10.0)));
+        assert!(!culler.is_aabb_visible(&aabb(Vec3::new(0.0, 120.0, 0.0), 10.0)));
+        assert!(!culler.is_aabb_visible(&Aabb::EMPTY));
+
+        let sphere = |center: Vec3, radius: f32| BoundingSphere { center, radius };
+        assert!(culler.is_sphere_visible(&sphere(Vec3::new(105.0, 0.0, 0.0), 10.0)));
+        assert!(!culler.is_sphere_visible(&sphere(Vec3::new(120.0, 0.0, 0.0), 10.0)));
+    }
+
+    #[test]
+    fn disabled_culling_keeps_everything() {
+        let settings = CullingSettings {
+            frustum_culling: false,
+            distance_culling: false,
+            max_distance: 0.0,
+        };
+        let culler = ViewCuller::new(&settings, &test_proj_view(), Vec3::ZERO);
+        assert!(culler.is_aabb_visible(&aabb(Vec3::new(0.0, -1.0e5, 0.0), 1.0)));
+    }
+
+    #[test]
+    fn compaction() {
+        let items = [10, 11, 12, 13, 14];
+        let mut indices = vec![];
+        visible_indices(items.len(), |i| i % 2 == 0, &mut indices);
+        assert_eq!(indices, [0, 2, 4]);
+
+        let mut compacted = vec![99];
+        compact(&items, &indices, &mut compacted);
+        assert_eq!(compacted, [10, 12, 14]);
+
+        visible_indices(items.len(), |_| false, &mut indices);
+        compact(&items, &indices, &mut compacted);
+        assert!(compacted.is_empty());
+    }
+}
@@ -0,0 +1,1420 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, ensure};
+use destiny_pkg::TagHash;
+use tracing::error;
+
+use crate::disassembler::{
+    decode_program, Instruction, Opcode, Operand, OperandComponents, OperandIndex, OperandModifier,
+    OperandType, COMPONENT_NAMES,
+};
+use crate::dxbc::{
+    DxbcConstantBuffer, DxbcInputType, DxbcProgramType, DxbcShader, DxbcSignature,
+    DxbcVariableClass,
+};
+use crate::types::Vector4;
+
+/// How an instruction interprets the bits of its registers.
+/// Registers are declared as `float4`, integer operations go through `asint`/`asuint`/`asfloat`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Float,
+    Int,
+    Uint,
+}
+
+impl Kind {
+    fn type_name(self, components: usize) -> String {
+        let base = match self {
+            Kind::Float => "float",
+            Kind::Int => "int",
+            Kind::Uint => "uint",
+        };
+        if components > 1 {
+            format!("{base}{components}")
+        } else {
+            base.to_string()
+        }
+    }
+}
+
+/// Source kind, result kind and expression of instructions that map directly to an HLSL expression.
+/// `{0}`..`{3}` are the source operands, `{int}`/`{float}` the type matching the destination width
+fn alu_expression(name: &str) -> Option<(Kind, Kind, &'static str)> {
+    use Kind::*;
+
+    Some(match name {
+        "mov" => (Float, Float, "{0}"),
+        "add" => (Float, Float, "{0} + {1}"),
+        "mul" => (Float, Float, "{0} * {1}"),
+        "div" => (Float, Float, "{0} / {1}"),
+        "mad" => (Float, Float, "{0} * {1} + {2}"),
+        "min" => (Float, Float, "min({0}, {1})"),
+        "max" => (Float, Float, "max({0}, {1})"),
+        "frc" => (Float, Float, "frac({0})"),
+        "exp" => (Float, Float, "exp2({0})"),
+        "log" => (Float, Float, "log2({0})"),
+        "rsq" => (Float, Float, "rsqrt({0})"),
+        "sqrt" => (Float, Float, "sqrt({0})"),
+        "rcp" => (Float, Float, "rcp({0})"),
+        "round_ne" => (Float, Float, "round({0})"),
+        "round_ni" => (Float, Float, "floor({0})"),
+        "round_pi" => (Float, Float, "ceil({0})"),
+        "round_z" => (Float, Float, "trunc({0})"),
+        "deriv_rtx" => (Float, Float, "ddx({0})"),
+        "deriv_rty" => (Float, Float, "ddy({0})"),
+        "deriv_rtx_coarse" => (Float, Float, "ddx_coarse({0})"),
+        "deriv_rtx_fine" => (Float, Float, "ddx_fine({0})"),
+        "deriv_rty_coarse" => (Float, Float, "ddy_coarse({0})"),
+        "deriv_rty_fine" => (Float, Float, "ddy_fine({0})"),
+        "eq" => (Float, Int, "-({int})({0} == {1})"),
+        "ne" => (Float, Int, "-({int})({0} != {1})"),
+        "lt" => (Float, Int, "-({int})({0} < {1})"),
+        "ge" => (Float, Int, "-({int})({0} >= {1})"),
+        "ieq" => (Int, Int, "-({int})({0} == {1})"),
+        "ine" => (Int, Int, "-({int})({0} != {1})"),
+        "ilt" => (Int, Int, "-({int})({0} < {1})"),
+        "ige" => (Int, Int, "-({int})({0} >= {1})"),
+        "ult" => (Uint, Int, "-({int})({0} < {1})"),
+        "uge" => (Uint, Int, "-({int})({0} >= {1})"),
+        "iadd" => (Int, Int, "{0} + {1}"),
+        "imad" => (Int, Int, "{0} * {1} + {2}"),
+        "umad" => (Uint, Uint, "{0} * {1} + {2}"),
+        "imax" => (Int, Int, "max({0}, {1})"),
+        "imin" => (Int, Int, "min({0}, {1})"),
+        "umax" => (Uint, Uint, "max({0}, {1})"),
+        "umin" => (Uint, Uint, "min({0}, {1})"),
+        "ineg" => (Int, Int, "-{0}"),
+        "ishl" => (Int, Int, "{0} << {1}"),
+        "ishr" => (Int, Int, "{0} >> {1}"),
+        "ushr" => (Uint, Uint, "{0} >> {1}"),
+        "and" => (Uint, Uint, "{0} & {1}"),
+        "or" => (Uint, Uint, "{0} | {1}"),
+        "xor" => (Uint, Uint, "{0} ^ {1}"),
+        "not" => (Uint, Uint, "~{0}"),
+        "countbits" => (Uint, Uint, "countbits({0})"),
+        "firstbit_hi" => (Uint, Uint, "firstbithigh({0})"),
+        "firstbit_lo" => (Uint, Uint, "firstbitlow({0})"),
+        "firstbit_shi" => (Int, Int, "firstbithigh({0})"),
+        "bfrev" => (Uint, Uint, "reversebits({0})"),
+        "ubfe" => (Uint, Uint, "({2} >> {1}) & ((1u << {0}) - 1)"),
+        "ibfe" => (Int, Int, "({2} << (32 - {0} - {1})) >> (32 - {0})"),
+        "bfi" => (
+            Uint,
+            Uint,
+            "(({2} << {1}) & (((1u << {0}) - 1) << {1})) | ({3} & ~(((1u << {0}) - 1) << {1}))",
+        ),
+        "ftoi" => (Float, Int, "({int}){0}"),
+        "ftou" => (Float, Uint, "({uint}){0}"),
+        "itof" => (Int, Float, "({float}){0}"),
+        "utof" => (Uint, Float, "({float}){0}"),
+        "f32tof16" => (Float, Uint, "f32tof16({0})"),
+        "f16tof32" => (Uint, Float, "f16tof32({0})"),
+        _ => return None,
+    })
+}
+
+fn texture_type_name(dimension: u32) -> Option<&'static str> {
+    Some(match dimension {
+        1 => "Buffer",
+        2 => "Texture1D",
+        3 => "Texture2D",
+        4 => "Texture2DMS",
+        5 => "Texture3D",
+        6 => "TextureCube",
+        7 => "Texture1DArray",
+        8 => "Texture2DArray",
+        9 => "Texture2DMSArray",
+        10 => "TextureCubeArray",
+        _ => return None,
+    })
+}
+
+/// Number of components in the texture coordinates of a resource dimension
+fn coordinate_count(dimension: u32) -> usize {
+    match dimension {
+        1 | 2 => 1,
+        3 | 4 | 7 => 2,
+        5 | 6 | 8 | 9 => 3,
+        _ => 4,
+    }
+}
+
+/// Number of components in texel offsets and gradients
+fn gradient_count(dimension: u32) -> usize {
+    match dimension {
+        2 | 7 => 1,
+        3 | 4 | 8 | 9 => 2,
+        _ => 3,
+    }
+}
+
+fn float_literal(bits: u32) -> String {
+    let value = f32::from_bits(bits);
+    // Denormals are almost always integers moved through a float register, keep their bits
+    if (bits & 0x7f800000 == 0 && bits & 0x7fffff != 0) || !value.is_finite() {
+        format!("asfloat(0x{bits:08x})")
+    } else {
+        format!("{value:?}")
+    }
+}
+
+fn literal(operand: &Operand, components: &[u8], kind: Kind) -> String {
+    let values = components
+        .iter()
+        .map(|&c| {
+            operand
+                .immediate
+                .get(c as usize)
+                .or(operand.immediate.last())
+                .copied()
+                .unwrap_or_default()
+        })
+        .map(|v| match kind {
+            Kind::Float => float_literal(v),
+            Kind::Int => (v as i32).to_string(),
+            Kind::Uint if v > i32::MAX as u32 => format!("0x{v:x}"),
+            Kind::Uint => v.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    if values.len() == 1 {
+        values[0].clone()
+    } else {
+        format!("{}({})", kind.type_name(values.len()), values.join(", "))
+    }
+}
+
+/// Component of the register that is read for the given destination component
+/// First operand of an instruction; the decoder doesn't guarantee there is one
+fn first_operand(instruction: &Instruction) -> anyhow::Result<&Operand> {
+    instruction
+        .operands
+        .first()
+        .ok_or_else(|| anyhow!("{} has no operands", instruction.opcode.name()))
+}
+
+fn swizzle_component(operand: &Operand, component: u8) -> u8 {
+    match operand.components {
+        OperandComponents::Swizzle(s) => s[component as usize],
+        OperandComponents::Select(c) => c,
+        _ => component,
+    }
+}
+
+fn swizzle(components: impl IntoIterator<Item = u8>) -> String {
+    components
+        .into_iter()
+        .map(|c| COMPONENT_NAMES[c as usize])
+        .collect()
+}
+
+fn identifier(name: &str) -> String {
+    let mut s: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.insert(0, '_');
+    }
+    s
+}
+
+/// Signature element as a struct field
+struct SignatureField {
+    name: String,
+    ty: String,
+    semantic: String,
+    /// Register the field is copied from/to, `None` for registers without an index such as oDepth
+    register: Option<u32>,
+    components: Vec<u8>,
+    kind: Kind,
+    is_bool: bool,
+}
+
+fn signature_fields(signature: Option<&DxbcSignature>) -> Vec<SignatureField> {
+    let Some(signature) = signature else {
+        return vec![];
+    };
+
+    signature
+        .elements
+        .iter()
+        .map(|e| {
+            let semantic = format!("{}{}", *e.semantic_name, e.semantic_index);
+            let mask = e.component_mask.bits();
+            let components = (0..4).filter(|i| mask & (1 << i) != 0).collect::<Vec<u8>>();
+            let kind = match e.component_type {
+                DxbcInputType::Float => Kind::Float,
+                DxbcInputType::Int => Kind::Int,
+                DxbcInputType::Uint => Kind::Uint,
+            };
+            // SV_IsFrontFace
+            let is_bool = e.system_value_type == 9;
+
+            SignatureField {
+                name: identifier(&semantic),
+                ty: if is_bool {
+                    "bool".to_string()
+                } else {
+                    kind.type_name(components.len().max(1))
+                },
+                semantic,
+                register: (e.register != u32::MAX).then_some(e.register),
+                components,
+                kind,
+                is_bool,
+            }
+        })
+        .collect()
+}
+
+/// Name of output registers that don't have an index (oDepth, oMask, ...)
+fn special_output(semantic: &str) -> &'static str {
+    let semantic = semantic.to_ascii_lowercase();
+    if semantic.starts_with("sv_depthgreaterequal") {
+        "oDepthGE"
+    } else if semantic.starts_with("sv_depthlessequal") {
+        "oDepthLE"
+    } else if semantic.starts_with("sv_coverage") {
+        "oMask"
+    } else {
+        "oDepth"
+    }
+}
+
+struct Decompiler<'a> {
+    shader: &'a DxbcShader,
+    /// t# register -> (`D3D10_SB_RESOURCE_DIMENSION`, return type token)
+    textures: BTreeMap<u32, (u32, u32)>,
+    /// s# register -> comparison sampler
+    samplers: BTreeMap<u32, bool>,
+    /// cb# slot -> size in registers
+    constant_buffers: BTreeMap<u32, u32>,
+    immediate_constants: Vec<u32>,
+    temps: u32,
+    /// x# register -> size
+    indexable_temps: BTreeMap<u32, u32>,
+    /// Constant buffers that are declared as a plain float4 array instead of their RDEF variables
+    raw_constant_buffers: BTreeSet<u32>,
+    /// Constant buffers with an access that couldn't be mapped to a variable in this pass
+    unresolved: BTreeSet<u32>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(shader: &'a DxbcShader, instructions: &[Instruction]) -> anyhow::Result<Self> {
+        let mut decompiler = Self {
+            shader,
+            textures: Default::default(),
+            samplers: Default::default(),
+            constant_buffers: Default::default(),
+            immediate_constants: vec![],
+            temps: 0,
+            indexable_temps: Default::default(),
+            raw_constant_buffers: Default::default(),
+            unresolved: Default::default(),
+        };
+
+        for ins in instructions {
+            let first_index = ins
+                .operands
+                .first()
+                .and_then(|o| o.indices.first())
+                .map(|i| i.offset as u32)
+                .unwrap_or_default();
+
+            match ins.opcode {
+                Opcode::DCL_RESOURCE => {
+                    decompiler.textures.insert(
+                        first_index,
+                        (ins.controls & 0x1f, ins.extra.first().copied().unwrap_or(5)),
+                    );
+                }
+                Opcode::DCL_SAMPLER => {
+                    decompiler
+                        .samplers
+                        .insert(first_index, ins.controls & 0xf == 1);
+                }
+                Opcode::DCL_CONSTANT_BUFFER => {
+                    let size = first_operand(ins)?
+                        .indices
+                        .get(1)
+                        .map(|i| i.offset as u32)
+                        .unwrap_or_default();
+                    decompiler.constant_buffers.insert(first_index, size);
+                }
+                Opcode::DCL_TEMPS => decompiler.temps = ins.extra.first().copied().unwrap_or(0),
+                Opcode::DCL_INDEXABLE_TEMP => {
+                    if let [register, size, ..] = ins.extra[..] {
+                        decompiler.indexable_temps.insert(register, size);
+                    }
+                }
+                // Immediate constant buffer
+                Opcode::CUSTOMDATA if ins.controls == 3 => {
+                    decompiler.immediate_constants = ins.extra.clone();
+                }
+                _ => {}
+            }
+        }
+
+        let slots = decompiler
+            .constant_buffers
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for slot in slots {
+            let representable = decompiler.constant_buffer(slot).is_some_and(|cb| {
+                cb.buffer_type == 0
+                    && cb.variables.iter().all(|v| {
+                        matches!(v.ty.base_type, 2 | 3 | 19)
+                            && matches!(
+                                v.ty.class,
+                                DxbcVariableClass::Scalar
+                                    | DxbcVariableClass::Vector
+                                    | DxbcVariableClass::MatrixRows
+                                    | DxbcVariableClass::MatrixColumns
+                            )
+                    })
+            });
+            if !representable {
+                decompiler.raw_constant_buffers.insert(slot);
+            }
+        }
+
+        Ok(decompiler)
+    }
+
+    /// RDEF definition of the constant buffer bound to `slot`
+    fn constant_buffer(&self, slot: u32) -> Option<&'a DxbcConstantBuffer> {
+        let resources = self.shader.resources.as_ref()?;
+        resources
+            .constant_buffers
+            .iter()
+            .find(|cb| resources.constant_buffer_slot(cb) == Some(slot))
+    }
+
+    fn index(&mut self, index: Option<&OperandIndex>) -> String {
+        let Some(index) = index else {
+            return "0".to_string();
+        };
+
+        match &index.relative {
+            None => index.offset.to_string(),
+            Some(relative) if index.offset == 0 => self.source(relative, &[0], Kind::Int),
+            Some(relative) => {
+                format!(
+                    "{} + {}",
+                    self.source(relative, &[0], Kind::Int),
+                    index.offset
+                )
+            }
+        }
+    }
+
+    fn register(&mut self, operand: &Operand) -> String {
+        let first = operand.indices.first();
+        match operand.ty {
+            OperandType::CONSTANT_BUFFER => format!(
+                "cb{}[{}]",
+                first.map(|i| i.offset).unwrap_or_default(),
+                self.index(operand.indices.get(1))
+            ),
+            OperandType::INDEXABLE_TEMP => format!(
+                "x{}[{}]",
+                first.map(|i| i.offset).unwrap_or_default(),
+                self.index(operand.indices.get(1))
+            ),
+            OperandType::IMMEDIATE_CONSTANT_BUFFER => {
+                format!("asfloat(icb[{}])", self.index(first))
+            }
+            _ => {
+                let mut s = operand.ty.prefix().to_string();
+                for (i, index) in operand.indices.iter().enumerate() {
+                    if i == 0 && index.relative.is_none() {
+                        write!(s, "{}", index.offset).ok();
+                    } else {
+                        write!(s, "[{}]", self.index(Some(index))).ok();
+                    }
+                }
+                s
+            }
+        }
+    }
+
+    /// Maps a constant buffer access to the RDEF variable it reads, if it reads exactly one
+    fn resolve_constant(&mut self, operand: &Operand, components: &[u8]) -> Option<String> {
+        let slot = operand.indices.first()?.offset as u32;
+        let index = operand.indices.get(1)?;
+        let cb = self.constant_buffer(slot)?;
+
+        let register = index.offset as u32;
+        let first_byte = register
+            .checked_mul(16)?
+            .checked_add(*components.first()? as u32 * 4)?;
+        let variable = cb
+            .variables
+            .iter()
+            .find(|v| v.offset <= first_byte && first_byte < v.offset + v.size)?;
+
+        let ty = &variable.ty;
+        let base_register = variable.offset / 16;
+        let start_component = ((variable.offset % 16) / 4) as u8;
+        let relative = index
+            .relative
+            .as_ref()
+            .map(|r| self.source(r, &[0], Kind::Int));
+
+        let value = match ty.class {
+            DxbcVariableClass::Scalar | DxbcVariableClass::Vector => {
+                let columns = ty.columns.max(1) as u8;
+                let local = components
+                    .iter()
+                    .map(|&c| c.checked_sub(start_component).filter(|&c| c < columns))
+                    .collect::<Option<Vec<u8>>>()?;
+
+                if ty.elements > 0 {
+                    // Every array element starts at a new register
+                    let element = register - base_register;
+                    if start_component != 0 || element >= ty.elements as u32 {
+                        return None;
+                    }
+
+                    let element = match relative {
+                        Some(r) if element == 0 => r,
+                        Some(r) => format!("{r} + {element}"),
+                        None => element.to_string(),
+                    };
+                    format!("{}[{element}].{}", variable.name, swizzle(local))
+                } else {
+                    if relative.is_some() || register != base_register {
+                        return None;
+                    }
+                    format!("{}.{}", variable.name, swizzle(local))
+                }
+            }
+            DxbcVariableClass::MatrixRows | DxbcVariableClass::MatrixColumns => {
+                if relative.is_some() {
+                    return None;
+                }
+
+                // row_major matrices store a row per register, column_major ones a column
+                let row_major = ty.class == DxbcVariableClass::MatrixRows;
+                let (registers, length) = if row_major {
+                    (ty.rows as u32, ty.columns as u8)
+                } else {
+                    (ty.columns as u32, ty.rows as u8)
+                };
+                if registers == 0 || components.iter().any(|&c| c >= length) {
+                    return None;
+                }
+
+                let offset = register - base_register;
+                let (element, vector) = (offset / registers, offset % registers);
+                let base = if ty.elements > 0 {
+                    if element >= ty.elements as u32 {
+                        return None;
+                    }
+                    format!("{}[{element}]", variable.name)
+                } else {
+                    variable.name.clone()
+                };
+
+                if row_major {
+                    format!("{base}[{vector}].{}", swizzle(components.iter().copied()))
+                } else {
+                    let elements: String = components
+                        .iter()
+                        .map(|c| format!("_m{c}{vector}"))
+                        .collect();
+                    format!("{base}.{elements}")
+                }
+            }
+            _ => return None,
+        };
+
+        Some(if ty.base_type == 3 {
+            value
+        } else {
+            format!("asfloat({value})")
+        })
+    }
+
+    /// Formats a source operand, read as `kind`, for the given destination components
+    fn source(&mut self, operand: &Operand, components: &[u8], kind: Kind) -> String {
+        let value = if operand.ty == OperandType::IMMEDIATE32 {
+            literal(operand, components, kind)
+        } else {
+            let selected = components
+                .iter()
+                .map(|&c| swizzle_component(operand, c))
+                .collect::<Vec<_>>();
+
+            let mut named = None;
+            if operand.ty == OperandType::CONSTANT_BUFFER {
+                let slot = operand
+                    .indices
+                    .first()
+                    .map(|i| i.offset as u32)
+                    .unwrap_or_default();
+                if !self.raw_constant_buffers.contains(&slot) {
+                    named = self.resolve_constant(operand, &selected);
+                    if named.is_none() {
+                        self.unresolved.insert(slot);
+                    }
+                }
+            }
+
+            let value = named.unwrap_or_else(|| match operand.components {
+                OperandComponents::None | OperandComponents::Scalar => self.register(operand),
+                _ => format!("{}.{}", self.register(operand), swizzle(selected)),
+            });
+
+            match kind {
+                Kind::Float => value,
+                Kind::Int => format!("asint({value})"),
+                Kind::Uint => format!("asuint({value})"),
+            }
+        };
+
+        match operand.modifier {
+            OperandModifier::None => value,
+            OperandModifier::Neg => format!("-{value}"),
+            OperandModifier::Abs => format!("abs({value})"),
+            OperandModifier::AbsNeg => format!("-abs({value})"),
+        }
+    }
+
+    /// Destination register and the components it writes, `None` for null destinations
+    fn destination(&mut self, operand: &Operand) -> Option<(String, Vec<u8>)> {
+        if operand.ty == OperandType::NULL {
+            return None;
+        }
+
+        let register = self.register(operand);
+        Some(match operand.components {
+            OperandComponents::Mask(m) if m != 0 => {
+                let components = (0..4).filter(|i| m & (1 << i) != 0).collect::<Vec<u8>>();
+                (
+                    format!("{register}.{}", swizzle(components.iter().copied())),
+                    components,
+                )
+            }
+            OperandComponents::Select(c) => (format!("{register}.{}", swizzle([c])), vec![c]),
+            _ => (register, vec![0]),
+        })
+    }
+
+    /// `destination = value;`, where `value` is built for the written components and has type `kind`
+    fn assign(
+        &mut self,
+        instruction: &Instruction,
+        destination: &Operand,
+        kind: Kind,
+        value: impl FnOnce(&mut Self, &[u8]) -> String,
+    ) -> Option<String> {
+        let (target, components) = self.destination(destination)?;
+        let mut value = value(self, &components);
+        if kind != Kind::Float {
+            value = format!("asfloat({value})");
+        }
+        if instruction.saturate {
+            value = format!("saturate({value})");
+        }
+
+        Some(format!("{target} = {value};"))
+    }
+
+    fn expand(
+        &mut self,
+        template: &str,
+        sources: &[Operand],
+        components: &[u8],
+        kind: Kind,
+    ) -> String {
+        let width = components.len();
+        let mut s = template
+            .replace("{int}", &Kind::Int.type_name(width))
+            .replace("{uint}", &Kind::Uint.type_name(width))
+            .replace("{float}", &Kind::Float.type_name(width));
+        for (i, operand) in sources.iter().enumerate() {
+            let value = self.source(operand, components, kind);
+            s = s.replace(&format!("{{{i}}}"), &value);
+        }
+        s
+    }
+
+    /// `_z`/`_nz` test of a conditional instruction
+    fn condition(&mut self, instruction: &Instruction) -> anyhow::Result<String> {
+        let value = self.source(first_operand(instruction)?, &[0], Kind::Uint);
+        Ok(if instruction.test_nonzero {
+            format!("{value} != 0")
+        } else {
+            format!("{value} == 0")
+        })
+    }
+
+    fn texture_offset(instruction: &Instruction, dimension: u32) -> String {
+        match instruction.sample_offsets {
+            Some(offsets) if offsets != [0; 3] => {
+                let count = gradient_count(dimension);
+                let values = offsets[..count]
+                    .iter()
+                    .map(|o| o.to_string())
+                    .collect::<Vec<_>>();
+                if count == 1 {
+                    format!(", {}", values[0])
+                } else {
+                    format!(", int{count}({})", values.join(", "))
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn texture_kind(return_type: u32) -> Kind {
+        match return_type & 0xf {
+            3 => Kind::Int,
+            4 => Kind::Uint,
+            _ => Kind::Float,
+        }
+    }
+
+    /// Texture sampling, loads and queries
+    fn texture_instruction(&mut self, ins: &Instruction, name: &str) -> Option<Vec<String>> {
+        let (texture, sampler) = match name {
+            "sample" | "sample_b" | "sample_l" | "sample_d" | "sample_c" | "sample_c_lz"
+            | "gather4" | "gather4_c" | "lod" => (2, Some(3)),
+            "gather4_po" | "gather4_po_c" => (3, Some(4)),
+            "ld" | "ld_ms" | "resinfo" => (2, None),
+            _ => return None,
+        };
+
+        let t = ins.operands.get(texture)?;
+        let &(dimension, return_type) = self.textures.get(&(t.indices.first()?.offset as u32))?;
+        let t_name = self.register(t);
+        let s_name = match sampler {
+            Some(i) => self.register(ins.operands.get(i)?),
+            None => String::new(),
+        };
+        let kind = Self::texture_kind(return_type);
+        let result =
+            |components: &[u8]| swizzle(components.iter().map(|&c| swizzle_component(t, c)));
+
+        let coordinates = (0..coordinate_count(dimension) as u8).collect::<Vec<_>>();
+        let gradients = (0..gradient_count(dimension) as u8).collect::<Vec<_>>();
+        let offset = Self::texture_offset(ins, dimension);
+        let ops = &ins.operands;
+
+        let line = match name {
+            "sample" | "sample_b" | "sample_l" | "sample_d" => {
+                let coordinate = self.source(&ops[1], &coordinates, Kind::Float);
+                let (method, extra) = match name {
+                    "sample" => ("Sample", String::new()),
+                    "sample_b" => (
+                        "SampleBias",
+                        format!(", {}", self.source(&ops[4], &[0], Kind::Float)),
+                    ),
+                    "sample_l" => (
+                        "SampleLevel",
+                        format!(", {}", self.source(&ops[4], &[0], Kind::Float)),
+                    ),
+                    _ => (
+                        "SampleGrad",
+                        format!(
+                            ", {}, {}",
+                            self.source(&ops[4], &gradients, Kind::Float),
+                            self.source(&ops[5], &gradients, Kind::Float)
+                        ),
+                    ),
+                };
+                let call = format!("{t_name}.{method}({s_name}, {coordinate}{extra}{offset})");
+                self.assign(ins, &ops[0], kind, |_, c| format!("{call}.{}", result(c)))
+            }
+            "sample_c" | "sample_c_lz" => {
+                let coordinate = self.source(&ops[1], &coordinates, Kind::Float);
+                let reference = self.source(&ops[4], &[0], Kind::Float);
+                let method = if name == "sample_c" {
+                    "SampleCmp"
+                } else {
+                    "SampleCmpLevelZero"
+                };
+                let call =
+                    format!("{t_name}.{method}({s_name}, {coordinate}, {reference}{offset})");
+                self.assign(ins, &ops[0], Kind::Float, |_, _| call)
+            }
+            "gather4" | "gather4_c" | "gather4_po" | "gather4_po_c" => {
+                let sampler = &ops[sampler?];
+                let channel =
+                    ["Red", "Green", "Blue", "Alpha"][swizzle_component(sampler, 0) as usize];
+                let coordinate = self.source(&ops[1], &coordinates, Kind::Float);
+                let compare = name.ends_with("_c");
+                let method = if compare { "GatherCmp" } else { "Gather" };
+
+                let mut arguments = format!("{s_name}, {coordinate}");
+                if compare {
+                    let reference = self.source(ops.last()?, &[0], Kind::Float);
+                    write!(arguments, ", {reference}").ok();
+                }
+                if name.starts_with("gather4_po") {
+                    let offset = self.source(&ops[2], &gradients, Kind::Int);
+                    write!(arguments, ", {offset}").ok();
+                } else {
+                    arguments.push_str(&offset);
+                }
+
+                let call = format!("{t_name}.{method}{channel}({arguments})");
+                self.assign(ins, &ops[0], kind, |_, c| format!("{call}.{}", result(c)))
+            }
+            "lod" => {
+                let coordinate = self.source(&ops[1], &coordinates, Kind::Float);
+                let call = format!(
+                    "float4({t_name}.CalculateLevelOfDetail({s_name}, {coordinate}), \
+                     {t_name}.CalculateLevelOfDetailUnclamped({s_name}, {coordinate}), 0, 0)"
+                );
+                self.assign(ins, &ops[0], Kind::Float, |_, c| {
+                    format!("{call}.{}", result(c))
+                })
+            }
+            "ld" => {
+                let address = if dimension == 1 {
+                    self.source(&ops[1], &[0], Kind::Int)
+                } else {
+                    format!(
+                        "{}({}, {})",
+                        Kind::Int.type_name(coordinates.len() + 1),
+                        self.source(&ops[1], &coordinates, Kind::Int),
+                        self.source(&ops[1], &[3], Kind::Int)
+                    )
+                };
+                let call = format!("{t_name}.Load({address}{offset})");
+                self.assign(ins, &ops[0], kind, |_, c| format!("{call}.{}", result(c)))
+            }
+            "ld_ms" => {
+                let address = self.source(&ops[1], &coordinates, Kind::Int);
+                let sample = self.source(&ops[3], &[0], Kind::Int);
+                let call = format!("{t_name}.Load({address}, {sample}{offset})");
+                self.assign(ins, &ops[0], kind, |_, c| format!("{call}.{}", result(c)))
+            }
+            "resinfo" => {
+                let outputs = match dimension {
+                    2 => "dimensions.x, dimensions.w",
+                    3 | 4 | 6 | 7 => "dimensions.x, dimensions.y, dimensions.w",
+                    _ => "dimensions.x, dimensions.y, dimensions.z, dimensions.w",
+                };
+                let mip = if matches!(dimension, 4 | 9) {
+                    String::new()
+                } else {
+                    format!("{}, ", self.source(&ops[1], &[0], Kind::Uint))
+                };
+                let assignment = match ins.controls & 3 {
+                    // _uint
+                    2 => self.assign(ins, &ops[0], Kind::Uint, |_, c| {
+                        format!("dimensions.{}", result(c))
+                    }),
+                    // _rcpFloat
+                    1 => self.assign(ins, &ops[0], Kind::Float, |_, c| {
+                        format!(
+                            "1.0 / ({})dimensions.{}",
+                            Kind::Float.type_name(c.len()),
+                            result(c)
+                        )
+                    }),
+                    _ => self.assign(ins, &ops[0], Kind::Float, |_, c| {
+                        format!(
+                            "({})dimensions.{}",
+                            Kind::Float.type_name(c.len()),
+                            result(c)
+                        )
+                    }),
+                }?;
+
+                return Some(vec![
+                    "{".to_string(),
+                    "    uint4 dimensions = 0;".to_string(),
+                    format!("    {t_name}.GetDimensions({mip}{outputs});"),
+                    format!("    {assignment}"),
+                    "}".to_string(),
+                ]);
+            }
+            _ => None,
+        };
+
+        Some(line.into_iter().collect())
+    }
+
+    /// Translates a non-control flow instruction into statements
+    fn statements(&mut self, ins: &Instruction) -> Vec<String> {
+        let name = ins.opcode.name();
+        let ops = &ins.operands;
+
+        if let Some((source_kind, result_kind, template)) = alu_expression(name) {
+            if let Some((destination, sources)) = ops.split_first() {
+                return self
+                    .assign(ins, destination, result_kind, |d, c| {
+                        d.expand(template, sources, c, source_kind)
+                    })
+                    .into_iter()
+                    .collect();
+            }
+        }
+
+        let lines = match name {
+            "dp2" | "dp3" | "dp4" => {
+                let count = name[2..].parse::<u8>().unwrap_or(4);
+                let components = (0..count).collect::<Vec<_>>();
+                self.assign(ins, &ops[0], Kind::Float, |d, _| {
+                    format!(
+                        "dot({}, {})",
+                        d.source(&ops[1], &components, Kind::Float),
+                        d.source(&ops[2], &components, Kind::Float)
+                    )
+                })
+                .into_iter()
+                .collect()
+            }
+            "movc" => self
+                .assign(ins, &ops[0], Kind::Float, |d, c| {
+                    format!(
+                        "({} != 0) ? {} : {}",
+                        d.source(&ops[1], c, Kind::Uint),
+                        d.source(&ops[2], c, Kind::Float),
+                        d.source(&ops[3], c, Kind::Float)
+                    )
+                })
+                .into_iter()
+                .collect(),
+            "sincos" => {
+                let sin = self.assign(ins, &ops[0], Kind::Float, |d, c| {
+                    format!("sin({})", d.source(&ops[2], c, Kind::Float))
+                });
+                let cos = self.assign(ins, &ops[1], Kind::Float, |d, c| {
+                    format!("cos({})", d.source(&ops[2], c, Kind::Float))
+                });
+                sin.into_iter().chain(cos).collect()
+            }
+            "imul" | "umul" | "udiv" if ops.len() == 4 => {
+                let kind = if name == "imul" {
+                    Kind::Int
+                } else {
+                    Kind::Uint
+                };
+                let (first, second) = if name == "udiv" {
+                    (Some("{0} / {1}"), "{0} % {1}")
+                } else {
+                    // The high 32 bits of the product can't be expressed in HLSL
+                    if ops[0].ty != OperandType::NULL {
+                        return vec![format!("// Unsupported: {}", ins.display())];
+                    }
+                    (None, "{0} * {1}")
+                };
+
+                let sources = &ops[2..];
+                let mut lines = vec![];
+                if let Some(first) = first {
+                    lines.extend(
+                        self.assign(ins, &ops[0], kind, |d, c| d.expand(first, sources, c, kind)),
+                    );
+                }
+                lines.extend(self.assign(ins, &ops[1], kind, |d, c| {
+                    d.expand(second, sources, c, kind)
+                }));
+                lines
+            }
+            _ => match self.texture_instruction(ins, name) {
+                Some(lines) => lines,
+                None => vec![format!("// Unsupported: {}", ins.display())],
+            },
+        };
+
+        lines
+    }
+
+    /// Body of the shader, with structured control flow
+    fn body(&mut self, instructions: &[Instruction]) -> anyhow::Result<String> {
+        let mut out = String::new();
+        let mut depth = 1usize;
+
+        for (i, ins) in instructions.iter().enumerate() {
+            if ins.opcode.is_declaration() {
+                continue;
+            }
+
+            let name = ins.opcode.name();
+            if matches!(name, "else" | "endif" | "endloop" | "endswitch") {
+                depth = depth.saturating_sub(1);
+            }
+
+            let lines = match name {
+                "if" => vec![format!("if ({}) {{", self.condition(ins)?)],
+                "else" => vec!["} else {".to_string()],
+                "endif" | "endloop" | "endswitch" => vec!["}".to_string()],
+                "loop" => vec!["while (true) {".to_string()],
+                "break" => vec!["break;".to_string()],
+                "continue" => vec!["continue;".to_string()],
+                "breakc" => vec![format!("if ({}) break;", self.condition(ins)?)],
+                "continuec" => vec![format!("if ({}) continue;", self.condition(ins)?)],
+                "retc" => vec![format!("if ({}) return;", self.condition(ins)?)],
+                "discard" => vec![format!("if ({}) discard;", self.condition(ins)?)],
+                "switch" => vec![format!(
+                    "switch ({}) {{",
+                    self.source(first_operand(ins)?, &[0], Kind::Int)
+                )],
+                "case" => vec![format!(
+                    "case {}:",
+                    self.source(first_operand(ins)?, &[0], Kind::Int)
+                )],
+                "default" => vec!["default:".to_string()],
+                // The final return is implied
+                "ret" if i + 1 == instructions.len() => vec![],
+                "ret" => vec!["return;".to_string()],
+                "nop" => vec![],
+                _ => self.statements(ins),
+            };
+
+            let indent = "    ".repeat(depth);
+            for line in lines {
+                writeln!(out, "{indent}{line}").ok();
+            }
+
+            if matches!(name, "if" | "else" | "loop" | "switch") {
+                depth += 1;
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn write_constant_buffers(&self, out: &mut String, material_constants: Option<&[Vector4]>) {
+        for (&slot, &size) in &self.constant_buffers {
+            if slot == 0 {
+                if let Some(constants) = material_constants.filter(|c| !c.is_empty()) {
+                    writeln!(out, "// Material constants bound to cb0:").ok();
+                    for (i, v) in constants.iter().enumerate() {
+                        writeln!(
+                            out,
+                            "//   cb0[{i}] = float4({:?}, {:?}, {:?}, {:?})",
+                            v.x, v.y, v.z, v.w
+                        )
+                        .ok();
+                    }
+                }
+            }
+
+            let definition = self.constant_buffer(slot);
+            let name = definition
+                .map(|cb| identifier(&cb.name))
+                .unwrap_or_else(|| format!("cb{slot}"));
+            writeln!(out, "cbuffer {name} : register(b{slot})\n{{").ok();
+
+            match definition {
+                Some(cb) if !self.raw_constant_buffers.contains(&slot) => {
+                    for v in &cb.variables {
+                        let register = v.offset / 16;
+                        let component = match (v.offset % 16) / 4 {
+                            0 => String::new(),
+                            c => format!(".{}", COMPONENT_NAMES[c as usize]),
+                        };
+                        let array = if v.ty.elements > 0 {
+                            format!("[{}]", v.ty.elements)
+                        } else {
+                            String::new()
+                        };
+                        let packing = match v.ty.class {
+                            DxbcVariableClass::MatrixRows => "row_major ",
+                            DxbcVariableClass::MatrixColumns => "column_major ",
+                            _ => "",
+                        };
+                        writeln!(
+                            out,
+                            "    {packing}{} {}{array} : packoffset(c{register}{component});",
+                            v.ty, v.name
+                        )
+                        .ok();
+                    }
+                }
+                _ => {
+                    let size = definition.map_or(size, |cb| size.max(cb.size.div_ceil(16)));
+                    writeln!(out, "    float4 cb{slot}[{}];", size.max(1)).ok();
+                    for v in definition.iter().flat_map(|cb| &cb.variables) {
+                        writeln!(
+                            out,
+                            "    // {} {} at cb{slot}[{}].{}",
+                            v.ty,
+                            v.name,
+                            v.offset / 16,
+                            COMPONENT_NAMES[((v.offset % 16) / 4) as usize]
+                        )
+                        .ok();
+                    }
+                }
+            }
+
+            writeln!(out, "}}\n").ok();
+        }
+    }
+
+    fn write_resources(&self, out: &mut String) {
+        let binding_name = |prefix: char, register: u32| {
+            self.shader
+                .resources
+                .as_ref()
+                .and_then(|r| {
+                    r.bindings.iter().find(|b| {
+                        b.input_type.register_prefix() == prefix && b.bind_point == register
+                    })
+                })
+                .map(|b| format!(" // {}", b.name))
+                .unwrap_or_default()
+        };
+
+        if !self.immediate_constants.is_empty() {
+            let values = self
+                .immediate_constants
+                .chunks(4)
+                .map(|c| {
+                    let v = c.iter().map(|v| format!("0x{v:08x}")).collect::<Vec<_>>();
+                    format!("    uint4({})", v.join(", "))
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "static const uint4 icb[{}] =\n{{\n{}\n}};\n",
+                values.len(),
+                values.join(",\n")
+            )
+            .ok();
+        }
+
+        for (&register, &(dimension, return_type)) in &self.textures {
+            let element = match return_type & 0xf {
+                1 => "unorm float4",
+                2 => "snorm float4",
+                3 => "int4",
+                4 => "uint4",
+                _ => "float4",
+            };
+            writeln!(
+                out,
+                "{}<{element}> t{register} : register(t{register});{}",
+                texture_type_name(dimension).unwrap_or("Texture2D"),
+                binding_name('t', register)
+            )
+            .ok();
+        }
+
+        for (&register, &comparison) in &self.samplers {
+            writeln!(
+                out,
+                "{} s{register} : register(s{register});{}",
+                if comparison {
+                    "SamplerComparisonState"
+                } else {
+                    "SamplerState"
+                },
+                binding_name('s', register)
+            )
+            .ok();
+        }
+
+        if !self.textures.is_empty() || !self.samplers.is_empty() {
+            out.push('\n');
+        }
+    }
+}
+
+fn write_struct(out: &mut String, name: &str, fields: &[SignatureField]) {
+    writeln!(out, "struct {name}\n{{").ok();
+    for f in fields {
+        writeln!(out, "    {} {} : {};", f.ty, f.name, f.semantic).ok();
+    }
+    writeln!(out, "}};\n").ok();
+}
+
+/// Register count of a signature, `v#`/`o#` registers are declared as `float4 v0, v1, ...`
+fn register_list(prefix: &str, fields: &[SignatureField]) -> Option<String> {
+    let count = fields.iter().filter_map(|f| f.register).max()? + 1;
+    Some(
+        (0..count)
+            .map(|i| format!("{prefix}{i}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// Lifts a vertex or pixel shader to HLSL that can be compiled with fxc.
+///
+/// Registers keep their names (`r0`, `v1`, `cb0[3]`, `t2`, ...) so the output can be compared with
+/// the disassembly. Constant buffers use their RDEF variables when every access lines up with one,
+/// otherwise they are declared as `float4` arrays. `material_constants` are the cb0 values a
+/// material binds (`unk98`/`unk318`), which are listed above the cb0 declaration
+pub fn decompile(
+    shader: &DxbcShader,
+    material_constants: Option<&[Vector4]>,
+) -> anyhow::Result<String> {
+    let bytecode = shader
+        .bytecode
+        .as_ref()
+        .ok_or_else(|| anyhow!("Shader has no SHEX/SHDR chunk"))?;
+    let stage = match bytecode.program_type {
+        DxbcProgramType::Vertex => "VS",
+        DxbcProgramType::Pixel => "PS",
+        t => return Err(anyhow!("Decompiling {t} shaders is not supported")),
+    };
+    let instructions = decode_program(bytecode)?;
+    ensure!(!instructions.is_empty(), "Shader has no instructions");
+
+    let mut decompiler = Decompiler::new(shader, &instructions)?;
+    let body = loop {
+        let body = decompiler.body(&instructions)?;
+        if decompiler.unresolved.is_empty() {
+            break body;
+        }
+
+        // Redo the body with the constant buffers that couldn't be mapped declared as arrays
+        let mut unresolved = std::mem::take(&mut decompiler.unresolved);
+        decompiler.raw_constant_buffers.append(&mut unresolved);
+    };
+
+    let inputs = signature_fields(shader.input_signature.as_ref());
+    let outputs = signature_fields(shader.output_signature.as_ref());
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// Decompiled from {}_{}_{}\n",
+        bytecode.program_type, bytecode.major_version, bytecode.minor_version
+    )
+    .ok();
+
+    decompiler.write_constant_buffers(&mut out, material_constants);
+    decompiler.write_resources(&mut out);
+
+    if !inputs.is_empty() {
+        write_struct(&mut out, &format!("{stage}_INPUT"), &inputs);
+    }
+    if !outputs.is_empty() {
+        write_struct(&mut out, &format!("{stage}_OUTPUT"), &outputs);
+    }
+
+    if let Some(registers) = register_list("v", &inputs) {
+        writeln!(out, "static float4 {registers};").ok();
+    }
+    if let Some(registers) = register_list("o", &outputs) {
+        writeln!(out, "static float4 {registers};").ok();
+    }
+    let mut special_outputs = outputs
+        .iter()
+        .filter(|f| f.register.is_none())
+        .map(|f| special_output(&f.semantic))
+        .collect::<Vec<_>>();
+    special_outputs.dedup();
+    for register in &special_outputs {
+        writeln!(out, "static float {register};").ok();
+    }
+    out.push('\n');
+
+    writeln!(out, "void run()\n{{").ok();
+    if decompiler.temps > 0 {
+        let temps = (0..decompiler.temps)
+            .map(|i| format!("r{i}"))
+            .collect::<Vec<_>>();
+        writeln!(out, "    float4 {};", temps.join(", ")).ok();
+    }
+    for (register, size) in &decompiler.indexable_temps {
+        writeln!(out, "    float4 x{register}[{size}];").ok();
+    }
+    if decompiler.temps > 0 || !decompiler.indexable_temps.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&body);
+    writeln!(out, "}}\n").ok();
+
+    let parameters = if inputs.is_empty() {
+        String::new()
+    } else {
+        format!("{stage}_INPUT input")
+    };
+    if outputs.is_empty() {
+        writeln!(out, "void main({parameters})\n{{").ok();
+    } else {
+        writeln!(out, "{stage}_OUTPUT main({parameters})\n{{").ok();
+    }
+
+    for f in &inputs {
+        let Some(register) = f.register else {
+            continue;
+        };
+        let value = if f.is_bool {
+            format!("input.{} ? 0xffffffff : 0", f.name)
+        } else {
+            format!("input.{}", f.name)
+        };
+        let value = if f.kind == Kind::Float && !f.is_bool {
+            value
+        } else {
+            format!("asfloat({value})")
+        };
+        writeln!(
+            out,
+            "    v{register}.{} = {value};",
+            swizzle(f.components.iter().copied())
+        )
+        .ok();
+    }
+    writeln!(out, "    run();").ok();
+
+    if !outputs.is_empty() {
+        writeln!(out, "\n    {stage}_OUTPUT output;").ok();
+        for f in &outputs {
+            let register = match f.register {
+                Some(register) => format!("o{register}.{}", swizzle(f.components.iter().copied())),
+                None => special_output(&f.semantic).to_string(),
+            };
+            let value = match f.kind {
+                Kind::Float => register,
+                Kind::Int => format!("asint({register})"),
+                Kind::Uint => format!("asuint({register})"),
+            };
+            writeln!(out, "    output.{} = {value};", f.name).ok();
+        }
+        writeln!(out, "    return output;").ok();
+    }
+    writeln!(out, "}}").ok();
+
+    Ok(out)
+}
+
+/// Decompiles every shader to `<tag>.hlsl` in `directory` and returns how many were written.
+/// Shaders that fail to decompile are logged and skipped
+pub fn decompile_to_directory(
+    shaders: impl IntoIterator<Item = TagHash>,
+    directory: &Path,
+) -> anyhow::Result<usize> {
+    std::fs::create_dir_all(directory)?;
+
+    let mut written = 0;
+    for shader in shaders {
+        match DxbcShader::load(shader).and_then(|s| decompile(&s, None)) {
+            Ok(source) => {
+                std::fs::write(directory.join(format!("{:08X}.hlsl", shader.0)), source)?;
+                written += 1;
+            }
+            Err(e) => error!("Failed to decompile shader {shader}: {e}"),
+        }
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::tests::{pixel_shader, IF_ELSE, MOV_IMMEDIATE, SAMPLE_CBUFFER};
+
+    /// The `run()` function holding the translated instructions
+    fn run_body(tokens: &[u32]) -> String {
+        let hlsl = decompile(&pixel_shader(tokens), None).unwrap();
+        let start = hlsl.find("void run()").unwrap();
+        let end = start + hlsl[start..].find("\n}\n").unwrap() + 3;
+        hlsl[start..end].to_string()
+    }
+
+    #[test]
+    fn texture_sample_and_constant_buffer() {
+        assert_eq!(
+            decompile(&pixel_shader(SAMPLE_CBUFFER), None).unwrap(),
+            "\
+// Decompiled from ps_5_0
+
+cbuffer cb0 : register(b0)
+{
+    float4 cb0[1];
+}
+
+Texture2D<float4> t0 : register(t0);
+SamplerState s0 : register(s0);
+
+struct PS_INPUT
+{
+    float4 SV_POSITION0 : SV_POSITION0;
+    float2 TEXCOORD0 : TEXCOORD0;
+};
+
+struct PS_OUTPUT
+{
+    float4 SV_TARGET0 : SV_TARGET0;
+};
+
+static float4 v0, v1;
+static float4 o0;
+
+void run()
+{
+    float4 r0;
+
+    r0.xyzw = t0.Sample(s0, v1.xy).xyzw;
+    o0.xyzw = r0.xyzw * cb0[0].xyzw;
+}
+
+PS_OUTPUT main(PS_INPUT input)
+{
+    v0.xyzw = input.SV_POSITION0;
+    v1.xy = input.TEXCOORD0;
+    run();
+
+    PS_OUTPUT output;
+    output.SV_TARGET0 = o0.xyzw;
+    return output;
+}
+"
+        );
+    }
+
+    #[test]
+    fn immediate_values() {
+        assert_eq!(
+            run_body(MOV_IMMEDIATE),
+            "\
+void run()
+{
+    float4 r0;
+
+    o0.xyzw = float4(1.0, 0.0, 0.0, 1.0);
+}
+"
+        );
+    }
+
+    #[test]
+    fn structured_control_flow() {
+        assert_eq!(
+            run_body(IF_ELSE),
+            "\
+void run()
+{
+    float4 r0;
+
+    if (asuint(r0.x) != 0) {
+        o0.xyzw = float4(1.0, 0.0, 0.0, 1.0);
+    } else {
+        o0.xyzw = r0.xyzw;
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn missing_operands_are_errors() {
+        // switch, case and dcl_constantbuffer without any operand tokens
+        for tokens in [
+            &[0x0100004c, 0x0100003e],
+            &[0x01000006, 0x0100003e],
+            &[0x01000059, 0x0100003e],
+        ] {
+            assert!(decompile(&pixel_shader(tokens), None).is_err());
+        }
+    }
+}
@@ -0,0 +1,1377 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, ensure};
+
+use crate::dxbc::{
+    DxbcBytecode, DxbcInputType, DxbcResourceDefinitions, DxbcResourceDimension, DxbcShader,
+    DxbcShaderInputType, DxbcSignature,
+};
+
+/// SM4/SM5 opcode, see `D3D10_SB_OPCODE_TYPE` in d3d11TokenizedProgramFormat.hpp
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opcode(pub u32);
+
+#[rustfmt::skip]
+const OPCODE_NAMES: [&str; 218] = [
+    "add", "and", "break", "breakc", "call", "callc", "case", "continue", "continuec", "cut",
+    "default", "deriv_rtx", "deriv_rty", "discard", "div", "dp2", "dp3", "dp4", "else", "emit",
+    "emitthencut", "endif", "endloop", "endswitch", "eq", "exp", "frc", "ftoi", "ftou", "ge",
+    "iadd", "if", "ieq", "ige", "ilt", "imad", "imax", "imin", "imul", "ine", "ineg", "ishl",
+    "ishr", "itof", "label", "ld", "ld_ms", "log", "loop", "lt", "mad", "min", "max",
+    "customdata", "mov", "movc", "mul", "ne", "nop", "not", "or", "resinfo", "ret", "retc",
+    "round_ne", "round_ni", "round_pi", "round_z", "rsq", "sample", "sample_c", "sample_c_lz",
+    "sample_l", "sample_d", "sample_b", "sqrt", "switch", "sincos", "udiv", "ult", "uge", "umul",
+    "umad", "umax", "umin", "ushr", "utof", "xor", "dcl_resource", "dcl_constantbuffer",
+    "dcl_sampler", "dcl_indexrange", "dcl_outputtopology", "dcl_inputprimitive", "dcl_maxout",
+    "dcl_input", "dcl_input_sgv", "dcl_input_siv", "dcl_input_ps", "dcl_input_ps_sgv",
+    "dcl_input_ps_siv", "dcl_output", "dcl_output_sgv", "dcl_output_siv", "dcl_temps",
+    "dcl_indexableTemp", "dcl_globalFlags", "reserved0", "lod", "gather4", "sample_pos",
+    "sample_info", "reserved1", "hs_decls", "hs_control_point_phase", "hs_fork_phase",
+    "hs_join_phase", "emit_stream", "cut_stream", "emitThenCut_stream", "fcall", "bufinfo",
+    "deriv_rtx_coarse", "deriv_rtx_fine", "deriv_rty_coarse", "deriv_rty_fine", "gather4_c",
+    "gather4_po", "gather4_po_c", "rcp", "f32tof16", "f16tof32", "uaddc", "usubb", "countbits",
+    "firstbit_hi", "firstbit_lo", "firstbit_shi", "ubfe", "ibfe", "bfi", "bfrev", "swapc",
+    "dcl_stream", "dcl_function_body", "dcl_function_table", "dcl_interface",
+    "dcl_input_control_point_count", "dcl_output_control_point_count",
+    "dcl_tessellator_domain", "dcl_tessellator_partitioning",
+    "dcl_tessellator_output_primitive", "dcl_hs_max_tessfactor",
+    "dcl_hs_fork_phase_instance_count", "dcl_hs_join_phase_instance_count", "dcl_thread_group",
+    "dcl_uav_typed", "dcl_uav_raw", "dcl_uav_structured", "dcl_tgsm_raw", "dcl_tgsm_structured",
+    "dcl_resource_raw", "dcl_resource_structured", "ld_uav_typed", "store_uav_typed", "ld_raw",
+    "store_raw", "ld_structured", "store_structured", "atomic_and", "atomic_or", "atomic_xor",
+    "atomic_cmp_store", "atomic_iadd", "atomic_imax", "atomic_imin", "atomic_umax",
+    "atomic_umin", "imm_atomic_alloc", "imm_atomic_consume", "imm_atomic_iadd",
+    "imm_atomic_and", "imm_atomic_or", "imm_atomic_xor", "imm_atomic_exch",
+    "imm_atomic_cmp_exch", "imm_atomic_imax", "imm_atomic_imin", "imm_atomic_umax",
+    "imm_atomic_umin", "sync", "dadd", "dmax", "dmin", "dmul", "deq", "dge", "dlt", "dne", "dmov",
+    "dmovc", "dtof", "ftod", "eval_snapped", "eval_sample_index", "eval_centroid",
+    "dcl_gsinstances", "abort", "debug_break", "reserved2", "ddiv", "dfma", "drcp", "msad",
+    "dtoi", "dtou", "itod", "utod",
+];
+
+impl Opcode {
+    pub const BREAKC: Opcode = Opcode(3);
+    pub const CALLC: Opcode = Opcode(5);
+    pub const CONTINUEC: Opcode = Opcode(8);
+    pub const DISCARD: Opcode = Opcode(13);
+    pub const ELSE: Opcode = Opcode(18);
+    pub const ENDIF: Opcode = Opcode(21);
+    pub const ENDLOOP: Opcode = Opcode(22);
+    pub const ENDSWITCH: Opcode = Opcode(23);
+    pub const IF: Opcode = Opcode(31);
+    pub const LOOP: Opcode = Opcode(48);
+    pub const CUSTOMDATA: Opcode = Opcode(53);
+    pub const RESINFO: Opcode = Opcode(61);
+    pub const RETC: Opcode = Opcode(63);
+    pub const SWITCH: Opcode = Opcode(76);
+    pub const DCL_RESOURCE: Opcode = Opcode(88);
+    pub const DCL_CONSTANT_BUFFER: Opcode = Opcode(89);
+    pub const DCL_SAMPLER: Opcode = Opcode(90);
+    pub const DCL_INDEX_RANGE: Opcode = Opcode(91);
+    pub const DCL_OUTPUT_TOPOLOGY: Opcode = Opcode(92);
+    pub const DCL_INPUT_PRIMITIVE: Opcode = Opcode(93);
+    pub const DCL_MAX_OUTPUT_VERTEX_COUNT: Opcode = Opcode(94);
+    pub const DCL_INPUT_SGV: Opcode = Opcode(96);
+    pub const DCL_INPUT_SIV: Opcode = Opcode(97);
+    pub const DCL_INPUT_PS: Opcode = Opcode(98);
+    pub const DCL_INPUT_PS_SGV: Opcode = Opcode(99);
+    pub const DCL_INPUT_PS_SIV: Opcode = Opcode(100);
+    pub const DCL_OUTPUT_SGV: Opcode = Opcode(102);
+    pub const DCL_OUTPUT_SIV: Opcode = Opcode(103);
+    pub const DCL_TEMPS: Opcode = Opcode(104);
+    pub const DCL_INDEXABLE_TEMP: Opcode = Opcode(105);
+    pub const DCL_GLOBAL_FLAGS: Opcode = Opcode(106);
+    pub const SAMPLE_INFO: Opcode = Opcode(111);
+    pub const INTERFACE_CALL: Opcode = Opcode(120);
+    pub const DCL_FUNCTION_BODY: Opcode = Opcode(144);
+    pub const DCL_FUNCTION_TABLE: Opcode = Opcode(145);
+    pub const DCL_INTERFACE: Opcode = Opcode(146);
+    pub const DCL_INPUT_CONTROL_POINT_COUNT: Opcode = Opcode(147);
+    pub const DCL_OUTPUT_CONTROL_POINT_COUNT: Opcode = Opcode(148);
+    pub const DCL_TESS_DOMAIN: Opcode = Opcode(149);
+    pub const DCL_TESS_PARTITIONING: Opcode = Opcode(150);
+    pub const DCL_TESS_OUTPUT_PRIMITIVE: Opcode = Opcode(151);
+    pub const DCL_HS_MAX_TESSFACTOR: Opcode = Opcode(152);
+    pub const DCL_HS_FORK_PHASE_INSTANCE_COUNT: Opcode = Opcode(153);
+    pub const DCL_HS_JOIN_PHASE_INSTANCE_COUNT: Opcode = Opcode(154);
+    pub const DCL_THREAD_GROUP: Opcode = Opcode(155);
+    pub const DCL_UAV_TYPED: Opcode = Opcode(156);
+    pub const DCL_UAV_STRUCTURED: Opcode = Opcode(158);
+    pub const DCL_TGSM_RAW: Opcode = Opcode(159);
+    pub const DCL_TGSM_STRUCTURED: Opcode = Opcode(160);
+    pub const DCL_RESOURCE_STRUCTURED: Opcode = Opcode(162);
+    pub const SYNC: Opcode = Opcode(190);
+    pub const DCL_GS_INSTANCE_COUNT: Opcode = Opcode(206);
+
+    pub fn name(&self) -> &'static str {
+        OPCODE_NAMES
+            .get(self.0 as usize)
+            .copied()
+            .unwrap_or("unknown")
+    }
+
+    pub fn is_declaration(&self) -> bool {
+        self.name().starts_with("dcl_") || *self == Opcode::CUSTOMDATA
+    }
+
+    /// Instructions that operate on integers, used to decide how immediates are printed
+    pub fn is_integer(&self) -> bool {
+        let name = self.name();
+        name.starts_with('i')
+            || name.starts_with('u')
+            || name.contains("atomic")
+            || name.starts_with("ld")
+            || name.starts_with("store")
+            || name.starts_with("firstbit")
+            || matches!(
+                name,
+                "and"
+                    | "or"
+                    | "xor"
+                    | "not"
+                    | "switch"
+                    | "case"
+                    | "bfi"
+                    | "bfrev"
+                    | "countbits"
+                    | "f16tof32"
+                    | "resinfo"
+                    | "bufinfo"
+                    | "sample_info"
+                    | "sample_pos"
+            )
+    }
+
+    /// Instructions that take a `_z`/`_nz` test
+    fn has_test(&self) -> bool {
+        matches!(
+            *self,
+            Opcode::IF
+                | Opcode::BREAKC
+                | Opcode::CONTINUEC
+                | Opcode::RETC
+                | Opcode::DISCARD
+                | Opcode::CALLC
+        )
+    }
+
+    /// Number of operands that come before the raw tokens of a declaration, `None` if the
+    /// instruction only contains operands
+    fn leading_operands(&self) -> Option<usize> {
+        Some(match *self {
+            Opcode::DCL_RESOURCE
+            | Opcode::DCL_INDEX_RANGE
+            | Opcode::DCL_INPUT_SGV
+            | Opcode::DCL_INPUT_SIV
+            | Opcode::DCL_INPUT_PS_SGV
+            | Opcode::DCL_INPUT_PS_SIV
+            | Opcode::DCL_OUTPUT_SGV
+            | Opcode::DCL_OUTPUT_SIV
+            | Opcode::DCL_UAV_TYPED
+            | Opcode::DCL_UAV_STRUCTURED
+            | Opcode::DCL_TGSM_RAW
+            | Opcode::DCL_TGSM_STRUCTURED
+            | Opcode::DCL_RESOURCE_STRUCTURED => 1,
+            Opcode::DCL_TEMPS
+            | Opcode::DCL_INDEXABLE_TEMP
+            | Opcode::DCL_GLOBAL_FLAGS
+            | Opcode::DCL_OUTPUT_TOPOLOGY
+            | Opcode::DCL_INPUT_PRIMITIVE
+            | Opcode::DCL_MAX_OUTPUT_VERTEX_COUNT
+            | Opcode::DCL_FUNCTION_BODY
+            | Opcode::DCL_FUNCTION_TABLE
+            | Opcode::DCL_INTERFACE
+            | Opcode::DCL_INPUT_CONTROL_POINT_COUNT
+            | Opcode::DCL_OUTPUT_CONTROL_POINT_COUNT
+            | Opcode::DCL_TESS_DOMAIN
+            | Opcode::DCL_TESS_PARTITIONING
+            | Opcode::DCL_TESS_OUTPUT_PRIMITIVE
+            | Opcode::DCL_HS_MAX_TESSFACTOR
+            | Opcode::DCL_HS_FORK_PHASE_INSTANCE_COUNT
+            | Opcode::DCL_HS_JOIN_PHASE_INSTANCE_COUNT
+            | Opcode::DCL_THREAD_GROUP
+            | Opcode::DCL_GS_INSTANCE_COUNT => 0,
+            _ => return None,
+        })
+    }
+}
+
+/// Register file of an operand, see `D3D10_SB_OPERAND_TYPE`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperandType(pub u32);
+
+impl OperandType {
+    pub const INDEXABLE_TEMP: OperandType = OperandType(3);
+    pub const IMMEDIATE32: OperandType = OperandType(4);
+    pub const IMMEDIATE64: OperandType = OperandType(5);
+    pub const CONSTANT_BUFFER: OperandType = OperandType(8);
+    pub const IMMEDIATE_CONSTANT_BUFFER: OperandType = OperandType(9);
+    pub const NULL: OperandType = OperandType(13);
+
+    pub fn prefix(&self) -> &'static str {
+        match self.0 {
+            0 => "r",
+            1 => "v",
+            2 => "o",
+            3 => "x",
+            4 | 5 => "l",
+            6 => "s",
+            7 => "t",
+            8 => "cb",
+            9 => "icb",
+            10 => "label",
+            11 => "vPrim",
+            12 => "oDepth",
+            13 => "null",
+            14 => "rasterizer",
+            15 => "oMask",
+            16 => "m",
+            17 => "fb",
+            18 => "ft",
+            19 => "fp",
+            20 => "fi",
+            21 => "fo",
+            22 => "vOutputControlPointID",
+            23 => "vForkInstanceID",
+            24 => "vJoinInstanceID",
+            25 => "vicp",
+            26 => "vocp",
+            27 => "vpc",
+            28 => "vDomain",
+            29 => "this",
+            30 => "u",
+            31 => "g",
+            32 => "vThreadID",
+            33 => "vThreadGroupID",
+            34 => "vThreadIDInGroup",
+            35 => "vCoverage",
+            36 => "vThreadIDInGroupFlattened",
+            37 => "vGSInstanceID",
+            38 => "oDepthGE",
+            39 => "oDepthLE",
+            40 => "vCycleCounter",
+            41 => "oStencilRef",
+            42 => "vInnerCoverage",
+            _ => "unknown",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandComponents {
+    /// No components (samplers, labels, ...)
+    None,
+    /// Scalar operand, e.g. a single immediate
+    Scalar,
+    Mask(u8),
+    Swizzle([u8; 4]),
+    Select(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandModifier {
+    None,
+    Neg,
+    Abs,
+    AbsNeg,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperandIndex {
+    pub offset: u64,
+    /// Register added to `offset`, for dynamically indexed operands
+    pub relative: Option<Box<Operand>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operand {
+    pub ty: OperandType,
+    pub components: OperandComponents,
+    pub indices: Vec<OperandIndex>,
+    pub modifier: OperandModifier,
+    /// Values of immediate operands, one per component (two words per component for 64-bit)
+    pub immediate: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    /// Opcode specific control bits (bits 11-23 of the opcode token, 11-30 for custom data)
+    pub controls: u32,
+    pub saturate: bool,
+    /// `_nz` if true, `_z` otherwise. Only meaningful for conditional instructions
+    pub test_nonzero: bool,
+    pub sample_offsets: Option<[i8; 3]>,
+    /// `D3D10_SB_RESOURCE_DIMENSION` and structure stride from an extended opcode token
+    pub resource_dimension: Option<(u32, u32)>,
+    /// `D3D10_SB_RESOURCE_RETURN_TYPE` per component from an extended opcode token
+    pub resource_return_type: Option<[u8; 4]>,
+    pub operands: Vec<Operand>,
+    /// Tokens that aren't operands, such as declaration counts or custom data
+    pub extra: Vec<u32>,
+}
+
+struct TokenReader<'a> {
+    tokens: &'a [u32],
+    position: usize,
+}
+
+impl TokenReader<'_> {
+    fn next(&mut self) -> anyhow::Result<u32> {
+        let token = *self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| anyhow!("Unexpected end of program at token {}", self.position))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+}
+
+/// Decodes the instruction stream of a SHEX/SHDR chunk
+pub fn decode_program(bytecode: &DxbcBytecode) -> anyhow::Result<Vec<Instruction>> {
+    let tokens = &bytecode.tokens;
+    let mut instructions = vec![];
+    let mut position = 0;
+
+    while position < tokens.len() {
+        let token = tokens[position];
+        let opcode = Opcode(token & 0x7ff);
+
+        if opcode == Opcode::CUSTOMDATA {
+            let length = *tokens
+                .get(position + 1)
+                .ok_or_else(|| anyhow!("Custom data at token {position} has no length"))?
+                as usize;
+            ensure!(
+                length >= 2 && position + length <= tokens.len(),
+                "Custom data at token {position} has an invalid length of {length}"
+            );
+
+            instructions.push(Instruction {
+                opcode,
+                controls: token >> 11,
+                saturate: false,
+                test_nonzero: false,
+                sample_offsets: None,
+                resource_dimension: None,
+                resource_return_type: None,
+                operands: vec![],
+                extra: tokens[position + 2..position + length].to_vec(),
+            });
+            position += length;
+            continue;
+        }
+
+        let length = ((token >> 24) & 0x7f) as usize;
+        ensure!(
+            length > 0 && position + length <= tokens.len(),
+            "Instruction {} at token {position} has an invalid length of {length}",
+            opcode.name()
+        );
+
+        let mut reader = TokenReader {
+            tokens: &tokens[position + 1..position + length],
+            position: 0,
+        };
+        instructions.push(decode_instruction(token, &mut reader)?);
+        position += length;
+    }
+
+    Ok(instructions)
+}
+
+fn decode_instruction(token: u32, reader: &mut TokenReader) -> anyhow::Result<Instruction> {
+    let opcode = Opcode(token & 0x7ff);
+    let mut instruction = Instruction {
+        opcode,
+        controls: (token >> 11) & 0x1fff,
+        // Sync stores its flags where other instructions keep the saturate bit
+        saturate: token & (1 << 13) != 0 && !opcode.is_declaration() && opcode != Opcode::SYNC,
+        test_nonzero: token & (1 << 18) != 0,
+        sample_offsets: None,
+        resource_dimension: None,
+        resource_return_type: None,
+        operands: vec![],
+        extra: vec![],
+    };
+
+    let mut extended = token & (1 << 31) != 0;
+    while extended {
+        let ext = reader.next()?;
+        extended = ext & (1 << 31) != 0;
+        match ext & 0x3f {
+            1 => {
+                let offset = |shift: u32| (((ext >> shift) & 0xf) as i8) << 4 >> 4;
+                instruction.sample_offsets = Some([offset(9), offset(13), offset(17)]);
+            }
+            2 => instruction.resource_dimension = Some(((ext >> 6) & 0x1f, (ext >> 11) & 0xfff)),
+            3 => {
+                let rt = |i: u32| ((ext >> (6 + i * 4)) & 0xf) as u8;
+                instruction.resource_return_type = Some([rt(0), rt(1), rt(2), rt(3)]);
+            }
+            _ => {}
+        }
+    }
+
+    if opcode == Opcode::INTERFACE_CALL {
+        instruction.extra.push(reader.next()?);
+    }
+
+    match opcode.leading_operands() {
+        Some(count) => {
+            for _ in 0..count {
+                instruction.operands.push(decode_operand(reader)?);
+            }
+            while !reader.is_empty() {
+                instruction.extra.push(reader.next()?);
+            }
+        }
+        None => {
+            while !reader.is_empty() {
+                instruction.operands.push(decode_operand(reader)?);
+            }
+        }
+    }
+
+    Ok(instruction)
+}
+
+fn decode_operand(reader: &mut TokenReader) -> anyhow::Result<Operand> {
+    let token = reader.next()?;
+
+    let components = match token & 3 {
+        0 => OperandComponents::None,
+        1 => OperandComponents::Scalar,
+        2 => match (token >> 2) & 3 {
+            0 => OperandComponents::Mask(((token >> 4) & 0xf) as u8),
+            1 => {
+                let c = |i: u32| ((token >> (4 + i * 2)) & 3) as u8;
+                OperandComponents::Swizzle([c(0), c(1), c(2), c(3)])
+            }
+            _ => OperandComponents::Select(((token >> 4) & 3) as u8),
+        },
+        _ => return Err(anyhow!("N-component operands are not supported")),
+    };
+
+    let ty = OperandType((token >> 12) & 0xff);
+    let index_dimension = (token >> 20) & 3;
+
+    let mut modifier = OperandModifier::None;
+    let mut extended = token & (1 << 31) != 0;
+    while extended {
+        let ext = reader.next()?;
+        extended = ext & (1 << 31) != 0;
+        if ext & 0x3f == 1 {
+            modifier = match (ext >> 6) & 0xff {
+                1 => OperandModifier::Neg,
+                2 => OperandModifier::Abs,
+                3 => OperandModifier::AbsNeg,
+                _ => OperandModifier::None,
+            };
+        }
+    }
+
+    let mut immediate = vec![];
+    let component_count = if token & 3 == 2 { 4 } else { 1 };
+    if ty == OperandType::IMMEDIATE32 {
+        for _ in 0..component_count {
+            immediate.push(reader.next()?);
+        }
+    } else if ty == OperandType::IMMEDIATE64 {
+        for _ in 0..component_count * 2 {
+            immediate.push(reader.next()?);
+        }
+    }
+
+    let mut indices = vec![];
+    for i in 0..index_dimension {
+        let representation = (token >> (22 + i * 3)) & 7;
+        let offset = match representation {
+            0 | 3 => reader.next()? as u64,
+            1 | 4 => {
+                let high = reader.next()? as u64;
+                (high << 32) | reader.next()? as u64
+            }
+            2 => 0,
+            r => return Err(anyhow!("Unknown operand index representation {r}")),
+        };
+        let relative = if representation >= 2 {
+            Some(Box::new(decode_operand(reader)?))
+        } else {
+            None
+        };
+
+        indices.push(OperandIndex { offset, relative });
+    }
+
+    Ok(Operand {
+        ty,
+        components,
+        indices,
+        modifier,
+        immediate,
+    })
+}
+
+pub const COMPONENT_NAMES: [char; 4] = ['x', 'y', 'z', 'w'];
+
+/// Formats an immediate value as an integer or float, the way fxc would
+fn format_immediate(value: u32, integer: bool) -> String {
+    // Denormals are almost always integer constants in float instructions
+    if integer || value & 0x7f800000 == 0 {
+        if (value as i32) < 0 && value > 0xffff0000 {
+            format!("{}", value as i32)
+        } else {
+            format!("{value}")
+        }
+    } else {
+        format!("{:.6}", f32::from_bits(value))
+    }
+}
+
+impl Operand {
+    /// Formats the operand in fxc syntax. `integer` selects how immediates are printed
+    pub fn display(&self, integer: bool) -> String {
+        let mut s = String::new();
+
+        if self.ty == OperandType::IMMEDIATE32 {
+            let values = self
+                .immediate
+                .iter()
+                .map(|v| format_immediate(*v, integer))
+                .collect::<Vec<_>>();
+            s = format!("l({})", values.join(","));
+        } else if self.ty == OperandType::IMMEDIATE64 {
+            let values = self
+                .immediate
+                .chunks_exact(2)
+                .map(|v| {
+                    let bits = v[0] as u64 | (v[1] as u64) << 32;
+                    if integer {
+                        format!("{bits}")
+                    } else {
+                        format!("{:.6}", f64::from_bits(bits))
+                    }
+                })
+                .collect::<Vec<_>>();
+            s = format!("d({})", values.join(","));
+        } else {
+            s.push_str(self.ty.prefix());
+            for (i, index) in self.indices.iter().enumerate() {
+                let inline = i == 0
+                    && index.relative.is_none()
+                    && self.ty != OperandType::IMMEDIATE_CONSTANT_BUFFER;
+                if inline {
+                    write!(s, "{}", index.offset).ok();
+                } else {
+                    s.push('[');
+                    if let Some(relative) = &index.relative {
+                        write!(s, "{} + {}", relative.display(true), index.offset).ok();
+                    } else {
+                        write!(s, "{}", index.offset).ok();
+                    }
+                    s.push(']');
+                }
+            }
+
+            match self.components {
+                OperandComponents::Mask(m) if m != 0 => {
+                    s.push('.');
+                    s.extend(
+                        (0..4)
+                            .filter(|i| m & (1 << i) != 0)
+                            .map(|i| COMPONENT_NAMES[i]),
+                    );
+                }
+                OperandComponents::Swizzle(sw) => {
+                    s.push('.');
+                    s.extend(sw.iter().map(|&c| COMPONENT_NAMES[c as usize]));
+                }
+                OperandComponents::Select(c) => {
+                    s.push('.');
+                    s.push(COMPONENT_NAMES[c as usize]);
+                }
+                _ => {}
+            }
+        }
+
+        match self.modifier {
+            OperandModifier::None => s,
+            OperandModifier::Neg => format!("-{s}"),
+            OperandModifier::Abs => format!("|{s}|"),
+            OperandModifier::AbsNeg => format!("-|{s}|"),
+        }
+    }
+}
+
+fn resource_dimension_name(dimension: u32) -> &'static str {
+    match dimension {
+        1 => "buffer",
+        2 => "texture1d",
+        3 => "texture2d",
+        4 => "texture2dms",
+        5 => "texture3d",
+        6 => "texturecube",
+        7 => "texture1darray",
+        8 => "texture2darray",
+        9 => "texture2dmsarray",
+        10 => "texturecubearray",
+        11 => "raw_buffer",
+        12 => "structured_buffer",
+        _ => "unknown",
+    }
+}
+
+fn return_type_name(return_type: u8) -> &'static str {
+    match return_type {
+        1 => "unorm",
+        2 => "snorm",
+        3 => "sint",
+        4 => "uint",
+        5 => "float",
+        6 => "mixed",
+        7 => "double",
+        8 => "continued",
+        _ => "unused",
+    }
+}
+
+fn return_types(token: u32) -> String {
+    let names = (0..4)
+        .map(|i| return_type_name(((token >> (i * 4)) & 0xf) as u8))
+        .collect::<Vec<_>>();
+    format!("({})", names.join(","))
+}
+
+fn system_value_name(name: u32) -> &'static str {
+    match name {
+        1 => "position",
+        2 => "clip_distance",
+        3 => "cull_distance",
+        4 => "rendertarget_array_index",
+        5 => "viewport_array_index",
+        6 => "vertex_id",
+        7 => "primitive_id",
+        8 => "instance_id",
+        9 => "is_front_face",
+        10 => "sampleIndex",
+        11..=16 => "finalQuadEdgeTessfactor",
+        17 | 18 => "finalQuadInsideTessfactor",
+        19..=21 => "finalTriEdgeTessfactor",
+        22 => "finalTriInsideTessfactor",
+        23 => "finalLineDetailTessfactor",
+        24 => "finalLineDensityTessfactor",
+        _ => "undefined",
+    }
+}
+
+fn interpolation_mode_name(mode: u32) -> &'static str {
+    match mode {
+        1 => "constant",
+        2 => "linear",
+        3 => "linear centroid",
+        4 => "linear noperspective",
+        5 => "linear noperspective centroid",
+        6 => "linear sample",
+        7 => "linear noperspective sample",
+        _ => "undefined",
+    }
+}
+
+fn global_flags(flags: u32) -> String {
+    const NAMES: [&str; 8] = [
+        "refactoringAllowed",
+        "enableDoublePrecisionFloatOps",
+        "forceEarlyDepthStencil",
+        "enableRawAndStructuredBuffers",
+        "skipOptimization",
+        "enableMinimumPrecision",
+        "enable11_1DoubleExtensions",
+        "enable11_1ShaderExtensions",
+    ];
+
+    NAMES
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| flags & (1 << i) != 0)
+        .map(|(_, n)| *n)
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+impl Instruction {
+    fn operand_list(&self) -> String {
+        let integer = self.opcode.is_integer();
+        self.operands
+            .iter()
+            .map(|o| o.display(integer))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn extra(&self, i: usize) -> u32 {
+        self.extra.get(i).copied().unwrap_or_default()
+    }
+
+    /// Instruction name with all of its suffixes (`_sat`, `_nz`, `_indexable(...)`, ...)
+    fn full_name(&self) -> String {
+        let mut name = self.opcode.name().to_string();
+
+        if self.opcode.has_test() {
+            name.push_str(if self.test_nonzero { "_nz" } else { "_z" });
+        }
+
+        if self.sample_offsets.is_some() {
+            name.push_str("_aoffimmi");
+        }
+        if self.resource_dimension.is_some() {
+            name.push_str("_indexable");
+        }
+        if let Some([u, v, w]) = self.sample_offsets {
+            write!(name, "({u},{v},{w})").ok();
+        }
+        if let Some((dimension, stride)) = self.resource_dimension {
+            if dimension == 12 {
+                write!(
+                    name,
+                    "({}, stride={stride})",
+                    resource_dimension_name(dimension)
+                )
+                .ok();
+            } else {
+                write!(name, "({})", resource_dimension_name(dimension)).ok();
+            }
+        }
+        if let Some(rt) = self.resource_return_type {
+            let names = rt.map(return_type_name);
+            write!(name, "({})", names.join(",")).ok();
+        }
+
+        match self.opcode {
+            Opcode::RESINFO => match self.controls & 3 {
+                1 => name.push_str("_rcpFloat"),
+                2 => name.push_str("_uint"),
+                _ => {}
+            },
+            Opcode::SAMPLE_INFO if self.controls & 1 != 0 => name.push_str("_uint"),
+            Opcode::SYNC => {
+                for (bit, suffix) in [(3, "_uglobal"), (2, "_ugroup"), (1, "_g"), (0, "_t")] {
+                    if self.controls & (1 << bit) != 0 {
+                        name.push_str(suffix);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if self.saturate {
+            name.push_str("_sat");
+        }
+
+        name
+    }
+
+    /// Formats the instruction as a single line of fxc-style disassembly
+    pub fn display(&self) -> String {
+        let op = self.operands.first();
+        let op_str = op.map(|o| o.display(false)).unwrap_or_default();
+
+        match self.opcode {
+            Opcode::CUSTOMDATA => {
+                if self.controls == 3 {
+                    let values = self
+                        .extra
+                        .chunks(4)
+                        .map(|c| {
+                            let v = c
+                                .iter()
+                                .map(|v| format_immediate(*v, false))
+                                .collect::<Vec<_>>();
+                            format!("{{ {}}}", v.join(", "))
+                        })
+                        .collect::<Vec<_>>();
+                    // Rows line up under the first one, like fxc
+                    format!(
+                        "dcl_immediateConstantBuffer {{ {} }}",
+                        values.join(&format!(",\n{:30}", ""))
+                    )
+                } else {
+                    format!(
+                        "// custom data (class {}, {} words)",
+                        self.controls,
+                        self.extra.len()
+                    )
+                }
+            }
+            Opcode::DCL_GLOBAL_FLAGS => format!("dcl_globalFlags {}", global_flags(self.controls)),
+            Opcode::DCL_CONSTANT_BUFFER => {
+                let (slot, size) = op
+                    .map(|o| {
+                        (
+                            o.indices.first().map(|i| i.offset).unwrap_or_default(),
+                            o.indices.get(1).map(|i| i.offset).unwrap_or_default(),
+                        )
+                    })
+                    .unwrap_or_default();
+                let access = if self.controls & 1 != 0 {
+                    "dynamicIndexed"
+                } else {
+                    "immediateIndexed"
+                };
+                format!("dcl_constantbuffer CB{slot}[{size}], {access}")
+            }
+            Opcode::DCL_SAMPLER => {
+                let mode = match self.controls & 0xf {
+                    1 => "mode_comparison",
+                    2 => "mode_mono",
+                    _ => "mode_default",
+                };
+                format!("dcl_sampler {op_str}, {mode}")
+            }
+            Opcode::DCL_RESOURCE | Opcode::DCL_UAV_TYPED => {
+                let prefix = if self.opcode == Opcode::DCL_RESOURCE {
+                    "dcl_resource"
+                } else {
+                    "dcl_uav_typed"
+                };
+                let dimension = self.controls & 0x1f;
+                let samples = (self.controls >> 5) & 0x7f;
+                let dimension_name = if matches!(dimension, 4 | 9) && samples > 0 {
+                    format!("{}({samples})", resource_dimension_name(dimension))
+                } else {
+                    resource_dimension_name(dimension).to_string()
+                };
+                format!(
+                    "{prefix}_{dimension_name} {} {op_str}",
+                    return_types(self.extra(0))
+                )
+            }
+            Opcode::DCL_INPUT_PS | Opcode::DCL_INPUT_PS_SGV | Opcode::DCL_INPUT_PS_SIV => {
+                let mode = interpolation_mode_name(self.controls & 0xf);
+                if self.opcode == Opcode::DCL_INPUT_PS {
+                    format!("dcl_input_ps {mode} {op_str}")
+                } else {
+                    format!(
+                        "{} {mode} {op_str}, {}",
+                        self.opcode.name(),
+                        system_value_name(self.extra(0))
+                    )
+                }
+            }
+            Opcode::DCL_INPUT_SGV
+            | Opcode::DCL_INPUT_SIV
+            | Opcode::DCL_OUTPUT_SGV
+            | Opcode::DCL_OUTPUT_SIV => format!(
+                "{} {op_str}, {}",
+                self.opcode.name(),
+                system_value_name(self.extra(0))
+            ),
+            Opcode::DCL_TEMPS
+            | Opcode::DCL_MAX_OUTPUT_VERTEX_COUNT
+            | Opcode::DCL_GS_INSTANCE_COUNT
+            | Opcode::DCL_HS_FORK_PHASE_INSTANCE_COUNT
+            | Opcode::DCL_HS_JOIN_PHASE_INSTANCE_COUNT
+            | Opcode::DCL_FUNCTION_BODY => format!("{} {}", self.opcode.name(), self.extra(0)),
+            Opcode::DCL_INDEXABLE_TEMP => format!(
+                "dcl_indexableTemp x{}[{}], {}",
+                self.extra(0),
+                self.extra(1),
+                self.extra(2)
+            ),
+            Opcode::DCL_INDEX_RANGE
+            | Opcode::DCL_RESOURCE_STRUCTURED
+            | Opcode::DCL_UAV_STRUCTURED
+            | Opcode::DCL_TGSM_RAW => {
+                format!("{} {op_str}, {}", self.opcode.name(), self.extra(0))
+            }
+            Opcode::DCL_TGSM_STRUCTURED => format!(
+                "dcl_tgsm_structured {op_str}, {}, {}",
+                self.extra(0),
+                self.extra(1)
+            ),
+            Opcode::DCL_THREAD_GROUP => format!(
+                "dcl_thread_group {}, {}, {}",
+                self.extra(0),
+                self.extra(1),
+                self.extra(2)
+            ),
+            Opcode::DCL_HS_MAX_TESSFACTOR => format!(
+                "dcl_hs_max_tessfactor l({:.6})",
+                f32::from_bits(self.extra(0))
+            ),
+            Opcode::DCL_INPUT_CONTROL_POINT_COUNT | Opcode::DCL_OUTPUT_CONTROL_POINT_COUNT => {
+                format!("{} {}", self.opcode.name(), self.controls & 0x3f)
+            }
+            Opcode::DCL_TESS_DOMAIN => {
+                let domain = ["undefined", "isoline", "tri", "quad"];
+                format!(
+                    "dcl_tessellator_domain domain_{}",
+                    domain[(self.controls & 3) as usize]
+                )
+            }
+            Opcode::DCL_TESS_PARTITIONING => {
+                let partitioning = [
+                    "undefined",
+                    "integer",
+                    "pow2",
+                    "fractional_odd",
+                    "fractional_even",
+                ];
+                format!(
+                    "dcl_tessellator_partitioning partitioning_{}",
+                    partitioning
+                        .get((self.controls & 7) as usize)
+                        .unwrap_or(&"undefined")
+                )
+            }
+            Opcode::DCL_TESS_OUTPUT_PRIMITIVE => {
+                let primitive = ["undefined", "point", "line", "triangle_cw", "triangle_ccw"];
+                format!(
+                    "dcl_tessellator_output_primitive output_{}",
+                    primitive
+                        .get((self.controls & 7) as usize)
+                        .unwrap_or(&"undefined")
+                )
+            }
+            Opcode::DCL_OUTPUT_TOPOLOGY => {
+                let topology = match self.controls & 0x7f {
+                    1 => "pointlist",
+                    2 => "linelist",
+                    3 => "linestrip",
+                    4 => "trianglelist",
+                    5 => "trianglestrip",
+                    _ => "undefined",
+                };
+                format!("dcl_outputtopology {topology}")
+            }
+            Opcode::DCL_INPUT_PRIMITIVE => {
+                let primitive = match self.controls & 0x3f {
+                    1 => "point",
+                    2 => "line",
+                    3 => "triangle",
+                    6 => "lineadj",
+                    7 => "triangleadj",
+                    n @ 8..=39 => {
+                        return format!("dcl_inputprimitive {}_control_point_patch", n - 7)
+                    }
+                    _ => "undefined",
+                };
+                format!("dcl_inputprimitive {primitive}")
+            }
+            Opcode::DCL_FUNCTION_TABLE | Opcode::DCL_INTERFACE => {
+                let values = self.extra.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+                format!("{} {}", self.opcode.name(), values.join(", "))
+            }
+            Opcode::INTERFACE_CALL => {
+                format!("fcall fp{}, {}", self.extra(0), self.operand_list())
+            }
+            _ => {
+                let operands = self.operand_list();
+                if operands.is_empty() {
+                    self.full_name()
+                } else {
+                    format!("{} {operands}", self.full_name())
+                }
+            }
+        }
+    }
+}
+
+fn mask_columns(mask: u8) -> String {
+    (0..4)
+        .map(|i| {
+            if mask & (1 << i) != 0 {
+                COMPONENT_NAMES[i]
+            } else {
+                ' '
+            }
+        })
+        .collect()
+}
+
+/// Writes a signature table. The read/write mask of an output element holds the components that are never written,
+/// fxc shows the inverse of that as "Used"
+fn write_signature(out: &mut String, title: &str, signature: &DxbcSignature, output: bool) {
+    writeln!(out, "//\n// {title}:\n//").ok();
+    writeln!(
+        out,
+        "// Name                 Index   Mask Register SysValue  Format   Used"
+    )
+    .ok();
+    writeln!(
+        out,
+        "// -------------------- ----- ------ -------- -------- ------- ------"
+    )
+    .ok();
+
+    for e in &signature.elements {
+        let mask = mask_columns(e.component_mask.bits());
+        let used = if output {
+            e.component_mask.bits() & !e.component_mask_rw.bits()
+        } else {
+            e.component_mask_rw.bits()
+        };
+        let system_value = match e.system_value_type {
+            0 => "NONE".to_string(),
+            1 => "POS".to_string(),
+            6 => "VERTID".to_string(),
+            8 => "INSTID".to_string(),
+            9 => "FFACE".to_string(),
+            64 => "TARGET".to_string(),
+            65 => "DEPTH".to_string(),
+            v => v.to_string(),
+        };
+        let format = match e.component_type {
+            DxbcInputType::Uint => "uint",
+            DxbcInputType::Int => "int",
+            DxbcInputType::Float => "float",
+        };
+
+        let row = format!(
+            "// {:<20} {:>5}   {mask} {:>8} {:>8} {:>7}   {}",
+            e.semantic_name.to_string(),
+            e.semantic_index,
+            e.register,
+            system_value,
+            format,
+            mask_columns(used)
+        );
+        writeln!(out, "{}", row.trim_end()).ok();
+    }
+    writeln!(out, "//").ok();
+}
+
+fn write_resources(out: &mut String, resources: &DxbcResourceDefinitions) {
+    if !resources.constant_buffers.is_empty() {
+        writeln!(out, "//\n// Buffer Definitions:\n//").ok();
+        for cb in &resources.constant_buffers {
+            writeln!(out, "// cbuffer {}\n// {{\n//", cb.name).ok();
+            for v in &cb.variables {
+                let array = if v.ty.elements > 0 {
+                    format!("[{}]", v.ty.elements)
+                } else {
+                    String::new()
+                };
+                writeln!(
+                    out,
+                    "//   {:<36} // Offset: {:>4} Size: {:>5}{}",
+                    format!("{} {}{array};", v.ty, v.name),
+                    v.offset,
+                    v.size,
+                    if v.is_used() { "" } else { " [unused]" }
+                )
+                .ok();
+            }
+            writeln!(out, "//\n// }}\n//").ok();
+        }
+    }
+
+    if resources.bindings.is_empty() {
+        return;
+    }
+
+    writeln!(out, "//\n// Resource Bindings:\n//").ok();
+    writeln!(
+        out,
+        "// Name                                 Type          Dim      HLSL Bind  Count"
+    )
+    .ok();
+    writeln!(
+        out,
+        "// ------------------------------ ---------- ----------- -------------- ------"
+    )
+    .ok();
+    for b in &resources.bindings {
+        let ty = match b.input_type {
+            DxbcShaderInputType::CBuffer => "cbuffer",
+            DxbcShaderInputType::TBuffer => "tbuffer",
+            DxbcShaderInputType::Texture => "texture",
+            DxbcShaderInputType::Sampler => "sampler",
+            DxbcShaderInputType::Structured => "structured",
+            DxbcShaderInputType::ByteAddress => "raw",
+            _ => "UAV",
+        };
+        let bind = match b.input_type {
+            DxbcShaderInputType::CBuffer => format!("cb{}", b.bind_point),
+            t => format!("{}{}", t.register_prefix(), b.bind_point),
+        };
+        let dimension = match b.dimension {
+            DxbcResourceDimension::Unknown => "NA",
+            DxbcResourceDimension::Buffer | DxbcResourceDimension::BufferEx => "buf",
+            DxbcResourceDimension::Texture1D => "1d",
+            DxbcResourceDimension::Texture1DArray => "1darray",
+            DxbcResourceDimension::Texture2D => "2d",
+            DxbcResourceDimension::Texture2DArray => "2darray",
+            DxbcResourceDimension::Texture2DMS => "2dMS",
+            DxbcResourceDimension::Texture2DMSArray => "2dMSarray",
+            DxbcResourceDimension::Texture3D => "3d",
+            DxbcResourceDimension::TextureCube => "cube",
+            DxbcResourceDimension::TextureCubeArray => "cubearray",
+        };
+        writeln!(
+            out,
+            "// {:<30} {:>10} {:>11} {:>14} {:>6}",
+            b.name, ty, dimension, bind, b.bind_count
+        )
+        .ok();
+    }
+}
+
+/// Disassembles a shader into text resembling `fxc /dumpbin` output
+pub fn disassemble(shader: &DxbcShader) -> anyhow::Result<String> {
+    let bytecode = shader
+        .bytecode
+        .as_ref()
+        .ok_or_else(|| anyhow!("Shader has no SHEX/SHDR chunk"))?;
+    let instructions = decode_program(bytecode)?;
+
+    let mut out = String::new();
+    if let Some(resources) = &shader.resources {
+        if !resources.creator.is_empty() {
+            writeln!(out, "//\n// Generated by {}", resources.creator).ok();
+        }
+        write_resources(&mut out, resources);
+    }
+    if let Some(signature) = &shader.input_signature {
+        write_signature(&mut out, "Input signature", signature, false);
+    }
+    if let Some(signature) = &shader.output_signature {
+        write_signature(&mut out, "Output signature", signature, true);
+    }
+
+    writeln!(
+        out,
+        "{}_{}_{}",
+        bytecode.program_type, bytecode.major_version, bytecode.minor_version
+    )
+    .ok();
+
+    let mut depth = 0usize;
+    for instruction in &instructions {
+        let opcode = instruction.opcode;
+        if matches!(
+            opcode,
+            Opcode::ELSE | Opcode::ENDIF | Opcode::ENDLOOP | Opcode::ENDSWITCH
+        ) {
+            depth = depth.saturating_sub(1);
+        }
+
+        let indent = "  ".repeat(depth);
+        for line in instruction.display().lines() {
+            writeln!(out, "{indent}{line}").ok();
+        }
+
+        if matches!(
+            opcode,
+            Opcode::IF | Opcode::ELSE | Opcode::LOOP | Opcode::SWITCH
+        ) {
+            depth += 1;
+        }
+    }
+
+    if let Some(stats) = &shader.statistics {
+        writeln!(
+            out,
+            "// Approximately {} instruction slots used",
+            stats.instruction_count
+        )
+        .ok();
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::dxbc::tests::{container, program_chunk, signature_chunk_rw};
+    use crate::dxbc::DxbcProgramType;
+    use crate::render::shader::{blob_bytes, compile_hlsl_source};
+    use windows::core::PCSTR;
+    use windows::Win32::Graphics::Direct3D::Fxc::{D3DDisassemble, D3D_DISASM_DISABLE_DEBUG_INFO};
+
+    #[rustfmt::skip]
+    pub(crate) const MOV_IMMEDIATE: &[u32] = &[
+        0x01000800 | 106,                                                // dcl_globalFlags
+        0x03001062, 0x00101032, 1,                                       // dcl_input_ps
+        0x03000065, 0x001020f2, 0,                                       // dcl_output
+        0x02000068, 1,                                                   // dcl_temps
+        0x08000036, 0x001020f2, 0, 0x00004002, 0x3f800000, 0, 0, 0x3f800000, // mov
+        0x0100003e,                                                      // ret
+    ];
+
+    #[rustfmt::skip]
+    pub(crate) const SAMPLE_CBUFFER: &[u32] = &[
+        0x04000059, 0x00208e46, 0, 1,                                    // dcl_constantbuffer
+        0x0300005a, 0x00106000, 0,                                       // dcl_sampler
+        0x04001858, 0x00107000, 0, 0x5555,                               // dcl_resource_texture2d
+        0x03001062, 0x00101032, 1,                                       // dcl_input_ps
+        0x03000065, 0x001020f2, 0,                                       // dcl_output
+        0x02000068, 1,                                                   // dcl_temps
+        0x09000045, 0x001000f2, 0, 0x00101046, 1, 0x00107e46, 0, 0x00106000, 0, // sample
+        0x08000038, 0x001020f2, 0, 0x00100e46, 0, 0x00208e46, 0, 0,      // mul
+        0x0100003e,                                                      // ret
+    ];
+
+    #[rustfmt::skip]
+    pub(crate) const IF_ELSE: &[u32] = &[
+        0x02000068, 1,                                                   // dcl_temps
+        0x0304001f, 0x0010000a, 0,                                       // if_nz
+        0x08000036, 0x001020f2, 0, 0x00004002, 0x3f800000, 0, 0, 0x3f800000, // mov
+        0x01000012,                                                      // else
+        0x05000036, 0x001020f2, 0, 0x00100e46, 0,                        // mov
+        0x01000015,                                                      // endif
+        0x0100003e,                                                      // ret
+    ];
+
+    /// Pixel shader reading TEXCOORD0.xy (v1) and writing all of SV_Target0
+    pub(crate) fn pixel_shader(tokens: &[u32]) -> DxbcShader {
+        let dxbc = container(&[
+            (
+                b"ISGN",
+                signature_chunk_rw(&[("SV_POSITION", 0, 0, 0xf, 0), ("TEXCOORD", 0, 1, 0x3, 0x3)]),
+            ),
+            (b"OSGN", signature_chunk_rw(&[("SV_TARGET", 0, 0, 0xf, 0)])),
+            (b"SHEX", program_chunk(DxbcProgramType::Pixel, tokens)),
+        ]);
+        DxbcShader::parse(&dxbc).unwrap()
+    }
+
+    /// Disassembly from the version line on, the signatures are covered by `signatures_and_declarations`
+    fn program_text(tokens: &[u32]) -> String {
+        let text = disassemble(&pixel_shader(tokens)).unwrap();
+        text[text.find("ps_5_0").unwrap()..].to_string()
+    }
+
+    #[test]
+    fn signatures_and_declarations() {
+        assert_eq!(
+            disassemble(&pixel_shader(MOV_IMMEDIATE)).unwrap(),
+            "\
+//
+// Input signature:
+//
+// Name                 Index   Mask Register SysValue  Format   Used
+// -------------------- ----- ------ -------- -------- ------- ------
+// SV_POSITION              0   xyzw        0     NONE   float
+// TEXCOORD                 0   xy          1     NONE   float   xy
+//
+//
+// Output signature:
+//
+// Name                 Index   Mask Register SysValue  Format   Used
+// -------------------- ----- ------ -------- -------- ------- ------
+// SV_TARGET                0   xyzw        0     NONE   float   xyzw
+//
+ps_5_0
+dcl_globalFlags refactoringAllowed
+dcl_input_ps linear v1.xy
+dcl_output o0.xyzw
+dcl_temps 1
+mov o0.xyzw, l(1.000000,0,0,1.000000)
+ret
+"
+        );
+    }
+
+    #[test]
+    fn texture_sample_and_constant_buffer() {
+        assert_eq!(
+            program_text(SAMPLE_CBUFFER),
+            "\
+ps_5_0
+dcl_constantbuffer CB0[1], immediateIndexed
+dcl_sampler s0, mode_default
+dcl_resource_texture2d (float,float,float,float) t0
+dcl_input_ps linear v1.xy
+dcl_output o0.xyzw
+dcl_temps 1
+sample r0.xyzw, v1.xyxx, t0.xyzw, s0
+mul o0.xyzw, r0.xyzw, cb0[0].xyzw
+ret
+"
+        );
+    }
+
+    #[test]
+    fn control_flow_is_indented() {
+        assert_eq!(
+            program_text(IF_ELSE),
+            "\
+ps_5_0
+dcl_temps 1
+if_nz r0.x
+  mov o0.xyzw, l(1.000000,0,0,1.000000)
+else
+  mov o0.xyzw, r0.xyzw
+endif
+ret
+"
+        );
+    }
+
+    /// Non-empty lines with runs of whitespace collapsed, fxc pads columns with trailing spaces and separates sections
+    /// with extra empty comment lines
+    fn listing_lines(text: &str) -> Vec<String> {
+        text.trim_end_matches('\0')
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|l| !l.is_empty() && l != "//")
+            .collect()
+    }
+
+    #[test]
+    fn fullscreen_vertex_shader_matches_fxc() {
+        let bytecode = compile_hlsl_source(
+            include_str!("../fullscreen.hlsl"),
+            "fullscreen.hlsl",
+            "VShader",
+            "vs_5_0",
+        )
+        .unwrap();
+        let fxc = unsafe {
+            D3DDisassemble(
+                bytecode.as_ptr() as _,
+                bytecode.len(),
+                D3D_DISASM_DISABLE_DEBUG_INFO,
+                PCSTR::null(),
+            )
+        }
+        .unwrap();
+
+        let shader = DxbcShader::parse(&bytecode).unwrap();
+        assert_eq!(
+            listing_lines(&disassemble(&shader).unwrap()),
+            listing_lines(&String::from_utf8_lossy(blob_bytes(&fxc)))
+        );
+    }
+
+    #[test]
+    fn immediate_constant_buffer_rows_line_up() {
+        #[rustfmt::skip]
+        let tokens = [
+            0x00001835, 10, 0x3f800000, 0, 0, 0, 0, 0x3f800000, 0, 0, // dcl_immediateConstantBuffer
+            0x0100003e,                                               // ret
+        ];
+        assert_eq!(
+            program_text(&tokens),
+            "\
+ps_5_0
+dcl_immediateConstantBuffer { { 1.000000, 0, 0, 0},
+                              { 0, 1.000000, 0, 0} }
+ret
+"
+        );
+    }
+
+    #[test]
+    fn truncated_instruction_is_an_error() {
+        // mov with a length of 8 tokens, but only 3 present
+        let shader = pixel_shader(&[0x08000036, 0x001020f2, 0]);
+        assert!(disassemble(&shader).is_err());
+    }
+}
@@ -1,11 +1,14 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, ensure};
 use binrw::{BinRead, BinReaderExt, BinResult, Endian, FilePtr32, NullString};
 use bitflags::bitflags;
+use destiny_pkg::TagHash;
 use std::{
     fmt::Display,
-    io::{Read, Seek, SeekFrom},
+    io::{Cursor, Read, Seek, SeekFrom},
 };
-use windows::core::PCSTR;
+use tracing::warn;
+
+use crate::packages::package_manager;
 
 #[derive(BinRead, Debug)]
 #[br(magic = b"DXBC")]
@@ -19,9 +22,9 @@ pub struct DxbcHeader {
     pub chunk_offsets: Vec<u32>,
 }
 
+/// Input (ISGN) or output (OSGN) signature
 #[derive(BinRead, Debug)]
-// #[br(magic = b"ISGN")]
-pub struct DxbcInputSignature {
+pub struct DxbcSignature {
     pub chunk_size: u32,
 
     #[br(try_calc(__binrw_generated_var_reader.stream_position()))]
@@ -30,13 +33,17 @@ pub struct DxbcInputSignature {
     pub element_count: u32,
     pub _unkc: u32,
 
-    #[br(count = element_count, args { inner: (_string_base_offset,) })]
-    pub elements: Vec<DxbcInputElement>,
+    // Elements are 24 bytes
+    #[br(try_calc(checked_count(element_count, 24, chunk_size)))]
+    _checked_element_count: usize,
+
+    #[br(count = _checked_element_count, args { inner: (_string_base_offset,) })]
+    pub elements: Vec<DxbcSignatureElement>,
 }
 
 #[derive(BinRead, Debug)]
 #[br(import(string_base_offset: u64))]
-pub struct DxbcInputElement {
+pub struct DxbcSignatureElement {
     #[br(offset = string_base_offset)]
     pub semantic_name: FilePtr32<NullString>,
     pub semantic_index: u32,
@@ -66,59 +73,6 @@ impl Display for DxbcInputType {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum DxbcSemanticType {
-    Position,
-    TexCoord,
-    Normal,
-    Tangent,
-    Color,
-    BlendWeight,
-    BlendIndices,
-
-    SystemVertexId,
-    SystemInstanceId,
-}
-
-impl DxbcSemanticType {
-    pub fn from_str(s: &str) -> Option<DxbcSemanticType> {
-        Some(match s {
-            "POSITION" => DxbcSemanticType::Position,
-            "TEXCOORD" => DxbcSemanticType::TexCoord,
-            "NORMAL" => DxbcSemanticType::Normal,
-            "TANGENT" => DxbcSemanticType::Tangent,
-            "COLOR" => DxbcSemanticType::Color,
-            "BLENDWEIGHT" => DxbcSemanticType::BlendWeight,
-            "BLENDINDICES" => DxbcSemanticType::BlendIndices,
-            "SV_VERTEXID" => DxbcSemanticType::SystemVertexId,
-            "SV_InstanceID" => DxbcSemanticType::SystemInstanceId,
-            _ => return None,
-        })
-    }
-
-    pub fn to_pcstr(&self) -> PCSTR {
-        match self {
-            DxbcSemanticType::Position => s!("POSITION"),
-            DxbcSemanticType::TexCoord => s!("TEXCOORD"),
-            DxbcSemanticType::Normal => s!("NORMAL"),
-            DxbcSemanticType::Tangent => s!("TANGENT"),
-            DxbcSemanticType::Color => s!("COLOR"),
-            DxbcSemanticType::BlendWeight => s!("BLENDWEIGHT"),
-            DxbcSemanticType::BlendIndices => s!("BLENDINDICES"),
-
-            DxbcSemanticType::SystemVertexId => s!("SV_VERTEXID"),
-            DxbcSemanticType::SystemInstanceId => s!("SV_InstanceID"),
-        }
-    }
-
-    pub fn is_system_value(&self) -> bool {
-        matches!(
-            self,
-            DxbcSemanticType::SystemVertexId | DxbcSemanticType::SystemInstanceId
-        )
-    }
-}
-
 bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct ComponentMask: u8 {
@@ -144,19 +98,838 @@ impl BinRead for ComponentMask {
     }
 }
 
-/// Find ISGN chunk and read it
-pub fn get_input_signature<R: Read + Seek>(
+/// Seeks to the data of the first chunk with the given magic (right after the magic itself)
+fn find_chunk<R: Read + Seek>(
     reader: &mut R,
     header: &DxbcHeader,
-) -> anyhow::Result<DxbcInputSignature> {
+    magic: &[u8; 4],
+) -> anyhow::Result<bool> {
     for chunk_offset in &header.chunk_offsets {
         reader.seek(SeekFrom::Start(*chunk_offset as _))?;
 
         let chunk_magic: [u8; 4] = reader.read_le()?;
-        if &chunk_magic == b"ISGN" {
-            return Ok(reader.read_le()?);
+        if &chunk_magic == magic {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Number of render targets written by a pixel shader, from its output signature
+pub fn render_target_count(output_signature: &DxbcSignature) -> u32 {
+    output_signature
+        .elements
+        .iter()
+        .filter(|e| {
+            e.semantic_name
+                .to_string()
+                .eq_ignore_ascii_case("SV_TARGET")
+        })
+        .count() as u32
+}
+
+/// Output register of TEXCOORD0, if it has at least 2 components. Used by the UV checker view, which reads its UVs
+/// from a fixed register
+pub fn texcoord0_register(output_signature: &DxbcSignature) -> Option<u32> {
+    output_signature
+        .elements
+        .iter()
+        .find(|e| {
+            e.semantic_index == 0 && e.semantic_name.to_string().eq_ignore_ascii_case("TEXCOORD")
+        })
+        .filter(|e| e.component_mask.contains(ComponentMask::XY))
+        .map(|e| e.register)
+}
+
+/// A DXBC shader container and the chunks we know how to read.
+/// Missing chunks are `None`, chunks that fail to parse are logged and skipped
+#[derive(Debug)]
+pub struct DxbcShader {
+    pub header: DxbcHeader,
+    pub input_signature: Option<DxbcSignature>,
+    pub output_signature: Option<DxbcSignature>,
+    pub resources: Option<DxbcResourceDefinitions>,
+    pub statistics: Option<DxbcStatistics>,
+    pub bytecode: Option<DxbcBytecode>,
+}
+
+impl DxbcShader {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut cur = Cursor::new(data);
+        let header: DxbcHeader = cur.read_le()?;
+
+        fn optional<T>(chunk: &str, r: anyhow::Result<Option<T>>) -> Option<T> {
+            r.map_err(|e| warn!("Failed to read {chunk} chunk: {e}"))
+                .ok()
+                .flatten()
         }
+
+        let input_signature = optional("ISGN", read_optional_chunk(&mut cur, &header, b"ISGN"));
+        let output_signature = optional("OSGN", read_optional_chunk(&mut cur, &header, b"OSGN"));
+        let resources = optional("RDEF", DxbcResourceDefinitions::read(&mut cur, &header));
+        let statistics = optional("STAT", read_optional_chunk(&mut cur, &header, b"STAT"));
+        let bytecode = optional("SHEX/SHDR", DxbcBytecode::read(&mut cur, &header));
+
+        Ok(Self {
+            header,
+            input_signature,
+            output_signature,
+            resources,
+            statistics,
+            bytecode,
+        })
+    }
+
+    /// Reads the shader referenced by a material's `vertex_shader`/`pixel_shader` tag
+    pub fn load(shader: TagHash) -> anyhow::Result<Self> {
+        let entry = package_manager().get_entry(shader)?;
+        let data = package_manager().read_tag(entry.reference)?;
+        Self::parse(&data)
     }
+}
+
+fn read_optional_chunk<T, R>(
+    reader: &mut R,
+    header: &DxbcHeader,
+    magic: &[u8; 4],
+) -> anyhow::Result<Option<T>>
+where
+    T: for<'a> BinRead<Args<'a> = ()>,
+    R: Read + Seek,
+{
+    if find_chunk(reader, header, magic)? {
+        Ok(Some(reader.read_le()?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads the size of the chunk the reader is at, making sure the chunk doesn't extend past the end of the data
+fn read_chunk_size<R: Read + Seek>(reader: &mut R) -> anyhow::Result<u32> {
+    let chunk_size: u32 = reader.read_le()?;
+    let position = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+
+    ensure!(
+        chunk_size as u64 <= end - position,
+        "Chunk size {chunk_size} exceeds the {} remaining bytes",
+        end - position
+    );
+    Ok(chunk_size)
+}
+
+/// Checks that `count` entries of `entry_size` bytes fit in a chunk, so corrupt counts can't cause huge allocations
+fn checked_count(count: u32, entry_size: u64, chunk_size: u32) -> anyhow::Result<usize> {
+    ensure!(
+        count as u64 * entry_size <= chunk_size as u64,
+        "{count} entries of {entry_size} bytes don't fit in a {chunk_size} byte chunk"
+    );
+    Ok(count as usize)
+}
+
+/// Instruction counts from the STAT chunk (`D3D11_SHADER_DESC`)
+#[derive(BinRead, Debug, Clone)]
+pub struct DxbcStatistics {
+    pub chunk_size: u32,
+
+    pub instruction_count: u32,
+    pub temp_register_count: u32,
+    pub def_count: u32,
+    pub dcl_count: u32,
+    pub float_instruction_count: u32,
+    pub int_instruction_count: u32,
+    pub uint_instruction_count: u32,
+    pub static_flow_control_count: u32,
+    pub dynamic_flow_control_count: u32,
+    pub macro_instruction_count: u32,
+    pub temp_array_count: u32,
+    pub array_instruction_count: u32,
+    pub cut_instruction_count: u32,
+    pub emit_instruction_count: u32,
+    pub texture_normal_instructions: u32,
+    pub texture_load_instructions: u32,
+    pub texture_comp_instructions: u32,
+    pub texture_bias_instructions: u32,
+    pub texture_gradient_instructions: u32,
+    pub mov_instruction_count: u32,
+    pub movc_instruction_count: u32,
+    pub conversion_instruction_count: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DxbcProgramType {
+    Pixel = 0,
+    Vertex = 1,
+    Geometry = 2,
+    Hull = 3,
+    Domain = 4,
+    Compute = 5,
+}
+
+impl Display for DxbcProgramType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DxbcProgramType::Pixel => "ps",
+            DxbcProgramType::Vertex => "vs",
+            DxbcProgramType::Geometry => "gs",
+            DxbcProgramType::Hull => "hs",
+            DxbcProgramType::Domain => "ds",
+            DxbcProgramType::Compute => "cs",
+        })
+    }
+}
+
+/// Shader program from the SHEX (SM5) or SHDR (SM4) chunk
+#[derive(Debug, Clone)]
+pub struct DxbcBytecode {
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub program_type: DxbcProgramType,
+    /// Instruction tokens, without the version and length tokens
+    pub tokens: Vec<u32>,
+}
+
+impl DxbcBytecode {
+    fn read<R: Read + Seek>(reader: &mut R, header: &DxbcHeader) -> anyhow::Result<Option<Self>> {
+        if !find_chunk(reader, header, b"SHEX")? && !find_chunk(reader, header, b"SHDR")? {
+            return Ok(None);
+        }
+
+        let chunk_size = read_chunk_size(reader)?;
+        let version: u32 = reader.read_le()?;
+        let length: u32 = reader.read_le()?;
+        ensure!(length >= 2, "Program length {length} is too small");
+        // The length includes the version and length tokens
+        let token_count = checked_count(length, 4, chunk_size)? - 2;
+
+        let program_type = match version >> 16 {
+            0 => DxbcProgramType::Pixel,
+            1 => DxbcProgramType::Vertex,
+            2 => DxbcProgramType::Geometry,
+            3 => DxbcProgramType::Hull,
+            4 => DxbcProgramType::Domain,
+            5 => DxbcProgramType::Compute,
+            u => return Err(anyhow!("Unknown program type {u}")),
+        };
+
+        let mut tokens = vec![0u32; token_count];
+        for t in &mut tokens {
+            *t = reader.read_le()?;
+        }
+
+        Ok(Some(Self {
+            major_version: ((version >> 4) & 0xf) as u8,
+            minor_version: (version & 0xf) as u8,
+            program_type,
+            tokens,
+        }))
+    }
+}
+
+/// Resource definitions from the RDEF chunk: constant buffers and everything bound to a register
+#[derive(Debug, Clone)]
+pub struct DxbcResourceDefinitions {
+    pub major_version: u8,
+    pub minor_version: u8,
+    pub flags: u32,
+    pub creator: String,
+    pub bindings: Vec<DxbcResourceBinding>,
+    pub constant_buffers: Vec<DxbcConstantBuffer>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DxbcResourceBinding {
+    pub name: String,
+    pub input_type: DxbcShaderInputType,
+    pub return_type: u32,
+    pub dimension: DxbcResourceDimension,
+    pub sample_count: u32,
+    /// Register index (b#, t#, s# or u# depending on `input_type`)
+    pub bind_point: u32,
+    pub bind_count: u32,
+    pub flags: u32,
+}
+
+#[derive(BinRead, Debug, PartialEq, Eq, Clone, Copy)]
+#[br(repr(u32))]
+pub enum DxbcShaderInputType {
+    CBuffer = 0,
+    TBuffer = 1,
+    Texture = 2,
+    Sampler = 3,
+    UavRwTyped = 4,
+    Structured = 5,
+    UavRwStructured = 6,
+    ByteAddress = 7,
+    UavRwByteAddress = 8,
+    UavAppendStructured = 9,
+    UavConsumeStructured = 10,
+    UavRwStructuredWithCounter = 11,
+}
+
+impl DxbcShaderInputType {
+    /// HLSL register class
+    pub fn register_prefix(&self) -> char {
+        match self {
+            DxbcShaderInputType::CBuffer => 'b',
+            DxbcShaderInputType::Sampler => 's',
+            DxbcShaderInputType::TBuffer
+            | DxbcShaderInputType::Texture
+            | DxbcShaderInputType::Structured
+            | DxbcShaderInputType::ByteAddress => 't',
+            _ => 'u',
+        }
+    }
+}
+
+#[derive(BinRead, Debug, PartialEq, Eq, Clone, Copy)]
+#[br(repr(u32))]
+pub enum DxbcResourceDimension {
+    Unknown = 0,
+    Buffer = 1,
+    Texture1D = 2,
+    Texture1DArray = 3,
+    Texture2D = 4,
+    Texture2DArray = 5,
+    Texture2DMS = 6,
+    Texture2DMSArray = 7,
+    Texture3D = 8,
+    TextureCube = 9,
+    TextureCubeArray = 10,
+    BufferEx = 11,
+}
+
+impl Display for DxbcResourceDimension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DxbcResourceDimension::Unknown => "unknown",
+            DxbcResourceDimension::Buffer | DxbcResourceDimension::BufferEx => "Buffer",
+            DxbcResourceDimension::Texture1D => "Texture1D",
+            DxbcResourceDimension::Texture1DArray => "Texture1DArray",
+            DxbcResourceDimension::Texture2D => "Texture2D",
+            DxbcResourceDimension::Texture2DArray => "Texture2DArray",
+            DxbcResourceDimension::Texture2DMS => "Texture2DMS",
+            DxbcResourceDimension::Texture2DMSArray => "Texture2DMSArray",
+            DxbcResourceDimension::Texture3D => "Texture3D",
+            DxbcResourceDimension::TextureCube => "TextureCube",
+            DxbcResourceDimension::TextureCubeArray => "TextureCubeArray",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DxbcConstantBuffer {
+    pub name: String,
+    /// Size in bytes
+    pub size: u32,
+    pub flags: u32,
+    pub buffer_type: u32,
+    pub variables: Vec<DxbcVariable>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DxbcVariable {
+    pub name: String,
+    /// Offset in bytes from the start of the constant buffer
+    pub offset: u32,
+    pub size: u32,
+    pub flags: u32,
+    pub ty: DxbcType,
+}
+
+impl DxbcVariable {
+    /// `D3D_SVF_USED`, set when the shader actually reads the variable
+    pub fn is_used(&self) -> bool {
+        self.flags & 2 != 0
+    }
+}
+
+#[derive(BinRead, Debug, PartialEq, Eq, Clone, Copy)]
+#[br(repr(u16))]
+pub enum DxbcVariableClass {
+    Scalar = 0,
+    Vector = 1,
+    MatrixRows = 2,
+    MatrixColumns = 3,
+    Object = 4,
+    Struct = 5,
+    InterfaceClass = 6,
+    InterfacePointer = 7,
+}
+
+#[derive(Debug, Clone)]
+pub struct DxbcType {
+    pub class: DxbcVariableClass,
+    /// `D3D_SHADER_VARIABLE_TYPE`
+    pub base_type: u16,
+    pub rows: u16,
+    pub columns: u16,
+    /// Array length, 0 if the type is not an array
+    pub elements: u16,
+    pub members: Vec<DxbcTypeMember>,
+    /// Only stored by SM5 compilers
+    pub name: Option<String>,
+}
 
-    Err(anyhow!("Could not find ISGN chunk"))
+#[derive(Debug, Clone)]
+pub struct DxbcTypeMember {
+    pub name: String,
+    pub offset: u32,
+    pub ty: DxbcType,
+}
+
+impl DxbcType {
+    pub fn base_type_name(&self) -> &'static str {
+        match self.base_type {
+            0 => "void",
+            1 => "bool",
+            2 => "int",
+            3 => "float",
+            19 => "uint",
+            20 => "uint8",
+            39 => "double",
+            58 => "min16float",
+            60 => "min16int",
+            61 => "min16uint",
+            _ => "unknown",
+        }
+    }
+}
+
+impl Display for DxbcType {
+    /// HLSL type name, without the array size
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.class {
+            DxbcVariableClass::Scalar => f.write_str(self.base_type_name()),
+            DxbcVariableClass::Vector => {
+                write!(f, "{}{}", self.base_type_name(), self.columns)
+            }
+            DxbcVariableClass::MatrixRows | DxbcVariableClass::MatrixColumns => {
+                write!(f, "{}{}x{}", self.base_type_name(), self.rows, self.columns)
+            }
+            _ => f.write_str(self.name.as_deref().unwrap_or("struct")),
+        }
+    }
+}
+
+#[derive(BinRead)]
+struct RdefHeader {
+    constant_buffer_count: u32,
+    constant_buffer_offset: u32,
+    binding_count: u32,
+    binding_offset: u32,
+    minor_version: u8,
+    major_version: u8,
+    _program_type: u16,
+    flags: u32,
+    creator_offset: u32,
+}
+
+#[derive(BinRead)]
+struct RdefBinding {
+    name_offset: u32,
+    input_type: DxbcShaderInputType,
+    return_type: u32,
+    dimension: DxbcResourceDimension,
+    sample_count: u32,
+    bind_point: u32,
+    bind_count: u32,
+    flags: u32,
+}
+
+#[derive(BinRead)]
+struct RdefConstantBuffer {
+    name_offset: u32,
+    variable_count: u32,
+    variable_offset: u32,
+    size: u32,
+    flags: u32,
+    buffer_type: u32,
+}
+
+#[derive(BinRead)]
+struct RdefVariable {
+    name_offset: u32,
+    start_offset: u32,
+    size: u32,
+    flags: u32,
+    type_offset: u32,
+    _default_value_offset: u32,
+}
+
+#[derive(BinRead)]
+struct RdefType {
+    class: DxbcVariableClass,
+    base_type: u16,
+    rows: u16,
+    columns: u16,
+    elements: u16,
+    member_count: u16,
+    member_offset: u32,
+}
+
+#[derive(BinRead)]
+struct RdefMember {
+    name_offset: u32,
+    type_offset: u32,
+    offset: u32,
+}
+
+/// Size of a variable entry, SM5 adds texture and sampler ranges
+const RDEF_VARIABLE_SIZE: [u64; 2] = [24, 40];
+const RDEF_MAX_TYPE_DEPTH: usize = 8;
+
+impl DxbcResourceDefinitions {
+    fn read<R: Read + Seek>(reader: &mut R, header: &DxbcHeader) -> anyhow::Result<Option<Self>> {
+        if !find_chunk(reader, header, b"RDEF")? {
+            return Ok(None);
+        }
+
+        let chunk_size = read_chunk_size(reader)?;
+        // All offsets in the chunk are relative to the start of its data
+        let base = reader.stream_position()?;
+        let rdef: RdefHeader = reader.read_le()?;
+        let sm5 = (rdef.major_version >= 5) as usize;
+
+        let mut bindings = Vec::with_capacity(checked_count(rdef.binding_count, 32, chunk_size)?);
+        for i in 0..rdef.binding_count as u64 {
+            reader.seek(SeekFrom::Start(base + rdef.binding_offset as u64 + i * 32))?;
+            let b: RdefBinding = reader.read_le()?;
+            bindings.push(DxbcResourceBinding {
+                name: read_string(reader, base, b.name_offset)?,
+                input_type: b.input_type,
+                return_type: b.return_type,
+                dimension: b.dimension,
+                sample_count: b.sample_count,
+                bind_point: b.bind_point,
+                bind_count: b.bind_count,
+                flags: b.flags,
+            });
+        }
+
+        let mut constant_buffers =
+            Vec::with_capacity(checked_count(rdef.constant_buffer_count, 24, chunk_size)?);
+        for i in 0..rdef.constant_buffer_count as u64 {
+            reader.seek(SeekFrom::Start(
+                base + rdef.constant_buffer_offset as u64 + i * 24,
+            ))?;
+            let cb: RdefConstantBuffer = reader.read_le()?;
+
+            let mut variables = Vec::with_capacity(checked_count(
+                cb.variable_count,
+                RDEF_VARIABLE_SIZE[sm5],
+                chunk_size,
+            )?);
+            for v in 0..cb.variable_count as u64 {
+                reader.seek(SeekFrom::Start(
+                    base + cb.variable_offset as u64 + v * RDEF_VARIABLE_SIZE[sm5],
+                ))?;
+                let var: RdefVariable = reader.read_le()?;
+                variables.push(DxbcVariable {
+                    name: read_string(reader, base, var.name_offset)?,
+                    offset: var.start_offset,
+                    size: var.size,
+                    flags: var.flags,
+                    ty: read_type(reader, base, chunk_size, var.type_offset, sm5 == 1, 0)?,
+                });
+            }
+
+            constant_buffers.push(DxbcConstantBuffer {
+                name: read_string(reader, base, cb.name_offset)?,
+                size: cb.size,
+                flags: cb.flags,
+                buffer_type: cb.buffer_type,
+                variables,
+            });
+        }
+
+        Ok(Some(Self {
+            major_version: rdef.major_version,
+            minor_version: rdef.minor_version,
+            flags: rdef.flags,
+            creator: read_string(reader, base, rdef.creator_offset)?,
+            bindings,
+            constant_buffers,
+        }))
+    }
+
+    /// Register (b#) the given constant buffer is bound to
+    pub fn constant_buffer_slot(&self, cb: &DxbcConstantBuffer) -> Option<u32> {
+        self.bindings
+            .iter()
+            .find(|b| b.input_type == DxbcShaderInputType::CBuffer && b.name == cb.name)
+            .map(|b| b.bind_point)
+    }
+
+    pub fn bindings_of_type(
+        &self,
+        input_type: DxbcShaderInputType,
+    ) -> impl Iterator<Item = &DxbcResourceBinding> {
+        self.bindings
+            .iter()
+            .filter(move |b| b.input_type == input_type)
+    }
+}
+
+fn read_string<R: Read + Seek>(reader: &mut R, base: u64, offset: u32) -> anyhow::Result<String> {
+    reader.seek(SeekFrom::Start(base + offset as u64))?;
+    Ok(reader.read_le::<NullString>()?.to_string())
+}
+
+fn read_type<R: Read + Seek>(
+    reader: &mut R,
+    base: u64,
+    chunk_size: u32,
+    offset: u32,
+    sm5: bool,
+    depth: usize,
+) -> anyhow::Result<DxbcType> {
+    ensure!(depth < RDEF_MAX_TYPE_DEPTH, "Type nesting is too deep");
+
+    reader.seek(SeekFrom::Start(base + offset as u64))?;
+    let ty: RdefType = reader.read_le()?;
+
+    let name = if sm5 {
+        reader.seek(SeekFrom::Current(16))?;
+        let name_offset: u32 = reader.read_le()?;
+        (name_offset != 0)
+            .then(|| read_string(reader, base, name_offset))
+            .transpose()?
+    } else {
+        None
+    };
+
+    let mut members = Vec::with_capacity(checked_count(ty.member_count as u32, 12, chunk_size)?);
+    for i in 0..ty.member_count as u64 {
+        reader.seek(SeekFrom::Start(base + ty.member_offset as u64 + i * 12))?;
+        let m: RdefMember = reader.read_le()?;
+        members.push(DxbcTypeMember {
+            name: read_string(reader, base, m.name_offset)?,
+            offset: m.offset,
+            ty: read_type(reader, base, chunk_size, m.type_offset, sm5, depth + 1)?,
+        });
+    }
+
+    Ok(DxbcType {
+        class: ty.class,
+        base_type: ty.base_type,
+        rows: ty.rows,
+        columns: ty.columns,
+        elements: ty.elements,
+        members,
+        name,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a DXBC container from (magic, chunk data) pairs, chunk data starts after the chunk size
+    pub(crate) fn container(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let header_size = 32 + 4 * chunks.len();
+        let mut offsets = vec![];
+        let mut body = vec![];
+        for (magic, data) in chunks {
+            offsets.push((header_size + body.len()) as u32);
+            body.extend(*magic);
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(data);
+        }
+
+        let mut dxbc = b"DXBC".to_vec();
+        dxbc.extend([0; 16]);
+        dxbc.extend(1u32.to_le_bytes());
+        dxbc.extend(((header_size + body.len()) as u32).to_le_bytes());
+        dxbc.extend((chunks.len() as u32).to_le_bytes());
+        for offset in offsets {
+            dxbc.extend(offset.to_le_bytes());
+        }
+        dxbc.extend(body);
+        dxbc
+    }
+
+    /// Data of a SM5 SHEX chunk with the given instruction tokens
+    pub(crate) fn program_chunk(program_type: DxbcProgramType, tokens: &[u32]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(((program_type as u32) << 16 | 0x50).to_le_bytes());
+        data.extend((tokens.len() as u32 + 2).to_le_bytes());
+        for t in tokens {
+            data.extend(t.to_le_bytes());
+        }
+        data
+    }
+
+    /// Data of a signature chunk from (name, index, register, mask) tuples, every component is read/written
+    pub(crate) fn signature_chunk(elements: &[(&str, u32, u32, u8)]) -> Vec<u8> {
+        let elements = elements
+            .iter()
+            .map(|&(name, index, register, mask)| (name, index, register, mask, mask))
+            .collect::<Vec<_>>();
+        signature_chunk_rw(&elements)
+    }
+
+    /// Data of a signature chunk from (name, index, register, mask, read/write mask) tuples
+    pub(crate) fn signature_chunk_rw(elements: &[(&str, u32, u32, u8, u8)]) -> Vec<u8> {
+        let strings_offset = 8 + 24 * elements.len();
+        let mut strings = vec![];
+        let mut data = vec![];
+        data.extend((elements.len() as u32).to_le_bytes());
+        data.extend(8u32.to_le_bytes());
+        for (name, index, register, mask, rw_mask) in elements {
+            // Names are relative to the start of the chunk data
+            data.extend(((strings_offset + strings.len()) as u32).to_le_bytes());
+            strings.extend(name.as_bytes());
+            strings.push(0);
+
+            data.extend(index.to_le_bytes());
+            data.extend(0u32.to_le_bytes());
+            data.extend(3u32.to_le_bytes());
+            data.extend(register.to_le_bytes());
+            data.extend([*mask, *rw_mask, 0, 0]);
+        }
+        data.extend(strings);
+        data
+    }
+
+    fn signature(elements: &[(&str, u32, u32, u8)]) -> DxbcSignature {
+        let data = signature_chunk(elements);
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(data);
+        Cursor::new(chunk).read_le().unwrap()
+    }
+
+    /// Data of an RDEF chunk with a single constant buffer bound to `slot`
+    fn rdef_chunk(binding_count: u32, slot: u32) -> Vec<u8> {
+        let mut data = vec![];
+        for v in [1, 60, binding_count, 28] {
+            data.extend(u32::to_le_bytes(v));
+        }
+        data.extend([0, 5, 0, 0]);
+        data.extend(0u32.to_le_bytes());
+        data.extend(88u32.to_le_bytes());
+
+        // Binding: name, type, return type, dimension, samples, bind point, bind count, flags
+        for v in [84, 0, 0, 0, 0, slot, 1, 0] {
+            data.extend(u32::to_le_bytes(v));
+        }
+        // Constant buffer: name, variable count and offset, size, flags, type
+        for v in [84, 0, 0, 16, 0, 0] {
+            data.extend(u32::to_le_bytes(v));
+        }
+        data.extend(b"cb0\0alkahest\0");
+        data
+    }
+
+    fn read_bytecode(dxbc: &[u8]) -> anyhow::Result<Option<DxbcBytecode>> {
+        let mut cur = Cursor::new(dxbc);
+        let header: DxbcHeader = cur.read_le()?;
+        DxbcBytecode::read(&mut cur, &header)
+    }
+
+    #[test]
+    fn bytecode_reads_tokens() {
+        let dxbc = container(&[(b"SHEX", program_chunk(DxbcProgramType::Vertex, &[1, 2, 3]))]);
+        let bytecode = DxbcShader::parse(&dxbc).unwrap().bytecode.unwrap();
+
+        assert_eq!(bytecode.program_type, DxbcProgramType::Vertex);
+        assert_eq!((bytecode.major_version, bytecode.minor_version), (5, 0));
+        assert_eq!(bytecode.tokens, [1, 2, 3]);
+    }
+
+    #[test]
+    fn bytecode_length_is_bounded_by_the_chunk() {
+        let mut data = program_chunk(DxbcProgramType::Pixel, &[1]);
+        data[4..8].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+        assert!(read_bytecode(&container(&[(b"SHEX", data)])).is_err());
+
+        let mut data = program_chunk(DxbcProgramType::Pixel, &[]);
+        data[4..8].copy_from_slice(&1u32.to_le_bytes());
+        assert!(read_bytecode(&container(&[(b"SHEX", data)])).is_err());
+    }
+
+    #[test]
+    fn chunk_size_is_bounded_by_the_data() {
+        let mut dxbc = container(&[(b"SHEX", program_chunk(DxbcProgramType::Pixel, &[1, 2]))]);
+        dxbc.truncate(dxbc.len() - 4);
+        assert!(read_bytecode(&dxbc).is_err());
+    }
+
+    #[test]
+    fn rdef_reads_constant_buffer_slot() {
+        let dxbc = container(&[(b"RDEF", rdef_chunk(1, 2))]);
+        let rdef = DxbcShader::parse(&dxbc).unwrap().resources.unwrap();
+
+        assert_eq!(rdef.creator, "alkahest");
+        assert_eq!(rdef.bindings[0].name, "cb0");
+        assert_eq!(rdef.constant_buffers[0].size, 16);
+        assert_eq!(
+            rdef.constant_buffer_slot(&rdef.constant_buffers[0]),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn rdef_counts_are_bounded_by_the_chunk() {
+        let dxbc = container(&[(b"RDEF", rdef_chunk(1_000_000, 0))]);
+        let mut cur = Cursor::new(&dxbc);
+        let header: DxbcHeader = cur.read_le().unwrap();
+        assert!(DxbcResourceDefinitions::read(&mut cur, &header).is_err());
+    }
+
+    #[test]
+    fn signature_count_is_bounded_by_the_chunk() {
+        let mut data = signature_chunk(&[("TEXCOORD", 0, 0, 0xf)]);
+        data[0..4].copy_from_slice(&0x1000_0000u32.to_le_bytes());
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(data);
+
+        let error = Cursor::new(chunk).read_le::<DxbcSignature>().unwrap_err();
+        assert!(error.to_string().contains("don't fit"), "{error}");
+    }
+
+    #[test]
+    fn signature_parses_elements() {
+        let s = signature(&[("SV_POSITION", 0, 0, 0xf), ("TEXCOORD", 1, 1, 0x3)]);
+        assert_eq!(s.elements.len(), 2);
+        assert_eq!(s.elements[1].semantic_name.to_string(), "TEXCOORD");
+        assert_eq!(s.elements[1].semantic_index, 1);
+        assert_eq!(s.elements[1].register, 1);
+        assert_eq!(s.elements[1].component_mask, ComponentMask::XY);
+    }
+
+    #[test]
+    fn render_targets_are_counted() {
+        let gbuffer = signature(&[
+            ("SV_TARGET", 0, 0, 0xf),
+            ("SV_TARGET", 1, 1, 0xf),
+            ("SV_TARGET", 2, 2, 0xf),
+        ]);
+        assert_eq!(render_target_count(&gbuffer), 3);
+        assert_eq!(
+            render_target_count(&signature(&[("SV_DEPTH", 0, 0xffffffff, 1)])),
+            0
+        );
+    }
+
+    #[test]
+    fn texcoord0_register_from_output_signature() {
+        let first = signature(&[("TEXCOORD", 0, 0, 0xf), ("SV_POSITION", 0, 1, 0xf)]);
+        assert_eq!(texcoord0_register(&first), Some(0));
+
+        let after_position = signature(&[("SV_POSITION", 0, 0, 0xf), ("texcoord", 0, 1, 0x3)]);
+        assert_eq!(texcoord0_register(&after_position), Some(1));
+
+        // A single component can't hold UVs, and other texcoord sets don't count
+        assert_eq!(
+            texcoord0_register(&signature(&[("TEXCOORD", 0, 0, 0x1)])),
+            None
+        );
+        assert_eq!(
+            texcoord0_register(&signature(&[("TEXCOORD", 1, 0, 0xf)])),
+            None
+        );
+    }
 }
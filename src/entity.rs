@@ -1,12 +1,14 @@
+use crate::material::Unk808071e8;
+use crate::packages::package_manager;
 use crate::structure::{DeadBeefMarker, ResourcePointer, TablePointer, Tag};
 use crate::types::{Vector2, Vector4};
 
-use binrw::BinRead;
+use binrw::{BinRead, BinReaderExt};
 
 use destiny_pkg::TagHash;
 
 use std::cmp::Ordering;
-use std::io::SeekFrom;
+use std::io::{Cursor, Seek, SeekFrom};
 
 #[derive(BinRead, Debug)]
 pub struct Unk80809c0f {
@@ -31,6 +33,37 @@ pub struct Unk80809c36 {
     pub unk18: ResourcePointer,
 }
 
+/// Entity model resource (D2Class_BD728080), pointed to by [`Unk80809c36::unk18`]
+pub struct EntityModel {
+    pub model: Tag<Unk808073a5>,
+    pub material_map: TablePointer<Unk808072c5>,
+    pub materials: TablePointer<Tag<Unk808071e8>>,
+}
+
+impl EntityModel {
+    pub const RESOURCE_TYPE: u32 = 0x808072BD;
+
+    /// Returns `None` if the entity resource is not a model
+    pub fn read(resource: &Tag<Unk80809c36>) -> anyhow::Result<Option<Self>> {
+        if resource.unk18.resource_type != Self::RESOURCE_TYPE {
+            return Ok(None);
+        }
+
+        let mut cur = Cursor::new(package_manager().read_tag(resource.tag())?);
+        cur.seek(SeekFrom::Start(resource.unk18.offset + 0x1dc))?;
+        let model = cur.read_le()?;
+        cur.seek(SeekFrom::Start(resource.unk18.offset + 0x300))?;
+        let material_map = cur.read_le()?;
+        let materials = cur.read_le()?;
+
+        Ok(Some(Self {
+            model,
+            material_map,
+            materials,
+        }))
+    }
+}
+
 #[derive(BinRead, Debug)]
 pub struct Unk808073a5 {
     pub file_size: u64,
@@ -133,6 +166,20 @@ impl ELodCategory {
         }
     }
 
+    /// LOD level (0-3) this category belongs to
+    pub fn lod_level(&self) -> u8 {
+        match self {
+            ELodCategory::Lod_0_0
+            | ELodCategory::Lod_0_1
+            | ELodCategory::Lod_0_2
+            | ELodCategory::Lod_0_3
+            | ELodCategory::Lod_Detail => 0,
+            ELodCategory::Lod_1_0 => 1,
+            ELodCategory::Lod_2_0 | ELodCategory::Lod_2_1 => 2,
+            ELodCategory::Lod_3_0 => 3,
+        }
+    }
+
     pub fn is_highest_detail(&self) -> bool {
         matches!(
             self,
@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use crate::entity::{EPrimitiveType, IndexBufferHeader};
+
+/// Value restart indices are mapped to by [`read_indices`], for both 16 and 32-bit buffers
+pub const INDEX_RESTART: u32 = u32::MAX;
+
+/// Reads an index buffer, mapping restart indices to [`INDEX_RESTART`]
+pub fn read_indices(header: &IndexBufferHeader, data: &[u8]) -> Vec<u32> {
+    if header.is_32bit {
+        data.chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect()
+    } else {
+        data.chunks_exact(2)
+            .map(|c| match u16::from_le_bytes([c[0], c[1]]) {
+                u16::MAX => INDEX_RESTART,
+                i => i as u32,
+            })
+            .collect()
+    }
+}
+
+/// Indices of a single part, clamped to the size of the buffer
+pub fn part_indices(indices: &[u32], index_start: u32, index_count: u32) -> &[u32] {
+    let start = (index_start as usize).min(indices.len());
+    let end = (start + index_count as usize).min(indices.len());
+    &indices[start..end]
+}
+
+/// Converts a triangle list or strip to a list of triangles, skipping restarts and degenerate triangles.
+///
+/// Every other triangle of a strip has its first two indices swapped, so all triangles keep the winding of the
+/// first one (the same order D3D rasterizes them in). Degenerate triangles still count towards that, and a restart
+/// starts over with the original winding
+pub fn triangles(indices: &[u32], primitive_type: EPrimitiveType) -> Vec<[u32; 3]> {
+    let is_degenerate = |t: &[u32]| t[0] == t[1] || t[1] == t[2] || t[0] == t[2];
+
+    let mut triangles = vec![];
+    match primitive_type {
+        EPrimitiveType::Triangles => {
+            for t in indices.chunks_exact(3) {
+                if !t.contains(&INDEX_RESTART) && !is_degenerate(t) {
+                    triangles.push([t[0], t[1], t[2]]);
+                }
+            }
+        }
+        EPrimitiveType::TriangleStrip => {
+            for strip in indices.split(|&i| i == INDEX_RESTART) {
+                for (i, t) in strip.windows(3).enumerate() {
+                    if is_degenerate(t) {
+                        continue;
+                    }
+
+                    if i % 2 == 0 {
+                        triangles.push([t[0], t[1], t[2]]);
+                    } else {
+                        triangles.push([t[1], t[0], t[2]]);
+                    }
+                }
+            }
+        }
+    }
+
+    triangles
+}
+
+/// Range of the vertices referenced by the indices, ignoring restarts. Returns `None` if there are none
+pub fn vertex_range(indices: &[u32]) -> Option<Range<u32>> {
+    let mut used = indices.iter().copied().filter(|&i| i != INDEX_RESTART);
+    let first = used.next()?;
+    let (min, max) = used.fold((first, first), |(min, max), i| (min.min(i), max.max(i)));
+    Some(min..max + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use binrw::BinReaderExt;
+    use std::io::Cursor;
+
+    fn header(is_32bit: bool) -> IndexBufferHeader {
+        let mut data = vec![0u8, is_32bit as u8, 0, 0];
+        data.extend(0u32.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.extend(0xdeadbeefu32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        Cursor::new(data).read_le().unwrap()
+    }
+
+    fn read_u16(indices: &[u16]) -> Vec<u32> {
+        let data: Vec<u8> = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        read_indices(&header(false), &data)
+    }
+
+    #[test]
+    fn restart_index_16bit() {
+        assert_eq!(read_u16(&[0, 1, 0xFFFF, 2]), [0, 1, INDEX_RESTART, 2]);
+    }
+
+    #[test]
+    fn restart_index_32bit() {
+        // 0xFFFF is a regular index in 32-bit buffers
+        let data: Vec<u8> = [0u32, 0xFFFF, u32::MAX]
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        assert_eq!(
+            read_indices(&header(true), &data),
+            [0, 0xFFFF, INDEX_RESTART]
+        );
+    }
+
+    #[test]
+    fn strip_flips_odd_triangles() {
+        assert_eq!(
+            triangles(&[0, 1, 2, 3, 4], EPrimitiveType::TriangleStrip),
+            [[0, 1, 2], [2, 1, 3], [2, 3, 4]]
+        );
+    }
+
+    #[test]
+    fn strip_degenerate_triangles_keep_parity() {
+        assert_eq!(
+            triangles(&[0, 1, 1, 2, 3, 4], EPrimitiveType::TriangleStrip),
+            [[1, 2, 3], [3, 2, 4]]
+        );
+    }
+
+    #[test]
+    fn strip_restart_resets_winding() {
+        let indices = read_u16(&[0, 1, 2, 3, 0xFFFF, 4, 5, 6, 7]);
+        assert_eq!(
+            triangles(&indices, EPrimitiveType::TriangleStrip),
+            [[0, 1, 2], [2, 1, 3], [4, 5, 6], [6, 5, 7]]
+        );
+    }
+
+    #[test]
+    fn list_skips_restarts_and_degenerates() {
+        let indices = [0, 1, 2, 3, 3, 4, 5, INDEX_RESTART, 6, 7, 8, 9];
+        assert_eq!(
+            triangles(&indices, EPrimitiveType::Triangles),
+            [[0, 1, 2], [7, 8, 9]]
+        );
+    }
+
+    #[test]
+    fn part_indices_are_clamped() {
+        let indices = [0, 1, 2, 3];
+        assert_eq!(part_indices(&indices, 2, 8), [2, 3]);
+        assert!(part_indices(&indices, 10, 3).is_empty());
+    }
+
+    #[test]
+    fn vertex_range_ignores_restarts() {
+        assert_eq!(vertex_range(&[5, INDEX_RESTART, 2, 9]), Some(2..10));
+        assert_eq!(vertex_range(&[INDEX_RESTART]), None);
+    }
+}
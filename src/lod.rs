@@ -0,0 +1,230 @@
+use glam::Vec3;
+
+use crate::bounds::BoundingSphere;
+use crate::camera::{FpsCamera, ProjectionMode};
+
+pub const LOD_LEVELS: usize = 4;
+
+/// Largest LOD bias in either direction, each step halves or doubles the coverage thresholds
+pub const MAX_LOD_BIAS: f32 = 2.0;
+
+/// Minimum screen coverage (bounding sphere diameter relative to the viewport height) for LOD 0, 1 and 2. Anything smaller uses LOD 3
+const LOD_COVERAGE_THRESHOLDS: [f32; LOD_LEVELS - 1] = [0.25, 0.08, 0.02];
+
+pub struct LodSettings {
+    /// Positive values select lower detail levels earlier, like a mip bias
+    pub bias: f32,
+    /// Debug mode, always selects the given level (when available)
+    pub force_lod: Option<u8>,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            bias: 0.0,
+            force_lod: None,
+        }
+    }
+}
+
+/// Selects LOD levels by projected screen size for a given view
+pub struct LodSelector {
+    camera_position: Vec3,
+    perspective: bool,
+    /// Viewport height in world units, at a distance of 1 for perspective projections
+    view_height: f32,
+    bias_scale: f32,
+    force_lod: Option<u8>,
+}
+
+impl LodSelector {
+    pub fn new(settings: &LodSettings, camera: &FpsCamera) -> Self {
+        let perspective = camera.projection.mode == ProjectionMode::Perspective;
+        Self {
+            camera_position: camera.position,
+            perspective,
+            view_height: if perspective {
+                2.0 * (camera.projection.fov.to_radians() * 0.5).tan()
+            } else {
+                camera.projection.orthographic_height
+            },
+            bias_scale: 2f32.powf(-settings.bias.clamp(-MAX_LOD_BIAS, MAX_LOD_BIAS)),
+            force_lod: settings.force_lod,
+        }
+    }
+
+    /// Fraction of the viewport height covered by the sphere
+    pub fn screen_coverage(&self, sphere: &BoundingSphere) -> f32 {
+        let view_height = if self.perspective {
+            sphere
+                .center
+                .distance(self.camera_position)
+                .max(f32::EPSILON)
+                * self.view_height
+        } else {
+            self.view_height
+        };
+
+        (sphere.radius * 2.0) / view_height
+    }
+
+    pub fn select(&self, sphere: &BoundingSphere) -> u8 {
+        if let Some(level) = self.force_lod {
+            return level.min(LOD_LEVELS as u8 - 1);
+        }
+
+        let coverage = self.screen_coverage(sphere) * self.bias_scale;
+        LOD_COVERAGE_THRESHOLDS
+            .iter()
+            .position(|&t| coverage >= t)
+            .unwrap_or(LOD_LEVELS - 1) as u8
+    }
+}
+
+/// Returns the requested level if available, otherwise the closest more detailed level, and finally the closest less detailed one
+pub fn nearest_available(level: u8, available: &[bool; LOD_LEVELS]) -> Option<u8> {
+    let level = (level as usize).min(LOD_LEVELS - 1);
+    (0..=level)
+        .rev()
+        .chain(level + 1..LOD_LEVELS)
+        .find(|&l| available[l])
+        .map(|l| l as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::ELodCategory;
+
+    /// Orthographic view with a height of 1, so the coverage is the diameter of the sphere
+    fn selector(bias: f32, force_lod: Option<u8>) -> LodSelector {
+        let mut camera = FpsCamera::default();
+        camera.projection.mode = ProjectionMode::Orthographic;
+        camera.projection.orthographic_height = 1.0;
+
+        LodSelector::new(&LodSettings { bias, force_lod }, &camera)
+    }
+
+    fn sphere(radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: Vec3::new(0.0, 10.0, 0.0),
+            radius,
+        }
+    }
+
+    #[test]
+    fn coverage_thresholds() {
+        let selector = selector(0.0, None);
+        for (level, &threshold) in LOD_COVERAGE_THRESHOLDS.iter().enumerate() {
+            let at = sphere(threshold * 0.5);
+            assert_eq!(selector.screen_coverage(&at), threshold);
+            assert_eq!(selector.select(&at), level as u8);
+
+            let below = sphere(threshold * 0.5 * 0.99);
+            assert_eq!(selector.select(&below), level as u8 + 1);
+        }
+
+        assert_eq!(selector.select(&sphere(10.0)), 0);
+        assert_eq!(selector.select(&sphere(0.0)), LOD_LEVELS as u8 - 1);
+    }
+
+    #[test]
+    fn perspective_coverage_scales_with_distance() {
+        let camera = FpsCamera::default();
+        let selector = LodSelector::new(&LodSettings::default(), &camera);
+
+        // 90 degree FOV, so the viewport is twice as high as the distance to the camera
+        let near = BoundingSphere {
+            center: Vec3::new(0.0, 10.0, 0.0),
+            radius: 2.5,
+        };
+        let far = BoundingSphere {
+            center: Vec3::new(0.0, 20.0, 0.0),
+            ..near
+        };
+        assert!((selector.screen_coverage(&near) - 0.25).abs() < 1e-5);
+        assert!((selector.screen_coverage(&far) - 0.125).abs() < 1e-5);
+        assert_eq!(selector.select(&far), 1);
+    }
+
+    #[test]
+    fn bias_scales_thresholds() {
+        let at_lod0 = sphere(LOD_COVERAGE_THRESHOLDS[0] * 0.5);
+        assert_eq!(selector(1.0, None).select(&at_lod0), 1);
+        assert_eq!(selector(-1.0, None).select(&at_lod0), 0);
+
+        let at_lod2 = sphere(LOD_COVERAGE_THRESHOLDS[2] * 0.5);
+        assert_eq!(selector(-1.0, None).select(&at_lod2), 2);
+        assert_eq!(selector(-2.0, None).select(&at_lod2), 1);
+    }
+
+    #[test]
+    fn bias_is_clamped() {
+        for radius in [0.001, 0.01, 0.03, 0.1, 0.2] {
+            let s = sphere(radius);
+            assert_eq!(
+                selector(100.0, None).select(&s),
+                selector(MAX_LOD_BIAS, None).select(&s)
+            );
+            assert_eq!(
+                selector(-100.0, None).select(&s),
+                selector(-MAX_LOD_BIAS, None).select(&s)
+            );
+        }
+
+        // Would be LOD 3 with an unclamped bias of 5
+        assert_eq!(selector(5.0, None).select(&sphere(0.125)), 2);
+    }
+
+    #[test]
+    fn forced_level() {
+        for level in 0..LOD_LEVELS as u8 {
+            let selector = selector(0.0, Some(level));
+            assert_eq!(selector.select(&sphere(0.0)), level);
+            assert_eq!(selector.select(&sphere(10.0)), level);
+        }
+
+        assert_eq!(
+            selector(0.0, Some(200)).select(&sphere(1.0)),
+            LOD_LEVELS as u8 - 1
+        );
+    }
+
+    #[test]
+    fn nearest_available_prefers_more_detail() {
+        let all = [true; LOD_LEVELS];
+        assert_eq!(nearest_available(2, &all), Some(2));
+
+        // Missing levels fall back to more detailed ones first
+        assert_eq!(nearest_available(2, &[true, false, false, true]), Some(0));
+        assert_eq!(nearest_available(3, &[false, true, false, false]), Some(1));
+        // Then to less detailed ones
+        assert_eq!(nearest_available(0, &[false, false, true, true]), Some(2));
+        assert_eq!(nearest_available(1, &[false, false, false, true]), Some(3));
+
+        // Out of range levels are treated as the lowest detail level
+        assert_eq!(nearest_available(9, &[false, false, true, false]), Some(2));
+        assert_eq!(nearest_available(0, &[false; LOD_LEVELS]), None);
+    }
+
+    #[test]
+    fn lod_category_levels() {
+        let levels = [
+            (ELodCategory::Lod_0_0, 0),
+            (ELodCategory::Lod_0_1, 0),
+            (ELodCategory::Lod_0_2, 0),
+            (ELodCategory::Lod_0_3, 0),
+            (ELodCategory::Lod_Detail, 0),
+            (ELodCategory::Lod_1_0, 1),
+            (ELodCategory::Lod_2_0, 2),
+            (ELodCategory::Lod_2_1, 2),
+            (ELodCategory::Lod_3_0, 3),
+        ];
+
+        for (category, level) in levels {
+            assert_eq!(category.lod_level(), level, "{category:?}");
+            assert_eq!(category.is_highest_detail(), level == 0, "{category:?}");
+            assert!((category.lod_level() as usize) < LOD_LEVELS);
+        }
+    }
+}
@@ -3,7 +3,7 @@ extern crate windows;
 
 use std::cell::RefCell;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -15,8 +15,9 @@ use anyhow::Context;
 use binrw::BinReaderExt;
 use destiny_pkg::PackageVersion::Destiny2PreBeyondLight;
 use destiny_pkg::{PackageManager, TagHash};
-use glam::{Mat4, Quat, Vec3, Vec4};
+use glam::{Mat4, UVec2, Vec2, Vec4};
 use itertools::Itertools;
+use rayon::prelude::*;
 use nohash_hasher::IntMap;
 
 use strum::EnumCount;
@@ -24,75 +25,109 @@ use tracing::level_filters::LevelFilter;
 use tracing::{debug, debug_span, error, info, info_span, trace, warn};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::EnvFilter;
-use windows::Win32::Graphics::Direct3D::Fxc::{
-    D3DCompileFromFile, D3DCOMPILE_DEBUG, D3DCOMPILE_SKIP_OPTIMIZATION,
-};
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
 use winit::dpi::{PhysicalPosition, PhysicalSize};
-use winit::event::VirtualKeyCode;
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
 use winit::{
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
 };
 
+use crate::bounds::BoundingSphere;
 use crate::camera::FpsCamera;
 use crate::config::{WindowConfig, CONFIGURATION};
-use crate::dxbc::{get_input_signature, DxbcHeader, DxbcInputType};
+use crate::culling::{CullingSettings, CullingStats, ViewCuller};
+use crate::lod::{nearest_available, LodSelector, LodSettings};
+use crate::dxbc::{render_target_count, texcoord0_register, DxbcShader};
 use crate::dxgi::calculate_pitch;
-use crate::entity::{Unk808072c5, Unk808073a5, Unk80809c0f};
+use crate::decompiler::{decompile, decompile_to_directory};
+use crate::disassembler::disassemble;
+use crate::entity::{EntityModel, Unk80809c0f};
 use crate::input::InputState;
 use crate::map::{MapData, MapDataList, Unk80806ef4, Unk8080714f, Unk80807dae, Unk80808a54};
-use crate::map_resources::{MapResource, Unk80806b7f, Unk80806e68, Unk8080714b};
-use crate::material::{Material, Unk808071e8};
+use crate::map_resources::{
+    MapResource, PointLight, PointLightTag, Unk80806b7f, Unk80806e68, Unk8080714b,
+    DEFAULT_LIGHT_RADIUS,
+};
+use crate::material::Material;
+use crate::material_patch::{apply_material_patch, MaterialPatches};
+use crate::overlays::camera_path::CameraPathOverlay;
 use crate::overlays::camera_settings::CameraPositionOverlay;
 use crate::overlays::console::ConsoleOverlay;
+use crate::overlays::debug_text::DebugTextOverlay;
 use crate::overlays::fps_display::FpsDisplayOverlay;
 use crate::overlays::gbuffer_viewer::{
-    CompositorMode, CompositorOptions, GBufferInfoOverlay, COMPOSITOR_MODES,
+    CompositionMode, CompositorMode, CompositorOptions, GBufferInfoOverlay,
 };
 use crate::overlays::gui::GuiManager;
+use crate::overlays::material_inspector::MaterialInspectorOverlay;
 use crate::overlays::resource_nametags::{ResourcePoint, ResourceTypeOverlay};
-use crate::packages::{package_manager, PACKAGE_MANAGER};
-use crate::render::scopes::ScopeRigidModel;
+use crate::overlays::selection::SelectionOverlay;
+use crate::overlays::shader_viewer::ShaderViewerOverlay;
+use crate::packages::{initialize_package_manager, package_manager};
+use crate::picking::{Ray, Selection};
 use crate::render::static_render::StaticModel;
 use crate::render::terrain::TerrainRenderer;
+use crate::render::entity::EntityInstance;
+use crate::render::decals::Decal;
+use crate::render::compositor::CompositorShaders;
+use crate::render::cubemaps::{CubemapRenderer, CubemapVolume};
+use crate::render::debug_draw::{DebugDrawRenderer, DebugShapes};
+use crate::render::debug_shaders::GeometryView;
+use crate::render::vertex_input::VertexShader;
+use crate::render::lights::{LightBuffers, ShaderLight};
 use crate::render::{
-    ConstantBuffer, DeviceContextSwapchain, EntityRenderer, GBuffer, InstancedRenderer, RenderData,
+    ConstantBuffer, DebugShaders, DecalRenderer, DeviceContextSwapchain, DrawStats,
+    EntityRenderer, GBuffer, InstancedRenderer, PassDrawLists, RenderData,
 };
 use crate::resources::Resources;
+use crate::sampler::SamplerDesc;
 use crate::statics::{Unk808071a7, Unk8080966d};
-use crate::structure::{TablePointer, Tag};
 use crate::text::{decode_text, StringData, StringPart, StringSetHeader};
 use crate::texture::{Texture, TextureHandle, TextureHeader};
+use crate::transform::Transform;
 use crate::types::Vector4;
-use crate::vertex_layout::InputElement;
+use crate::vertex_layout::ShaderInput;
+use crate::vertex_stream::MaterialShaderInputs;
 use render::scopes::ScopeView;
 use crate::overlays::package_dump::PackageDumper;
 
+mod bounds;
 mod camera;
+mod camera_path;
 mod config;
+mod culling;
 mod dds;
+mod decompiler;
+mod disassembler;
 mod dxbc;
 mod dxgi;
 mod entity;
 mod icons;
+mod index_buffer;
 mod input;
+mod lod;
 mod map;
 mod map_resources;
 mod material;
+mod material_patch;
 mod overlays;
 mod packages;
+mod picking;
 mod render;
 mod resources;
+mod sampler;
 mod statics;
 mod structure;
 mod text;
 mod texture;
+mod transform;
 mod types;
 mod unknown;
 mod vertex_layout;
+mod vertex_stream;
 
 pub fn main() -> anyhow::Result<()> {
     rayon::ThreadPoolBuilder::new()
@@ -141,7 +176,33 @@ pub fn main() -> anyhow::Result<()> {
         )
     });
 
-    PACKAGE_MANAGER.with(|v| *v.borrow_mut() = Some(Rc::new(pm)));
+    initialize_package_manager(pm);
+
+    // `alkahest <package> --disassemble/--decompile <shader tag>` prints the shader and exits
+    let args = std::env::args().collect_vec();
+    let flag_value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .map(|i| args.get(i + 1).with_context(|| format!("{flag} needs an argument")))
+            .transpose()
+    };
+    let shader_tag = |tag: &str| {
+        u32::from_str_radix(tag.trim_start_matches("0x"), 16)
+            .map(TagHash)
+            .context("Shader tag should be a hexadecimal tag hash")
+    };
+    if let Some(tag) = flag_value("--disassemble")? {
+        println!("{}", disassemble(&DxbcShader::load(shader_tag(tag)?)?)?);
+        return Ok(());
+    }
+    if let Some(tag) = flag_value("--decompile")? {
+        println!("{}", decompile(&DxbcShader::load(shader_tag(tag)?)?, None)?);
+        return Ok(());
+    }
+
+    // `--decompile-all <directory>` writes every shader used by the maps in the package and exits
+    // once the materials are parsed, before any window or device is created
+    let decompile_directory = flag_value("--decompile-all")?.map(PathBuf::from);
 
     let mut stringmap: IntMap<u32, String> = Default::default();
     let all_global_packages = [
@@ -186,254 +247,238 @@ pub fn main() -> anyhow::Result<()> {
 
     info!("Loaded {} global strings", stringmap.len());
 
-    let event_loop = EventLoop::new();
-    let window = winit::window::WindowBuilder::new()
-        .with_title("Alkahest")
-        .with_inner_size(config::with(|c| {
-            PhysicalSize::new(c.window.width, c.window.height)
-        }))
-        .with_position(config::with(|c| {
-            PhysicalPosition::new(c.window.pos_x, c.window.pos_y)
-        }))
-        .with_maximized(config!().window.maximised)
-        .build(&event_loop)?;
-
-    // cohae: Slight concern for thread safety here. ID3D11Device is threadsafe, but ID3D11DeviceContext is *not*
-    let dcs = Rc::new(DeviceContextSwapchain::create(&window)?);
-    let mut gbuffer = GBuffer::create(
-        (window.inner_size().width, window.inner_size().height),
-        dcs.clone(),
-    )?;
-
     let mut static_map: IntMap<u32, Arc<StaticModel>> = Default::default();
     let mut material_map: IntMap<u32, Material> = Default::default();
-    let mut vshader_map: IntMap<u32, (ID3D11VertexShader, Option<ID3D11InputLayout>)> =
-        Default::default();
-    let mut pshader_map: IntMap<u32, ID3D11PixelShader> = Default::default();
+    let mut vshader_map: IntMap<u32, VertexShader> = Default::default();
+    let mut pshader_map: IntMap<u32, (ID3D11PixelShader, Option<u32>)> = Default::default();
     let mut cbuffer_map_vs: IntMap<u32, ConstantBuffer<Vector4>> = Default::default();
     let mut cbuffer_map_ps: IntMap<u32, ConstantBuffer<Vector4>> = Default::default();
     let mut texture_map: IntMap<u32, Texture> = Default::default();
     let mut sampler_map: IntMap<u32, ID3D11SamplerState> = Default::default();
-    let mut terrain_headers = vec![];
-    let mut maps: Vec<MapData> = vec![];
-
-    // First light reserved for camera light
-    let mut point_lights = vec![Vec4::ZERO];
-    for (index, _) in package.get_all_by_reference(0x80807dae) {
-        let think: Unk80807dae = package_manager()
-            .read_tag_struct((package.pkg_id(), index as _))
-            .unwrap();
-
-        let mut placement_groups = vec![];
-        let mut resource_points = vec![];
-        let mut terrains = vec![];
-        for res in &think.child_map.map_resources {
-            let thing2: Unk80808a54 = if res.is_hash32 != 0 {
-                package_manager().read_tag_struct(res.hash32).unwrap()
-            } else {
-                package_manager().read_tag64_struct(res.hash64.0).unwrap()
-            };
-
-            for table in &thing2.data_tables {
-                let table_data = package_manager().read_tag(table.tag()).unwrap();
-                let mut cur = Cursor::new(&table_data);
-
-                for data in &table.data_entries {
-                    if data.data_resource.is_valid {
-                        match data.data_resource.resource_type {
-                            // D2Class_C96C8080 (placement)
-                            0x808071b3 => {
-                                cur.seek(SeekFrom::Start(data.data_resource.offset + 16))
-                                    .unwrap();
-                                let preheader_tag: TagHash = cur.read_le().unwrap();
-                                let preheader: Unk80806ef4 =
-                                    package_manager().read_tag_struct(preheader_tag).unwrap();
 
-                                placement_groups.push(preheader.placement_group);
-                            }
-                            // D2Class_7D6C8080 (terrain)
-                            0x8080714b => {
-                                cur.seek(SeekFrom::Start(data.data_resource.offset))
-                                    .unwrap();
+    /// Contents of a map tag, parsed on the rayon pool
+    struct LoadedMap {
+        data: MapData,
+        terrain_headers: Vec<(TagHash, Unk8080714f)>,
+        materials: Vec<TagHash>,
+        light_count: usize,
+        plausible_light_count: usize,
+    }
 
-                                let terrain_resource: Unk8080714b = cur.read_le().unwrap();
-                                let terrain: Unk8080714f = package_manager()
-                                    .read_tag_struct(terrain_resource.terrain)
-                                    .unwrap();
+    let load_start = Instant::now();
+    let pkg_id = package.pkg_id();
+    let map_indices = package
+        .get_all_by_reference(0x80807dae)
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect_vec();
+    let loaded_maps: Vec<LoadedMap> = info_span!("Loading maps").in_scope(|| {
+        map_indices
+            .into_par_iter()
+            .map(|index| {
+                let think: Unk80807dae = package_manager()
+                    .read_tag_struct((pkg_id, index as _))
+                    .unwrap();
+
+                let mut placement_groups = vec![];
+                let mut resource_points = vec![];
+                let mut terrains = vec![];
+                let mut terrain_headers = vec![];
+                let mut materials = vec![];
+                let mut light_count = 0;
+                let mut plausible_light_count = 0;
+                for res in &think.child_map.map_resources {
+                    let thing2: Unk80808a54 = if res.is_hash32 != 0 {
+                        package_manager().read_tag_struct(res.hash32).unwrap()
+                    } else {
+                        package_manager().read_tag64_struct(res.hash64.0).unwrap()
+                    };
 
-                                for p in &terrain.mesh_parts {
-                                    if p.material.is_valid() {
-                                        material_map.insert(
-                                            p.material.0,
-                                            Material(
-                                                package_manager().read_tag_struct(p.material)?,
-                                                p.material,
-                                            ),
-                                        );
+                    for table in &thing2.data_tables {
+                        let table_data = package_manager().read_tag(table.tag()).unwrap();
+                        let mut cur = Cursor::new(&table_data);
+
+                        for data in &table.data_entries {
+                            if data.data_resource.is_valid {
+                                match data.data_resource.resource_type {
+                                    // D2Class_C96C8080 (placement)
+                                    0x808071b3 => {
+                                        cur.seek(SeekFrom::Start(data.data_resource.offset + 16))
+                                            .unwrap();
+                                        let preheader_tag: TagHash = cur.read_le().unwrap();
+                                        let preheader: Unk80806ef4 = package_manager()
+                                            .read_tag_struct(preheader_tag)
+                                            .unwrap();
+
+                                        placement_groups.push(preheader.placement_group);
                                     }
-                                }
-
-                                terrain_headers.push((terrain_resource.terrain, terrain));
-                                terrains.push(terrain_resource.terrain);
-                            }
-                            // Cubemap volume
-                            0x80806b7f => {
-                                cur.seek(SeekFrom::Start(data.data_resource.offset))
-                                    .unwrap();
-
-                                let cubemap_volume: Unk80806b7f = cur.read_le().unwrap();
-                                resource_points.push(ResourcePoint {
-                                    translation: Vec4::new(
-                                        data.translation.x,
-                                        data.translation.y,
-                                        data.translation.z,
-                                        data.translation.w,
-                                    ),
-                                    rotation: Quat::from_xyzw(
-                                        data.rotation.x,
-                                        data.rotation.y,
-                                        data.rotation.z,
-                                        data.rotation.w,
-                                    ),
-                                    entity: data.entity,
-                                    resource_type: data.data_resource.resource_type,
-                                    resource: MapResource::CubemapVolume(Box::new(cubemap_volume)),
-                                });
-                            }
-                            // Point light
-                            0x80806cbf => {
-                                cur.seek(SeekFrom::Start(data.data_resource.offset + 16))
-                                    .unwrap();
-                                let tag: TagHash = cur.read_le().unwrap();
-                                resource_points.push(ResourcePoint {
-                                    translation: Vec4::new(
-                                        data.translation.x,
-                                        data.translation.y,
-                                        data.translation.z,
-                                        data.translation.w,
-                                    ),
-                                    rotation: Quat::from_xyzw(
-                                        data.rotation.x,
-                                        data.rotation.y,
-                                        data.rotation.z,
-                                        data.rotation.w,
-                                    ),
-                                    entity: data.entity,
-                                    resource_type: data.data_resource.resource_type,
-                                    resource: MapResource::PointLight(tag),
-                                });
-                                point_lights.push(Vec4::new(
-                                    data.translation.x,
-                                    data.translation.y,
-                                    data.translation.z,
-                                    data.translation.w,
-                                ));
-                            }
-                            // Decal collection
-                            0x80806e62 => {
-                                cur.seek(SeekFrom::Start(data.data_resource.offset + 16))
-                                    .unwrap();
-                                let tag: TagHash = cur.read_le().unwrap();
-                                if !tag.is_valid() {
-                                    continue;
-                                }
+                                    // D2Class_7D6C8080 (terrain)
+                                    0x8080714b => {
+                                        cur.seek(SeekFrom::Start(data.data_resource.offset))
+                                            .unwrap();
+
+                                        let terrain_resource: Unk8080714b = cur.read_le().unwrap();
+                                        let terrain: Unk8080714f = package_manager()
+                                            .read_tag_struct(terrain_resource.terrain)
+                                            .unwrap();
+
+                                        for p in &terrain.mesh_parts {
+                                            if p.material.is_valid() {
+                                                materials.push(p.material);
+                                            }
+                                        }
+
+                                        terrain_headers.push((terrain_resource.terrain, terrain));
+                                        terrains.push(terrain_resource.terrain);
+                                    }
+                                    // Cubemap volume
+                                    0x80806b7f => {
+                                        cur.seek(SeekFrom::Start(data.data_resource.offset))
+                                            .unwrap();
 
-                                let header: Unk80806e68 =
-                                    package_manager().read_tag_struct(tag).unwrap();
+                                        let cubemap_volume: Unk80806b7f = cur.read_le().unwrap();
+                                        resource_points.push(ResourcePoint {
+                                            transform: Transform::from(data),
+                                            entity: data.entity,
+                                            resource_type: data.data_resource.resource_type,
+                                            resource: MapResource::CubemapVolume(Box::new(
+                                                cubemap_volume,
+                                            )),
+                                        });
+                                    }
+                                    // Point light
+                                    0x80806cbf => {
+                                        cur.seek(SeekFrom::Start(data.data_resource.offset + 16))
+                                            .unwrap();
+                                        let tag: TagHash = cur.read_le().unwrap();
+                                        let light_tag: PointLightTag =
+                                            match package_manager().read_tag_struct(tag) {
+                                                Ok(l) => l,
+                                                Err(e) => {
+                                                    warn!("Failed to read light tag {tag:?}: {e}");
+                                                    continue;
+                                                }
+                                            };
+
+                                        light_count += 1;
+                                        if light_tag.is_plausible() {
+                                            plausible_light_count += 1;
+                                        }
 
-                                for inst in &header.instances {
-                                    for i in inst.start..(inst.start + inst.count) {
-                                        let transform = header.transforms[i as usize];
                                         resource_points.push(ResourcePoint {
-                                            translation: Vec4::new(
-                                                transform.x,
-                                                transform.y,
-                                                transform.z,
-                                                transform.w,
-                                            ),
-                                            rotation: Quat::from_xyzw(
-                                                data.rotation.x,
-                                                data.rotation.y,
-                                                data.rotation.z,
-                                                data.rotation.w,
-                                            ),
+                                            transform: Transform::from(data),
                                             entity: data.entity,
                                             resource_type: data.data_resource.resource_type,
-                                            resource: MapResource::Decal {
-                                                material: inst.material,
-                                            },
-                                        })
+                                            resource: MapResource::PointLight(Box::new(
+                                                PointLight::from_tag(
+                                                    tag,
+                                                    &light_tag,
+                                                    DEFAULT_LIGHT_RADIUS,
+                                                ),
+                                            )),
+                                        });
                                     }
-                                }
-                            }
-                            u => {
-                                debug!(
-                                    "Skipping unknown resource type {u:x} {:?} (table file {:?})",
-                                    data.translation,
-                                    table.tag()
-                                );
+                                    // Decal collection
+                                    0x80806e62 => {
+                                        cur.seek(SeekFrom::Start(data.data_resource.offset + 16))
+                                            .unwrap();
+                                        let tag: TagHash = cur.read_le().unwrap();
+                                        if !tag.is_valid() {
+                                            continue;
+                                        }
+
+                                        let header: Unk80806e68 =
+                                            package_manager().read_tag_struct(tag).unwrap();
+
+                                        for inst in &header.instances {
+                                            if inst.material.is_valid() {
+                                                materials.push(inst.material);
+                                            }
+
+                                            for i in inst.start..(inst.start + inst.count) {
+                                                resource_points.push(ResourcePoint {
+                                                    transform: Transform::from_decal(
+                                                        &header.transforms[i as usize],
+                                                        data,
+                                                    ),
+                                                    entity: data.entity,
+                                                    resource_type: data.data_resource.resource_type,
+                                                    resource: MapResource::Decal {
+                                                        material: inst.material,
+                                                    },
+                                                })
+                                            }
+                                        }
+                                    }
+                                    u => {
+                                        debug!(
+                                            "Skipping unknown resource type {u:x} {:?} (table file {:?})",
+                                            data.translation,
+                                            table.tag()
+                                        );
+                                        resource_points.push(ResourcePoint {
+                                            transform: Transform::from(data),
+                                            entity: data.entity,
+                                            resource_type: data.data_resource.resource_type,
+                                            resource: MapResource::Unknown(
+                                                data.data_resource.resource_type,
+                                            ),
+                                        });
+                                    }
+                                };
+                            } else {
                                 resource_points.push(ResourcePoint {
-                                    translation: Vec4::new(
-                                        data.translation.x,
-                                        data.translation.y,
-                                        data.translation.z,
-                                        data.translation.w,
-                                    ),
-                                    rotation: Quat::from_xyzw(
-                                        data.rotation.x,
-                                        data.rotation.y,
-                                        data.rotation.z,
-                                        data.rotation.w,
-                                    ),
+                                    transform: Transform::from(data),
                                     entity: data.entity,
-                                    resource_type: data.data_resource.resource_type,
-                                    resource: MapResource::Unknown(
-                                        data.data_resource.resource_type,
-                                    ),
+                                    resource_type: u32::MAX,
+                                    resource: MapResource::Entity(data.entity),
                                 });
                             }
-                        };
-                    } else {
-                        resource_points.push(ResourcePoint {
-                            translation: Vec4::new(
-                                data.translation.x,
-                                data.translation.y,
-                                data.translation.z,
-                                data.translation.w,
-                            ),
-                            rotation: Quat::from_xyzw(
-                                data.rotation.x,
-                                data.rotation.y,
-                                data.rotation.z,
-                                data.rotation.w,
-                            ),
-                            entity: data.entity,
-                            resource_type: u32::MAX,
-                            resource: MapResource::Entity(data.entity),
-                        });
+                        }
                     }
                 }
-            }
-        }
 
-        let map_name = stringmap
-            .get(&think.map_name.0)
-            .cloned()
-            .unwrap_or(format!("[MissingString_{:08x}]", think.map_name.0));
-        info!(
-            "Map {:x?} '{map_name}' - {} placement groups",
-            think.map_name,
-            placement_groups.len()
-        );
+                let map_name = stringmap
+                    .get(&think.map_name.0)
+                    .cloned()
+                    .unwrap_or(format!("[MissingString_{:08x}]", think.map_name.0));
+                info!(
+                    "Map {:x?} '{map_name}' - {} placement groups",
+                    think.map_name,
+                    placement_groups.len()
+                );
 
-        maps.push(MapData {
-            hash: (package.pkg_id(), index as _).into(),
-            name: map_name,
-            placement_groups,
-            resource_points,
-            terrains,
-        })
+                LoadedMap {
+                    data: MapData {
+                        hash: (pkg_id, index as _).into(),
+                        name: map_name,
+                        placement_groups,
+                        resource_points,
+                        terrains,
+                    },
+                    terrain_headers,
+                    materials,
+                    light_count,
+                    plausible_light_count,
+                }
+            })
+            .collect()
+    });
+
+    // Materials are parsed in bulk once everything that references them is known
+    let mut to_load_materials: HashSet<TagHash> = HashSet::new();
+    let mut terrain_headers = vec![];
+    let mut maps: Vec<MapData> = vec![];
+    let mut light_count = 0;
+    let mut plausible_light_count = 0;
+    for m in loaded_maps {
+        to_load_materials.extend(m.materials);
+        terrain_headers.extend(m.terrain_headers);
+        light_count += m.light_count;
+        plausible_light_count += m.plausible_light_count;
+        maps.push(m.data);
     }
+    info!("Loaded {} maps in {:.2?}", maps.len(), load_start.elapsed());
 
     let to_load_entities: IntMap<TagHash, ()> = maps
         .iter()
@@ -441,65 +486,40 @@ pub fn main() -> anyhow::Result<()> {
         .filter(|(v, _)| v.is_valid())
         .collect();
 
-    let mut entity_renderers: IntMap<TagHash, EntityRenderer> = Default::default();
+    let mut entity_models: Vec<(TagHash, EntityModel)> = vec![];
     for te in to_load_entities.keys().filter(|h| h.is_valid()) {
         let header: Unk80809c0f = package_manager().read_tag_struct(*te)?;
         for e in &header.unk10 {
-            match e.unk0.unk18.resource_type {
-                0x808072BD => {
-                    let mut cur = Cursor::new(package_manager().read_tag(e.unk0.tag())?);
-                    cur.seek(SeekFrom::Start(e.unk0.unk18.offset + 0x1dc))?;
-                    let model: Tag<Unk808073a5> = cur.read_le()?;
-                    cur.seek(SeekFrom::Start(e.unk0.unk18.offset + 0x300))?;
-                    let entity_material_map: TablePointer<Unk808072c5> = cur.read_le()?;
-                    let materials: TablePointer<Tag<Unk808071e8>> = cur.read_le()?;
-
-                    for m in &materials {
+            match EntityModel::read(&e.unk0)? {
+                Some(entity_model) => {
+                    for m in &entity_model.materials {
                         material_map.insert(m.tag().0, Material(m.0.clone(), m.tag()));
                     }
 
-                    for m in &model.meshes {
+                    for m in &entity_model.model.meshes {
                         for p in &m.parts {
                             if p.material.is_valid() {
-                                material_map.insert(
-                                    p.material.0,
-                                    Material(
-                                        package_manager().read_tag_struct(p.material)?,
-                                        p.material,
-                                    ),
-                                );
+                                to_load_materials.insert(p.material);
                             }
                         }
                     }
 
-                    entity_renderers.insert(
-                        *te,
-                        EntityRenderer::load(
-                            model.0,
-                            entity_material_map.to_vec(),
-                            materials.iter().map(|m| m.tag()).collect_vec(),
-                            &dcs,
-                        )?,
-                    );
-
-                    // println!(" - EntityModel {model:?}");
+                    entity_models.push((*te, entity_model));
                 }
-                u => trace!(
-                    "Unknown entity resource type {u:08X} (0x{:08X})",
+                None => trace!(
+                    "Unknown entity resource type {:08X} (0x{:08X})",
+                    e.unk0.unk18.resource_type,
                     e.unk0.unk10.resource_type
                 ),
             }
         }
     }
 
-    info!(
-        "Found {} entity models ({} entities)",
-        entity_renderers.len(),
-        to_load_entities.len()
+    info!("{} lights", light_count);
+    debug!(
+        "{plausible_light_count}/{light_count} light tags have a plausible color and radius"
     );
 
-    info!("{} lights", point_lights.len());
-
     let mut placement_groups: IntMap<u32, (Unk8080966d, Vec<InstancedRenderer>)> =
         IntMap::default();
 
@@ -519,126 +539,84 @@ pub fn main() -> anyhow::Result<()> {
         panic!("No map placements found in package");
     }
 
-    let mut terrain_renderers: IntMap<u32, TerrainRenderer> = Default::default();
-    info_span!("Loading terrain").in_scope(|| {
-        for (t, header) in terrain_headers.into_iter() {
-            for t in &header.mesh_groups {
-                to_load_textures.insert(t.dyemap, ());
-            }
-
-            match TerrainRenderer::load(header, &dcs.device) {
-                Ok(renderer) => {
-                    terrain_renderers.insert(t.0, renderer);
-                }
-                Err(e) => {
-                    error!("Failed to load terrain: {e}");
-                }
-            }
+    for (_, header) in &terrain_headers {
+        for t in &header.mesh_groups {
+            to_load_textures.insert(t.dyemap, ());
         }
-    });
+    }
 
     let to_load_statics: Vec<TagHash> = to_load.keys().cloned().collect();
 
-    info_span!("Loading statics").in_scope(|| {
-        for almostloadable in &to_load_statics {
-            let mheader: Unk808071a7 = package_manager().read_tag_struct(*almostloadable).unwrap();
-            for m in &mheader.materials {
-                if m.is_valid() {
-                    material_map.insert(
-                        m.0,
-                        Material(package_manager().read_tag_struct(*m).unwrap(), *m),
-                    );
-                }
-            }
-
-            match StaticModel::load(mheader, &dcs.device) {
-                Ok(model) => {
-                    static_map.insert(almostloadable.0, Arc::new(model));
-                }
-                Err(e) => {
-                    error!(model = ?almostloadable, "Failed to load model: {e}");
-                }
+    // Headers are parsed in parallel, the buffers are created once the device exists
+    let static_headers: Vec<(TagHash, Unk808071a7)> = info_span!("Reading static headers")
+        .in_scope(|| {
+            to_load_statics
+                .par_iter()
+                .map(|t| (*t, package_manager().read_tag_struct(*t).unwrap()))
+                .collect()
+        });
+    for (_, mheader) in &static_headers {
+        for m in &mheader.materials {
+            if m.is_valid() {
+                to_load_materials.insert(*m);
             }
         }
-    });
-
-    info!("Loaded {} statics", static_map.len());
-
-    info_span!("Constructing instance renderers").in_scope(|| {
-        let mut total_instance_data = 0;
-        for (placements, renderers) in placement_groups.values_mut() {
-            for instance in &placements.instances {
-                if let Some(model_hash) =
-                    placements.statics.iter().nth(instance.static_index as _)
-                {
-                    let _span =
-                        debug_span!("Draw static instance", count = instance.instance_count, model = ?model_hash)
-                            .entered();
-
-                    if let Some(model) = static_map.get(&model_hash.0) {
-                        let transforms = &placements.transforms[instance.instance_offset
-                            as usize
-                            ..(instance.instance_offset + instance.instance_count) as usize];
-
-                        renderers.push(InstancedRenderer::load(model.clone(), transforms, dcs.clone()).unwrap());
-                    }
+    }
 
-                    total_instance_data += instance.instance_count as usize * 16 * 4;
+    let materials_start = Instant::now();
+    let materials: Vec<Material> = info_span!("Loading materials").in_scope(|| {
+        to_load_materials
+            .into_par_iter()
+            .filter(|t| !material_map.contains_key(&t.0))
+            .filter_map(|t| match package_manager().read_tag_struct(t) {
+                Ok(m) => Some(Material(m, t)),
+                Err(e) => {
+                    warn!("Failed to read material {t}, skipping: {e}");
+                    None
                 }
-            }
-        }
-        debug!("Total instance data: {}kb", total_instance_data / 1024);
+            })
+            .collect()
     });
+    material_map.extend(materials.into_iter().map(|m| (m.1 .0, m)));
+    info!(
+        "Loaded {} materials in {:.2?}",
+        material_map.len(),
+        materials_start.elapsed()
+    );
 
-    let mut vshader_fullscreen = None;
-    let mut pshader_fullscreen = None;
-    let mut errors = None;
-
-    let flags = if cfg!(debug_assertions) {
-        D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
-    } else {
-        0
-    };
-    unsafe {
-        (
-            D3DCompileFromFile(
-                w!("fullscreen.hlsl"),
-                None,
-                None,
-                s!("VShader"),
-                s!("vs_5_0"),
-                flags,
-                0,
-                &mut vshader_fullscreen,
-                Some(&mut errors),
-            )
-            .context("Failed to compile vertex shader")?,
-            D3DCompileFromFile(
-                w!("fullscreen.hlsl"),
-                None,
-                None,
-                s!("PShader"),
-                s!("ps_5_0"),
-                flags,
-                0,
-                &mut pshader_fullscreen,
-                Some(&mut errors),
-            )
-            .context("Failed to compile pixel shader")?,
-        )
-    };
-
-    if let Some(errors) = errors {
-        let estr = unsafe {
-            let eptr = errors.GetBufferPointer();
-            std::slice::from_raw_parts(eptr.cast(), errors.GetBufferSize())
-        };
-        let errors = String::from_utf8_lossy(estr);
-        warn!("{}", errors);
+    if let Some(directory) = &decompile_directory {
+        let shaders: HashSet<TagHash> = material_map
+            .values()
+            .flat_map(|m| [m.vertex_shader, m.pixel_shader])
+            .filter(|s| s.is_valid())
+            .collect();
+        let written = decompile_to_directory(shaders.iter().copied(), directory)?;
+        info!(
+            "Decompiled {written}/{} shaders to {}",
+            shaders.len(),
+            directory.display()
+        );
+        return Ok(());
     }
 
-    let vshader_fullscreen = vshader_fullscreen.unwrap();
-    let pshader_fullscreen = pshader_fullscreen.unwrap();
+    let event_loop = EventLoop::new();
+    let window = winit::window::WindowBuilder::new()
+        .with_title("Alkahest")
+        .with_inner_size(config::with(|c| {
+            PhysicalSize::new(c.window.width, c.window.height)
+        }))
+        .with_position(config::with(|c| {
+            PhysicalPosition::new(c.window.pos_x, c.window.pos_y)
+        }))
+        .with_maximized(config!().window.maximised)
+        .build(&event_loop)?;
+
+    // cohae: Slight concern for thread safety here. ID3D11Device is threadsafe, but ID3D11DeviceContext is *not*
+    let dcs = Rc::new(DeviceContextSwapchain::create(&window)?);
+    let mut gbuffer = GBuffer::create(
+        (window.inner_size().width, window.inner_size().height),
+        dcs.clone(),
+    )?;
 
     info_span!("Loading shaders").in_scope(|| {
         for (t, m) in material_map.iter() {
@@ -649,64 +627,48 @@ pub fn main() -> anyhow::Result<()> {
             if let Ok(v) = package_manager().get_entry(m.vertex_shader) {
                 let _span = debug_span!("load vshader", shader = ?m.vertex_shader).entered();
 
-                vshader_map.entry(m.vertex_shader.0).or_insert_with(|| {
+                if !vshader_map.contains_key(&m.vertex_shader.0) {
                     let vs_data = package_manager().read_tag(v.reference).unwrap();
-                    let mut vs_cur = Cursor::new(&vs_data);
-                    let dxbc_header: DxbcHeader = vs_cur.read_le().unwrap();
-                    let input_sig = get_input_signature(&mut vs_cur, &dxbc_header).unwrap();
-
-                    let layout_converted = input_sig
-                        .elements
-                        .iter()
-                        .map(|e| {
-                            InputElement::from_dxbc(
-                                e,
-                                e.component_type == DxbcInputType::Float,
-                                false,
-                            )
-                        })
-                        .collect_vec();
-                    let layout = vertex_layout::build_input_layout(&layout_converted);
-                    unsafe {
-                        let v = dcs
-                            .device
-                            .CreateVertexShader(&vs_data, None)
-                            .context("Failed to load vertex shader")
-                            .unwrap();
-
-                        let name = format!("VS {:?} (mat 0x{:x})\0", m.vertex_shader, t);
-                        v.SetPrivateData(
-                            &WKPDID_D3DDebugObjectName,
-                            name.len() as u32 - 1,
-                            Some(name.as_ptr() as _),
-                        )
-                        .expect("Failed to set VS name");
-
-                        let input_layout = dcs.device.CreateInputLayout(&layout, &vs_data).ok();
-                        if input_layout.is_none() {
-                            let layout_string = layout_converted
-                                .iter()
-                                .enumerate()
-                                .map(|(i, e)| {
-                                    format!(
-                                        "\t{}{} v{i} : {}{}",
-                                        e.component_type,
-                                        e.component_count,
-                                        e.semantic_type.to_pcstr().display(),
-                                        e.semantic_index
-                                    )
-                                })
-                                .join("\n");
-
-                            error!(
-                                "Failed to load vertex layout for VS {:?}, layout:\n{}\n",
-                                m.vertex_shader, layout_string
-                            );
-                        }
+                    match DxbcShader::parse(&vs_data) {
+                        Ok(dxbc) => {
+                            let inputs = dxbc
+                                .input_signature
+                                .map(|s| ShaderInput::from_signature(&s))
+                                .unwrap_or_default();
+                            let texcoord0_register =
+                                dxbc.output_signature.as_ref().and_then(texcoord0_register);
+
+                            unsafe {
+                                let v = dcs
+                                    .device
+                                    .CreateVertexShader(&vs_data, None)
+                                    .context("Failed to load vertex shader")
+                                    .unwrap();
 
-                        (v, input_layout)
+                                let name = format!("VS {:?} (mat 0x{:x})\0", m.vertex_shader, t);
+                                v.SetPrivateData(
+                                    &WKPDID_D3DDebugObjectName,
+                                    name.len() as u32 - 1,
+                                    Some(name.as_ptr() as _),
+                                )
+                                .expect("Failed to set VS name");
+
+                                vshader_map.insert(
+                                    m.vertex_shader.0,
+                                    VertexShader {
+                                        shader: v,
+                                        bytecode: vs_data,
+                                        inputs,
+                                        texcoord0_register,
+                                    },
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to parse VS {:?}, skipping: {e}", m.vertex_shader)
+                        }
                     }
-                });
+                }
             }
 
             // return Ok(());
@@ -714,26 +676,34 @@ pub fn main() -> anyhow::Result<()> {
             if let Ok(v) = package_manager().get_entry(m.pixel_shader) {
                 let _span = debug_span!("load pshader", shader = ?m.pixel_shader).entered();
 
-                pshader_map.entry(m.pixel_shader.0).or_insert_with(|| {
+                if !pshader_map.contains_key(&m.pixel_shader.0) {
                     let ps_data = package_manager().read_tag(v.reference).unwrap();
-                    unsafe {
-                        let v = dcs
-                            .device
-                            .CreatePixelShader(&ps_data, None)
-                            .context("Failed to load pixel shader")
-                            .unwrap();
-
-                        let name = format!("PS {:?} (mat 0x{:x})\0", m.pixel_shader, t);
-                        v.SetPrivateData(
-                            &WKPDID_D3DDebugObjectName,
-                            name.len() as u32 - 1,
-                            Some(name.as_ptr() as _),
-                        )
-                        .expect("Failed to set VS name");
+                    match DxbcShader::parse(&ps_data) {
+                        Ok(dxbc) => {
+                            let render_targets =
+                                dxbc.output_signature.as_ref().map(render_target_count);
+
+                            unsafe {
+                                let v = dcs
+                                    .device
+                                    .CreatePixelShader(&ps_data, None)
+                                    .context("Failed to load pixel shader")
+                                    .unwrap();
 
-                        v
+                                let name = format!("PS {:?} (mat 0x{:x})\0", m.pixel_shader, t);
+                                v.SetPrivateData(
+                                    &WKPDID_D3DDebugObjectName,
+                                    name.len() as u32 - 1,
+                                    Some(name.as_ptr() as _),
+                                )
+                                .expect("Failed to set VS name");
+
+                                pshader_map.insert(m.pixel_shader.0, (v, render_targets));
+                            }
+                        }
+                        Err(e) => error!("Failed to parse PS {:?}, skipping: {e}", m.pixel_shader),
                     }
-                });
+                }
             }
 
             if m.unk98.len() > 1
@@ -779,65 +749,258 @@ pub fn main() -> anyhow::Result<()> {
         pshader_map.len()
     );
 
-    let (vshader_fullscreen, pshader_fullscreen) = unsafe {
-        let vs_blob = std::slice::from_raw_parts(
-            vshader_fullscreen.GetBufferPointer() as *const u8,
-            vshader_fullscreen.GetBufferSize(),
-        );
-        let v2 = dcs.device.CreateVertexShader(vs_blob, None)?;
-        let ps_blob = std::slice::from_raw_parts(
-            pshader_fullscreen.GetBufferPointer() as *const u8,
-            pshader_fullscreen.GetBufferSize(),
-        );
-        let v3 = dcs.device.CreatePixelShader(ps_blob, None)?;
-        (v2, v3)
+    // Vertex data is decoded on the CPU with the input signatures of the loaded vertex shaders
+    let shader_inputs = MaterialShaderInputs {
+        materials: &material_map,
+        vshaders: &vshader_map,
     };
 
+    let mut entity_renderers: IntMap<TagHash, EntityRenderer> = Default::default();
+    for (te, entity_model) in entity_models {
+        entity_renderers.insert(
+            te,
+            EntityRenderer::load(
+                entity_model.model.0,
+                entity_model.material_map.to_vec(),
+                entity_model.materials.iter().map(|m| m.tag()).collect_vec(),
+                &dcs,
+                &shader_inputs,
+            )?,
+        );
+    }
+
+    info!(
+        "Found {} entity models ({} entities)",
+        entity_renderers.len(),
+        to_load_entities.len()
+    );
+
+    let mut terrain_renderers: IntMap<u32, TerrainRenderer> = Default::default();
+    info_span!("Loading terrain").in_scope(|| {
+        for (t, header) in terrain_headers.into_iter() {
+            match TerrainRenderer::load(header, t, dcs.clone(), &shader_inputs) {
+                Ok(renderer) => {
+                    terrain_renderers.insert(t.0, renderer);
+                }
+                Err(e) => {
+                    error!("Failed to load terrain: {e}");
+                }
+            }
+        }
+    });
+
+    let statics_start = Instant::now();
+    info_span!("Loading statics").in_scope(|| {
+        // Tags are read in parallel, in batches to limit how much vertex data is held at once. The buffers are
+        // created on this thread, the device can't be shared with the pool
+        for batch in static_headers.chunks(256) {
+            let statics: Vec<_> = batch
+                .par_iter()
+                .map(|(hash, mheader)| StaticModel::read(mheader.clone(), *hash))
+                .collect();
+            for ((almostloadable, _), data) in batch.iter().zip(statics) {
+                match data.and_then(|d| StaticModel::create(d, &dcs.device, &shader_inputs)) {
+                    Ok(model) => {
+                        static_map.insert(almostloadable.0, Arc::new(model));
+                    }
+                    Err(e) => {
+                        error!(model = ?almostloadable, "Failed to load model: {e}");
+                    }
+                }
+            }
+        }
+    });
+
+    info!(
+        "Loaded {} statics in {:.2?}",
+        static_map.len(),
+        statics_start.elapsed()
+    );
+    debug!(
+        "{}/{} statics have an unk38 within 1% of their computed bounds",
+        static_map
+            .values()
+            .filter(|m| m.unk38_difference() < 0.01)
+            .count(),
+        static_map.len()
+    );
+
+    info_span!("Constructing instance renderers").in_scope(|| {
+        let mut total_instance_data = 0;
+        for (placements, renderers) in placement_groups.values_mut() {
+            for instance in &placements.instances {
+                if let Some(model_hash) =
+                    placements.statics.iter().nth(instance.static_index as _)
+                {
+                    let _span =
+                        debug_span!("Draw static instance", count = instance.instance_count, model = ?model_hash)
+                            .entered();
+
+                    if let Some(model) = static_map.get(&model_hash.0) {
+                        let transforms = &placements.transforms[instance.instance_offset
+                            as usize
+                            ..(instance.instance_offset + instance.instance_count) as usize];
+
+                        renderers.push(InstancedRenderer::load(model.clone(), transforms, dcs.clone()).unwrap());
+                    }
+
+                    total_instance_data += instance.instance_count as usize * 16 * 4;
+                }
+            }
+        }
+        debug!("Total instance data: {}kb", total_instance_data / 1024);
+    });
+
+    let debug_shaders = DebugShaders::create(dcs.clone())?;
+    let decal_renderer = DecalRenderer::create(dcs.clone())?;
+
     for m in material_map.values() {
         for t in m.ps_textures.iter().chain(m.vs_textures.iter()) {
             to_load_textures.insert(t.texture, ());
         }
     }
 
-    let to_load_textures: Vec<TagHash> = to_load_textures.keys().cloned().collect();
-    info_span!("Loading textures").in_scope(|| {
-        for tex_hash in to_load_textures.into_iter() {
-            if !tex_hash.is_valid() || texture_map.contains_key(&tex_hash.0) {
-                continue;
+    for m in &maps {
+        for rp in &m.resource_points {
+            if let MapResource::CubemapVolume(c) = &rp.resource {
+                to_load_textures.insert(c.cubemap_texture, ());
             }
-            let _span = debug_span!("load texture", texture = ?tex_hash).entered();
+        }
+    }
 
-            texture_map.insert(tex_hash.0, Texture::load(&dcs, tex_hash).unwrap());
+    let to_load_textures: Vec<TagHash> = to_load_textures
+        .keys()
+        .filter(|t| t.is_valid())
+        .cloned()
+        .collect();
+    let textures_start = Instant::now();
+    info_span!("Loading textures").in_scope(|| {
+        // Texture data is read in parallel, in batches to limit how much of it is held at once
+        for batch in to_load_textures.chunks(256) {
+            let textures: Vec<_> = batch.par_iter().map(|t| Texture::read(*t)).collect();
+            for (tex_hash, data) in batch.iter().zip(textures) {
+                let _span = debug_span!("load texture", texture = ?tex_hash).entered();
+
+                match data.and_then(|d| Texture::create(&dcs, &d)) {
+                    Ok(texture) => {
+                        texture_map.insert(tex_hash.0, texture);
+                    }
+                    Err(e) => warn!("Failed to load texture {tex_hash}, skipping: {e}"),
+                }
+            }
         }
     });
 
-    info!("Loaded {} textures", texture_map.len());
+    info!(
+        "Loaded {} textures in {:.2?}",
+        texture_map.len(),
+        textures_start.elapsed()
+    );
 
     let to_load_samplers: Vec<TagHash> = to_load_samplers.keys().cloned().collect();
     for s in to_load_samplers {
-        let sampler_header_ref = package_manager().get_entry(s).unwrap().reference;
-        let sampler_data = package_manager().read_tag(sampler_header_ref).unwrap();
+        let desc = match SamplerDesc::load(s) {
+            Ok(desc) => desc,
+            Err(e) => {
+                error!("Failed to read sampler {s}: {e}");
+                continue;
+            }
+        };
 
-        let sampler = unsafe {
-            dcs.device
-                .CreateSamplerState(sampler_data.as_ptr() as _)
-                .expect("Failed to create sampler state")
+        let sampler =
+            unsafe { dcs.device.CreateSamplerState(&D3D11_SAMPLER_DESC::from(&desc)) };
+        let sampler = match sampler {
+            Ok(sampler) => sampler,
+            Err(e) => {
+                error!("Failed to create sampler state for {s} ({desc}): {e}");
+                continue;
+            }
         };
 
         sampler_map.insert(s.0, sampler);
     }
 
     info!("Loaded {} samplers", sampler_map.len());
+    info!("Finished loading in {:.2?}", load_start.elapsed());
+
+    // Indexed by map, then by resource point
+    let entity_instances: Vec<Vec<Option<EntityInstance>>> = maps
+        .iter()
+        .map(|m| {
+            m.resource_points
+                .iter()
+                .map(|rp| {
+                    let renderer = entity_renderers.get(&rp.entity)?;
+                    EntityInstance::create(renderer, rp.entity, &rp.transform, dcs.clone())
+                    .map_err(|e| error!("Failed to create entity instance: {e}"))
+                    .ok()
+                })
+                .collect()
+        })
+        .collect();
+    // Indexed by map
+    let decals: Vec<Vec<Decal>> = maps
+        .iter()
+        .map(|m| {
+            m.resource_points
+                .iter()
+                .filter_map(|rp| {
+                    let MapResource::Decal { material } = rp.resource else {
+                        return None;
+                    };
 
-    let le_terrain_cb11 = ConstantBuffer::<Mat4>::create(dcs.clone(), None)?;
-    let le_entity_cb11 = ConstantBuffer::<ScopeRigidModel>::create(dcs.clone(), None)?;
+                    Decal::create(material, &rp.transform, dcs.clone())
+                        .map_err(|e| error!("Failed to create decal: {e}"))
+                        .ok()
+                })
+                .collect()
+        })
+        .collect();
+    // Indexed by map
+    let map_lights: Vec<Vec<ShaderLight>> = maps
+        .iter()
+        .map(|m| {
+            m.resource_points
+                .iter()
+                .filter_map(|rp| match &rp.resource {
+                    MapResource::PointLight(light) => Some(ShaderLight::new(
+                        light,
+                        rp.transform.translation,
+                        rp.transform.rotation,
+                    )),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+    let mut light_buffers = LightBuffers::create(dcs.clone())?;
+    // Indexed by map
+    let cubemap_volumes: Vec<Vec<CubemapVolume>> = maps
+        .iter()
+        .map(|m| {
+            m.resource_points
+                .iter()
+                .filter_map(|rp| {
+                    let MapResource::CubemapVolume(c) = &rp.resource else {
+                        return None;
+                    };
+
+                    let volume = CubemapVolume::new(c, rp.transform.translation);
+                    if volume.is_none() {
+                        warn!("Cubemap volume '{}' has no usable transform", *c.cubemap_name);
+                    }
+                    volume
+                })
+                .collect()
+        })
+        .collect();
+    let mut cubemap_renderer = CubemapRenderer::create(dcs.clone())?;
+    let mut debug_draw_renderer = DebugDrawRenderer::create(dcs.clone())?;
+    let mut draw_lists = PassDrawLists::default();
 
     let le_vertex_cb12 = ConstantBuffer::<ScopeView>::create(dcs.clone(), None)?;
     let le_entity_cb13 = ConstantBuffer::<Vec4>::create(dcs.clone(), None)?;
 
-    let cb_composite_lights =
-        ConstantBuffer::<Vec4>::create_array_init(dcs.clone(), &point_lights)?;
-
     let cb_composite_options = ConstantBuffer::<CompositorOptions>::create(dcs.clone(), None)?;
 
     let rasterizer_state = unsafe {
@@ -857,13 +1020,38 @@ pub fn main() -> anyhow::Result<()> {
             .context("Failed to create Rasterizer State")?
     };
 
+    let rasterizer_state_wireframe = unsafe {
+        dcs.device
+            .CreateRasterizerState(&D3D11_RASTERIZER_DESC {
+                FillMode: D3D11_FILL_WIREFRAME,
+                CullMode: D3D11_CULL_BACK,
+                FrontCounterClockwise: true.into(),
+                // Pulls the lines towards the camera so they don't z-fight with the surfaces
+                SlopeScaledDepthBias: 1.0,
+                DepthClipEnable: true.into(),
+                ..Default::default()
+            })
+            .context("Failed to create wireframe Rasterizer State")?
+    };
+
     let mut resources: Resources = Resources::default();
-    resources.insert(FpsCamera::default());
+    resources.insert(FpsCamera {
+        projection: config!().projection,
+        ..Default::default()
+    });
     resources.insert(InputState::default());
     resources.insert(MapDataList {
         current_map: 0,
         maps,
     });
+    resources.insert(Selection::default());
+    resources.insert(CullingSettings::default());
+    resources.insert(CullingStats::default());
+    resources.insert(DrawStats::default());
+    resources.insert(DebugShapes::default());
+    resources.insert(LodSettings::default());
+    resources.insert(MaterialPatches::default());
+    resources.insert(CompositorShaders::new(dcs.clone()));
 
     let matcap = unsafe {
         const MATCAP_DATA: &[u8] = include_bytes!("matte.data");
@@ -924,12 +1112,56 @@ pub fn main() -> anyhow::Result<()> {
         })?
     };
 
+    // Transparent geometry is blended into the forward target with premultiplied alpha
+    let blend_state_transparent = unsafe {
+        dcs.device.CreateBlendState(&D3D11_BLEND_DESC {
+            RenderTarget: [D3D11_RENDER_TARGET_BLEND_DESC {
+                BlendEnable: true.into(),
+                SrcBlend: D3D11_BLEND_SRC_ALPHA,
+                DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+                BlendOp: D3D11_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D11_BLEND_ONE,
+                DestBlendAlpha: D3D11_BLEND_INV_SRC_ALPHA,
+                BlendOpAlpha: D3D11_BLEND_OP_ADD,
+                RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+            }; 8],
+            ..Default::default()
+        })?
+    };
+
+    // Used by the overdraw view to count layers
+    let blend_state_additive = unsafe {
+        dcs.device.CreateBlendState(&D3D11_BLEND_DESC {
+            RenderTarget: [D3D11_RENDER_TARGET_BLEND_DESC {
+                BlendEnable: true.into(),
+                SrcBlend: D3D11_BLEND_ONE,
+                DestBlend: D3D11_BLEND_ONE,
+                BlendOp: D3D11_BLEND_OP_ADD,
+                SrcBlendAlpha: D3D11_BLEND_ONE,
+                DestBlendAlpha: D3D11_BLEND_ONE,
+                BlendOpAlpha: D3D11_BLEND_OP_ADD,
+                RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+            }; 8],
+            ..Default::default()
+        })?
+    };
+
     let gui_fps = Rc::new(RefCell::new(FpsDisplayOverlay::default()));
     let gui_gbuffer = Rc::new(RefCell::new(GBufferInfoOverlay {
-        composition_mode: CompositorMode::Combined as usize,
+        composition_mode: CompositionMode::BuiltIn(CompositorMode::Combined),
         renderlayer_statics: true,
         renderlayer_terrain: true,
         renderlayer_entities: true,
+        renderlayer_decals: true,
+        renderlayer_transparent: true,
+        debug_entity_materials: false,
+        debug_light_radii: false,
+        debug_decal_projectors: false,
+        debug_entity_bounds: false,
+        debug_picking_ray: false,
+        debug_frozen_frustum: false,
+        geometry_view: GeometryView::Shaded,
+        wireframe: false,
     }));
     let gui_debug = Rc::new(RefCell::new(CameraPositionOverlay {
         show_map_resources: false,
@@ -940,6 +1172,7 @@ pub fn main() -> anyhow::Result<()> {
             f
         },
         map_resource_distance: 2000.0,
+        show_cubemap_volumes: false,
         render_scale: 100.0,
         render_scale_changed: false,
         render_lights: false,
@@ -949,7 +1182,18 @@ pub fn main() -> anyhow::Result<()> {
         debug_overlay: gui_debug.clone(),
     }));
 
-    let gui_dump = Rc::new(RefCell::new(PackageDumper::new()));
+    let gui_camera_path = Rc::new(RefCell::new(CameraPathOverlay::default()));
+    let gui_shaders = Rc::new(RefCell::new(ShaderViewerOverlay::default()));
+    let gui_materials = Rc::new(RefCell::new(MaterialInspectorOverlay::new(
+        dcs.clone(),
+        gui_shaders.clone(),
+    )));
+    let gui_dump = Rc::new(RefCell::new(PackageDumper::new(gui_materials.clone())));
+    let gui_selection = Rc::new(RefCell::new(SelectionOverlay {
+        dumper: gui_dump.clone(),
+        shader_viewer: gui_shaders.clone(),
+        material_inspector: gui_materials.clone(),
+    }));
 
     let mut gui = GuiManager::create(&window, &dcs.device);
     let gui_console = Rc::new(RefCell::new(ConsoleOverlay::default()));
@@ -959,21 +1203,34 @@ pub fn main() -> anyhow::Result<()> {
     gui.add_overlay(gui_resources.clone());
     gui.add_overlay(gui_console);
     gui.add_overlay(gui_dump.clone());
+    gui.add_overlay(gui_camera_path.clone());
+    gui.add_overlay(gui_selection);
+    gui.add_overlay(gui_shaders);
+    gui.add_overlay(gui_materials);
+    gui.add_overlay(Rc::new(RefCell::new(DebugTextOverlay)));
 
     // TODO(cohae): resources should be added to renderdata directly
-    let render_data = RenderData {
+    let mut render_data = RenderData {
         materials: material_map,
         vshaders: vshader_map,
+        input_layouts: Default::default(),
         pshaders: pshader_map,
         cbuffers_vs: cbuffer_map_vs,
         cbuffers_ps: cbuffer_map_ps,
         textures: texture_map,
         samplers: sampler_map,
+        texture_overrides: Default::default(),
     };
 
     let start_time = Instant::now();
     let mut last_frame = Instant::now();
     let mut last_cursor_pos: Option<PhysicalPosition<f64>> = None;
+    // Cursor position when the left mouse button was pressed, to tell clicks apart from drags
+    let mut click_start_pos: Option<PhysicalPosition<f64>> = None;
+    let mut pending_pick: Option<PhysicalPosition<f64>> = None;
+    // Last picking ray and the distance to what it hit
+    let mut last_pick: Option<(Ray, Option<f32>)> = None;
+    let mut frozen_frustum: Option<Mat4> = None;
 
     event_loop.run(move |event, _, control_flow| {
         gui.handle_event(&event, &window);
@@ -1030,6 +1287,27 @@ pub fn main() -> anyhow::Result<()> {
                         last_cursor_pos = Some(*position);
                     }
                 }
+                WindowEvent::MouseInput {
+                    state,
+                    button: MouseButton::Left,
+                    ..
+                } => match state {
+                    ElementState::Pressed => {
+                        click_start_pos = if gui.imgui.io().want_capture_mouse {
+                            None
+                        } else {
+                            last_cursor_pos
+                        };
+                    }
+                    ElementState::Released => {
+                        if let (Some(start), Some(end)) = (click_start_pos.take(), last_cursor_pos)
+                        {
+                            if (start.x - end.x).abs() < 4.0 && (start.y - end.y).abs() < 4.0 {
+                                pending_pick = Some(end);
+                            }
+                        }
+                    }
+                },
                 // TODO(cohae): Should this even be in here at this point?
                 WindowEvent::KeyboardInput { .. } => {
                     let input = resources.get::<InputState>().unwrap();
@@ -1054,10 +1332,28 @@ pub fn main() -> anyhow::Result<()> {
                     gui_debug.borrow_mut().render_scale_changed = false;
                 }
 
+                resources.get_mut::<CompositorShaders>().unwrap().update();
+
+                {
+                    let maps = resources.get::<MapDataList>().unwrap();
+                    let mut patches = resources.get_mut::<MaterialPatches>().unwrap();
+                    if let Some(map) = maps.current_map() {
+                        patches.select_map(map.hash);
+                    }
+                    for (material, patch) in patches.take_pending() {
+                        apply_material_patch(&dcs, &mut render_data, material, patch.as_ref());
+                    }
+                }
+
                 let mut camera = resources.get_mut::<FpsCamera>().unwrap();
-                if !gui.imgui.io().want_capture_keyboard {
+                let frame_delta = last_frame.elapsed().as_secs_f32();
+                if !gui_camera_path
+                    .borrow_mut()
+                    .update_camera(&mut camera, frame_delta)
+                    && !gui.imgui.io().want_capture_keyboard
+                {
                     let input_state = resources.get::<InputState>().unwrap();
-                    camera.update(&input_state, last_frame.elapsed().as_secs_f32());
+                    camera.update(&input_state, frame_delta);
                 }
                 last_frame = Instant::now();
 
@@ -1076,6 +1372,10 @@ pub fn main() -> anyhow::Result<()> {
                         &gbuffer.rt2.render_target,
                         [0.0, 0.0, 0.0, 0.0].as_ptr() as _,
                     );
+                    dcs.context.ClearRenderTargetView(
+                        &gbuffer.forward.render_target,
+                        [0.0, 0.0, 0.0, 0.0].as_ptr() as _,
+                    );
                     dcs.context.ClearDepthStencilView(
                         &gbuffer.depth.view,
                         D3D11_CLEAR_DEPTH.0 as _,
@@ -1108,15 +1408,9 @@ pub fn main() -> anyhow::Result<()> {
                     );
                     dcs.context.OMSetDepthStencilState(&gbuffer.depth.state, 0);
 
-                    let projection = Mat4::perspective_infinite_reverse_rh(
-                        90f32.to_radians(),
+                    let proj_view = camera.projection_view_matrix(
                         window_dims.width as f32 / window_dims.height as f32,
-                        0.0001,
                     );
-
-                    let view = camera.calculate_matrix();
-
-                    let proj_view = projection * view;
                     let mut view2 = Mat4::IDENTITY;
                     view2.w_axis = camera.position.extend(1.0);
 
@@ -1124,7 +1418,7 @@ pub fn main() -> anyhow::Result<()> {
                         world_to_projective: proj_view,
                         camera_to_world: view2,
                         // Account for missing depth value in output
-                        view_miscellaneous: Vec4::new(0.0, 0.0, 0.0001, 0.0),
+                        view_miscellaneous: Vec4::new(0.0, 0.0, camera.projection.near, 0.0),
                         ..Default::default()
                     };
                     le_vertex_cb12.write(&scope_view).unwrap();
@@ -1136,17 +1430,66 @@ pub fn main() -> anyhow::Result<()> {
                         .PSSetConstantBuffers(12, Some(&[Some(le_vertex_cb12.buffer().clone())]));
 
                     let maps = resources.get::<MapDataList>().unwrap();
-                    let map = &maps.maps[maps.current_map % maps.maps.len()];
+                    let map_index = maps.current_map % maps.maps.len();
+                    let map = &maps.maps[map_index];
+
+                    if let Some(cursor) = pending_pick.take() {
+                        let ndc = Vec2::new(
+                            (cursor.x as f32 / window_dims.width as f32) * 2.0 - 1.0,
+                            1.0 - (cursor.y as f32 / window_dims.height as f32) * 2.0,
+                        );
+                        let ray = Ray::from_screen(proj_view, ndc);
+
+                        let debug = gui_debug.borrow();
+                        let resource_filter = debug
+                            .show_map_resources
+                            .then_some(&debug.map_resource_filter[..]);
+                        let result = picking::pick(
+                            &ray,
+                            map,
+                            &placement_groups,
+                            &terrain_renderers,
+                            resource_filter,
+                        );
+
+                        last_pick = Some((ray, result.as_ref().map(|(d, _)| *d)));
+
+                        let mut selection = resources.get_mut::<Selection>().unwrap();
+                        match result {
+                            Some((distance, item)) => {
+                                selection.distance = distance;
+                                selection.item = Some(item);
+                            }
+                            None => selection.item = None,
+                        }
+                    }
 
                     {
                         let gb = gui_gbuffer.borrow();
+                        let culler = ViewCuller::new(
+                            &resources.get::<CullingSettings>().unwrap(),
+                            &proj_view,
+                            camera.position,
+                        );
+                        let lod_selector =
+                            LodSelector::new(&resources.get::<LodSettings>().unwrap(), &camera);
+                        let mut culling_stats = CullingStats::default();
 
                         if gb.renderlayer_statics {
                             for ptag in &map.placement_groups {
                                 let (_placements, instance_renderers) =
-                                    &placement_groups[&ptag.tag().0];
-                                for instance in instance_renderers.iter() {
-                                    instance.draw(&dcs, &render_data).unwrap();
+                                    placement_groups.get_mut(&ptag.tag().0).unwrap();
+                                for instance in instance_renderers.iter_mut() {
+                                    culling_stats
+                                        .statics
+                                        .add(instance.cull(&culler, &lod_selector).unwrap());
+                                    if let Err(e) = instance.collect_draws(
+                                        &mut draw_lists,
+                                        &render_data,
+                                        ptag.tag().0,
+                                    ) {
+                                        error!("Failed to draw statics: {e}");
+                                    }
                                 }
                             }
                         }
@@ -1154,7 +1497,15 @@ pub fn main() -> anyhow::Result<()> {
                         if gb.renderlayer_terrain {
                             for th in &map.terrains {
                                 if let Some(t) = terrain_renderers.get(&th.0) {
-                                    t.draw(&dcs, &render_data, le_terrain_cb11.buffer());
+                                    match t.collect_draws(
+                                        &mut draw_lists,
+                                        &render_data,
+                                        &culler,
+                                        &lod_selector,
+                                    ) {
+                                        Ok(stats) => culling_stats.terrain.add(stats),
+                                        Err(e) => error!("Failed to draw terrain: {e}"),
+                                    }
                                 }
                             }
                         }
@@ -1172,39 +1523,258 @@ pub fn main() -> anyhow::Result<()> {
                                 10,
                                 Some(&[Some(gbuffer.depth.texture_view.clone())]),
                             );
-                            for rp in &map.resource_points {
-                                if let Some(ent) = entity_renderers.get(&rp.entity) {
-                                    let mm = Mat4::from_scale_rotation_translation(
-                                        Vec3::splat(rp.translation.w),
-                                        rp.rotation.inverse(),
-                                        Vec3::ZERO,
-                                    );
-                                    let model_matrix = Mat4::from_cols(
-                                        mm.x_axis.truncate().extend(rp.translation.x),
-                                        mm.y_axis.truncate().extend(rp.translation.y),
-                                        mm.z_axis.truncate().extend(rp.translation.z),
-                                        mm.w_axis,
-                                    );
+                            for (rp, instance) in
+                                map.resource_points.iter().zip(&entity_instances[map_index])
+                            {
+                                let (Some(ent), Some(instance)) =
+                                    (entity_renderers.get(&rp.entity), instance)
+                                else {
+                                    continue;
+                                };
+
+                                culling_stats.entities.total += 1;
+                                if !culler.is_aabb_visible(&instance.bounds) {
+                                    continue;
+                                }
+                                let Some(lod) = nearest_available(
+                                    lod_selector
+                                        .select(&BoundingSphere::from_aabb(&instance.bounds)),
+                                    &ent.available_lods,
+                                ) else {
+                                    continue;
+                                };
+                                culling_stats.entities.visible += 1;
+                                culling_stats.entities.lods[lod as usize] += 1;
+
+                                ent.collect_draws(
+                                    &mut draw_lists,
+                                    &render_data,
+                                    instance,
+                                    lod,
+                                    gb.debug_entity_materials,
+                                );
+                            }
+                        }
+
+                        let mut draw_stats = DrawStats::default();
+                        draw_lists.opaque.sort();
+                        let overdraw = gb.geometry_view == GeometryView::Overdraw;
+                        if overdraw {
+                            dcs.context.OMSetBlendState(
+                                &blend_state_additive,
+                                Some(&[1f32, 1., 1., 1.] as _),
+                                0xffffffff,
+                            );
+                            dcs.context
+                                .OMSetDepthStencilState(&gbuffer.depth.state_disabled, 0);
+                        }
+
+                        match draw_lists.opaque.execute(
+                            &dcs,
+                            &render_data,
+                            &debug_shaders,
+                            gb.geometry_view,
+                        ) {
+                            Ok(stats) => draw_stats.add(stats),
+                            Err(e) => error!("Failed to execute opaque draws: {e}"),
+                        }
+
+                        if overdraw {
+                            dcs.context.OMSetBlendState(
+                                &blend_state,
+                                Some(&[1f32, 1., 1., 1.] as _),
+                                0xffffffff,
+                            );
+                            dcs.context.OMSetDepthStencilState(&gbuffer.depth.state, 0);
+                        }
+
+                        if gb.wireframe {
+                            dcs.context.RSSetState(&rasterizer_state_wireframe);
+                            dcs.context
+                                .OMSetDepthStencilState(&gbuffer.depth.state_read_only, 0);
+                            if let Err(e) = draw_lists.opaque.execute(
+                                &dcs,
+                                &render_data,
+                                &debug_shaders,
+                                GeometryView::Wireframe,
+                            ) {
+                                error!("Failed to execute wireframe draws: {e}");
+                            }
+                            dcs.context.RSSetState(&rasterizer_state);
+                            dcs.context.OMSetDepthStencilState(&gbuffer.depth.state, 0);
+                        }
+
+                        // Decals would add their own layers to the overdraw count
+                        if gb.renderlayer_decals && !overdraw {
+                            // Decals read depth, so it can't be bound as the depth target. The
+                            // decal renderer binds the GBuffer targets itself
+                            dcs.context.PSSetShaderResources(
+                                10,
+                                Some(&[Some(gbuffer.depth.texture_view.clone())]),
+                            );
+
+                            match decal_renderer.draw(
+                                &dcs,
+                                &render_data,
+                                &decals[map_index],
+                                &culler,
+                                proj_view,
+                                Vec2::new(
+                                    window_dims.width as f32 * render_scale,
+                                    window_dims.height as f32 * render_scale,
+                                ),
+                                &[
+                                    gbuffer.rt0.render_target.clone(),
+                                    gbuffer.rt1.render_target.clone(),
+                                    gbuffer.rt2.render_target.clone(),
+                                ],
+                            ) {
+                                Ok(count) => draw_stats.decals = count,
+                                Err(e) => error!("Failed to draw decals: {e}"),
+                            }
+                        }
 
-                                    le_entity_cb11
-                                        .write(&ScopeRigidModel {
-                                            mesh_to_world: model_matrix.transpose(),
-                                            position_scale: ent.mesh_scale(),
-                                            position_offset: ent.mesh_offset(),
-                                            texcoord0_scale_offset: ent.texcoord_transform(),
-                                            dynamic_sh_ao_values: Vec4::ZERO,
-                                        })
-                                        .unwrap();
-
-                                    dcs.context.VSSetConstantBuffers(
-                                        11,
-                                        Some(&[Some(le_entity_cb11.buffer().clone())]),
+                        if gb.renderlayer_transparent {
+                            dcs.context.PSSetShaderResources(10, Some(&[None]));
+                            dcs.context.RSSetState(&rasterizer_state);
+                            dcs.context.OMSetBlendState(
+                                &blend_state_transparent,
+                                Some(&[1f32, 1., 1., 1.] as _),
+                                0xffffffff,
+                            );
+                            dcs.context.OMSetRenderTargets(
+                                Some(&[Some(gbuffer.forward.render_target.clone())]),
+                                &gbuffer.depth.view,
+                            );
+                            dcs.context
+                                .OMSetDepthStencilState(&gbuffer.depth.state_read_only, 0);
+
+                            draw_lists.transparent.sort_back_to_front(camera.position);
+                            match draw_lists.transparent.execute(
+                                &dcs,
+                                &render_data,
+                                &debug_shaders,
+                                GeometryView::Shaded,
+                            ) {
+                                Ok(stats) => {
+                                    draw_stats.add(stats);
+                                    draw_stats.transparent_draws = stats.draw_calls;
+                                }
+                                Err(e) => error!("Failed to execute transparent draws: {e}"),
+                            }
+                        }
+                        // Lists are rebuilt every frame
+                        draw_lists.opaque.clear();
+                        draw_lists.transparent.clear();
+
+                        dcs.context.RSSetState(&rasterizer_state);
+                        dcs.context.OMSetBlendState(
+                            &blend_state,
+                            Some(&[1f32, 1., 1., 1.] as _),
+                            0xffffffff,
+                        );
+                        if gui_debug.borrow().render_lights {
+                            match light_buffers.update(
+                                &map_lights[map_index],
+                                ShaderLight::camera(camera.position),
+                                &culler,
+                                &proj_view,
+                                UVec2::new(window_dims.width, window_dims.height),
+                            ) {
+                                Ok(()) => {
+                                    draw_stats.lights = light_buffers.light_count();
+                                    draw_stats.max_tile_lights = light_buffers.max_tile_lights();
+                                }
+                                Err(e) => error!("Failed to update light buffers: {e}"),
+                            }
+                        }
+
+                        {
+                            let mut shapes = resources.get_mut::<DebugShapes>().unwrap();
+                            if gui_debug.borrow().show_cubemap_volumes {
+                                for v in &cubemap_volumes[map_index] {
+                                    shapes.world.draw_box(
+                                        &v.volume_to_world,
+                                        Vec4::new(0.2, 1.0, 0.2, 1.0),
                                     );
+                                }
+                            }
+
+                            if gb.debug_light_radii {
+                                for l in &map_lights[map_index] {
+                                    let bounds = l.bounds();
+                                    if culler.is_sphere_visible(&bounds) {
+                                        shapes.world.sphere(
+                                            bounds.center,
+                                            bounds.radius,
+                                            l.color.truncate().normalize_or_zero().extend(1.0),
+                                        );
+                                    }
+                                }
+                            }
+
+                            if gb.debug_decal_projectors {
+                                for d in &decals[map_index] {
+                                    if culler.is_aabb_visible(&d.bounds) {
+                                        shapes
+                                            .world
+                                            .draw_box(&d.transform, Vec4::new(1.0, 0.5, 0.0, 1.0));
+                                        shapes.world.axes(&d.transform, 0.5);
+                                    }
+                                }
+                            }
+
+                            if gb.debug_entity_bounds {
+                                for instance in entity_instances[map_index].iter().flatten() {
+                                    if culler.is_aabb_visible(&instance.bounds) {
+                                        shapes
+                                            .world
+                                            .aabb(&instance.bounds, Vec4::new(1.0, 1.0, 0.0, 1.0));
+                                    }
+                                }
+                            }
+
+                            if gb.debug_frozen_frustum {
+                                let frustum = *frozen_frustum.get_or_insert(proj_view);
+                                shapes
+                                    .batch(false)
+                                    .frustum(&frustum, Vec4::new(0.0, 1.0, 1.0, 1.0));
+                            } else {
+                                frozen_frustum = None;
+                            }
 
-                                    ent.draw(&dcs, &render_data);
+                            if gb.debug_picking_ray {
+                                if let Some((ray, distance)) = last_pick {
+                                    let end =
+                                        ray.origin + ray.direction * distance.unwrap_or(1000.0);
+                                    shapes.batch(false).line(
+                                        ray.origin,
+                                        end,
+                                        Vec4::new(1.0, 0.0, 1.0, 1.0),
+                                    );
+                                    if let Some(distance) = distance {
+                                        shapes.text3d(
+                                            end,
+                                            format!("{distance:.2}"),
+                                            Vec4::new(1.0, 0.0, 1.0, 1.0),
+                                        );
+                                    }
                                 }
                             }
                         }
+
+                        match cubemap_renderer.update(
+                            &cubemap_volumes[map_index],
+                            &render_data,
+                            camera.position,
+                        ) {
+                            Ok(count) => draw_stats.cubemap_volumes = count,
+                            Err(e) => error!("Failed to update cubemap volumes: {e}"),
+                        }
+
+                        *resources.get_mut::<DrawStats>().unwrap() = draw_stats;
+
+                        *resources.get_mut::<CullingStats>().unwrap() = culling_stats;
                     }
 
                     dcs.context.OMSetRenderTargets(
@@ -1219,28 +1789,42 @@ pub fn main() -> anyhow::Result<()> {
                             Some(gbuffer.rt2.view.clone()),
                             Some(gbuffer.depth.texture_view.clone()),
                             Some(matcap_view.clone()),
+                            Some(gbuffer.forward.view.clone()),
                         ]),
                     );
+                    dcs.context
+                        .PSSetShaderResources(6, Some(&light_buffers.views()));
+                    cubemap_renderer.bind(&dcs);
 
+                    // User modes are built on the combined output
+                    let (mode, user_mode) =
+                        if gui_gbuffer.borrow().geometry_view == GeometryView::Overdraw {
+                            (CompositorMode::Overdraw, None)
+                        } else {
+                            match gui_gbuffer.borrow().composition_mode.clone() {
+                                CompositionMode::BuiltIn(mode) => (mode, None),
+                                CompositionMode::User(name) => {
+                                    (CompositorMode::Combined, Some(name))
+                                }
+                            }
+                        };
                     let compositor_options = CompositorOptions {
                         proj_view_matrix_inv: proj_view.inverse(),
                         camera_pos: camera.position.extend(1.0),
                         camera_dir: camera.front.extend(1.0),
-                        mode: COMPOSITOR_MODES[gui_gbuffer.borrow().composition_mode] as u32,
+                        mode: mode as u32,
                         light_count: if gui_debug.borrow().render_lights {
-                            point_lights.len() as u32
+                            light_buffers.light_count() as u32
                         } else {
                             0
                         },
+                        light_tiles_x: light_buffers.tiles_x(),
                     };
                     cb_composite_options.write(&compositor_options).unwrap();
 
                     dcs.context.PSSetConstantBuffers(
                         0,
-                        Some(&[
-                            Some(cb_composite_options.buffer().clone()),
-                            Some(cb_composite_lights.buffer().clone()),
-                        ]),
+                        Some(&[Some(cb_composite_options.buffer().clone())]),
                     );
 
                     dcs.context.RSSetViewports(Some(&[D3D11_VIEWPORT {
@@ -1252,15 +1836,29 @@ pub fn main() -> anyhow::Result<()> {
                         MaxDepth: 1.0,
                     }]));
 
-                    dcs.context.VSSetShader(&vshader_fullscreen, None);
-                    dcs.context.PSSetShader(&pshader_fullscreen, None);
-                    dcs.context
-                        .IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP);
-                    dcs.context.Draw(3, 0);
+                    if resources
+                        .get::<CompositorShaders>()
+                        .unwrap()
+                        .bind(user_mode.as_deref())
+                    {
+                        dcs.context
+                            .IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP);
+                        dcs.context.Draw(3, 0);
+                    }
+
+                    if let Err(e) = debug_draw_renderer.draw(
+                        &resources.get::<DebugShapes>().unwrap(),
+                        proj_view,
+                        &gbuffer.depth.texture_view,
+                        Vec2::new(window_dims.width as f32, window_dims.height as f32),
+                    ) {
+                        error!("Failed to draw debug shapes: {e}");
+                    }
 
                     drop(camera);
                     drop(maps);
                     gui.draw_frame(&window, last_frame.elapsed(), &mut resources);
+                    resources.get_mut::<DebugShapes>().unwrap().clear();
 
                     dcs.context.OMSetDepthStencilState(None, 0);
 
@@ -1291,6 +1889,7 @@ pub fn main() -> anyhow::Result<()> {
                         pos_y: pos.y,
                         maximised: window.is_maximized(),
                     };
+                    c.projection = resources.get::<FpsCamera>().unwrap().projection;
                 });
                 config::persist();
             }
@@ -88,6 +88,7 @@ pub struct Unk80806ef4 {
 #[derive(BinRead, Debug)]
 pub struct Unk8080714f {
     pub file_size: u64,
+    // ? unk10/unk20 are compared against the computed bounds in TerrainRenderer::load (debug log)
     #[br(seek_before(SeekFrom::Start(0x10)))]
     pub unk10: Vector4,
     pub unk20: Vector4,
@@ -107,6 +108,7 @@ pub struct Unk8080714f {
 
 #[derive(BinRead, Debug)]
 pub struct Unk80807154 {
+    // ? unk0-unk14 are compared against the computed bounds in TerrainRenderer::load (debug log)
     pub unk0: f32,
     pub unk4: f32,
     pub unk8: f32,
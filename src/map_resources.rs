@@ -3,6 +3,7 @@ use crate::structure::{RelPointer, TablePointer};
 use crate::types::{DestinyHash, Vector4};
 use binrw::{BinRead, NullString};
 use destiny_pkg::TagHash;
+use glam::{Mat4, Vec3, Vec4};
 use std::io::SeekFrom;
 use std::mem::MaybeUninit;
 use strum::{EnumCount, EnumIs, EnumVariantNames};
@@ -15,7 +16,7 @@ pub enum MapResource {
     /// Generic data entry with no resource
     Entity(TagHash),
     CubemapVolume(Box<Unk80806b7f>),
-    PointLight(TagHash),
+    PointLight(Box<PointLight>),
     Decal {
         material: TagHash,
     },
@@ -34,7 +35,21 @@ impl MapResource {
                 )
             }
             MapResource::Decal { material } => format!("Decal (mat {material})"),
-            MapResource::PointLight(_) => "Point light".to_string(),
+            MapResource::PointLight(l) => {
+                let name = match l.shape {
+                    LightShape::Point => "Point light",
+                    LightShape::Spot { .. } => "Spot light",
+                };
+                format!(
+                    "{name} {:08X}\nColor ({:.2}, {:.2}, {:.2}) x {:.2}\nRadius {:.2}",
+                    l.tag.0.to_be(),
+                    l.color.x,
+                    l.color.y,
+                    l.color.z,
+                    l.intensity,
+                    l.radius
+                )
+            }
             MapResource::Unknown(u) => format!("Unknown {:08X}", u.to_be()),
         }
     }
@@ -116,6 +131,7 @@ pub struct Unk80806b7f {
     #[br(seek_before(SeekFrom::Current(0x20)))]
     pub unk20: Vector4,
     pub unk30: Vector4,
+    /// Blend distance at the volume edges (world units?)
     pub unk40: f32,
     pub unk44: [u32; 3],
     pub unk50: Vector4,
@@ -123,7 +139,7 @@ pub struct Unk80806b7f {
 
     pub unk70: [u32; 20],
 
-    // Transform matrices?
+    /// Volume transform and its inverse, in an unknown order. See [`Unk80806b7f::volume_to_world`]
     pub unkc0: [Vector4; 4],
     pub unk100: [Vector4; 4],
 
@@ -136,6 +152,131 @@ pub struct Unk80806b7f {
     pub unk1a4: [u32; 7],
 }
 
+impl Unk80806b7f {
+    /// Transform from the volume box (-1..1 on each axis) to world space.
+    /// Whichever of the stored matrices (or their inverses) puts the volume origin closest to `translation` is used
+    pub fn volume_to_world(&self, translation: Vec3) -> Option<Mat4> {
+        let to_mat4 = |m: &[Vector4; 4]| Mat4::from_cols_array_2d(&m.map(|v| [v.x, v.y, v.z, v.w]));
+
+        let a = to_mat4(&self.unkc0);
+        let b = to_mat4(&self.unk100);
+        [a, b, a.inverse(), b.inverse()]
+            .into_iter()
+            .filter(|m| m.is_finite() && m.determinant().abs() > f32::EPSILON)
+            .min_by(|x, y| {
+                let dx = x.transform_point3(Vec3::ZERO).distance_squared(translation);
+                let dy = y.transform_point3(Vec3::ZERO).distance_squared(translation);
+                dx.total_cmp(&dy)
+            })
+    }
+
+    /// Distance over which the volume fades out towards its edges, 0 if unknown
+    pub fn blend_distance(&self) -> f32 {
+        if self.unk40.is_finite() && self.unk40 > 0.0 {
+            self.unk40
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Light tag referenced by point light resources (0x80806cbf)
+#[derive(BinRead, Debug, Clone)]
+pub struct PointLightTag {
+    pub file_size: u64,
+    pub unk8: u64,
+    // ? Light space bounds (min/max)
+    pub unk10: Vector4,
+    pub unk20: Vector4,
+    // ? Linear color, w = intensity
+    pub unk30: Vector4,
+    // ? x = attenuation radius, z/w = spot cone inner/outer angle (degrees)
+    pub unk40: Vector4,
+    // ? 0 = point, 1 = spot
+    pub unk50: u32,
+    pub unk54: [u32; 3],
+}
+
+impl PointLightTag {
+    /// Whether `unk30` and `unk40.x` hold values that could be a color and a radius
+    pub fn is_plausible(&self) -> bool {
+        let color = Vec4::new(self.unk30.x, self.unk30.y, self.unk30.z, self.unk30.w);
+        color.is_finite()
+            && color.cmpge(Vec4::ZERO).all()
+            && color.w > 0.0
+            && self.unk40.x.is_finite()
+            && self.unk40.x > 0.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightShape {
+    Point,
+    /// Cone angles in degrees. The light is assumed to point along its local X axis
+    Spot {
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// Radius used for lights without a usable radius
+pub const DEFAULT_LIGHT_RADIUS: f32 = 16.0;
+
+/// Light parameters parsed from a [`PointLightTag`]. The field mapping is guessed, see the comments on the tag
+#[derive(Clone, Debug)]
+pub struct PointLight {
+    pub tag: TagHash,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub radius: f32,
+    pub shape: LightShape,
+}
+
+impl PointLight {
+    /// Values that don't look valid are replaced with defaults, `fallback_radius` is used when the tag has no usable radius
+    pub fn from_tag(tag: TagHash, data: &PointLightTag, fallback_radius: f32) -> Self {
+        let color = Vec4::new(data.unk30.x, data.unk30.y, data.unk30.z, data.unk30.w);
+        let valid_color = color.is_finite() && color.truncate().cmpge(Vec3::ZERO).all();
+
+        let radius = if data.unk40.x.is_finite() && data.unk40.x > 0.0 {
+            data.unk40.x
+        } else {
+            fallback_radius
+        };
+
+        let (inner_angle, outer_angle) = (data.unk40.z, data.unk40.w);
+        let shape = if data.unk50 == 1
+            && inner_angle.is_finite()
+            && outer_angle.is_finite()
+            && 0.0 < outer_angle
+            && outer_angle <= 180.0
+        {
+            LightShape::Spot {
+                inner_angle: inner_angle.clamp(0.0, outer_angle),
+                outer_angle,
+            }
+        } else {
+            LightShape::Point
+        };
+
+        Self {
+            tag,
+            color: if valid_color {
+                color.truncate()
+            } else {
+                Vec3::ONE
+            },
+            intensity: if valid_color && color.w > 0.0 {
+                color.w
+            } else {
+                1.0
+            },
+            radius,
+            shape,
+        }
+    }
+}
+
 /// Decal collection resource
 #[derive(BinRead, Debug, Clone)]
 pub struct Unk80806e68 {
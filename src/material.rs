@@ -1,8 +1,11 @@
 use std::ops::Deref;
 
+use crate::material_patch::ShaderStage;
+use crate::packages::package_manager;
 use crate::render::{DeviceContextSwapchain, RenderData};
 use crate::structure::{RelPointer, TablePointer, Tag};
 use crate::types::Vector4;
+use anyhow::anyhow;
 use binrw::{BinRead, NullString};
 use destiny_pkg::TagHash;
 
@@ -49,6 +52,21 @@ pub struct Unk808071e8 {
     pub unk34c: TagHash,
 }
 
+impl Unk808071e8 {
+    /// Pixel shader cb0 contents, either from the buffer tag in `unk34c` or from the material itself
+    pub fn pixel_constants(&self) -> anyhow::Result<Vec<Vector4>> {
+        if !self.unk34c.is_valid() {
+            return Ok(self.unk318.to_vec());
+        }
+
+        let entry = package_manager().get_entry(self.unk34c)?;
+        let data = package_manager().read_tag(entry.reference)?;
+        Ok(bytemuck::try_cast_slice(&data)
+            .map_err(|e| anyhow!("Invalid cbuffer size: {e:?}"))?
+            .to_vec())
+    }
+}
+
 #[derive(BinRead, Debug, Clone)]
 pub struct Unk80807211 {
     /// Material slot to assign to
@@ -64,6 +82,41 @@ pub struct Unk808073f3 {
     pub unkc: u32,
 }
 
+/// Pass a material is drawn in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderStage {
+    /// Writes all GBuffer targets
+    Opaque,
+    /// Forward shaded, blended over the lit GBuffer
+    Transparent,
+    /// Depth prepass and other materials without color output, not drawn
+    DepthOnly,
+}
+
+impl RenderStage {
+    /// Classifies a material by its stage field (`unk8`) and the number of render targets written by its pixel shader.
+    ///
+    /// This is a heuristic, the meaning of `unk8` hasn't been confirmed. It keys off:
+    /// - `unk8 == 2`: always depth-only (seen on depth prepass materials)
+    /// - the `SV_Target` count of the pixel shader's output signature, when it could be parsed: 3 or more is a
+    ///   GBuffer (opaque) shader, 0 is depth-only and 1-2 is a forward (transparent) shader
+    /// - otherwise `unk8 == 1` is treated as opaque, and anything else as depth-only
+    pub fn classify(unk8: u32, render_targets: Option<u32>) -> Self {
+        if unk8 == 2 {
+            return RenderStage::DepthOnly;
+        }
+
+        match render_targets {
+            // GBuffer shaders write RT0-RT2
+            Some(n) if n >= 3 => RenderStage::Opaque,
+            Some(0) => RenderStage::DepthOnly,
+            Some(_) => RenderStage::Transparent,
+            None if unk8 == 1 => RenderStage::Opaque,
+            None => RenderStage::DepthOnly,
+        }
+    }
+}
+
 pub struct Material(pub Unk808071e8, pub TagHash);
 
 impl Material {
@@ -71,11 +124,48 @@ impl Material {
         self.1
     }
 
-    pub fn bind(
+    pub fn render_stage(&self, render_data: &RenderData) -> RenderStage {
+        RenderStage::classify(
+            self.unk8,
+            render_data
+                .pshaders
+                .get(&self.pixel_shader.0)
+                .and_then(|(_, render_targets)| *render_targets),
+        )
+    }
+
+    pub fn bind_vertex_shader(
         &self,
         dcs: &DeviceContextSwapchain,
         render_data: &RenderData,
     ) -> anyhow::Result<()> {
+        if let Some(vs) = render_data.vshaders.get(&self.vertex_shader.0) {
+            unsafe {
+                dcs.context.VSSetShader(&vs.shader, None);
+            }
+            Ok(())
+        } else {
+            anyhow::bail!("No vertex shader bound");
+        }
+    }
+
+    pub fn bind_pixel_shader(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        render_data: &RenderData,
+    ) -> anyhow::Result<()> {
+        if let Some((ps, _)) = render_data.pshaders.get(&self.pixel_shader.0) {
+            unsafe {
+                dcs.context.PSSetShader(ps, None);
+            }
+            Ok(())
+        } else {
+            anyhow::bail!("No pixel shader bound");
+        }
+    }
+
+    /// Binds samplers, constant buffers and textures
+    pub fn bind_resources(&self, dcs: &DeviceContextSwapchain, render_data: &RenderData) {
         unsafe {
             for (si, s) in self.vs_samplers.iter().enumerate() {
                 dcs.context.VSSetSamplers(
@@ -104,21 +194,20 @@ impl Material {
                 dcs.context.VSSetConstantBuffers(0, Some(&[None]));
             }
 
-            if let Some((vs, Some(input_layout))) = render_data.vshaders.get(&self.vertex_shader.0)
-            {
-                dcs.context.IASetInputLayout(input_layout);
-                dcs.context.VSSetShader(vs, None);
-            } else {
-                anyhow::bail!("No vertex shader/input layout bound");
-            }
-
-            if let Some(ps) = render_data.pshaders.get(&self.pixel_shader.0) {
-                dcs.context.PSSetShader(ps, None);
-            } else {
-                anyhow::bail!("No pixel shader bound");
-            }
+            let overrides = render_data.texture_overrides.get(&self.tag().0);
+            let texture_override = |stage: ShaderStage, slot: u32| {
+                overrides?
+                    .iter()
+                    .find(|o| o.stage == stage && o.slot == slot)
+            };
 
             for p in &self.vs_textures {
+                if let Some(o) = texture_override(ShaderStage::Vertex, p.index) {
+                    dcs.context
+                        .VSSetShaderResources(p.index, Some(&[o.view.clone()]));
+                    continue;
+                }
+
                 // TODO(cohae): Bind error texture on error
                 if let Some(t) = render_data.textures.get(&p.texture.0) {
                     dcs.context
@@ -127,6 +216,12 @@ impl Material {
             }
 
             for p in &self.ps_textures {
+                if let Some(o) = texture_override(ShaderStage::Pixel, p.index) {
+                    dcs.context
+                        .PSSetShaderResources(p.index, Some(&[o.view.clone()]));
+                    continue;
+                }
+
                 // TODO(cohae): Bind error texture on error
                 if let Some(t) = render_data.textures.get(&p.texture.0) {
                     dcs.context
@@ -134,8 +229,6 @@ impl Material {
                 }
             }
         }
-
-        Ok(())
     }
 }
 
@@ -166,3 +259,43 @@ pub struct Unk80806cb5 {
 }
 
 pub type Unk80806cb6 = Unk80806cb5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_stage_by_render_targets() {
+        for unk8 in [0, 1, 3] {
+            assert_eq!(RenderStage::classify(unk8, Some(0)), RenderStage::DepthOnly);
+            assert_eq!(
+                RenderStage::classify(unk8, Some(1)),
+                RenderStage::Transparent
+            );
+            assert_eq!(
+                RenderStage::classify(unk8, Some(2)),
+                RenderStage::Transparent
+            );
+            assert_eq!(RenderStage::classify(unk8, Some(3)), RenderStage::Opaque);
+            assert_eq!(RenderStage::classify(unk8, Some(4)), RenderStage::Opaque);
+        }
+    }
+
+    #[test]
+    fn render_stage_depth_prepass() {
+        // unk8 == 2 wins over the render target count
+        for render_targets in [None, Some(0), Some(1), Some(3)] {
+            assert_eq!(
+                RenderStage::classify(2, render_targets),
+                RenderStage::DepthOnly
+            );
+        }
+    }
+
+    #[test]
+    fn render_stage_without_signature() {
+        assert_eq!(RenderStage::classify(1, None), RenderStage::Opaque);
+        assert_eq!(RenderStage::classify(0, None), RenderStage::DepthOnly);
+        assert_eq!(RenderStage::classify(3, None), RenderStage::DepthOnly);
+    }
+}
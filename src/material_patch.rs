@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use destiny_pkg::TagHash;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use windows::Win32::Graphics::Direct3D11::ID3D11ShaderResourceView;
+
+use crate::render::{ConstantBuffer, DeviceContextSwapchain, RenderData};
+use crate::texture::Texture;
+use crate::types::Vector4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Pixel,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TextureOverride {
+    /// Unbinds the slot
+    Disabled,
+    /// Binds another texture tag
+    Tag(u32),
+    /// Binds a DDS or PNG file
+    File(PathBuf),
+}
+
+/// Overridden cb0 rows and texture slots of a single material stage
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct StagePatch {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub constants: BTreeMap<usize, [f32; 4]>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub textures: BTreeMap<u32, TextureOverride>,
+}
+
+impl StagePatch {
+    pub fn is_empty(&self) -> bool {
+        self.constants.is_empty() && self.textures.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct MaterialPatch {
+    #[serde(default)]
+    pub vertex: StagePatch,
+    #[serde(default)]
+    pub pixel: StagePatch,
+}
+
+impl MaterialPatch {
+    pub fn stage(&self, stage: ShaderStage) -> &StagePatch {
+        match stage {
+            ShaderStage::Vertex => &self.vertex,
+            ShaderStage::Pixel => &self.pixel,
+        }
+    }
+
+    pub fn stage_mut(&mut self, stage: ShaderStage) -> &mut StagePatch {
+        match stage {
+            ShaderStage::Vertex => &mut self.vertex,
+            ShaderStage::Pixel => &mut self.pixel,
+        }
+    }
+}
+
+/// Material overrides of the current map, stored in `material_patches/<map>.yml` and applied to the render data
+/// when they change
+#[derive(Serialize, Deserialize, Default)]
+pub struct MaterialPatches {
+    /// Keyed by material tag
+    materials: BTreeMap<u32, MaterialPatch>,
+
+    #[serde(skip)]
+    map: Option<TagHash>,
+    /// Materials that need to be (re)applied
+    #[serde(skip)]
+    pending: BTreeSet<u32>,
+}
+
+impl MaterialPatches {
+    fn path_for_map(map: TagHash) -> PathBuf {
+        PathBuf::from("material_patches").join(format!("{:08x}.yml", map.0))
+    }
+
+    /// Swaps in the patches of the given map if it isn't the current one. Materials patched by either map are
+    /// marked as pending, so the previous map's changes get reverted
+    pub fn select_map(&mut self, map: TagHash) {
+        if self.map == Some(map) {
+            return;
+        }
+
+        let loaded = match std::fs::read_to_string(Self::path_for_map(map)) {
+            Ok(data) => match serde_yaml::from_str::<MaterialPatches>(&data) {
+                Ok(p) => {
+                    info!(
+                        "Loaded material patches for map {map} ({} materials)",
+                        p.materials.len()
+                    );
+                    p.materials
+                }
+                Err(e) => {
+                    error!("Failed to parse material patches for map {map}: {e}");
+                    Default::default()
+                }
+            },
+            Err(_) => Default::default(),
+        };
+
+        self.replace(map, loaded);
+    }
+
+    fn replace(&mut self, map: TagHash, materials: BTreeMap<u32, MaterialPatch>) {
+        self.pending.extend(self.materials.keys());
+        self.pending.extend(materials.keys());
+        self.materials = materials;
+        self.map = Some(map);
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let Some(map) = self.map else {
+            anyhow::bail!("No map selected");
+        };
+
+        let path = Self::path_for_map(map);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, material: TagHash) -> Option<&MaterialPatch> {
+        self.materials.get(&material.0)
+    }
+
+    /// Modifies the patch of a material, creating it if needed. Empty patches are removed afterwards
+    pub fn modify(&mut self, material: TagHash, f: impl FnOnce(&mut MaterialPatch)) {
+        let patch = self.materials.entry(material.0).or_default();
+        f(patch);
+        if patch.vertex.is_empty() && patch.pixel.is_empty() {
+            self.materials.remove(&material.0);
+        }
+
+        self.pending.insert(material.0);
+    }
+
+    pub fn take_pending(&mut self) -> Vec<(u32, Option<MaterialPatch>)> {
+        std::mem::take(&mut self.pending)
+            .into_iter()
+            .map(|m| (m, self.materials.get(&m).cloned()))
+            .collect()
+    }
+}
+
+/// A replaced texture slot. `view` is `None` for disabled slots
+pub struct TextureSlotOverride {
+    pub stage: ShaderStage,
+    pub slot: u32,
+    pub view: Option<ID3D11ShaderResourceView>,
+}
+
+/// Rewrites the constant buffers and texture overrides of a material. `None` restores the original values
+pub fn apply_material_patch(
+    dcs: &Rc<DeviceContextSwapchain>,
+    render_data: &mut RenderData,
+    material: u32,
+    patch: Option<&MaterialPatch>,
+) {
+    let Some(m) = render_data.materials.get(&material) else {
+        warn!(
+            "Material {} is not loaded, can't apply its patch",
+            TagHash(material)
+        );
+        return;
+    };
+
+    let vs_constants = m.unk98.to_vec();
+    let ps_constants = match m.pixel_constants() {
+        Ok(c) => c,
+        Err(e) => {
+            error!(
+                "Failed to read the pixel shader constants of material {}: {e}",
+                TagHash(material)
+            );
+            vec![]
+        }
+    };
+
+    // Textures are replaced in slots that the material binds
+    let mut texture_slots = vec![];
+    if let Some(patch) = patch {
+        for (stage, textures) in [
+            (ShaderStage::Vertex, &m.vs_textures),
+            (ShaderStage::Pixel, &m.ps_textures),
+        ] {
+            for t in textures.iter() {
+                if let Some(o) = patch.stage(stage).textures.get(&t.index) {
+                    texture_slots.push((stage, t.index, t.texture, o.clone()));
+                }
+            }
+        }
+    }
+
+    for (stage, cbuffers, mut constants) in [
+        (
+            ShaderStage::Vertex,
+            &mut render_data.cbuffers_vs,
+            vs_constants,
+        ),
+        (
+            ShaderStage::Pixel,
+            &mut render_data.cbuffers_ps,
+            ps_constants,
+        ),
+    ] {
+        let rows = patch.map(|p| &p.stage(stage).constants);
+        let patched = rows.is_some_and(|r| !r.is_empty());
+        if constants.is_empty() || (!patched && !cbuffers.contains_key(&material)) {
+            continue;
+        }
+
+        for (&row, &[x, y, z, w]) in rows.into_iter().flatten() {
+            if let Some(c) = constants.get_mut(row) {
+                *c = Vector4 { x, y, z, w };
+            } else {
+                warn!(
+                    "{stage:?} constant {row} of material {} is out of range",
+                    TagHash(material)
+                );
+            }
+        }
+
+        let result = if let Some(cb) = cbuffers.get(&material) {
+            cb.write_array(&constants)
+        } else {
+            // Materials with all-zero constants don't get a buffer at load time
+            ConstantBuffer::create_array_init(dcs.clone(), &constants).map(|cb| {
+                cbuffers.insert(material, cb);
+            })
+        };
+
+        if let Err(e) = result {
+            error!(
+                "Failed to update the {stage:?} constants of material {}: {e}",
+                TagHash(material)
+            );
+        }
+    }
+
+    let mut overrides = vec![];
+    for (stage, slot, original, o) in texture_slots {
+        let view = match o {
+            TextureOverride::Disabled => None,
+            TextureOverride::Tag(tag) => {
+                if !render_data.textures.contains_key(&tag) {
+                    match Texture::load(dcs, TagHash(tag)) {
+                        Ok(t) => {
+                            render_data.textures.insert(tag, t);
+                        }
+                        Err(e) => {
+                            error!("Failed to load texture {}: {e}", TagHash(tag));
+                            continue;
+                        }
+                    }
+                }
+                render_data.textures.get(&tag).map(|t| t.view.clone())
+            }
+            TextureOverride::File(path) => {
+                // Match the color space of the texture that's being replaced
+                let srgb = render_data
+                    .textures
+                    .get(&original.0)
+                    .is_some_and(|t| t.format.is_srgb());
+                match Texture::load_file(dcs, &path, srgb) {
+                    Ok(t) => Some(t.view),
+                    Err(e) => {
+                        error!("Failed to load texture {}: {e}", path.display());
+                        continue;
+                    }
+                }
+            }
+        };
+
+        overrides.push(TextureSlotOverride { stage, slot, view });
+    }
+
+    if overrides.is_empty() {
+        render_data.texture_overrides.remove(&material);
+    } else {
+        render_data.texture_overrides.insert(material, overrides);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constant_patch(row: usize) -> MaterialPatch {
+        let mut patch = MaterialPatch::default();
+        patch.pixel.constants.insert(row, [1.0, 2.0, 3.0, 4.0]);
+        patch
+    }
+
+    #[test]
+    fn modify_removes_empty_patches() {
+        let material = TagHash(0x80801234);
+        let mut patches = MaterialPatches::default();
+
+        patches.modify(material, |p| {
+            p.vertex.constants.insert(2, [0.0; 4]);
+            p.pixel.textures.insert(1, TextureOverride::Disabled);
+        });
+        assert!(patches.get(material).is_some());
+
+        patches.modify(material, |p| {
+            p.vertex.constants.remove(&2);
+        });
+        assert_eq!(
+            patches.get(material).map(|p| p.pixel.textures.len()),
+            Some(1)
+        );
+
+        patches.modify(material, |p| {
+            p.pixel.textures.remove(&1);
+        });
+        assert!(patches.get(material).is_none());
+        // The removal still has to be applied, to restore the original values
+        assert_eq!(patches.take_pending(), [(material.0, None)]);
+    }
+
+    #[test]
+    fn modify_without_changes_leaves_nothing_behind() {
+        let material = TagHash(0x80801234);
+        let mut patches = MaterialPatches::default();
+        patches.modify(material, |_| {});
+        assert!(patches.get(material).is_none());
+    }
+
+    #[test]
+    fn switching_maps_marks_old_and_new_materials_pending() {
+        let mut patches = MaterialPatches::default();
+        patches.replace(
+            TagHash(1),
+            BTreeMap::from([(10, constant_patch(0)), (11, constant_patch(1))]),
+        );
+        assert_eq!(
+            patches
+                .take_pending()
+                .into_iter()
+                .map(|(m, _)| m)
+                .collect::<Vec<_>>(),
+            [10, 11]
+        );
+
+        patches.replace(
+            TagHash(2),
+            BTreeMap::from([(11, constant_patch(2)), (12, constant_patch(3))]),
+        );
+        assert_eq!(
+            patches.take_pending(),
+            [
+                (10, None),
+                (11, Some(constant_patch(2))),
+                (12, Some(constant_patch(3)))
+            ]
+        );
+
+        // Selecting the current map again doesn't reload it
+        patches.select_map(TagHash(2));
+        assert!(patches.take_pending().is_empty());
+    }
+
+    #[test]
+    fn texture_overrides_round_trip_through_yaml() {
+        let mut patch = constant_patch(3);
+        patch.vertex.textures.insert(0, TextureOverride::Disabled);
+        patch
+            .pixel
+            .textures
+            .insert(1, TextureOverride::Tag(0x80bc1234));
+        patch.pixel.textures.insert(
+            5,
+            TextureOverride::File(PathBuf::from("textures/replacement.dds")),
+        );
+
+        let mut patches = MaterialPatches::default();
+        patches.modify(TagHash(0x80801234), |p| *p = patch.clone());
+
+        let yaml = serde_yaml::to_string(&patches).unwrap();
+        let loaded = serde_yaml::from_str::<MaterialPatches>(&yaml).unwrap();
+        assert_eq!(loaded.materials, patches.materials);
+        assert_eq!(loaded.get(TagHash(0x80801234)), Some(&patch));
+    }
+
+    #[test]
+    fn empty_stages_are_optional_in_yaml() {
+        let loaded = serde_yaml::from_str::<MaterialPatches>(
+            "materials:\n  42:\n    pixel:\n      textures:\n        2: Disabled\n",
+        )
+        .unwrap();
+        let patch = loaded.get(TagHash(42)).unwrap();
+        assert!(patch.vertex.is_empty());
+        assert_eq!(patch.pixel.textures[&2], TextureOverride::Disabled);
+    }
+}
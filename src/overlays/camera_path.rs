@@ -0,0 +1,159 @@
+use destiny_pkg::TagHash;
+use tracing::{error, info};
+use winit::window::Window;
+
+use crate::camera::FpsCamera;
+use crate::camera_path::{CameraKeyframe, CameraPath, CameraPathPlayer};
+use crate::icons::{ICON_CAMERA, ICON_DELETE, ICON_PLAY, ICON_STOP};
+use crate::map::MapDataList;
+use crate::resources::Resources;
+
+use super::gui::OverlayProvider;
+
+pub struct CameraPathOverlay {
+    pub path: CameraPath,
+    pub player: CameraPathPlayer,
+
+    /// Time between newly recorded keyframes, in seconds
+    pub keyframe_spacing: f32,
+
+    loaded_map: Option<TagHash>,
+}
+
+impl Default for CameraPathOverlay {
+    fn default() -> Self {
+        Self {
+            path: CameraPath::default(),
+            player: CameraPathPlayer::default(),
+            keyframe_spacing: 2.0,
+            loaded_map: None,
+        }
+    }
+}
+
+impl CameraPathOverlay {
+    /// Moves the camera along the path if playback is active. Returns true if the camera is being controlled by the path
+    pub fn update_camera(&mut self, camera: &mut FpsCamera, delta: f32) -> bool {
+        if let Some(keyframe) = self.player.advance(&self.path, delta) {
+            keyframe.apply(camera);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn load_for_map(&mut self, map: TagHash) {
+        self.player.stop();
+        self.path = match CameraPath::load(map) {
+            Ok(p) => {
+                info!(
+                    "Loaded camera path for map {map} ({} keyframes)",
+                    p.keyframes().len()
+                );
+                p
+            }
+            Err(_) => CameraPath::default(),
+        };
+        self.loaded_map = Some(map);
+    }
+}
+
+impl OverlayProvider for CameraPathOverlay {
+    fn create_overlay(&mut self, ui: &mut imgui::Ui, _window: &Window, resources: &mut Resources) {
+        let current_map = resources
+            .get::<MapDataList>()
+            .unwrap()
+            .current_map()
+            .map(|m| m.hash);
+
+        if current_map != self.loaded_map {
+            if let Some(map) = current_map {
+                self.load_for_map(map);
+            }
+        }
+
+        ui.window(format!("{} Camera Path", ICON_CAMERA)).build(|| {
+            let mut camera = resources.get_mut::<FpsCamera>().unwrap();
+            if ui.button("Record keyframe") {
+                let time = if self.path.is_empty() {
+                    0.0
+                } else {
+                    self.path.duration() + self.keyframe_spacing
+                };
+                self.path.insert(CameraKeyframe::from_camera(&camera, time));
+            }
+            ui.same_line();
+            ui.set_next_item_width(96.0);
+            ui.input_float("Spacing (s)", &mut self.keyframe_spacing)
+                .build();
+            self.keyframe_spacing = self.keyframe_spacing.max(0.01);
+
+            ui.separator();
+            if self.player.playing {
+                if ui.button(format!("{} Stop", ICON_STOP)) {
+                    self.player.stop();
+                }
+            } else if ui.button(format!("{} Play", ICON_PLAY)) {
+                self.player.play();
+            }
+            ui.same_line();
+            ui.checkbox("Loop", &mut self.player.looping);
+
+            let mut fixed_timestep = self.player.fixed_timestep.is_some();
+            if ui.checkbox("Fixed timestep (60Hz)", &mut fixed_timestep) {
+                self.player.fixed_timestep = fixed_timestep.then_some(1.0 / 60.0);
+            }
+            ui.text(format!(
+                "{:.2}s / {:.2}s",
+                self.player.time,
+                self.path.duration()
+            ));
+
+            ui.separator();
+            let mut to_remove = None;
+            let mut to_goto = None;
+            for (i, k) in self.path.keyframes().iter().enumerate() {
+                if ui.button(format!("{}##remove_keyframe{i}", ICON_DELETE)) {
+                    to_remove = Some(i);
+                }
+                ui.same_line();
+                if ui.button(format!("Go##goto_keyframe{i}")) {
+                    to_goto = Some(*k);
+                }
+                ui.same_line();
+                ui.text(format!(
+                    "{:6.2}s - ({:.1}, {:.1}, {:.1}) FOV {:.0}",
+                    k.time, k.position.x, k.position.y, k.position.z, k.fov
+                ));
+            }
+
+            if let Some(i) = to_remove {
+                self.path.remove(i);
+            }
+
+            if let Some(k) = to_goto {
+                self.player.stop();
+                k.apply(&mut camera);
+            }
+
+            ui.separator();
+            if let Some(map) = self.loaded_map {
+                if ui.button("Save") {
+                    match self.path.save(map) {
+                        Ok(_) => info!("Saved camera path for map {map}"),
+                        Err(e) => error!("Failed to save camera path: {e}"),
+                    }
+                }
+                ui.same_line();
+                if ui.button("Reload") {
+                    self.load_for_map(map);
+                }
+                ui.same_line();
+            }
+            if ui.button("Clear") {
+                self.player.stop();
+                self.path.clear();
+            }
+        });
+    }
+}
@@ -1,6 +1,7 @@
 use strum::{EnumCount, VariantNames};
 use winit::window::Window;
 
+use crate::camera::ProjectionMode;
 use crate::icons::ICON_BUG;
 use crate::map_resources::MapResource;
 use crate::resources::Resources;
@@ -13,6 +14,8 @@ pub struct CameraPositionOverlay {
     pub show_map_resource_label: bool,
     pub map_resource_filter: [bool; MapResource::COUNT],
     pub map_resource_distance: f32,
+    /// Draws the bounds of cubemap volumes
+    pub show_cubemap_volumes: bool,
 
     pub render_scale: f32,
     pub render_scale_changed: bool,
@@ -30,7 +33,35 @@ impl OverlayProvider for CameraPositionOverlay {
             self.render_scale_changed =
                 ui.slider("Render Scale", 50.0, 200.0, &mut self.render_scale);
             ui.slider("Speed Multiplier", 0.01, 10.0, &mut camera.speed_mul);
+
+            let mut projection_mode = camera.projection.mode as usize;
+            if ui.combo_simple_string("Projection", &mut projection_mode, ProjectionMode::VARIANTS)
+            {
+                camera.projection.mode = ProjectionMode::from_repr(projection_mode)
+                    .unwrap_or(ProjectionMode::Perspective);
+            }
+            match camera.projection.mode {
+                ProjectionMode::Perspective => {
+                    ui.slider("FOV", 10.0, 150.0, &mut camera.projection.fov);
+                    ui.input_float("Near plane", &mut camera.projection.near)
+                        .display_format("%.5f")
+                        .build();
+                    camera.projection.near = camera.projection.near.max(0.00001);
+                }
+                ProjectionMode::Orthographic => {
+                    ui.slider(
+                        "View height",
+                        1.0,
+                        4000.0,
+                        &mut camera.projection.orthographic_height,
+                    );
+                    if ui.button("Top-down") {
+                        camera.look_top_down();
+                    }
+                }
+            }
             ui.checkbox("Render lights", &mut self.render_lights);
+            ui.checkbox("Show cubemap volume bounds", &mut self.show_cubemap_volumes);
             ui.separator();
             ui.checkbox("Show map resources", &mut self.show_map_resources);
             if self.show_map_resources {
@@ -0,0 +1,48 @@
+use glam::Vec2;
+use imgui::ImColor32;
+use winit::window::Window;
+
+use crate::camera::FpsCamera;
+use crate::render::debug_draw::DebugShapes;
+use crate::resources::Resources;
+
+use super::gui::OverlayProvider;
+
+/// Draws the [`DebugShapes`] text labels at their projected world positions
+pub struct DebugTextOverlay;
+
+impl OverlayProvider for DebugTextOverlay {
+    fn create_overlay(&mut self, ui: &mut imgui::Ui, window: &Window, resources: &mut Resources) {
+        let shapes = resources.get::<DebugShapes>().unwrap();
+        if shapes.texts.is_empty() {
+            return;
+        }
+
+        let screen_size = ui.io().display_size;
+        let window_dims = window.inner_size();
+        let proj_view = resources
+            .get::<FpsCamera>()
+            .unwrap()
+            .projection_view_matrix(window_dims.width as f32 / window_dims.height as f32);
+
+        let draw_list = ui.get_background_draw_list();
+        for text in &shapes.texts {
+            let clip = proj_view * text.position.extend(1.0);
+            if clip.w <= 0.0 {
+                continue;
+            }
+
+            let ndc = clip.truncate() / clip.w;
+            let screen_point = Vec2::new(
+                ((ndc.x + 1.0) * 0.5) * screen_size[0],
+                ((1.0 - ndc.y) * 0.5) * screen_size[1],
+            );
+            let c = text.color;
+            draw_list.add_text(
+                screen_point.to_array(),
+                ImColor32::from_rgba_f32s(c.x, c.y, c.z, c.w),
+                &text.text,
+            );
+        }
+    }
+}
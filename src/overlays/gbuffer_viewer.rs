@@ -3,16 +3,38 @@ use imgui::{Condition, TreeNodeFlags, WindowFlags};
 use std::{fmt::Display, fmt::Formatter};
 use winit::window::Window;
 
+use crate::culling::{CullingSettings, CullingStats, LayerStats};
+use crate::lod::{LodSettings, LOD_LEVELS, MAX_LOD_BIAS};
+use crate::render::compositor::CompositorShaders;
+use crate::render::debug_shaders::GeometryView;
+use crate::render::DrawStats;
 use crate::{map::MapDataList, resources::Resources};
 
 use super::gui::OverlayProvider;
 
 pub struct GBufferInfoOverlay {
-    pub composition_mode: usize,
+    pub composition_mode: CompositionMode,
 
     pub renderlayer_statics: bool,
     pub renderlayer_terrain: bool,
     pub renderlayer_entities: bool,
+    pub renderlayer_decals: bool,
+    pub renderlayer_transparent: bool,
+
+    /// Colors entity parts by the index of the material they resolved to
+    pub debug_entity_materials: bool,
+
+    pub debug_light_radii: bool,
+    pub debug_decal_projectors: bool,
+    pub debug_entity_bounds: bool,
+    pub debug_picking_ray: bool,
+    /// Draws the view frustum from the moment this was enabled
+    pub debug_frozen_frustum: bool,
+
+    /// Replaces the shading of opaque geometry
+    pub geometry_view: GeometryView,
+    /// Draws opaque geometry edges on top of the scene
+    pub wireframe: bool,
 }
 
 impl OverlayProvider for GBufferInfoOverlay {
@@ -21,9 +43,41 @@ impl OverlayProvider for GBufferInfoOverlay {
             .flags(WindowFlags::NO_TITLE_BAR)
             .size([178.0, 72.0], Condition::FirstUseEver)
             .build(|| {
-                ui.combo(" ", &mut self.composition_mode, COMPOSITOR_MODES, |v| {
-                    format!("{v}").into()
-                });
+                let compositor = resources.get::<CompositorShaders>().unwrap();
+                let selected = match &self.composition_mode {
+                    CompositionMode::BuiltIn(mode) => {
+                        COMPOSITOR_MODES.iter().position(|m| m == mode)
+                    }
+                    CompositionMode::User(name) => compositor
+                        .user_modes
+                        .iter()
+                        .position(|m| &m.name == name)
+                        .map(|i| COMPOSITOR_MODES.len() + i),
+                };
+                // User modes can disappear when their file is removed. Combined is shown until the file comes back
+                let mut index = selected.unwrap_or(0);
+                let modes: Vec<String> = COMPOSITOR_MODES
+                    .iter()
+                    .map(|m| m.to_string())
+                    .chain(
+                        compositor
+                            .user_modes
+                            .iter()
+                            .map(|m| format!("{} (user)", m.name)),
+                    )
+                    .collect();
+                if ui.combo_simple_string(" ", &mut index, &modes) {
+                    self.composition_mode = match COMPOSITOR_MODES.get(index) {
+                        Some(mode) => CompositionMode::BuiltIn(*mode),
+                        None => CompositionMode::User(
+                            compositor.user_modes[index - COMPOSITOR_MODES.len()]
+                                .name
+                                .clone(),
+                        ),
+                    };
+                }
+                drop(compositor);
+
                 let mut maps = resources.get_mut::<MapDataList>().unwrap();
                 let mut current_map = maps.current_map;
                 ui.combo("Map", &mut current_map, &maps.maps, |m| {
@@ -37,14 +91,112 @@ impl OverlayProvider for GBufferInfoOverlay {
                     ui.checkbox("Statics", &mut self.renderlayer_statics);
                     ui.checkbox("Terrain", &mut self.renderlayer_terrain);
                     ui.checkbox("Entities", &mut self.renderlayer_entities);
+                    ui.checkbox("Decals", &mut self.renderlayer_decals);
+                    ui.checkbox("Transparent", &mut self.renderlayer_transparent);
+                    ui.unindent();
+                }
+
+                if ui.collapsing_header("Debug Views", TreeNodeFlags::empty()) {
+                    ui.indent();
+                    let mut view_index = GeometryView::SELECTABLE
+                        .iter()
+                        .position(|v| *v == self.geometry_view)
+                        .unwrap_or(0);
+                    if ui.combo("Geometry", &mut view_index, GeometryView::SELECTABLE, |v| {
+                        format!("{v}").into()
+                    }) {
+                        self.geometry_view = GeometryView::SELECTABLE[view_index];
+                    }
+                    ui.checkbox("Wireframe", &mut self.wireframe);
+                    ui.checkbox("Entity material indices", &mut self.debug_entity_materials);
+                    ui.checkbox("Light radii", &mut self.debug_light_radii);
+                    ui.checkbox("Decal projectors", &mut self.debug_decal_projectors);
+                    ui.checkbox("Entity bounds", &mut self.debug_entity_bounds);
+                    ui.checkbox("Picking ray", &mut self.debug_picking_ray);
+                    ui.checkbox("Freeze frustum", &mut self.debug_frozen_frustum);
+                    ui.unindent();
+                }
+
+                if ui.collapsing_header("Culling", TreeNodeFlags::empty()) {
+                    ui.indent();
+                    let mut settings = resources.get_mut::<CullingSettings>().unwrap();
+                    ui.checkbox("Frustum culling", &mut settings.frustum_culling);
+                    ui.checkbox("Distance culling", &mut settings.distance_culling);
+                    if settings.distance_culling {
+                        ui.slider("Max distance", 10.0, 10000.0, &mut settings.max_distance);
+                    }
+
+                    let stats = resources.get::<CullingStats>().unwrap();
+                    let layer_row = |name: &str, s: &LayerStats| {
+                        ui.text(format!("{name}: {}/{} visible", s.visible, s.total));
+                        ui.text(format!(
+                            "  LOD 0-3: {} / {} / {} / {}",
+                            s.lods[0], s.lods[1], s.lods[2], s.lods[3]
+                        ));
+                    };
+                    layer_row("Statics", &stats.statics);
+                    layer_row("Terrain", &stats.terrain);
+                    layer_row("Entities", &stats.entities);
+                    ui.unindent();
+                }
+
+                if ui.collapsing_header("Level of Detail", TreeNodeFlags::empty()) {
+                    ui.indent();
+                    let mut settings = resources.get_mut::<LodSettings>().unwrap();
+                    ui.slider("LOD bias", -MAX_LOD_BIAS, MAX_LOD_BIAS, &mut settings.bias);
+
+                    let mut force_lod = settings.force_lod.is_some();
+                    if ui.checkbox("Force LOD", &mut force_lod) {
+                        settings.force_lod = force_lod.then_some(0);
+                    }
+                    if let Some(level) = settings.force_lod.as_mut() {
+                        ui.slider("Level", 0, LOD_LEVELS as u8 - 1, level);
+                    }
+                    ui.unindent();
+                }
+
+                if ui.collapsing_header("Draw Stats", TreeNodeFlags::empty()) {
+                    ui.indent();
+                    let stats = resources.get::<DrawStats>().unwrap();
+                    ui.text(format!("Draw calls: {}", stats.draw_calls));
+                    ui.text(format!("Shader binds: {}", stats.shader_binds));
+                    ui.text(format!("Material binds: {}", stats.material_binds));
+                    ui.text(format!(
+                        "Vertex buffer binds: {}",
+                        stats.vertex_buffer_binds
+                    ));
+                    ui.text(format!(
+                        "Constant buffer binds: {}",
+                        stats.constant_buffer_binds
+                    ));
+                    if stats.missing_input_layouts > 0 {
+                        ui.text_colored(
+                            [1.0, 0.6, 0.1, 1.0],
+                            format!("Skipped (no input layout): {}", stats.missing_input_layouts),
+                        );
+                    }
+                    ui.text(format!("Transparent draws: {}", stats.transparent_draws));
+                    ui.text(format!("Decals: {}", stats.decals));
+                    ui.text(format!("Lights: {}", stats.lights));
+                    ui.text(format!("Max lights per tile: {}", stats.max_tile_lights));
+                    ui.text(format!("Cubemap volumes: {}", stats.cubemap_volumes));
                     ui.unindent();
                 }
             });
     }
 }
 
+/// Compositor mode picked in the options. User modes are kept by name, so the selection doesn't move when modes are
+/// added or removed
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompositionMode {
+    BuiltIn(CompositorMode),
+    /// [`crate::render::compositor::UserCompositorMode::name`]
+    User(String),
+}
+
 #[repr(u32)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompositorMode {
     /// Rendered output
     Combined = 0,
@@ -63,7 +215,9 @@ pub enum CompositorMode {
     Transmission = 8,
     VertexAO = 9,
     Iridescence = 10,
-    // Matcap = 11,
+    Matcap = 11,
+    /// Layer count heatmap, used by the overdraw geometry view
+    Overdraw = 12,
 }
 
 pub const COMPOSITOR_MODES: &[CompositorMode] = &[
@@ -78,7 +232,7 @@ pub const COMPOSITOR_MODES: &[CompositorMode] = &[
     CompositorMode::Iridescence,
     CompositorMode::TextureAO,
     CompositorMode::VertexAO,
-    // CompositorMode::Matcap,
+    CompositorMode::Matcap,
 ];
 
 impl Display for CompositorMode {
@@ -95,7 +249,8 @@ impl Display for CompositorMode {
             CompositorMode::Transmission => "Transmission",
             CompositorMode::VertexAO => "Vertex AO",
             CompositorMode::Iridescence => "Iridescence",
-            // CompositorMode::Matcap => "Matcap",
+            CompositorMode::Matcap => "Matcap",
+            CompositorMode::Overdraw => "Overdraw",
         };
 
         f.write_str(name)
@@ -109,4 +264,6 @@ pub struct CompositorOptions {
     pub camera_dir: Vec4,
     pub mode: u32,
     pub light_count: u32,
+    /// Width of the light tile grid, see [`crate::render::lights::LightTiles`]
+    pub light_tiles_x: u32,
 }
@@ -0,0 +1,522 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use destiny_pkg::TagHash;
+use imgui::{Image, StyleColor, TextureId, TreeNodeFlags};
+use tracing::{error, info};
+use windows::core::Vtable;
+use winit::window::Window;
+
+use crate::dxbc::{DxbcResourceDefinitions, DxbcShader, DxbcShaderInputType};
+use crate::icons::ICON_PALETTE;
+use crate::material::{Unk808071e8, Unk80807211, Unk808073f3};
+use crate::material_patch::{MaterialPatches, ShaderStage, TextureOverride};
+use crate::packages::package_manager;
+use crate::render::DeviceContextSwapchain;
+use crate::resources::Resources;
+use crate::sampler::{GltfSampler, SamplerDesc};
+use crate::texture::{Texture, TextureHeader};
+use crate::types::Vector4;
+
+use super::gui::OverlayProvider;
+use super::shader_viewer::ShaderViewerOverlay;
+
+const THUMBNAIL_SIZE: f32 = 64.0;
+const PREVIEW_SIZE: f32 = 384.0;
+const PATCHED_COLOR: [f32; 4] = [1.0, 0.75, 0.2, 1.0];
+
+/// Texture bound to a material slot
+struct TextureSlot {
+    slot: u32,
+    tag: TagHash,
+    /// Name of the texture in the shader's resource definitions
+    name: Option<String>,
+    description: Result<String, String>,
+    /// Only 2D textures can be previewed
+    preview: Option<Texture>,
+    /// Tag or file path typed in to replace the texture with
+    replacement: String,
+}
+
+impl TextureSlot {
+    fn load(
+        dcs: &DeviceContextSwapchain,
+        t: &Unk80807211,
+        resources: Option<&DxbcResourceDefinitions>,
+    ) -> Self {
+        let header: Result<TextureHeader, String> = if t.texture.is_valid() {
+            package_manager()
+                .read_tag_struct(t.texture)
+                .map_err(|e| e.to_string())
+        } else {
+            Err("No texture".to_string())
+        };
+
+        let preview = match &header {
+            Ok(h) if h.depth <= 1 && !h.is_cubemap() => Texture::load(dcs, t.texture)
+                .map_err(|e| error!("Failed to load texture {}: {e}", t.texture))
+                .ok(),
+            _ => None,
+        };
+
+        Self {
+            slot: t.index,
+            tag: t.texture,
+            name: binding_name(resources, DxbcShaderInputType::Texture, t.index),
+            description: header.map(|h| {
+                let kind = if h.is_cubemap() {
+                    "cube"
+                } else if h.depth > 1 {
+                    "3D"
+                } else {
+                    "2D"
+                };
+                format!(
+                    "{}x{}x{} {kind}, {:?}",
+                    h.width, h.height, h.depth, h.format
+                )
+            }),
+            preview,
+            replacement: String::new(),
+        }
+    }
+}
+
+/// Sampler bound to a material slot
+struct SamplerSlot {
+    slot: u32,
+    sampler: Unk808073f3,
+    name: Option<String>,
+    desc: Result<SamplerDesc, String>,
+}
+
+impl SamplerSlot {
+    fn load(index: usize, s: &Unk808073f3, resources: Option<&DxbcResourceDefinitions>) -> Self {
+        // s0 is reserved, material samplers start at s1
+        let slot = 1 + index as u32;
+        Self {
+            slot,
+            sampler: s.clone(),
+            name: binding_name(resources, DxbcShaderInputType::Sampler, slot),
+            desc: SamplerDesc::load(s.sampler).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// A float4 of a constant buffer and the RDEF variables that overlap it
+struct ConstantRow {
+    value: Vector4,
+    variables: Vec<String>,
+}
+
+/// Resources of a single shader stage
+struct StageInfo {
+    stage: ShaderStage,
+    shader: TagHash,
+    textures: Vec<TextureSlot>,
+    samplers: Vec<SamplerSlot>,
+    /// Where the cb0 contents come from
+    constants_source: String,
+    constants: Result<Vec<ConstantRow>, String>,
+}
+
+impl StageInfo {
+    fn load(
+        dcs: &DeviceContextSwapchain,
+        stage: ShaderStage,
+        shader: TagHash,
+        textures: &[Unk80807211],
+        samplers: &[Unk808073f3],
+        constants_source: String,
+        constants: anyhow::Result<Vec<Vector4>>,
+    ) -> Self {
+        let parsed = if shader.is_valid() {
+            DxbcShader::load(shader)
+                .map_err(|e| error!("Failed to read {stage:?} shader {shader}: {e}"))
+                .ok()
+        } else {
+            None
+        };
+        let resources = parsed.as_ref().and_then(|s| s.resources.as_ref());
+
+        Self {
+            stage,
+            shader,
+            textures: textures
+                .iter()
+                .map(|t| TextureSlot::load(dcs, t, resources))
+                .collect(),
+            samplers: samplers
+                .iter()
+                .enumerate()
+                .map(|(i, s)| SamplerSlot::load(i, s, resources))
+                .collect(),
+            constants_source,
+            constants: constants
+                .map(|c| annotate_constants(&c, resources))
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Name of the resource bound to the given register, if the shader declares one
+fn binding_name(
+    resources: Option<&DxbcResourceDefinitions>,
+    input_type: DxbcShaderInputType,
+    slot: u32,
+) -> Option<String> {
+    resources?
+        .bindings_of_type(input_type)
+        .find(|b| (b.bind_point..b.bind_point + b.bind_count.max(1)).contains(&slot))
+        .map(|b| {
+            if b.bind_count > 1 {
+                format!("{}[{}]", b.name, slot - b.bind_point)
+            } else {
+                b.name.clone()
+            }
+        })
+}
+
+/// Pairs each float4 with the names of the cb0 variables it holds
+fn annotate_constants(
+    constants: &[Vector4],
+    resources: Option<&DxbcResourceDefinitions>,
+) -> Vec<ConstantRow> {
+    let cb0 = resources.and_then(|r| {
+        r.constant_buffers
+            .iter()
+            .find(|cb| r.constant_buffer_slot(cb) == Some(0))
+    });
+
+    constants
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let row_start = i as u32 * 16;
+            let variables = cb0
+                .iter()
+                .flat_map(|cb| &cb.variables)
+                .filter(|v| v.offset < row_start + 16 && v.offset + v.size > row_start)
+                .map(|v| {
+                    let mut name = if v.ty.elements > 0 && v.offset < row_start {
+                        format!("{}[{}]", v.name, (row_start - v.offset) / 16)
+                    } else {
+                        v.name.clone()
+                    };
+                    if !v.is_used() {
+                        name.push_str(" (unused)");
+                    }
+                    name
+                })
+                .collect();
+
+            ConstantRow {
+                value: *value,
+                variables,
+            }
+        })
+        .collect()
+}
+
+/// Shows the shaders, textures, samplers and constants of a material
+pub struct MaterialInspectorOverlay {
+    dcs: Rc<DeviceContextSwapchain>,
+    shader_viewer: Rc<RefCell<ShaderViewerOverlay>>,
+
+    open: bool,
+    material: Option<(TagHash, Result<Unk808071e8, String>)>,
+    stages: Vec<StageInfo>,
+}
+
+impl MaterialInspectorOverlay {
+    pub fn new(
+        dcs: Rc<DeviceContextSwapchain>,
+        shader_viewer: Rc<RefCell<ShaderViewerOverlay>>,
+    ) -> Self {
+        Self {
+            dcs,
+            shader_viewer,
+            open: false,
+            material: None,
+            stages: vec![],
+        }
+    }
+
+    pub fn open_material(&mut self, material: TagHash) {
+        self.open = true;
+        self.stages.clear();
+
+        let m: Unk808071e8 = match package_manager().read_tag_struct(material) {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Failed to read material {material}: {e}");
+                self.material = Some((material, Err(e.to_string())));
+                return;
+            }
+        };
+
+        self.stages.push(StageInfo::load(
+            &self.dcs,
+            ShaderStage::Vertex,
+            m.vertex_shader,
+            &m.vs_textures,
+            &m.vs_samplers,
+            "unk98".to_string(),
+            Ok(m.unk98.to_vec()),
+        ));
+        self.stages.push(StageInfo::load(
+            &self.dcs,
+            ShaderStage::Pixel,
+            m.pixel_shader,
+            &m.ps_textures,
+            &m.ps_samplers,
+            if m.unk34c.is_valid() {
+                format!("unk34c ({})", m.unk34c)
+            } else {
+                "unk318".to_string()
+            },
+            m.pixel_constants(),
+        ));
+
+        self.material = Some((material, Ok(m)));
+    }
+
+    fn stage_ui(
+        ui: &imgui::Ui,
+        shader_viewer: &RefCell<ShaderViewerOverlay>,
+        patches: &mut MaterialPatches,
+        material: TagHash,
+        stage: &mut StageInfo,
+    ) {
+        let name = format!("{:?}", stage.stage);
+        ui.text(format!("Shader: {}", stage.shader));
+        if stage.shader.is_valid() {
+            ui.same_line();
+            if ui.small_button(format!("Disassembly##{name}")) {
+                shader_viewer.borrow_mut().open_material(material);
+            }
+        }
+
+        if ui.collapsing_header(
+            format!("Textures ({})##{name}", stage.textures.len()),
+            TreeNodeFlags::DEFAULT_OPEN,
+        ) {
+            for t in &mut stage.textures {
+                if let Some(preview) = &t.preview {
+                    // The DX11 renderer takes shader resource views as texture IDs
+                    let id = TextureId::new(preview.view.as_raw() as usize);
+                    Image::new(id, [THUMBNAIL_SIZE, THUMBNAIL_SIZE]).build(ui);
+                    if ui.is_item_hovered() {
+                        ui.tooltip(|| Image::new(id, [PREVIEW_SIZE, PREVIEW_SIZE]).build(ui));
+                    }
+                } else {
+                    ui.dummy([THUMBNAIL_SIZE, THUMBNAIL_SIZE]);
+                }
+
+                ui.same_line();
+                ui.group(|| {
+                    ui.text(format!(
+                        "t{} {}",
+                        t.slot,
+                        t.name.as_deref().unwrap_or("(not declared)")
+                    ));
+                    ui.text(format!("Tag: {}", t.tag));
+                    match &t.description {
+                        Ok(d) => ui.text_disabled(d),
+                        Err(e) => ui.text_colored([1.0, 0.15, 0.15, 1.0], e),
+                    }
+                    Self::texture_override_ui(ui, patches, material, stage.stage, t);
+                });
+            }
+        }
+
+        if ui.collapsing_header(
+            format!("Samplers ({})##{name}", stage.samplers.len()),
+            TreeNodeFlags::DEFAULT_OPEN,
+        ) {
+            for s in &stage.samplers {
+                ui.text(format!(
+                    "s{} {}: {} (unk4={:x} unk8={:x} unkc={:x})",
+                    s.slot,
+                    s.name.as_deref().unwrap_or("(not declared)"),
+                    s.sampler.sampler,
+                    s.sampler.unk4,
+                    s.sampler.unk8,
+                    s.sampler.unkc
+                ));
+                match &s.desc {
+                    Ok(d) => {
+                        ui.text_disabled(d.to_string());
+                        let gltf = GltfSampler::from(d);
+                        ui.text_disabled(format!("glTF: {gltf}"));
+                        for a in &gltf.approximations {
+                            ui.text_disabled(format!("  approximated: {a}"));
+                        }
+                    }
+                    Err(e) => ui.text_colored([1.0, 0.15, 0.15, 1.0], e),
+                }
+            }
+        }
+
+        if ui.collapsing_header(
+            format!("Constants: {}##{name}", stage.constants_source),
+            TreeNodeFlags::DEFAULT_OPEN,
+        ) {
+            match &stage.constants {
+                Ok(rows) if rows.is_empty() => ui.text_disabled("Empty"),
+                Ok(rows) => {
+                    for (i, row) in rows.iter().enumerate() {
+                        let patched = patches
+                            .get(material)
+                            .and_then(|p| p.stage(stage.stage).constants.get(&i))
+                            .copied();
+                        let v = &row.value;
+                        let mut value = patched.unwrap_or([v.x, v.y, v.z, v.w]);
+
+                        let color =
+                            patched.map(|_| ui.push_style_color(StyleColor::Text, PATCHED_COLOR));
+                        let changed = ui
+                            .input_float4(format!("cb0[{i}]##{name}"), &mut value)
+                            .display_format("%.4f")
+                            .build();
+                        drop(color);
+
+                        if changed {
+                            patches.modify(material, |p| {
+                                p.stage_mut(stage.stage).constants.insert(i, value);
+                            });
+                        }
+                        if patched.is_some() {
+                            ui.same_line();
+                            if ui.small_button(format!("Reset##{name}cb{i}")) {
+                                patches.modify(material, |p| {
+                                    p.stage_mut(stage.stage).constants.remove(&i);
+                                });
+                            }
+                        }
+                        if !row.variables.is_empty() {
+                            ui.same_line();
+                            ui.text_disabled(row.variables.join(", "));
+                        }
+                    }
+                }
+                Err(e) => ui.text_colored([1.0, 0.15, 0.15, 1.0], e),
+            }
+        }
+    }
+
+    /// Controls to disable a texture slot or replace it with another tag or file
+    fn texture_override_ui(
+        ui: &imgui::Ui,
+        patches: &mut MaterialPatches,
+        material: TagHash,
+        stage: ShaderStage,
+        t: &mut TextureSlot,
+    ) {
+        let id = format!("{stage:?}{}", t.slot);
+        let current = patches
+            .get(material)
+            .and_then(|p| p.stage(stage).textures.get(&t.slot))
+            .cloned();
+
+        let mut enabled = current != Some(TextureOverride::Disabled);
+        if ui.checkbox(format!("Enabled##{id}"), &mut enabled) {
+            patches.modify(material, |p| {
+                let textures = &mut p.stage_mut(stage).textures;
+                if enabled {
+                    textures.remove(&t.slot);
+                } else {
+                    textures.insert(t.slot, TextureOverride::Disabled);
+                }
+            });
+        }
+
+        match &current {
+            Some(TextureOverride::Tag(tag)) => {
+                ui.same_line();
+                ui.text_colored(PATCHED_COLOR, format!("Replaced by {}", TagHash(*tag)));
+            }
+            Some(TextureOverride::File(path)) => {
+                ui.same_line();
+                ui.text_colored(PATCHED_COLOR, format!("Replaced by {}", path.display()));
+            }
+            _ => {}
+        }
+
+        ui.set_next_item_width(220.0);
+        ui.input_text(format!("##replacement{id}"), &mut t.replacement)
+            .hint("Tag (hex) or DDS/PNG path")
+            .build();
+        ui.same_line();
+        if ui.small_button(format!("Replace##{id}")) && !t.replacement.trim().is_empty() {
+            let input = t.replacement.trim();
+            let replacement = match u32::from_str_radix(input, 16) {
+                Ok(tag) if input.len() == 8 => TextureOverride::Tag(tag),
+                _ => TextureOverride::File(PathBuf::from(input)),
+            };
+            patches.modify(material, |p| {
+                p.stage_mut(stage).textures.insert(t.slot, replacement);
+            });
+        }
+        if current.is_some() {
+            ui.same_line();
+            if ui.small_button(format!("Reset##{id}")) {
+                patches.modify(material, |p| {
+                    p.stage_mut(stage).textures.remove(&t.slot);
+                });
+            }
+        }
+    }
+}
+
+impl OverlayProvider for MaterialInspectorOverlay {
+    fn create_overlay(&mut self, ui: &mut imgui::Ui, _window: &Window, resources: &mut Resources) {
+        let Some((material, header)) = &self.material else {
+            return;
+        };
+        if !self.open {
+            return;
+        }
+
+        let mut patches = resources.get_mut::<MaterialPatches>().unwrap();
+        let mut open = self.open;
+        ui.window(format!("{} Material", ICON_PALETTE))
+            .opened(&mut open)
+            .size([560.0, 720.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.text(format!("Material: {material}"));
+                let m = match header {
+                    Ok(m) => m,
+                    Err(e) => {
+                        ui.text_colored([1.0, 0.15, 0.15, 1.0], e);
+                        return;
+                    }
+                };
+                ui.text(format!("unk8: {} unkc: {}", m.unk8, m.unkc));
+
+                if patches.get(*material).is_some() {
+                    if ui.button("Revert all changes") {
+                        patches.modify(*material, |p| *p = Default::default());
+                    }
+                    ui.same_line();
+                }
+                if ui.button("Save map patches") {
+                    match patches.save() {
+                        Ok(_) => info!("Saved material patches"),
+                        Err(e) => error!("Failed to save material patches: {e}"),
+                    }
+                }
+
+                let Some(_tabs) = ui.tab_bar("##material_stages") else {
+                    return;
+                };
+                for stage in &mut self.stages {
+                    if let Some(_tab) = ui.tab_item(format!("{:?}", stage.stage)) {
+                        Self::stage_ui(ui, &self.shader_viewer, &mut patches, *material, stage);
+                    }
+                }
+            });
+        self.open = open;
+    }
+}
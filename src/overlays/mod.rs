@@ -1,7 +1,12 @@
+pub mod camera_path;
 pub mod camera_settings;
 pub mod console;
+pub mod debug_text;
 pub mod fps_display;
 pub mod gbuffer_viewer;
 pub mod gui;
+pub mod material_inspector;
 pub mod resource_nametags;
+pub mod selection;
+pub mod shader_viewer;
 pub mod package_dump;
\ No newline at end of file
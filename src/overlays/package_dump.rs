@@ -1,9 +1,12 @@
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::Write;
+use std::rc::Rc;
 use destiny_pkg::TagHash;
 use imgui::Ui;
 use winit::window::Window;
 use crate::overlays::gui::OverlayProvider;
+use crate::overlays::material_inspector::MaterialInspectorOverlay;
 use crate::packages::package_manager;
 use crate::resources::Resources;
 
@@ -11,17 +14,34 @@ use crate::resources::Resources;
 pub struct PackageDumper {
     package_id: String,
     entry_id: String,
-    message: Result<String, String>
+    message: Result<String, String>,
+    material_inspector: Rc<RefCell<MaterialInspectorOverlay>>
 }
 
 impl PackageDumper {
 
-    pub fn new() -> PackageDumper {
-        return PackageDumper{ package_id: "".to_string(), entry_id: "".to_string(), message: Ok("".to_string()) };
+    pub fn new(material_inspector: Rc<RefCell<MaterialInspectorOverlay>>) -> PackageDumper {
+        return PackageDumper{ package_id: "".to_string(), entry_id: "".to_string(), message: Ok("".to_string()), material_inspector };
     }
 
-    fn dump_entry(&self, pkg_id: u16, entry_id: u16) -> Result<String, String> {
-        let tag = TagHash::new(pkg_id, entry_id);
+    /// Fills in the input fields with the given tag
+    pub fn select_tag(&mut self, tag: TagHash) {
+        self.package_id = format!("{:04x}", tag.pkg_id());
+        self.entry_id = format!("{}", tag.0 & 0x1fff);
+    }
+
+    /// Tag entered in the input fields
+    fn input_tag(&self) -> Result<TagHash, String> {
+        let pkg = u16::from_str_radix(&self.package_id, 16);
+        let entry = u16::from_str_radix(&self.entry_id, 10);
+        if pkg.is_err() || entry.is_err() {
+            Err("Malformed input tag.".to_string())
+        } else {
+            Ok(TagHash::new(pkg.unwrap(), entry.unwrap()))
+        }
+    }
+
+    pub fn dump_tag(tag: TagHash) -> Result<String, String> {
         let entry_header = package_manager().get_entry(tag);
         if entry_header.is_ok() {
             let entry = entry_header.unwrap();
@@ -47,12 +67,16 @@ impl OverlayProvider for PackageDumper {
                     ui.input_text("Package ID", &mut self.package_id).hint("XXXX").enter_returns_true(false).build();
                     ui.input_text("Entry ID", &mut self.entry_id).hint("XXXX").enter_returns_true(false).build();
                     if ui.button("Dump!") {
-                        let pkg = u16::from_str_radix(&self.package_id, 16);
-                        let entry = u16::from_str_radix(&self.entry_id, 10);
-                        if pkg.is_err() || entry.is_err() {
-                            self.message = Err("Malformed input tag.".to_string());
-                        } else {
-                            self.message = self.dump_entry(pkg.unwrap(), entry.unwrap());
+                        self.message = self.input_tag().and_then(Self::dump_tag);
+                    }
+                    ui.same_line();
+                    if ui.button("Open as material") {
+                        match self.input_tag() {
+                            Ok(tag) => {
+                                self.material_inspector.borrow_mut().open_material(tag);
+                                self.message = Ok("".to_string());
+                            }
+                            Err(e) => self.message = Err(e)
                         }
                     }
 
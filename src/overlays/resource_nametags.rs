@@ -1,9 +1,9 @@
 use crate::{
-    camera::FpsCamera, map::MapDataList, map_resources::MapResource, resources::Resources,
+    camera::FpsCamera, culling::Frustum, map::MapDataList, map_resources::MapResource,
+    resources::Resources, transform::Transform,
 };
 use destiny_pkg::TagHash;
-use frustum_query::frustum::Frustum;
-use glam::{Mat4, Quat, Vec2, Vec4};
+use glam::Vec2;
 use imgui::{Condition, ImColor32, WindowFlags};
 use std::{cell::RefCell, rc::Rc};
 use winit::window::Window;
@@ -32,38 +32,28 @@ impl OverlayProvider for ResourceTypeOverlay {
                 .size(screen_size, Condition::Always)
                 .position([0.0, 0.0], Condition::Always)
                 .build(|| {
-                    let projection = Mat4::perspective_infinite_reverse_rh(
-                        90f32.to_radians(),
+                    let camera = resources.get::<FpsCamera>().unwrap();
+                    let proj_view = camera.projection_view_matrix(
                         window_dims.width as f32 / window_dims.height as f32,
-                        0.0001,
                     );
-
-                    let mut camera = resources.get_mut::<FpsCamera>().unwrap();
-                    let view = camera.calculate_matrix();
-                    let proj_view = projection.mul_mat4(&view);
-                    let camera_frustum =
-                        Frustum::from_modelview_projection(&proj_view.to_cols_array());
+                    let camera_frustum = Frustum::from_projection_view(&proj_view);
 
                     let draw_list = ui.get_background_draw_list();
                     draw_list.with_clip_rect([0.0, 0.0], screen_size, || {
                         let maps = resources.get::<MapDataList>().unwrap();
                         if let Some(m) = maps.current_map() {
                             for res in m.resource_points.iter() {
-                                if !camera_frustum.point_intersecting(
-                                    &res.translation.x,
-                                    &res.translation.y,
-                                    &res.translation.z,
-                                ) {
+                                if !camera_frustum.contains_point(res.transform.translation) {
                                     continue;
                                 }
 
-                                let distance = res.translation.truncate().distance(camera.position);
+                                let distance = res.transform.translation.distance(camera.position);
                                 if distance > self.debug_overlay.borrow().map_resource_distance {
                                     continue;
                                 }
 
                                 let projected_point =
-                                    proj_view.project_point3(res.translation.truncate());
+                                    proj_view.project_point3(res.transform.translation);
 
                                 let screen_point = Vec2::new(
                                     ((projected_point.x + 1.0) * 0.5) * screen_size[0],
@@ -116,8 +106,7 @@ impl OverlayProvider for ResourceTypeOverlay {
 
 #[derive(Clone)]
 pub struct ResourcePoint {
-    pub translation: Vec4,
-    pub rotation: Quat,
+    pub transform: Transform,
     pub entity: TagHash,
     pub resource_type: u32,
     pub resource: MapResource,
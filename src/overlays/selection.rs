@@ -0,0 +1,162 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use destiny_pkg::TagHash;
+use glam::Mat4;
+use tracing::{error, info};
+use winit::window::Window;
+
+use crate::icons::ICON_CURSOR_DEFAULT_CLICK;
+use crate::map::MapDataList;
+use crate::map_resources::{LightShape, MapResource};
+use crate::overlays::material_inspector::MaterialInspectorOverlay;
+use crate::overlays::package_dump::PackageDumper;
+use crate::overlays::shader_viewer::ShaderViewerOverlay;
+use crate::picking::{SelectedItem, Selection};
+use crate::resources::Resources;
+
+use super::gui::OverlayProvider;
+
+pub struct SelectionOverlay {
+    pub dumper: Rc<RefCell<PackageDumper>>,
+    pub shader_viewer: Rc<RefCell<ShaderViewerOverlay>>,
+    pub material_inspector: Rc<RefCell<MaterialInspectorOverlay>>,
+}
+
+impl SelectionOverlay {
+    fn tag_row(&self, ui: &imgui::Ui, label: &str, tag: TagHash) {
+        ui.text(format!("{label}: {tag}"));
+        if !tag.is_valid() {
+            return;
+        }
+
+        ui.same_line();
+        if ui.small_button(format!("Inspect##{label}{}", tag.0)) {
+            self.dumper.borrow_mut().select_tag(tag);
+        }
+        ui.same_line();
+        if ui.small_button(format!("Export##{label}{}", tag.0)) {
+            match PackageDumper::dump_tag(tag) {
+                Ok(_) => info!("Exported {label} {tag}"),
+                Err(e) => error!("Failed to export {label} {tag}: {e}"),
+            }
+        }
+    }
+
+    /// Tag row with extra buttons to inspect the material and view its shaders
+    fn material_row(&self, ui: &imgui::Ui, label: &str, material: TagHash) {
+        self.tag_row(ui, label, material);
+        if !material.is_valid() {
+            return;
+        }
+
+        ui.same_line();
+        if ui.small_button(format!("Material##{label}{}", material.0)) {
+            self.material_inspector.borrow_mut().open_material(material);
+        }
+        ui.same_line();
+        if ui.small_button(format!("Shaders##{label}{}", material.0)) {
+            self.shader_viewer.borrow_mut().open_material(material);
+        }
+    }
+
+    fn transform_rows(ui: &imgui::Ui, transform: &Mat4) {
+        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+        ui.text(format!(
+            "Translation: {:.3} {:.3} {:.3}",
+            translation.x, translation.y, translation.z
+        ));
+        ui.text(format!(
+            "Rotation: {:.3} {:.3} {:.3} {:.3}",
+            rotation.x, rotation.y, rotation.z, rotation.w
+        ));
+        ui.text(format!(
+            "Scale: {:.3} {:.3} {:.3}",
+            scale.x, scale.y, scale.z
+        ));
+    }
+}
+
+impl OverlayProvider for SelectionOverlay {
+    fn create_overlay(&mut self, ui: &mut imgui::Ui, _window: &Window, resources: &mut Resources) {
+        let mut selection = resources.get_mut::<Selection>().unwrap();
+        let Some(item) = selection.item.clone() else {
+            return;
+        };
+
+        ui.window(format!("{} Selection", ICON_CURSOR_DEFAULT_CLICK))
+            .build(|| {
+                ui.text(format!("Distance: {:.2}", selection.distance));
+                ui.separator();
+                match &item {
+                    SelectedItem::StaticInstance {
+                        placement_group,
+                        model,
+                        materials,
+                        instance_index,
+                        transform,
+                    } => {
+                        ui.text(format!("Static instance #{instance_index}"));
+                        self.tag_row(ui, "Static", *model);
+                        self.tag_row(ui, "Placement group", *placement_group);
+                        for (i, m) in materials.iter().enumerate() {
+                            self.material_row(ui, &format!("Material {i}"), *m);
+                        }
+                        ui.separator();
+                        Self::transform_rows(ui, transform);
+                    }
+                    SelectedItem::TerrainPart {
+                        terrain,
+                        part_index,
+                        group_index,
+                        material,
+                    } => {
+                        ui.text(format!("Terrain part #{part_index} (group {group_index})"));
+                        self.tag_row(ui, "Terrain", *terrain);
+                        self.material_row(ui, "Material", *material);
+                    }
+                    SelectedItem::MapResource {
+                        resource_index,
+                        entity,
+                        resource_type,
+                        transform,
+                    } => {
+                        let maps = resources.get::<MapDataList>().unwrap();
+                        if let Some(rp) = maps
+                            .current_map()
+                            .and_then(|m| m.resource_points.get(*resource_index))
+                        {
+                            ui.text(rp.resource.debug_string());
+                            if let MapResource::PointLight(light) = &rp.resource {
+                                ui.color_button("Light color", light.color.extend(1.0).to_array());
+                                ui.same_line();
+                                ui.text(format!(
+                                    "Intensity {:.2}, radius {:.2}",
+                                    light.intensity, light.radius
+                                ));
+                                if let LightShape::Spot {
+                                    inner_angle,
+                                    outer_angle,
+                                } = light.shape
+                                {
+                                    ui.text(format!(
+                                        "Cone: {inner_angle:.1}° inner, {outer_angle:.1}° outer"
+                                    ));
+                                }
+                                self.tag_row(ui, "Light", light.tag);
+                            }
+                        }
+                        ui.text(format!("Resource type: {resource_type:08X}"));
+                        self.tag_row(ui, "Entity", *entity);
+                        ui.separator();
+                        Self::transform_rows(ui, transform);
+                    }
+                }
+
+                ui.separator();
+                if ui.button("Deselect") {
+                    selection.item = None;
+                }
+            });
+    }
+}
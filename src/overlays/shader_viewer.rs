@@ -0,0 +1,134 @@
+use destiny_pkg::TagHash;
+use tracing::error;
+use winit::window::Window;
+
+use crate::decompiler::decompile;
+use crate::disassembler::disassemble;
+use crate::dxbc::DxbcShader;
+use crate::icons::ICON_CODE_BRACES;
+use crate::material::Unk808071e8;
+use crate::packages::package_manager;
+use crate::resources::Resources;
+use crate::types::Vector4;
+
+use super::gui::OverlayProvider;
+
+/// Disassembly and decompiled HLSL of a single shader stage
+struct ShaderListing {
+    stage: &'static str,
+    shader: TagHash,
+    disassembly: Result<String, String>,
+    decompiled: Result<String, String>,
+}
+
+impl ShaderListing {
+    fn load(stage: &'static str, shader: TagHash, constants: &[Vector4]) -> Self {
+        let parsed = if shader.is_valid() {
+            DxbcShader::load(shader).map_err(|e| e.to_string())
+        } else {
+            Err("Material has no shader for this stage".to_string())
+        };
+
+        let disassembly = parsed
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(|s| disassemble(s).map_err(|e| e.to_string()));
+        let decompiled = parsed
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(|s| decompile(s, Some(constants)).map_err(|e| e.to_string()));
+
+        Self {
+            stage,
+            shader,
+            disassembly,
+            decompiled,
+        }
+    }
+}
+
+/// Shows the disassembled vertex and pixel shader of a material
+#[derive(Default)]
+pub struct ShaderViewerOverlay {
+    open: bool,
+    material: Option<TagHash>,
+    listings: Vec<ShaderListing>,
+    show_hlsl: bool,
+}
+
+impl ShaderViewerOverlay {
+    pub fn open_material(&mut self, material: TagHash) {
+        self.open = true;
+        self.material = Some(material);
+        self.listings.clear();
+
+        let m: Unk808071e8 = match package_manager().read_tag_struct(material) {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Failed to read material {material}: {e}");
+                return;
+            }
+        };
+
+        let pixel_constants = m.pixel_constants().unwrap_or_else(|e| {
+            error!("Failed to read the pixel shader constants of material {material}: {e}");
+            vec![]
+        });
+
+        self.listings
+            .push(ShaderListing::load("Vertex", m.vertex_shader, &m.unk98));
+        self.listings.push(ShaderListing::load(
+            "Pixel",
+            m.pixel_shader,
+            &pixel_constants,
+        ));
+    }
+}
+
+impl OverlayProvider for ShaderViewerOverlay {
+    fn create_overlay(&mut self, ui: &mut imgui::Ui, _window: &Window, _resources: &mut Resources) {
+        let Some(material) = self.material else {
+            return;
+        };
+        if !self.open {
+            return;
+        }
+
+        ui.window(format!("{} Shaders", ICON_CODE_BRACES))
+            .opened(&mut self.open)
+            .size([640.0, 720.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.text(format!("Material: {material}"));
+                ui.same_line();
+                ui.checkbox("Decompile to HLSL", &mut self.show_hlsl);
+
+                let Some(_tabs) = ui.tab_bar("##shader_stages") else {
+                    return;
+                };
+                for listing in &self.listings {
+                    let Some(_tab) = ui.tab_item(listing.stage) else {
+                        continue;
+                    };
+
+                    ui.text(format!("Shader: {}", listing.shader));
+                    let listing_text = if self.show_hlsl {
+                        &listing.decompiled
+                    } else {
+                        &listing.disassembly
+                    };
+                    match listing_text {
+                        Ok(text) => {
+                            ui.same_line();
+                            if ui.small_button("Copy") {
+                                ui.set_clipboard_text(text);
+                            }
+                            ui.child_window("##disassembly")
+                                .horizontal_scrollbar(true)
+                                .build(|| ui.text(text));
+                        }
+                        Err(e) => ui.text_colored([1.0, 0.15, 0.15, 1.0], e),
+                    }
+                }
+            });
+    }
+}
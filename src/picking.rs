@@ -0,0 +1,493 @@
+use destiny_pkg::TagHash;
+use glam::{Mat4, Vec2, Vec3};
+use nohash_hasher::IntMap;
+
+use crate::bounds::Aabb;
+use crate::entity::EPrimitiveType;
+use crate::index_buffer::{part_indices, triangles};
+use crate::map::MapData;
+use crate::render::{InstancedRenderer, TerrainRenderer};
+use crate::statics::Unk8080966d;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Not necessarily normalized, distances along the ray are expressed in multiples of this vector
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// Creates a world-space ray going through the given normalized device coordinates
+    pub fn from_screen(proj_view: Mat4, ndc: Vec2) -> Ray {
+        let inv = proj_view.inverse();
+        // Reverse-Z, so 1.0 is the near plane
+        let near = inv.project_point3(ndc.extend(1.0));
+        let far = inv.project_point3(ndc.extend(0.5));
+
+        Ray {
+            origin: near,
+            direction: (far - near).normalize(),
+        }
+    }
+
+    /// Transforms the ray. The direction is intentionally left unnormalized so that distances stay comparable between spaces
+    pub fn transform(&self, m: &Mat4) -> Ray {
+        Ray {
+            origin: m.transform_point3(self.origin),
+            direction: m.transform_vector3(self.direction),
+        }
+    }
+}
+
+/// Slab test. Returns the distance to the entry point, or 0.0 if the origin is inside the box
+pub fn ray_aabb(ray: &Ray, aabb: &Aabb) -> Option<f32> {
+    let mut t_near = f32::NEG_INFINITY;
+    let mut t_far = f32::INFINITY;
+    for axis in 0..3 {
+        let origin = ray.origin[axis];
+        let direction = ray.direction[axis];
+        if direction == 0.0 {
+            // Parallel to the slab, dividing would give NaN for origins on one of its planes
+            if origin < aabb.min[axis] || origin > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let t0 = (aabb.min[axis] - origin) / direction;
+        let t1 = (aabb.max[axis] - origin) / direction;
+        t_near = t_near.max(t0.min(t1));
+        t_far = t_far.min(t0.max(t1));
+    }
+
+    if t_near > t_far || t_far < 0.0 {
+        None
+    } else {
+        Some(t_near.max(0.0))
+    }
+}
+
+/// Two-sided Möller–Trumbore ray/triangle intersection
+pub fn ray_triangle(ray: &Ray, v0: Vec3, v1: Vec3, v2: Vec3) -> Option<f32> {
+    const EPSILON: f32 = 1e-7;
+
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < EPSILON {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = ray.origin - v0;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = edge2.dot(q) * inv_det;
+    (t >= 0.0).then_some(t)
+}
+
+pub fn ray_sphere(ray: &Ray, center: Vec3, radius: f32) -> Option<f32> {
+    let oc = ray.origin - center;
+    let a = ray.direction.length_squared();
+    let b = oc.dot(ray.direction);
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let sqrt_d = discriminant.sqrt();
+    let t0 = (-b - sqrt_d) / a;
+    let t1 = (-b + sqrt_d) / a;
+    if t1 < 0.0 {
+        None
+    } else {
+        Some(t0.max(0.0))
+    }
+}
+
+/// CPU-side copy of a mesh, used for ray intersection
+#[derive(Default)]
+pub struct PickingMesh {
+    pub positions: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl PickingMesh {
+    pub fn bounds(&self, index_start: u32, index_count: u32) -> Aabb {
+        Aabb::from_points(
+            part_indices(&self.indices, index_start, index_count)
+                .iter()
+                .filter_map(|&i| self.positions.get(i as usize)),
+        )
+    }
+
+    /// Returns the distance to the closest intersecting triangle within the given index range
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        index_start: u32,
+        index_count: u32,
+        primitive_type: EPrimitiveType,
+    ) -> Option<f32> {
+        triangles(
+            part_indices(&self.indices, index_start, index_count),
+            primitive_type,
+        )
+        .into_iter()
+        .filter_map(|[i0, i1, i2]| {
+            ray_triangle(
+                ray,
+                *self.positions.get(i0 as usize)?,
+                *self.positions.get(i1 as usize)?,
+                *self.positions.get(i2 as usize)?,
+            )
+        })
+        .min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SelectedItem {
+    StaticInstance {
+        placement_group: TagHash,
+        model: TagHash,
+        materials: Vec<TagHash>,
+        instance_index: usize,
+        transform: Mat4,
+    },
+    TerrainPart {
+        terrain: TagHash,
+        part_index: usize,
+        group_index: u8,
+        material: TagHash,
+    },
+    MapResource {
+        /// Index into [`MapData::resource_points`]
+        resource_index: usize,
+        entity: TagHash,
+        resource_type: u32,
+        transform: Mat4,
+    },
+}
+
+/// Currently selected item in the viewport
+#[derive(Default)]
+pub struct Selection {
+    pub item: Option<SelectedItem>,
+    pub distance: f32,
+}
+
+/// Finds the closest item on the given map intersecting the ray
+///
+/// `resource_filter` determines which map resource types can be picked, resources are not pickable when it is None
+pub fn pick(
+    ray: &Ray,
+    map: &MapData,
+    placement_groups: &IntMap<u32, (Unk8080966d, Vec<InstancedRenderer>)>,
+    terrain_renderers: &IntMap<u32, TerrainRenderer>,
+    resource_filter: Option<&[bool]>,
+) -> Option<(f32, SelectedItem)> {
+    let mut closest: Option<(f32, SelectedItem)> = None;
+    let mut consider = |t: f32, item: &dyn Fn() -> SelectedItem| {
+        if closest.as_ref().map_or(true, |(ct, _)| t < *ct) {
+            closest = Some((t, item()));
+        }
+    };
+
+    for ptag in &map.placement_groups {
+        let Some((_, instance_renderers)) = placement_groups.get(&ptag.tag().0) else {
+            continue;
+        };
+
+        for renderer in instance_renderers {
+            if let Some((t, instance_index)) = renderer.intersect_ray(ray) {
+                consider(t, &|| SelectedItem::StaticInstance {
+                    placement_group: ptag.tag(),
+                    model: renderer.model().hash,
+                    materials: renderer.model().materials().to_vec(),
+                    instance_index,
+                    transform: renderer.transforms()[instance_index],
+                });
+            }
+        }
+    }
+
+    for th in &map.terrains {
+        if let Some(terrain) = terrain_renderers.get(&th.0) {
+            if let Some((t, part_index)) = terrain.intersect_ray(ray) {
+                let part = &terrain.parts()[part_index];
+                consider(t, &|| SelectedItem::TerrainPart {
+                    terrain: *th,
+                    part_index,
+                    group_index: part.group_index,
+                    material: part.material,
+                });
+            }
+        }
+    }
+
+    if let Some(filter) = resource_filter {
+        for (resource_index, rp) in map.resource_points.iter().enumerate() {
+            if !filter
+                .get(rp.resource.index() as usize)
+                .copied()
+                .unwrap_or_default()
+            {
+                continue;
+            }
+
+            let center = rp.transform.translation;
+            // Scale the sphere with distance so it roughly matches the size of the on-screen icon
+            let radius = (center.distance(ray.origin) * 0.015).max(0.1);
+            if let Some(t) = ray_sphere(ray, center, radius) {
+                consider(t, &|| SelectedItem::MapResource {
+                    resource_index,
+                    entity: rp.entity,
+                    resource_type: rp.resource_type,
+                    transform: Mat4::from_rotation_translation(rp.transform.rotation, center),
+                });
+            }
+        }
+    }
+
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_buffer::INDEX_RESTART;
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: Vec3::splat(-1.0),
+            max: Vec3::splat(1.0),
+        }
+    }
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray {
+            origin: origin.into(),
+            direction: direction.into(),
+        }
+    }
+
+    #[test]
+    fn ray_aabb_hit() {
+        let t = ray_aabb(&ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]), &unit_box());
+        assert_eq!(t, Some(4.0));
+
+        // Distances are in multiples of the (unnormalized) direction
+        let t = ray_aabb(&ray([-5.0, 0.0, 0.0], [2.0, 0.0, 0.0]), &unit_box());
+        assert_eq!(t, Some(2.0));
+
+        let t = ray_aabb(&ray([-3.0, -3.0, -3.0], [1.0, 1.0, 1.0]), &unit_box());
+        assert_eq!(t, Some(2.0));
+    }
+
+    #[test]
+    fn ray_aabb_miss() {
+        assert_eq!(
+            ray_aabb(&ray([-5.0, 2.0, 0.0], [1.0, 0.0, 0.0]), &unit_box()),
+            None
+        );
+        assert_eq!(
+            ray_aabb(&ray([-5.0, 0.0, 0.0], [1.0, 1.0, 0.0]), &unit_box()),
+            None
+        );
+        // Box behind the origin
+        assert_eq!(
+            ray_aabb(&ray([5.0, 0.0, 0.0], [1.0, 0.0, 0.0]), &unit_box()),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_aabb_inside() {
+        assert_eq!(
+            ray_aabb(&ray([0.0, 0.5, 0.0], [0.0, 0.0, -1.0]), &unit_box()),
+            Some(0.0)
+        );
+        assert_eq!(
+            ray_aabb(&ray([0.0, 0.0, 0.0], [0.0, 0.0, 0.0]), &unit_box()),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn ray_aabb_axis_parallel() {
+        // Lying exactly on the planes of the X and Z slabs
+        let t = ray_aabb(&ray([-1.0, -5.0, 1.0], [0.0, 1.0, 0.0]), &unit_box());
+        assert_eq!(t, Some(4.0));
+
+        let t = ray_aabb(&ray([1.0, 1.0, -5.0], [0.0, 0.0, 1.0]), &unit_box());
+        assert_eq!(t, Some(4.0));
+
+        // Parallel, but outside of the X slab
+        let t = ray_aabb(&ray([1.001, -5.0, 0.0], [0.0, 1.0, 0.0]), &unit_box());
+        assert_eq!(t, None);
+    }
+
+    const TRIANGLE: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    fn hit_triangle(origin: [f32; 3], direction: [f32; 3]) -> Option<f32> {
+        let [v0, v1, v2] = TRIANGLE;
+        ray_triangle(&ray(origin, direction), v0, v1, v2)
+    }
+
+    #[test]
+    fn ray_triangle_hit() {
+        assert_eq!(hit_triangle([0.25, 0.25, 5.0], [0.0, 0.0, -1.0]), Some(5.0));
+        // Distances are in multiples of the (unnormalized) direction
+        assert_eq!(hit_triangle([0.25, 0.25, 5.0], [0.0, 0.0, -2.0]), Some(2.5));
+    }
+
+    #[test]
+    fn ray_triangle_miss() {
+        assert_eq!(hit_triangle([0.75, 0.75, 5.0], [0.0, 0.0, -1.0]), None);
+        assert_eq!(hit_triangle([-0.25, 0.25, 5.0], [0.0, 0.0, -1.0]), None);
+        // Triangle behind the origin
+        assert_eq!(hit_triangle([0.25, 0.25, 5.0], [0.0, 0.0, 1.0]), None);
+    }
+
+    #[test]
+    fn ray_triangle_edges() {
+        // Edges and corners are part of the triangle
+        assert_eq!(hit_triangle([0.5, 0.0, 5.0], [0.0, 0.0, -1.0]), Some(5.0));
+        assert_eq!(hit_triangle([0.0, 0.5, 5.0], [0.0, 0.0, -1.0]), Some(5.0));
+        assert_eq!(hit_triangle([0.5, 0.5, 5.0], [0.0, 0.0, -1.0]), Some(5.0));
+        assert_eq!(hit_triangle([1.0, 0.0, 5.0], [0.0, 0.0, -1.0]), Some(5.0));
+    }
+
+    #[test]
+    fn ray_triangle_backface() {
+        assert_eq!(hit_triangle([0.25, 0.25, -5.0], [0.0, 0.0, 1.0]), Some(5.0));
+    }
+
+    #[test]
+    fn ray_triangle_parallel() {
+        // In the plane of the triangle
+        assert_eq!(hit_triangle([-1.0, 0.25, 0.0], [1.0, 0.0, 0.0]), None);
+        // Above it
+        assert_eq!(hit_triangle([-1.0, 0.25, 1.0], [1.0, 0.0, 0.0]), None);
+    }
+
+    /// Square in the XY plane at the given height, as a 4 vertex strip
+    fn quad(min: [f32; 2], max: [f32; 2], z: f32) -> [Vec3; 4] {
+        [
+            Vec3::new(min[0], min[1], z),
+            Vec3::new(max[0], min[1], z),
+            Vec3::new(min[0], max[1], z),
+            Vec3::new(max[0], max[1], z),
+        ]
+    }
+
+    fn mesh(quads: &[[Vec3; 4]], indices: &[u32]) -> PickingMesh {
+        PickingMesh {
+            positions: quads.iter().flatten().copied().collect(),
+            indices: indices.to_vec(),
+        }
+    }
+
+    #[test]
+    fn picking_mesh_nearest_hit() {
+        let mesh = mesh(
+            &[
+                quad([-1.0, -1.0], [1.0, 1.0], 0.0),
+                quad([-1.0, -1.0], [1.0, 1.0], 4.0),
+            ],
+            &[0, 1, 2, 3, INDEX_RESTART, 4, 5, 6, 7],
+        );
+        let r = ray([0.25, -0.5, 10.0], [0.0, 0.0, -1.0]);
+
+        assert_eq!(
+            mesh.intersect(&r, 0, 9, EPrimitiveType::TriangleStrip),
+            Some(6.0)
+        );
+        // Only the lower quad
+        assert_eq!(
+            mesh.intersect(&r, 0, 4, EPrimitiveType::TriangleStrip),
+            Some(10.0)
+        );
+        assert_eq!(
+            mesh.intersect(&r, 5, 4, EPrimitiveType::TriangleStrip),
+            Some(6.0)
+        );
+
+        let miss = ray([2.0, 0.0, 10.0], [0.0, 0.0, -1.0]);
+        assert_eq!(
+            mesh.intersect(&miss, 0, 9, EPrimitiveType::TriangleStrip),
+            None
+        );
+    }
+
+    #[test]
+    fn picking_mesh_strip_restart() {
+        // The second strip starts right below the ray, so the triangles bridging the two strips would be hit first
+        // if the restart was ignored
+        let lower = quad([-1.0, -1.0], [1.0, 1.0], 0.0);
+        let offset = quad([-1.0, -5.0], [1.0, -3.0], 5.0);
+        let r = ray([0.25, -0.5, 10.0], [0.0, 0.0, -1.0]);
+
+        let with_restart = mesh(&[lower, offset], &[0, 1, 2, 3, INDEX_RESTART, 4, 5, 6, 7]);
+        assert_eq!(
+            with_restart.intersect(&r, 0, 9, EPrimitiveType::TriangleStrip),
+            Some(10.0)
+        );
+
+        let without_restart = mesh(&[lower, offset], &[0, 1, 2, 3, 4, 5, 6, 7]);
+        let t = without_restart
+            .intersect(&r, 0, 8, EPrimitiveType::TriangleStrip)
+            .unwrap();
+        assert!((t - 8.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ray_from_screen_perspective() {
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        let proj = Mat4::perspective_infinite_reverse_rh(90f32.to_radians(), 1.0, 0.1);
+        let view = Mat4::look_at_rh(position, position + Vec3::Y, Vec3::Z);
+        let proj_view = proj * view;
+
+        let center = Ray::from_screen(proj_view, Vec2::ZERO);
+        assert!(center.origin.abs_diff_eq(position + Vec3::Y * 0.1, 1e-4));
+        assert!(center.direction.abs_diff_eq(Vec3::Y, 1e-4));
+
+        // With a 90 degree FOV the corners are 45 degrees off-axis, right is +X and up is +Z
+        let corner = Ray::from_screen(proj_view, Vec2::ONE);
+        assert!(corner.direction.abs_diff_eq(Vec3::ONE.normalize(), 1e-4));
+    }
+
+    #[test]
+    fn ray_from_screen_hits_projected_point() {
+        let proj = Mat4::perspective_infinite_reverse_rh(60f32.to_radians(), 16.0 / 9.0, 0.01);
+        let view = Mat4::look_at_rh(Vec3::new(-4.0, 3.0, 2.0), Vec3::ZERO, Vec3::Z);
+        let proj_view = proj * view;
+
+        let target = Vec3::new(0.5, -0.25, 0.75);
+        let ndc = proj_view.project_point3(target);
+        let ray = Ray::from_screen(proj_view, ndc.truncate());
+
+        let t = ray_sphere(&ray, target, 0.01).expect("Ray should pass through the point");
+        assert!(ray.origin.distance(target) - t < 0.02);
+    }
+
+    #[test]
+    fn ray_transform_keeps_distances() {
+        let r = ray([-5.0, 0.0, 0.0], [1.0, 0.0, 0.0]);
+        let scale = Mat4::from_scale(Vec3::splat(0.5));
+        let t = ray_aabb(&r.transform(&scale), &unit_box()).unwrap();
+        assert_eq!(r.origin + r.direction * t, Vec3::new(-2.0, 0.0, 0.0));
+    }
+}
@@ -105,23 +105,23 @@ impl<T> ConstantBuffer<T> {
         Ok(())
     }
 
-    // pub fn write_array(&self, data: &[T]) -> anyhow::Result<()> {
-    //     unsafe {
-    //         let memory = self
-    //             .dcs
-    //             .context
-    //             .Map(&self.buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)
-    //             .context("Failed to map ConstantBuffer for writing (array)")?;
+    pub fn write_array(&self, data: &[T]) -> anyhow::Result<()> {
+        unsafe {
+            let memory = self
+                .dcs
+                .context
+                .Map(&self.buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)
+                .context("Failed to map ConstantBuffer for writing (array)")?;
 
-    //         memory
-    //             .pData
-    //             .copy_from_nonoverlapping(data.as_ptr() as _, std::mem::size_of_val(data));
+            memory
+                .pData
+                .copy_from_nonoverlapping(data.as_ptr() as _, std::mem::size_of_val(data));
 
-    //         self.dcs.context.Unmap(&self.buffer, 0);
-    //     }
+            self.dcs.context.Unmap(&self.buffer, 0);
+        }
 
-    //     Ok(())
-    // }
+        Ok(())
+    }
 
     pub fn buffer(&self) -> &ID3D11Buffer {
         &self.buffer
@@ -0,0 +1,194 @@
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Context;
+use tracing::{error, info};
+use windows::Win32::Graphics::Direct3D11::*;
+
+use super::shader::compile_hlsl_source;
+use super::DeviceContextSwapchain;
+
+const COMPOSITOR_SHADER: &str = "fullscreen.hlsl";
+/// User compositor modes, one HLSL file per mode
+const USER_MODE_DIRECTORY: &str = "compositor_modes";
+/// Entry point of user compositor modes. Their source is appended to fullscreen.hlsl, so they can use everything in
+/// it, including `PShader` for the regular output
+const USER_MODE_ENTRY_POINT: &str = "UserPShader";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Compositor mode backed by a pixel shader from [`USER_MODE_DIRECTORY`]
+pub struct UserCompositorMode {
+    pub name: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// Last shader that compiled
+    shader: Option<ID3D11PixelShader>,
+}
+
+/// Compositor shaders, recompiled when their files change. Compile errors are logged and the last working shader
+/// is kept
+pub struct CompositorShaders {
+    dcs: Rc<DeviceContextSwapchain>,
+    vshader: Option<ID3D11VertexShader>,
+    pshader: Option<ID3D11PixelShader>,
+    /// Last source of fullscreen.hlsl that compiled, user modes are built on top of it
+    source: Option<String>,
+    modified: Option<SystemTime>,
+    pub user_modes: Vec<UserCompositorMode>,
+    last_poll: Option<Instant>,
+    /// Set once [`CompositorShaders::bind`] has reported that there's no shader to bind
+    reported_unbound: Cell<bool>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl CompositorShaders {
+    pub fn new(dcs: Rc<DeviceContextSwapchain>) -> Self {
+        let mut shaders = Self {
+            dcs,
+            vshader: None,
+            pshader: None,
+            source: None,
+            modified: None,
+            user_modes: vec![],
+            last_poll: None,
+            reported_unbound: Cell::new(false),
+        };
+        shaders.update();
+        shaders
+    }
+
+    /// Recompiles shaders whose files changed since the last call. Files are checked every [`POLL_INTERVAL`]
+    pub fn update(&mut self) {
+        if self.last_poll.is_some_and(|t| t.elapsed() < POLL_INTERVAL) {
+            return;
+        }
+        self.last_poll = Some(Instant::now());
+
+        // User modes include fullscreen.hlsl, so they're rebuilt whenever it changes
+        let mut reload_all = false;
+        let modified = modified_time(Path::new(COMPOSITOR_SHADER));
+        if modified != self.modified {
+            self.modified = modified;
+            match self.compile() {
+                Ok(()) => {
+                    info!("Compiled {COMPOSITOR_SHADER}");
+                    reload_all = true;
+                }
+                Err(e) => error!("{e:#}"),
+            }
+        }
+
+        self.update_user_modes(reload_all);
+    }
+
+    fn compile(&mut self) -> anyhow::Result<()> {
+        let source = std::fs::read_to_string(COMPOSITOR_SHADER)
+            .with_context(|| format!("Failed to read {COMPOSITOR_SHADER}"))?;
+        let vs_data = compile_hlsl_source(&source, COMPOSITOR_SHADER, "VShader", "vs_5_0")?;
+        let ps_data = compile_hlsl_source(&source, COMPOSITOR_SHADER, "PShader", "ps_5_0")?;
+
+        let (vshader, pshader) = unsafe {
+            (
+                self.dcs.device.CreateVertexShader(&vs_data, None)?,
+                self.dcs.device.CreatePixelShader(&ps_data, None)?,
+            )
+        };
+        self.vshader = Some(vshader);
+        self.pshader = Some(pshader);
+        self.source = Some(source);
+        Ok(())
+    }
+
+    /// Picks up added, changed and removed user modes. `reload_all` recompiles every mode
+    fn update_user_modes(&mut self, reload_all: bool) {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(USER_MODE_DIRECTORY)
+            .map(|dir| {
+                dir.filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| p.extension().is_some_and(|e| e == "hlsl"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        paths.sort();
+
+        self.user_modes.retain(|m| paths.contains(&m.path));
+        for path in paths {
+            let modified = modified_time(&path);
+            let index = match self.user_modes.iter().position(|m| m.path == path) {
+                Some(i) if !reload_all && self.user_modes[i].modified == modified => continue,
+                Some(i) => i,
+                None => {
+                    self.user_modes.push(UserCompositorMode {
+                        name: path
+                            .file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned(),
+                        path: path.clone(),
+                        modified: None,
+                        shader: None,
+                    });
+                    self.user_modes.len() - 1
+                }
+            };
+
+            let mode = &mut self.user_modes[index];
+            mode.modified = modified;
+            match Self::compile_user_mode(&self.dcs, self.source.as_deref(), &mode.path) {
+                Ok(shader) => {
+                    info!("Compiled compositor mode {}", mode.name);
+                    mode.shader = Some(shader);
+                }
+                Err(e) => error!("{e:#}"),
+            }
+        }
+
+        // New modes are appended, keep them in file order for the mode list
+        self.user_modes.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    fn compile_user_mode(
+        dcs: &DeviceContextSwapchain,
+        compositor_source: Option<&str>,
+        path: &Path,
+    ) -> anyhow::Result<ID3D11PixelShader> {
+        let compositor_source = compositor_source
+            .with_context(|| format!("{COMPOSITOR_SHADER} hasn't compiled yet"))?;
+        let user_source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        // Reset the line numbers so compiler messages point into the user file
+        let name = path.display().to_string().replace('\\', "/");
+        let source = format!("{compositor_source}\n#line 1 \"{name}\"\n{user_source}");
+        let ps_data = compile_hlsl_source(&source, &name, USER_MODE_ENTRY_POINT, "ps_5_0")?;
+
+        Ok(unsafe { dcs.device.CreatePixelShader(&ps_data, None)? })
+    }
+
+    /// Binds the compositor shaders, with the pixel shader of the named user mode if given. User modes that never
+    /// compiled or no longer exist fall back to the regular output. Returns false (and logs the first time) if the
+    /// compositor itself never compiled
+    pub fn bind(&self, user_mode: Option<&str>) -> bool {
+        let pshader = user_mode
+            .and_then(|name| self.user_modes.iter().find(|m| m.name == name))
+            .and_then(|m| m.shader.as_ref())
+            .or(self.pshader.as_ref());
+
+        let (Some(vshader), Some(pshader)) = (&self.vshader, pshader) else {
+            if !self.reported_unbound.replace(true) {
+                error!("{COMPOSITOR_SHADER} has never compiled, the frame can't be composited");
+            }
+            return false;
+        };
+
+        unsafe {
+            self.dcs.context.VSSetShader(vshader, None);
+            self.dcs.context.PSSetShader(pshader, None);
+        }
+        true
+    }
+}
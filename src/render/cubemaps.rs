@@ -0,0 +1,172 @@
+use std::rc::Rc;
+
+use anyhow::Context;
+use destiny_pkg::TagHash;
+use glam::{Mat4, Vec3, Vec4};
+use windows::Win32::Graphics::Direct3D11::*;
+
+use crate::bounds::Aabb;
+use crate::map_resources::Unk80806b7f;
+use crate::texture::TextureHandle;
+
+use super::{ConstantBuffer, DeviceContextSwapchain, RenderData};
+
+/// Number of cubemap volumes the compositor can blend between, must match `MAX_CUBEMAP_VOLUMES` in fullscreen.hlsl
+pub const MAX_CUBEMAP_VOLUMES: usize = 4;
+
+/// Cubemap volumes for the compositor (b1)
+#[repr(C)]
+struct ScopeCubemapVolumes {
+    world_to_volume: [Mat4; MAX_CUBEMAP_VOLUMES],
+    volume_to_world: [Mat4; MAX_CUBEMAP_VOLUMES],
+    /// x = edge blend width in volume space
+    params: [Vec4; MAX_CUBEMAP_VOLUMES],
+    count: u32,
+    _pad: [u32; 3],
+}
+
+/// Box shaped volume in which a cubemap is used for reflections and ambient lighting
+pub struct CubemapVolume {
+    pub texture: TagHash,
+    pub volume_to_world: Mat4,
+    pub world_to_volume: Mat4,
+    /// Width of the fade at the volume edges, relative to the volume size (0..1)
+    pub blend: f32,
+    pub bounds: Aabb,
+}
+
+impl CubemapVolume {
+    pub fn new(data: &Unk80806b7f, translation: Vec3) -> Option<Self> {
+        let volume_to_world = data.volume_to_world(translation)?;
+
+        let (scale, _, _) = volume_to_world.to_scale_rotation_translation();
+        let smallest_axis = scale.abs().min_element();
+        let blend = if smallest_axis > f32::EPSILON {
+            (data.blend_distance() / smallest_axis).clamp(0.001, 1.0)
+        } else {
+            0.001
+        };
+
+        Some(Self {
+            texture: data.cubemap_texture,
+            volume_to_world,
+            world_to_volume: volume_to_world.inverse(),
+            blend,
+            bounds: Aabb {
+                min: Vec3::NEG_ONE,
+                max: Vec3::ONE,
+            }
+            .transform(&volume_to_world),
+        })
+    }
+
+    /// Distance from `point` to the volume bounds, 0 if the point is inside
+    pub fn distance(&self, point: Vec3) -> f32 {
+        (self.bounds.min - point)
+            .max(point - self.bounds.max)
+            .max(Vec3::ZERO)
+            .length()
+    }
+}
+
+/// Orders volumes by priority for a viewer at `position`, keeping at most [`MAX_CUBEMAP_VOLUMES`].
+/// Volumes containing the viewer come first (smallest first), followed by the nearest ones
+pub fn select_volumes(volumes: &[CubemapVolume], position: Vec3, out: &mut Vec<usize>) {
+    out.clear();
+    out.extend(0..volumes.len());
+
+    let key = |i: usize| {
+        let v = &volumes[i];
+        (v.distance(position), v.bounds.extents().length_squared())
+    };
+    out.sort_by(|&a, &b| {
+        let (da, sa) = key(a);
+        let (db, sb) = key(b);
+        da.total_cmp(&db).then(sa.total_cmp(&sb))
+    });
+    out.truncate(MAX_CUBEMAP_VOLUMES);
+}
+
+/// Selects the cubemap volumes around the camera and binds them for the compositor
+pub struct CubemapRenderer {
+    cb: ConstantBuffer<ScopeCubemapVolumes>,
+    sampler: ID3D11SamplerState,
+    views: [Option<ID3D11ShaderResourceView>; MAX_CUBEMAP_VOLUMES],
+    selected: Vec<usize>,
+}
+
+impl CubemapRenderer {
+    pub fn create(dcs: Rc<DeviceContextSwapchain>) -> anyhow::Result<Self> {
+        let sampler = unsafe {
+            dcs.device
+                .CreateSamplerState(&D3D11_SAMPLER_DESC {
+                    Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+                    AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    MaxLOD: f32::MAX,
+                    ..Default::default()
+                })
+                .context("Failed to create cubemap sampler")?
+        };
+
+        Ok(Self {
+            cb: ConstantBuffer::create(dcs, None)?,
+            sampler,
+            views: Default::default(),
+            selected: vec![],
+        })
+    }
+
+    /// Picks the volumes to use this frame. Volumes without a loaded cubemap texture are ignored.
+    /// Returns the number of volumes selected
+    pub fn update(
+        &mut self,
+        volumes: &[CubemapVolume],
+        render_data: &RenderData,
+        camera_position: Vec3,
+    ) -> anyhow::Result<usize> {
+        select_volumes(volumes, camera_position, &mut self.selected);
+
+        let mut scope = ScopeCubemapVolumes {
+            world_to_volume: [Mat4::IDENTITY; MAX_CUBEMAP_VOLUMES],
+            volume_to_world: [Mat4::IDENTITY; MAX_CUBEMAP_VOLUMES],
+            params: [Vec4::ZERO; MAX_CUBEMAP_VOLUMES],
+            count: 0,
+            _pad: [0; 3],
+        };
+        self.views = Default::default();
+
+        for &i in &self.selected {
+            let volume = &volumes[i];
+            let Some(texture) = render_data.textures.get(&volume.texture.0) else {
+                continue;
+            };
+            if !matches!(texture.handle, TextureHandle::TextureCube(_)) {
+                continue;
+            }
+
+            let slot = scope.count as usize;
+            scope.world_to_volume[slot] = volume.world_to_volume;
+            scope.volume_to_world[slot] = volume.volume_to_world;
+            scope.params[slot] = Vec4::new(volume.blend, 0.0, 0.0, 0.0);
+            self.views[slot] = Some(texture.view.clone());
+            scope.count += 1;
+        }
+
+        self.cb.write(&scope)?;
+
+        Ok(scope.count as usize)
+    }
+
+    /// Binds the cubemaps to t9-t12, the volume constants to b1 and the sampler to s1
+    pub fn bind(&self, dcs: &DeviceContextSwapchain) {
+        unsafe {
+            dcs.context.PSSetShaderResources(9, Some(&self.views));
+            dcs.context
+                .PSSetConstantBuffers(1, Some(&[Some(self.cb.buffer().clone())]));
+            dcs.context
+                .PSSetSamplers(1, Some(&[Some(self.sampler.clone())]));
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use std::rc::Rc;
+
+use anyhow::Context;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use windows::Win32::Graphics::Direct3D::D3D_PRIMITIVE_TOPOLOGY_LINELIST;
+use windows::Win32::Graphics::Direct3D11::*;
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT_R32G32B32A32_FLOAT, DXGI_FORMAT_R32G32B32_FLOAT,
+};
+
+use crate::bounds::Aabb;
+
+use super::shader::compile_hlsl;
+use super::{ConstantBuffer, DeviceContextSwapchain};
+
+/// Depth used for the far plane of infinite projections in [`DebugBatch::frustum`]
+const FRUSTUM_FAR_DEPTH: f32 = 0.001;
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DebugVertex {
+    pub position: Vec3,
+    pub color: [f32; 4],
+}
+
+/// Line list built on the CPU, see [`DebugShapes`]
+#[derive(Default)]
+pub struct DebugBatch {
+    pub vertices: Vec<DebugVertex>,
+}
+
+impl DebugBatch {
+    pub fn line(&mut self, a: Vec3, b: Vec3, color: Vec4) {
+        let color = color.to_array();
+        self.vertices.push(DebugVertex { position: a, color });
+        self.vertices.push(DebugVertex { position: b, color });
+    }
+
+    /// Draws the 12 edges between 8 corners, indexed by their x/y/z bits
+    fn box_edges(&mut self, corners: &[Vec3; 8], color: Vec4) {
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corners[i], corners[i | axis], color);
+                }
+            }
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: Vec4) {
+        if aabb.is_empty() {
+            return;
+        }
+
+        self.box_edges(&aabb.corners(), color);
+    }
+
+    /// Draws the edges of the -1..1 box transformed by `transform` (cubemap volumes, decal projectors)
+    pub fn draw_box(&mut self, transform: &Mat4, color: Vec4) {
+        self.box_edges(
+            &Aabb {
+                min: Vec3::NEG_ONE,
+                max: Vec3::ONE,
+            }
+            .corners()
+            .map(|c| transform.transform_point3(c)),
+            color,
+        );
+    }
+
+    /// Circles around the 3 axes
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        for (u, v) in [(Vec3::X, Vec3::Y), (Vec3::Y, Vec3::Z), (Vec3::Z, Vec3::X)] {
+            let point = |i: usize| {
+                let angle = i as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+
+            for i in 0..SPHERE_SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    /// View frustum of a (reverse-Z) projection-view matrix. Infinite projections are cut off at a small depth
+    pub fn frustum(&mut self, proj_view: &Mat4, color: Vec4) {
+        let inverse = proj_view.inverse();
+        let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            inverse.project_point3(Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 1.0 } else { FRUSTUM_FAR_DEPTH },
+            ))
+        });
+
+        self.box_edges(&corners, color);
+    }
+
+    /// Red, green and blue lines along the X, Y and Z axes of `transform`
+    pub fn axes(&mut self, transform: &Mat4, size: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for (axis, color) in [
+            (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+            (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        ] {
+            self.line(
+                origin,
+                origin + transform.transform_vector3(axis).normalize_or_zero() * size,
+                color,
+            );
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+pub struct DebugText {
+    pub position: Vec3,
+    pub text: String,
+    pub color: Vec4,
+}
+
+/// Immediate-mode debug geometry, stored as a resource. Shapes are collected over a frame and cleared after drawing
+#[derive(Default)]
+pub struct DebugShapes {
+    /// Hidden behind scene geometry
+    pub world: DebugBatch,
+    /// Drawn on top of everything
+    pub overlay: DebugBatch,
+    pub texts: Vec<DebugText>,
+}
+
+impl DebugShapes {
+    pub fn batch(&mut self, depth_test: bool) -> &mut DebugBatch {
+        if depth_test {
+            &mut self.world
+        } else {
+            &mut self.overlay
+        }
+    }
+
+    pub fn text3d(&mut self, position: Vec3, text: impl Into<String>, color: Vec4) {
+        self.texts.push(DebugText {
+            position,
+            text: text.into(),
+            color,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.world.clear();
+        self.overlay.clear();
+        self.texts.clear();
+    }
+}
+
+/// Debug draw constants (b0)
+#[repr(C)]
+struct ScopeDebugDraw {
+    proj_view: Mat4,
+    /// xy = render target size, z = 1 to test against the scene depth
+    target_size: Vec4,
+}
+
+/// Draws [`DebugShapes`] lines, using the shaders from debug_draw.hlsl
+pub struct DebugDrawRenderer {
+    dcs: Rc<DeviceContextSwapchain>,
+    vshader: ID3D11VertexShader,
+    pshader: ID3D11PixelShader,
+    input_layout: ID3D11InputLayout,
+    rasterizer_state: ID3D11RasterizerState,
+    blend_state: ID3D11BlendState,
+    cb: ConstantBuffer<ScopeDebugDraw>,
+
+    vertex_buffer: Option<ID3D11Buffer>,
+    vertex_capacity: usize,
+}
+
+impl DebugDrawRenderer {
+    pub fn create(dcs: Rc<DeviceContextSwapchain>) -> anyhow::Result<Self> {
+        let vs_data = compile_hlsl(w!("debug_draw.hlsl"), s!("VSDebugDraw"), s!("vs_5_0"))
+            .context("Failed to compile debug draw vertex shader")?;
+        let ps_data = compile_hlsl(w!("debug_draw.hlsl"), s!("PSDebugDraw"), s!("ps_5_0"))
+            .context("Failed to compile debug draw pixel shader")?;
+
+        unsafe {
+            let vshader = dcs.device.CreateVertexShader(&vs_data, None)?;
+            let pshader = dcs.device.CreatePixelShader(&ps_data, None)?;
+
+            let input_layout = dcs
+                .device
+                .CreateInputLayout(
+                    &[
+                        D3D11_INPUT_ELEMENT_DESC {
+                            SemanticName: s!("POSITION"),
+                            SemanticIndex: 0,
+                            Format: DXGI_FORMAT_R32G32B32_FLOAT,
+                            InputSlot: 0,
+                            AlignedByteOffset: 0,
+                            InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
+                            InstanceDataStepRate: 0,
+                        },
+                        D3D11_INPUT_ELEMENT_DESC {
+                            SemanticName: s!("COLOR"),
+                            SemanticIndex: 0,
+                            Format: DXGI_FORMAT_R32G32B32A32_FLOAT,
+                            InputSlot: 0,
+                            AlignedByteOffset: 12,
+                            InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
+                            InstanceDataStepRate: 0,
+                        },
+                    ],
+                    &vs_data,
+                )
+                .context("Failed to create debug draw input layout")?;
+
+            let rasterizer_state = dcs
+                .device
+                .CreateRasterizerState(&D3D11_RASTERIZER_DESC {
+                    FillMode: D3D11_FILL_SOLID,
+                    CullMode: D3D11_CULL_NONE,
+                    DepthClipEnable: true.into(),
+                    AntialiasedLineEnable: true.into(),
+                    ..Default::default()
+                })
+                .context("Failed to create debug draw rasterizer state")?;
+
+            let blend_state = dcs
+                .device
+                .CreateBlendState(&D3D11_BLEND_DESC {
+                    RenderTarget: [D3D11_RENDER_TARGET_BLEND_DESC {
+                        BlendEnable: true.into(),
+                        SrcBlend: D3D11_BLEND_SRC_ALPHA,
+                        DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+                        BlendOp: D3D11_BLEND_OP_ADD,
+                        SrcBlendAlpha: D3D11_BLEND_ONE,
+                        DestBlendAlpha: D3D11_BLEND_ZERO,
+                        BlendOpAlpha: D3D11_BLEND_OP_ADD,
+                        RenderTargetWriteMask: D3D11_COLOR_WRITE_ENABLE_ALL.0 as u8,
+                    }; 8],
+                    ..Default::default()
+                })
+                .context("Failed to create debug draw blend state")?;
+
+            Ok(Self {
+                cb: ConstantBuffer::create(dcs.clone(), None)?,
+                dcs,
+                vshader,
+                pshader,
+                input_layout,
+                rasterizer_state,
+                blend_state,
+                vertex_buffer: None,
+                vertex_capacity: 0,
+            })
+        }
+    }
+
+    /// Uploads both batches into the vertex buffer, growing it if needed
+    fn upload(&mut self, shapes: &DebugShapes) -> anyhow::Result<()> {
+        let count = shapes.world.vertices.len() + shapes.overlay.vertices.len();
+        if count > self.vertex_capacity || self.vertex_buffer.is_none() {
+            let capacity = count.next_power_of_two().max(1024);
+            self.vertex_buffer = Some(unsafe {
+                self.dcs
+                    .device
+                    .CreateBuffer(
+                        &D3D11_BUFFER_DESC {
+                            Usage: D3D11_USAGE_DYNAMIC,
+                            BindFlags: D3D11_BIND_VERTEX_BUFFER,
+                            CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
+                            ByteWidth: (capacity * std::mem::size_of::<DebugVertex>()) as _,
+                            ..Default::default()
+                        },
+                        None,
+                    )
+                    .context("Failed to create debug draw vertex buffer")?
+            });
+            self.vertex_capacity = capacity;
+        }
+
+        let buffer = self.vertex_buffer.as_ref().unwrap();
+        unsafe {
+            let memory = self
+                .dcs
+                .context
+                .Map(buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)
+                .context("Failed to map debug draw vertex buffer")?;
+
+            let data = memory.pData as *mut DebugVertex;
+            data.copy_from_nonoverlapping(
+                shapes.world.vertices.as_ptr(),
+                shapes.world.vertices.len(),
+            );
+            data.add(shapes.world.vertices.len())
+                .copy_from_nonoverlapping(
+                    shapes.overlay.vertices.as_ptr(),
+                    shapes.overlay.vertices.len(),
+                );
+
+            self.dcs.context.Unmap(buffer, 0);
+        }
+
+        Ok(())
+    }
+
+    /// Draws the shape batches into the bound render target. The scene depth (`depth_view`) is compared in the
+    /// pixel shader, so the target doesn't have to match the GBuffer size
+    pub fn draw(
+        &mut self,
+        shapes: &DebugShapes,
+        proj_view: Mat4,
+        depth_view: &ID3D11ShaderResourceView,
+        target_size: Vec2,
+    ) -> anyhow::Result<()> {
+        if shapes.world.vertices.is_empty() && shapes.overlay.vertices.is_empty() {
+            return Ok(());
+        }
+
+        self.upload(shapes)?;
+
+        let dcs = self.dcs.clone();
+        unsafe {
+            dcs.context.VSSetShader(&self.vshader, None);
+            dcs.context.PSSetShader(&self.pshader, None);
+            dcs.context.IASetInputLayout(&self.input_layout);
+            dcs.context
+                .IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_LINELIST);
+            dcs.context.IASetVertexBuffers(
+                0,
+                1,
+                Some([self.vertex_buffer.clone()].as_ptr()),
+                Some([std::mem::size_of::<DebugVertex>() as u32].as_ptr()),
+                Some(&0),
+            );
+            dcs.context.RSSetState(&self.rasterizer_state);
+            dcs.context.OMSetBlendState(
+                &self.blend_state,
+                Some(&[1f32, 1., 1., 1.] as _),
+                0xffffffff,
+            );
+            dcs.context.OMSetDepthStencilState(None, 0);
+            dcs.context
+                .PSSetShaderResources(0, Some(&[Some(depth_view.clone())]));
+            dcs.context
+                .VSSetConstantBuffers(0, Some(&[Some(self.cb.buffer().clone())]));
+            dcs.context
+                .PSSetConstantBuffers(0, Some(&[Some(self.cb.buffer().clone())]));
+
+            let mut first_vertex = 0;
+            for (batch, depth_test) in [(&shapes.world, true), (&shapes.overlay, false)] {
+                let count = batch.vertices.len();
+                if count != 0 {
+                    self.cb.write(&ScopeDebugDraw {
+                        proj_view,
+                        target_size: Vec4::new(
+                            target_size.x,
+                            target_size.y,
+                            if depth_test { 1.0 } else { 0.0 },
+                            0.0,
+                        ),
+                    })?;
+                    dcs.context.Draw(count as u32, first_vertex as u32);
+                }
+                first_vertex += count;
+            }
+
+            dcs.context.PSSetShaderResources(0, Some(&[None]));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a line list into its lines
+    fn lines(batch: &DebugBatch) -> Vec<(Vec3, Vec3)> {
+        assert_eq!(
+            batch.vertices.len() % 2,
+            0,
+            "Line list has a dangling vertex"
+        );
+        batch
+            .vertices
+            .chunks_exact(2)
+            .map(|l| (l[0].position, l[1].position))
+            .collect()
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    #[test]
+    fn line_pushes_two_vertices() {
+        let mut batch = DebugBatch::default();
+        batch.line(Vec3::ZERO, Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+        assert_eq!(batch.vertices.len(), 2);
+        assert_eq!(batch.vertices[0].position, Vec3::ZERO);
+        assert_eq!(batch.vertices[1].position, Vec3::X);
+        assert_eq!(batch.vertices[1].color, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn draw_box_emits_the_12_unit_length_edges() {
+        let mut batch = DebugBatch::default();
+        batch.draw_box(&Mat4::IDENTITY, Vec4::ONE);
+
+        let lines = lines(&batch);
+        assert_eq!(lines.len(), 12);
+        for (a, b) in &lines {
+            // Edges run along a single axis, from a -1 to a +1 coordinate
+            let d = *b - *a;
+            assert_eq!(d.abs().max_element(), 2.0);
+            assert_eq!(d.abs().dot(Vec3::ONE), 2.0);
+            assert_eq!(a.abs(), Vec3::ONE);
+        }
+
+        // Every edge is unique, in either direction
+        for (i, (a, b)) in lines.iter().enumerate() {
+            for (c, d) in &lines[i + 1..] {
+                assert!(
+                    !((a == c && b == d) || (a == d && b == c)),
+                    "Duplicate edge {a} {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn draw_box_applies_transform() {
+        let transform = Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0))
+            * Mat4::from_scale(Vec3::new(2.0, 3.0, 4.0));
+        let mut batch = DebugBatch::default();
+        batch.draw_box(&transform, Vec4::ONE);
+
+        let (first_a, first_b) = lines(&batch)[0];
+        assert_near(first_a, Vec3::new(8.0, -3.0, -4.0));
+        assert_near(first_b, Vec3::new(12.0, -3.0, -4.0));
+        for v in &batch.vertices {
+            assert_eq!(
+                (v.position - Vec3::new(10.0, 0.0, 0.0)).abs(),
+                Vec3::new(2.0, 3.0, 4.0)
+            );
+        }
+    }
+
+    #[test]
+    fn aabb_matches_the_box_helper() {
+        let aabb = Aabb {
+            min: Vec3::new(-1.0, 0.0, 2.0),
+            max: Vec3::new(3.0, 2.0, 4.0),
+        };
+        let mut from_aabb = DebugBatch::default();
+        from_aabb.aabb(&aabb, Vec4::ONE);
+        let mut from_box = DebugBatch::default();
+        from_box.draw_box(
+            &Mat4::from_scale_rotation_translation(
+                aabb.extents() * 0.5,
+                Default::default(),
+                aabb.center(),
+            ),
+            Vec4::ONE,
+        );
+
+        assert_eq!(from_aabb.vertices.len(), 24);
+        for (a, b) in from_aabb.vertices.iter().zip(&from_box.vertices) {
+            assert_near(a.position, b.position);
+        }
+    }
+
+    #[test]
+    fn empty_aabb_is_skipped() {
+        let mut batch = DebugBatch::default();
+        batch.aabb(&Aabb::default(), Vec4::ONE);
+        assert!(batch.vertices.is_empty());
+    }
+
+    #[test]
+    fn sphere_circles_are_closed() {
+        let mut batch = DebugBatch::default();
+        batch.sphere(Vec3::new(1.0, 2.0, 3.0), 2.0, Vec4::ONE);
+
+        let lines = lines(&batch);
+        assert_eq!(lines.len(), 3 * SPHERE_SEGMENTS);
+        for circle in lines.chunks_exact(SPHERE_SEGMENTS) {
+            assert_near(circle[0].0, circle[SPHERE_SEGMENTS - 1].1);
+            for (a, b) in circle {
+                assert!((a.distance(Vec3::new(1.0, 2.0, 3.0)) - 2.0).abs() < 1e-4);
+                assert!((b.distance(Vec3::new(1.0, 2.0, 3.0)) - 2.0).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn frustum_unprojects_the_clip_corners() {
+        // Reverse-Z orthographic projection, near plane at depth 1
+        let proj_view = Mat4::orthographic_rh(-1.0, 1.0, -1.0, 1.0, 10.0, 0.0);
+        let mut batch = DebugBatch::default();
+        batch.frustum(&proj_view, Vec4::ONE);
+
+        assert_eq!(batch.vertices.len(), 24);
+        assert_near(batch.vertices[0].position, Vec3::new(-1.0, -1.0, 0.0));
+        for v in &batch.vertices {
+            assert_eq!(v.position.truncate().abs(), Vec2::ONE);
+            assert!(v.position.z <= 0.0 && v.position.z >= -10.0);
+        }
+    }
+
+    #[test]
+    fn axes_are_colored_per_axis() {
+        let mut batch = DebugBatch::default();
+        batch.axes(&Mat4::from_scale(Vec3::splat(5.0)), 1.0);
+
+        let lines = lines(&batch);
+        assert_eq!(lines.len(), 3);
+        assert_near(lines[0].1, Vec3::X);
+        assert_near(lines[1].1, Vec3::Y);
+        assert_near(lines[2].1, Vec3::Z);
+        assert_eq!(batch.vertices[0].color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(batch.vertices[4].color, [0.0, 0.0, 1.0, 1.0]);
+    }
+}
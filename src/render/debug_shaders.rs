@@ -0,0 +1,159 @@
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+use anyhow::Context;
+use glam::Vec4;
+use windows::Win32::Graphics::Direct3D11::ID3D11PixelShader;
+
+use super::shader::compile_hlsl;
+use super::vertex_input::VertexShader;
+use super::{ConstantBuffer, DeviceContextSwapchain};
+
+/// Adds up layers in the red channel of RT0, decoded by the compositor's overdraw mode
+const OVERDRAW_INCREMENT: Vec4 = Vec4::new(1.0 / 32.0, 0.0, 0.0, 1.0);
+/// Used for geometry without a LOD category (terrain)
+const NO_LOD_CATEGORY_COLOR: Vec4 = Vec4::new(0.5, 0.5, 0.5, 1.0);
+const WIREFRAME_COLOR: Vec4 = Vec4::new(0.9, 0.9, 0.9, 1.0);
+/// Used by the UV checker view for vertex shaders that don't write TEXCOORD0 to the register it reads
+const NO_UV_COLOR: Vec4 = Vec4::new(0.25, 0.25, 0.25, 1.0);
+/// Input register of the UV checker shader's TEXCOORD0
+const UV_CHECKER_REGISTER: u32 = 0;
+
+/// Replaces material shading of opaque geometry for debugging
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum GeometryView {
+    #[default]
+    Shaded,
+    /// Counts the layers drawn over each pixel, needs additive blending and no depth test
+    Overdraw,
+    UvChecker,
+    LodCategory,
+    MaterialId,
+    PlacementGroup,
+    /// Flat color, used for the wireframe overlay pass
+    Wireframe,
+}
+
+impl GeometryView {
+    /// Views selectable in the UI
+    pub const SELECTABLE: &'static [GeometryView] = &[
+        GeometryView::Shaded,
+        GeometryView::Overdraw,
+        GeometryView::UvChecker,
+        GeometryView::LodCategory,
+        GeometryView::MaterialId,
+        GeometryView::PlacementGroup,
+    ];
+
+    /// Flat color for a draw, `None` if the draw keeps its own pixel shader
+    pub fn color(&self, material: u32, lod_category: Option<u8>, group: u32) -> Option<Vec4> {
+        match self {
+            GeometryView::Shaded | GeometryView::UvChecker => None,
+            GeometryView::Overdraw => Some(OVERDRAW_INCREMENT),
+            GeometryView::LodCategory => Some(
+                lod_category
+                    .map(|c| index_color(c as usize))
+                    .unwrap_or(NO_LOD_CATEGORY_COLOR),
+            ),
+            GeometryView::MaterialId => Some(hash_color(material)),
+            GeometryView::PlacementGroup => Some(hash_color(group)),
+            GeometryView::Wireframe => Some(WIREFRAME_COLOR),
+        }
+    }
+}
+
+impl Display for GeometryView {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            GeometryView::Shaded => "Shaded",
+            GeometryView::Overdraw => "Overdraw",
+            GeometryView::UvChecker => "UV checker",
+            GeometryView::LodCategory => "LOD category",
+            GeometryView::MaterialId => "Material ID",
+            GeometryView::PlacementGroup => "Placement group",
+            GeometryView::Wireframe => "Wireframe",
+        })
+    }
+}
+
+/// Shaders used by debug views, compiled from debug.hlsl
+pub struct DebugShaders {
+    color_ps: ID3D11PixelShader,
+    color_cb: ConstantBuffer<Vec4>,
+    uv_checker_ps: ID3D11PixelShader,
+}
+
+impl DebugShaders {
+    pub fn create(dcs: Rc<DeviceContextSwapchain>) -> anyhow::Result<Self> {
+        let color_ps_data = compile_hlsl(w!("debug.hlsl"), s!("PSColor"), s!("ps_5_0"))
+            .context("Failed to compile debug color shader")?;
+        let color_ps = unsafe { dcs.device.CreatePixelShader(&color_ps_data, None)? };
+
+        let uv_checker_ps_data = compile_hlsl(w!("debug.hlsl"), s!("PSUvChecker"), s!("ps_5_0"))
+            .context("Failed to compile debug UV checker shader")?;
+        let uv_checker_ps = unsafe { dcs.device.CreatePixelShader(&uv_checker_ps_data, None)? };
+
+        Ok(Self {
+            color_ps,
+            color_cb: ConstantBuffer::create(dcs, None)?,
+            uv_checker_ps,
+        })
+    }
+
+    /// Replaces the bound pixel shader with one that writes a flat color to the GBuffer
+    pub fn bind_color(&self, dcs: &DeviceContextSwapchain, color: Vec4) -> anyhow::Result<()> {
+        self.color_cb.write(&color)?;
+        unsafe {
+            dcs.context.PSSetShader(&self.color_ps, None);
+            dcs.context
+                .PSSetConstantBuffers(0, Some(&[Some(self.color_cb.buffer().clone())]));
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the bound pixel shader with a checkerboard of the first texture coordinate set. Pixel shader inputs
+    /// are linked to vertex shader outputs by register, so vertex shaders that don't write TEXCOORD0 to the register
+    /// the checker reads get a flat color instead
+    pub fn bind_uv_checker(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        vertex_shader: Option<&VertexShader>,
+    ) -> anyhow::Result<()> {
+        if vertex_shader.and_then(|vs| vs.texcoord0_register) != Some(UV_CHECKER_REGISTER) {
+            return self.bind_color(dcs, NO_UV_COLOR);
+        }
+
+        unsafe {
+            dcs.context.PSSetShader(&self.uv_checker_ps, None);
+        }
+
+        Ok(())
+    }
+}
+
+/// Distinct color for the given index, using golden ratio hue steps
+pub fn index_color(index: usize) -> Vec4 {
+    hue_color((index as f32 * 0.618_034).fract())
+}
+
+/// Stable pseudo-random color for a hash (tag, material, ...)
+pub fn hash_color(hash: u32) -> Vec4 {
+    // Mix the bits so similar hashes end up with different hues
+    let mut h = hash.wrapping_mul(0x9e37_79b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    hue_color((h >> 8) as f32 / (1 << 24) as f32)
+}
+
+/// Fully saturated color for a hue in the 0..1 range
+fn hue_color(hue: f32) -> Vec4 {
+    let h = hue * 6.0;
+    Vec4::new(
+        ((h - 3.0).abs() - 1.0).clamp(0.0, 1.0),
+        (2.0 - (h - 2.0).abs()).clamp(0.0, 1.0),
+        (2.0 - (h - 4.0).abs()).clamp(0.0, 1.0),
+        1.0,
+    )
+}
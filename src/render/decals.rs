@@ -0,0 +1,469 @@
+use std::rc::Rc;
+
+use anyhow::{ensure, Context};
+use destiny_pkg::TagHash;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use windows::Win32::Graphics::Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST;
+use windows::Win32::Graphics::Direct3D11::*;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT_R16_UINT;
+
+use crate::bounds::Aabb;
+use crate::culling::ViewCuller;
+use crate::material::Material;
+use crate::transform::Transform;
+use crate::vertex_layout::InputSlot;
+
+use super::scopes::ScopeRigidModel;
+use super::shader::compile_hlsl;
+use super::vertex_input::VertexBuffers;
+use super::{ConstantBuffer, DeviceContextSwapchain, RenderData};
+
+/// Triangle list for a cube spanning -1..1, wound counter-clockwise when viewed from outside. Corner `i` sits at
+/// `(i & 1, (i >> 1) & 1, (i >> 2) & 1) * 2 - 1`, matching `cubeIndices` in decal.hlsl
+const CUBE_INDICES: [u16; 36] = [
+    4, 6, 2, 4, 2, 0, //
+    1, 3, 7, 1, 7, 5, //
+    0, 1, 5, 0, 5, 4, //
+    6, 7, 3, 6, 3, 2, //
+    2, 3, 1, 2, 1, 0, //
+    4, 5, 7, 4, 7, 6,
+];
+
+/// Cube corners as R16G16B16A16_SNORM positions, the format material vertex shaders expect for positions
+fn cube_corners() -> [[i16; 4]; 8] {
+    let axis = |bit: usize| if bit != 0 { i16::MAX } else { -i16::MAX };
+    std::array::from_fn(|i| [axis(i & 1), axis(i & 2), axis(i & 4), i16::MAX])
+}
+
+/// Per-frame decal pass constants (b0)
+#[repr(C)]
+struct ScopeDecalView {
+    proj_view: Mat4,
+    proj_view_inv: Mat4,
+    target_size: Vec4,
+}
+
+/// Per-decal constants (b1)
+#[repr(C)]
+#[derive(Debug)]
+struct ScopeDecal {
+    decal_to_world: Mat4,
+    world_to_decal: Mat4,
+}
+
+impl ScopeDecal {
+    fn new(transform: &Transform) -> anyhow::Result<Self> {
+        ensure!(
+            !transform.is_degenerate(),
+            "Decal transform has a scale of 0"
+        );
+
+        let decal_to_world = transform.to_mat4();
+        Ok(Self {
+            decal_to_world,
+            world_to_decal: decal_to_world.inverse(),
+        })
+    }
+
+    /// World space bounds of the -1..1 projection box
+    fn bounds(&self) -> Aabb {
+        Aabb {
+            min: Vec3::NEG_ONE,
+            max: Vec3::ONE,
+        }
+        .transform(&self.decal_to_world)
+    }
+}
+
+/// A decal projection box placed in the map
+pub struct Decal {
+    pub material: TagHash,
+    /// World space bounds of the projection box
+    pub bounds: Aabb,
+    /// Maps the -1..1 projection box to world space
+    pub transform: Mat4,
+    cb: ConstantBuffer<ScopeDecal>,
+    /// Object constants (cb11) for drawing the box with the material's own vertex shader
+    object_cb: ConstantBuffer<ScopeRigidModel>,
+}
+
+impl Decal {
+    pub fn create(
+        material: TagHash,
+        transform: &Transform,
+        dcs: Rc<DeviceContextSwapchain>,
+    ) -> anyhow::Result<Self> {
+        let scope = ScopeDecal::new(transform)?;
+        let bounds = scope.bounds();
+        let decal_to_world = scope.decal_to_world;
+
+        let cb = ConstantBuffer::create(dcs.clone(), Some(&scope))
+            .context("Failed to create decal constant buffer")?;
+
+        let object_cb = ConstantBuffer::create(
+            dcs,
+            Some(&ScopeRigidModel {
+                mesh_to_world: decal_to_world,
+                position_scale: Vec4::ONE,
+                position_offset: Vec4::ZERO,
+                texcoord0_scale_offset: Vec4::new(1.0, 1.0, 0.0, 0.0),
+                dynamic_sh_ao_values: Vec4::ZERO,
+            }),
+        )
+        .context("Failed to create decal object constant buffer")?;
+
+        Ok(Self {
+            material,
+            bounds,
+            transform: decal_to_world,
+            cb,
+            object_cb,
+        })
+    }
+}
+
+/// Draws decal boxes with their material shaders. Decals whose shaders aren't loaded, or whose vertex shader
+/// can't be fed from the box vertices, fall back to projecting their albedo with the shaders from decal.hlsl
+pub struct DecalRenderer {
+    /// Box geometry for material vertex shaders, a single slot of positions
+    cube_vertices: VertexBuffers,
+    cube_indices: ID3D11Buffer,
+    vshader: ID3D11VertexShader,
+    pshader: ID3D11PixelShader,
+    rasterizer_state: ID3D11RasterizerState,
+    blend_state: ID3D11BlendState,
+    sampler: ID3D11SamplerState,
+    view_cb: ConstantBuffer<ScopeDecalView>,
+}
+
+impl DecalRenderer {
+    pub fn create(dcs: Rc<DeviceContextSwapchain>) -> anyhow::Result<Self> {
+        let vs_data = compile_hlsl(w!("decal.hlsl"), s!("VSDecal"), s!("vs_5_0"))
+            .context("Failed to compile decal vertex shader")?;
+        let ps_data = compile_hlsl(w!("decal.hlsl"), s!("PSDecal"), s!("ps_5_0"))
+            .context("Failed to compile decal pixel shader")?;
+
+        let mut cube_vertices = VertexBuffers::default();
+        let vertices = cube_corners();
+        cube_vertices
+            .push_data(
+                &dcs.device,
+                InputSlot {
+                    stride: std::mem::size_of::<[i16; 4]>() as u32,
+                    vtype: 0,
+                    instance_step_rate: 0,
+                },
+                bytemuck::cast_slice(&vertices),
+            )
+            .context("Failed to create decal vertex buffer")?;
+
+        unsafe {
+            let cube_indices = dcs
+                .device
+                .CreateBuffer(
+                    &D3D11_BUFFER_DESC {
+                        ByteWidth: std::mem::size_of_val(&CUBE_INDICES) as _,
+                        Usage: D3D11_USAGE_IMMUTABLE,
+                        BindFlags: D3D11_BIND_INDEX_BUFFER,
+                        ..Default::default()
+                    },
+                    Some(&D3D11_SUBRESOURCE_DATA {
+                        pSysMem: CUBE_INDICES.as_ptr() as _,
+                        ..Default::default()
+                    }),
+                )
+                .context("Failed to create decal index buffer")?;
+
+            let vshader = dcs.device.CreateVertexShader(&vs_data, None)?;
+            let pshader = dcs.device.CreatePixelShader(&ps_data, None)?;
+
+            // Back faces are drawn so decals still show up when the camera is inside the box
+            let rasterizer_state = dcs
+                .device
+                .CreateRasterizerState(&D3D11_RASTERIZER_DESC {
+                    FillMode: D3D11_FILL_SOLID,
+                    CullMode: D3D11_CULL_FRONT,
+                    FrontCounterClockwise: true.into(),
+                    DepthClipEnable: true.into(),
+                    ..Default::default()
+                })
+                .context("Failed to create decal rasterizer state")?;
+
+            let blend_state = dcs
+                .device
+                .CreateBlendState(&D3D11_BLEND_DESC {
+                    RenderTarget: [D3D11_RENDER_TARGET_BLEND_DESC {
+                        BlendEnable: true.into(),
+                        SrcBlend: D3D11_BLEND_SRC_ALPHA,
+                        DestBlend: D3D11_BLEND_INV_SRC_ALPHA,
+                        BlendOp: D3D11_BLEND_OP_ADD,
+                        SrcBlendAlpha: D3D11_BLEND_ZERO,
+                        DestBlendAlpha: D3D11_BLEND_ONE,
+                        BlendOpAlpha: D3D11_BLEND_OP_ADD,
+                        // RT0 alpha holds iridescence, leave it untouched
+                        RenderTargetWriteMask: (D3D11_COLOR_WRITE_ENABLE_RED.0
+                            | D3D11_COLOR_WRITE_ENABLE_GREEN.0
+                            | D3D11_COLOR_WRITE_ENABLE_BLUE.0)
+                            as u8,
+                    }; 8],
+                    ..Default::default()
+                })
+                .context("Failed to create decal blend state")?;
+
+            let sampler = dcs
+                .device
+                .CreateSamplerState(&D3D11_SAMPLER_DESC {
+                    Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+                    AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+                    MaxLOD: f32::MAX,
+                    ..Default::default()
+                })
+                .context("Failed to create decal sampler")?;
+
+            Ok(Self {
+                cube_vertices,
+                cube_indices,
+                vshader,
+                pshader,
+                rasterizer_state,
+                blend_state,
+                sampler,
+                view_cb: ConstantBuffer::create(dcs, None)?,
+            })
+        }
+    }
+
+    /// Draws the visible decals into the GBuffer. `render_targets` are the GBuffer targets in order, the depth buffer
+    /// must be bound to t10 and not as the depth target. Returns the number of decals drawn
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        render_data: &RenderData,
+        decals: &[Decal],
+        culler: &ViewCuller,
+        proj_view: Mat4,
+        target_size: Vec2,
+        render_targets: &[ID3D11RenderTargetView],
+    ) -> anyhow::Result<usize> {
+        self.view_cb.write(&ScopeDecalView {
+            proj_view,
+            proj_view_inv: proj_view.inverse(),
+            target_size: target_size.extend(0.0).extend(0.0),
+        })?;
+
+        let mut drawn = 0;
+        // Material draws overwrite the fallback state, so it's rebound lazily
+        let mut fallback_bound = false;
+        unsafe {
+            dcs.context
+                .IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            dcs.context.RSSetState(&self.rasterizer_state);
+            dcs.context.OMSetBlendState(
+                &self.blend_state,
+                Some(&[1f32, 1., 1., 1.] as _),
+                0xffffffff,
+            );
+
+            for decal in decals {
+                if !culler.is_aabb_visible(&decal.bounds) {
+                    continue;
+                }
+
+                let Some(material) = render_data.materials.get(&decal.material.0) else {
+                    continue;
+                };
+
+                if self.draw_material(dcs, render_data, decal, material, render_targets) {
+                    fallback_bound = false;
+                    drawn += 1;
+                    continue;
+                }
+
+                // The texture in the lowest slot is used as the decal albedo
+                let Some(texture) = material
+                    .ps_textures
+                    .iter()
+                    .min_by_key(|t| t.index)
+                    .and_then(|t| render_data.textures.get(&t.texture.0))
+                else {
+                    continue;
+                };
+
+                if !fallback_bound {
+                    self.bind_fallback(dcs, render_targets);
+                    fallback_bound = true;
+                }
+
+                dcs.context
+                    .PSSetShaderResources(0, Some(&[Some(texture.view.clone())]));
+                dcs.context
+                    .VSSetConstantBuffers(1, Some(&[Some(decal.cb.buffer().clone())]));
+                dcs.context
+                    .PSSetConstantBuffers(1, Some(&[Some(decal.cb.buffer().clone())]));
+                dcs.context.Draw(36, 0);
+                drawn += 1;
+            }
+        }
+
+        Ok(drawn)
+    }
+
+    /// Draws the decal box with the material's own shaders and resources, the same way the geometry passes bind
+    /// materials. Returns false if the shaders aren't loaded or the vertex shader needs more than a position
+    fn draw_material(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        render_data: &RenderData,
+        decal: &Decal,
+        material: &Material,
+        render_targets: &[ID3D11RenderTargetView],
+    ) -> bool {
+        let Some((_, target_count)) = render_data.pshaders.get(&material.pixel_shader.0) else {
+            return false;
+        };
+
+        let Some(layout) = render_data
+            .vshaders
+            .get(&material.vertex_shader.0)
+            .and_then(|vs| {
+                render_data.input_layouts.get(
+                    dcs,
+                    material.vertex_shader.0,
+                    vs,
+                    self.cube_vertices.slots,
+                )
+            })
+        else {
+            return false;
+        };
+
+        if material.bind_vertex_shader(dcs, render_data).is_err()
+            || material.bind_pixel_shader(dcs, render_data).is_err()
+        {
+            return false;
+        }
+        material.bind_resources(dcs, render_data);
+
+        // Only bind the targets the pixel shader writes, unwritten targets would be left undefined
+        let target_count = target_count
+            .unwrap_or(1)
+            .clamp(1, render_targets.len() as u32);
+        let targets: Vec<_> = render_targets[..target_count as usize]
+            .iter()
+            .cloned()
+            .map(Some)
+            .collect();
+
+        unsafe {
+            dcs.context.OMSetRenderTargets(Some(&targets), None);
+            dcs.context.IASetInputLayout(&layout);
+            self.cube_vertices.bind(dcs);
+            dcs.context
+                .IASetIndexBuffer(Some(&self.cube_indices), DXGI_FORMAT_R16_UINT, 0);
+            dcs.context
+                .VSSetConstantBuffers(11, Some(&[Some(decal.object_cb.buffer().clone())]));
+            dcs.context.DrawIndexed(CUBE_INDICES.len() as u32, 0, 0);
+        }
+
+        true
+    }
+
+    /// Binds the decal.hlsl projection shaders, which only write albedo (RT0)
+    fn bind_fallback(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        render_targets: &[ID3D11RenderTargetView],
+    ) {
+        unsafe {
+            dcs.context
+                .OMSetRenderTargets(Some(&[render_targets.first().cloned()]), None);
+            dcs.context.VSSetShader(&self.vshader, None);
+            dcs.context.PSSetShader(&self.pshader, None);
+            dcs.context.IASetInputLayout(None);
+            dcs.context
+                .PSSetSamplers(0, Some(&[Some(self.sampler.clone())]));
+            dcs.context
+                .VSSetConstantBuffers(0, Some(&[Some(self.view_cb.buffer().clone())]));
+            dcs.context
+                .PSSetConstantBuffers(0, Some(&[Some(self.view_cb.buffer().clone())]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Quat;
+
+    /// Decal placed by an `Unk80806e68` transform (translation, w = scale) in a collection rotated a quarter turn
+    /// around Z
+    fn decal() -> ScopeDecal {
+        let transform = Transform::from_translation_scale(
+            Vec4::new(10.0, 20.0, 30.0, 2.0),
+            Quat::from_rotation_z(90f32.to_radians()),
+        );
+        ScopeDecal::new(&transform).unwrap()
+    }
+
+    #[test]
+    fn decal_box_to_world() {
+        let scope = decal();
+
+        let center = scope.decal_to_world.transform_point3(Vec3::ZERO);
+        assert!(center.abs_diff_eq(Vec3::new(10.0, 20.0, 30.0), 1e-5));
+
+        // Local +X turns towards world +Y, scaled by w
+        let x = scope.decal_to_world.transform_point3(Vec3::X);
+        assert!(x.abs_diff_eq(Vec3::new(10.0, 22.0, 30.0), 1e-5));
+        let z = scope.decal_to_world.transform_point3(Vec3::Z);
+        assert!(z.abs_diff_eq(Vec3::new(10.0, 20.0, 32.0), 1e-5));
+    }
+
+    #[test]
+    fn decal_projection_from_world() {
+        let scope = decal();
+
+        // A point on the projection box face the texture is projected along (local Z)
+        let local = scope
+            .world_to_decal
+            .transform_point3(Vec3::new(9.0, 21.0, 28.0));
+        assert!(local.abs_diff_eq(Vec3::new(0.5, 0.5, -1.0), 1e-5));
+
+        // Outside of the box, clipped by PSDecal
+        let outside = scope
+            .world_to_decal
+            .transform_point3(Vec3::new(13.0, 20.0, 30.0));
+        assert!(outside.abs().max_element() > 1.0);
+
+        assert!((scope.world_to_decal * scope.decal_to_world).abs_diff_eq(Mat4::IDENTITY, 1e-5));
+    }
+
+    #[test]
+    fn decal_bounds() {
+        let bounds = decal().bounds();
+        assert!(bounds.min.abs_diff_eq(Vec3::new(8.0, 18.0, 28.0), 1e-5));
+        assert!(bounds.max.abs_diff_eq(Vec3::new(12.0, 22.0, 32.0), 1e-5));
+    }
+
+    #[test]
+    fn zero_scale_decals_are_rejected() {
+        let transform =
+            Transform::from_translation_scale(Vec4::new(1.0, 2.0, 3.0, 0.0), Quat::IDENTITY);
+        assert!(ScopeDecal::new(&transform).is_err());
+    }
+
+    #[test]
+    fn cube_triangles_face_outwards() {
+        let corners = cube_corners()
+            .map(|c| Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32) / i16::MAX as f32);
+        for t in CUBE_INDICES.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| corners[i as usize]);
+            let normal = (b - a).cross(c - a);
+            let centroid = (a + b + c) / 3.0;
+            assert!(normal.dot(centroid) > 0.0, "Triangle {t:?} faces inwards");
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use glam::{Vec3, Vec4};
+use windows::core::Vtable;
+use windows::Win32::Graphics::Direct3D::D3D_PRIMITIVE_TOPOLOGY;
+use windows::Win32::Graphics::Direct3D11::{ID3D11Buffer, ID3D11ShaderResourceView};
+use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT;
+
+use crate::material::{Material, RenderStage};
+use crate::vertex_layout::InputSlots;
+
+use super::debug_shaders::GeometryView;
+use super::vertex_input::VertexBuffers;
+use super::{DebugShaders, DeviceContextSwapchain, RenderData};
+
+/// Draws are sorted by pixel shader, vertex shader, material and vertex buffer (in that order), so that
+/// consecutive draws share as much state as possible
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SortKey {
+    pub pixel_shader: u32,
+    pub vertex_shader: u32,
+    pub material: u32,
+    /// Identifies the vertex/index buffer pair, see [`vertex_buffer_id`]
+    pub vertex_buffer: u64,
+}
+
+impl SortKey {
+    pub fn new(material: &Material, vertex_buffer: u64) -> Self {
+        Self {
+            pixel_shader: material.pixel_shader.0,
+            vertex_shader: material.vertex_shader.0,
+            material: material.tag().0,
+            vertex_buffer,
+        }
+    }
+}
+
+/// Builds a vertex buffer identifier from the tag that owns the buffer and the index of the buffer within it
+pub fn vertex_buffer_id(owner: u32, buffer_index: usize) -> u64 {
+    ((owner as u64) << 32) | buffer_index as u64
+}
+
+pub struct DrawItem {
+    pub sort_key: SortKey,
+
+    pub vertex_buffers: VertexBuffers,
+    pub index_buffer: ID3D11Buffer,
+    pub index_format: DXGI_FORMAT,
+    pub topology: D3D_PRIMITIVE_TOPOLOGY,
+
+    pub index_start: u32,
+    pub index_count: u32,
+    /// Number of instances for instanced draws
+    pub instance_count: Option<u32>,
+
+    /// Per-object vertex shader constants (cb11)
+    pub object_constants: ID3D11Buffer,
+    /// Texture to bind to pixel shader slot 14 (terrain dyemaps)
+    pub dyemap: Option<Option<ID3D11ShaderResourceView>>,
+    /// Overrides the pixel shader with a flat color
+    pub debug_color: Option<Vec4>,
+    /// Raw `ELodCategory` of the part, if it has one
+    pub lod_category: Option<u8>,
+    /// Tag of the placement group, terrain or entity the draw belongs to
+    pub group: u32,
+
+    /// World space center, used to sort transparent draws
+    pub center: Vec3,
+}
+
+impl DrawItem {
+    fn draw_state(&self) -> DrawState {
+        DrawState {
+            key: self.sort_key,
+            slots: self.vertex_buffers.slots,
+            topology: self.topology,
+            object_constants: self.object_constants.as_raw() as usize,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct DrawStats {
+    pub draw_calls: usize,
+    pub shader_binds: usize,
+    pub material_binds: usize,
+    pub vertex_buffer_binds: usize,
+    pub constant_buffer_binds: usize,
+    /// Draws skipped because their vertex shader inputs couldn't be matched to their vertex buffers
+    pub missing_input_layouts: usize,
+
+    pub transparent_draws: usize,
+    pub decals: usize,
+
+    pub lights: usize,
+    pub max_tile_lights: usize,
+    pub cubemap_volumes: usize,
+}
+
+impl DrawStats {
+    fn add_binds(&mut self, changes: &StateChanges) {
+        self.shader_binds += changes.vertex_shader as usize + changes.pixel_shader as usize;
+        self.material_binds += changes.material as usize;
+        self.vertex_buffer_binds += changes.vertex_buffer as usize;
+        self.constant_buffer_binds += changes.object_constants as usize;
+    }
+
+    pub fn add(&mut self, other: DrawStats) {
+        self.draw_calls += other.draw_calls;
+        self.shader_binds += other.shader_binds;
+        self.material_binds += other.material_binds;
+        self.vertex_buffer_binds += other.vertex_buffer_binds;
+        self.constant_buffer_binds += other.constant_buffer_binds;
+        self.missing_input_layouts += other.missing_input_layouts;
+        self.transparent_draws += other.transparent_draws;
+        self.decals += other.decals;
+        self.lights += other.lights;
+        self.max_tile_lights = self.max_tile_lights.max(other.max_tile_lights);
+        self.cubemap_volumes += other.cubemap_volumes;
+    }
+}
+
+/// The state a draw needs bound
+#[derive(Clone, Copy)]
+struct DrawState {
+    key: SortKey,
+    slots: InputSlots,
+    topology: D3D_PRIMITIVE_TOPOLOGY,
+    /// Identity of the per-object constant buffer
+    object_constants: usize,
+}
+
+/// Parts of the state that have to be bound for a draw, see [`BoundState::changes`]
+#[derive(Default, Debug, PartialEq)]
+struct StateChanges {
+    vertex_shader: bool,
+    input_layout: bool,
+    pixel_shader: bool,
+    material: bool,
+    vertex_buffer: bool,
+    topology: bool,
+    object_constants: bool,
+}
+
+/// Last bound state, used to skip redundant binds
+#[derive(Default)]
+struct BoundState {
+    pixel_shader: Option<u32>,
+    vertex_shader: Option<u32>,
+    input_layout: Option<(u32, InputSlots)>,
+    material: Option<u32>,
+    vertex_buffer: Option<u64>,
+    topology: Option<D3D_PRIMITIVE_TOPOLOGY>,
+    object_constants: Option<usize>,
+}
+
+impl BoundState {
+    /// Records the state of the next draw as bound, and returns the parts that weren't bound already
+    fn changes(&mut self, draw: &DrawState) -> StateChanges {
+        let key = draw.key;
+        StateChanges {
+            vertex_shader: needs_bind(&mut self.vertex_shader, key.vertex_shader),
+            input_layout: needs_bind(&mut self.input_layout, (key.vertex_shader, draw.slots)),
+            pixel_shader: needs_bind(&mut self.pixel_shader, key.pixel_shader),
+            material: needs_bind(&mut self.material, key.material),
+            vertex_buffer: needs_bind(&mut self.vertex_buffer, key.vertex_buffer),
+            topology: needs_bind(&mut self.topology, draw.topology),
+            object_constants: needs_bind(&mut self.object_constants, draw.object_constants),
+        }
+    }
+
+    /// Forgets everything, so the next draw binds all of its state. Used when a bind fails halfway through a draw
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Records `value` as the bound state of a slot. Returns false if it was already bound, in which case the bind can
+/// be skipped
+fn needs_bind<T: PartialEq>(bound: &mut Option<T>, value: T) -> bool {
+    if bound.as_ref() == Some(&value) {
+        return false;
+    }
+
+    *bound = Some(value);
+    true
+}
+
+/// Draws collected over a frame, executed in sorted order
+#[derive(Default)]
+pub struct DrawList {
+    items: Vec<DrawItem>,
+}
+
+impl DrawList {
+    pub fn push(&mut self, item: DrawItem) {
+        self.items.push(item);
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Stable sort, items with equal keys keep their submission order
+    pub fn sort(&mut self) {
+        self.items.sort_by_key(|i| i.sort_key);
+    }
+
+    /// Sorts items by distance, farthest first, for correct blending
+    pub fn sort_back_to_front(&mut self, view_position: Vec3) {
+        self.items.sort_by(|a, b| {
+            b.center
+                .distance_squared(view_position)
+                .total_cmp(&a.center.distance_squared(view_position))
+        });
+    }
+
+    /// Draws all items in their current order. Items stay in the list until [`DrawList::clear`] is called
+    pub fn execute(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        render_data: &RenderData,
+        debug_shaders: &DebugShaders,
+        view: GeometryView,
+    ) -> anyhow::Result<DrawStats> {
+        let mut stats = DrawStats::default();
+        let mut state = BoundState::default();
+        for item in &self.items {
+            let key = item.sort_key;
+            let Some(material) = render_data.materials.get(&key.material) else {
+                continue;
+            };
+
+            let changes = state.changes(&item.draw_state());
+            if changes.vertex_shader && material.bind_vertex_shader(dcs, render_data).is_err() {
+                state.reset();
+                continue;
+            }
+
+            if changes.input_layout {
+                let Some(layout) = render_data.vshaders.get(&key.vertex_shader).and_then(|vs| {
+                    render_data.input_layouts.get(
+                        dcs,
+                        key.vertex_shader,
+                        vs,
+                        item.vertex_buffers.slots,
+                    )
+                }) else {
+                    state.reset();
+                    stats.missing_input_layouts += 1;
+                    continue;
+                };
+
+                unsafe {
+                    dcs.context.IASetInputLayout(&layout);
+                }
+            }
+
+            if changes.pixel_shader && material.bind_pixel_shader(dcs, render_data).is_err() {
+                state.reset();
+                continue;
+            }
+
+            if changes.material {
+                material.bind_resources(dcs, render_data);
+            }
+
+            if let Some(color) = item
+                .debug_color
+                .or_else(|| view.color(key.material, item.lod_category, item.group))
+            {
+                debug_shaders.bind_color(dcs, color)?;
+                // The debug shader replaces the pixel shader and cb0
+                state.pixel_shader = None;
+                state.material = None;
+            } else if view == GeometryView::UvChecker {
+                debug_shaders.bind_uv_checker(dcs, render_data.vshaders.get(&key.vertex_shader))?;
+                // Unsupported vertex shaders get the flat color shader, which replaces cb0
+                state.pixel_shader = None;
+                state.material = None;
+            }
+
+            unsafe {
+                if changes.vertex_buffer {
+                    item.vertex_buffers.bind(dcs);
+                    dcs.context
+                        .IASetIndexBuffer(Some(&item.index_buffer), item.index_format, 0);
+                }
+
+                if changes.topology {
+                    dcs.context.IASetPrimitiveTopology(item.topology);
+                }
+
+                if changes.object_constants {
+                    dcs.context
+                        .VSSetConstantBuffers(11, Some(&[Some(item.object_constants.clone())]));
+                }
+
+                if let Some(dyemap) = &item.dyemap {
+                    dcs.context
+                        .PSSetShaderResources(14, Some(&[dyemap.clone()]));
+                }
+
+                match item.instance_count {
+                    Some(instance_count) => dcs.context.DrawIndexedInstanced(
+                        item.index_count,
+                        instance_count,
+                        item.index_start,
+                        0,
+                        0,
+                    ),
+                    None => dcs
+                        .context
+                        .DrawIndexed(item.index_count, item.index_start, 0),
+                }
+            }
+            stats.add_binds(&changes);
+            stats.draw_calls += 1;
+        }
+
+        Ok(stats)
+    }
+}
+
+/// Draw lists for the geometry passes of a frame
+#[derive(Default)]
+pub struct PassDrawLists {
+    /// Drawn into the GBuffer
+    pub opaque: DrawList,
+    /// Drawn after the GBuffer passes, blended into the forward target
+    pub transparent: DrawList,
+}
+
+impl PassDrawLists {
+    /// Adds an item to the list of the given stage. Depth-only items are dropped
+    pub fn push(&mut self, stage: RenderStage, item: DrawItem) {
+        match stage {
+            RenderStage::Opaque => self.opaque.push(item),
+            RenderStage::Transparent => self.transparent.push(item),
+            RenderStage::DepthOnly => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vertex_layout::InputSlot;
+    use windows::Win32::Graphics::Direct3D::{
+        D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST, D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+    };
+
+    fn key(pixel_shader: u32, vertex_shader: u32, material: u32, vertex_buffer: u64) -> SortKey {
+        SortKey {
+            pixel_shader,
+            vertex_shader,
+            material,
+            vertex_buffer,
+        }
+    }
+
+    #[test]
+    fn sort_key_orders_by_shader_then_material() {
+        let mut keys = vec![
+            key(2, 1, 1, 0),
+            key(1, 2, 1, 0),
+            key(1, 1, 3, 0),
+            key(1, 1, 2, vertex_buffer_id(5, 0)),
+            key(1, 1, 2, vertex_buffer_id(4, 1)),
+            key(1, 1, 2, vertex_buffer_id(4, 0)),
+        ];
+        keys.sort();
+
+        assert_eq!(
+            keys,
+            [
+                key(1, 1, 2, vertex_buffer_id(4, 0)),
+                key(1, 1, 2, vertex_buffer_id(4, 1)),
+                key(1, 1, 2, vertex_buffer_id(5, 0)),
+                key(1, 1, 3, 0),
+                key(1, 2, 1, 0),
+                key(2, 1, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn shader_outranks_material() {
+        // A lower material tag doesn't move a draw ahead of one with a lower shader
+        assert!(key(1, 1, 9, 9) < key(2, 1, 0, 0));
+        assert!(key(1, 1, 9, 9) < key(1, 2, 0, 0));
+        assert!(key(1, 1, 1, 9) < key(1, 1, 2, 0));
+    }
+
+    #[test]
+    fn binds_are_skipped_only_for_identical_state() {
+        let mut bound = None;
+        assert!(needs_bind(&mut bound, 1u32));
+        assert!(!needs_bind(&mut bound, 1));
+        assert!(needs_bind(&mut bound, 2));
+        assert!(!needs_bind(&mut bound, 2));
+        assert!(needs_bind(&mut bound, 1));
+
+        // Resetting the state (after a failed bind, or a debug shader replacing it) forces a rebind
+        bound = None;
+        assert!(needs_bind(&mut bound, 1));
+    }
+
+    fn draw(
+        key: SortKey,
+        slots: InputSlots,
+        topology: D3D_PRIMITIVE_TOPOLOGY,
+        cb: usize,
+    ) -> DrawState {
+        DrawState {
+            key,
+            slots,
+            topology,
+            object_constants: cb,
+        }
+    }
+
+    /// Runs the draws through the bind elision in the given order, counting binds like [`DrawList::execute`]
+    fn execute(draws: &[DrawState]) -> (DrawStats, usize) {
+        let mut stats = DrawStats::default();
+        let mut input_layout_binds = 0;
+        let mut state = BoundState::default();
+        for draw in draws {
+            let changes = state.changes(draw);
+            input_layout_binds += changes.input_layout as usize;
+            stats.add_binds(&changes);
+            stats.draw_calls += 1;
+        }
+
+        (stats, input_layout_binds)
+    }
+
+    #[test]
+    fn sorted_draws_skip_redundant_binds() {
+        let one_slot = {
+            let mut slots = InputSlots::default();
+            slots
+                .push(InputSlot {
+                    stride: 16,
+                    ..Default::default()
+                })
+                .unwrap();
+            slots
+        };
+        let two_slots = {
+            let mut slots = one_slot;
+            slots
+                .push(InputSlot {
+                    stride: 8,
+                    ..Default::default()
+                })
+                .unwrap();
+            slots
+        };
+
+        let submitted = [
+            draw(
+                key(2, 1, 20, vertex_buffer_id(7, 0)),
+                one_slot,
+                D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                1,
+            ),
+            draw(
+                key(1, 1, 10, vertex_buffer_id(5, 0)),
+                one_slot,
+                D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                1,
+            ),
+            draw(
+                key(1, 2, 10, vertex_buffer_id(6, 0)),
+                one_slot,
+                D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                3,
+            ),
+            draw(
+                key(1, 1, 10, vertex_buffer_id(5, 0)),
+                one_slot,
+                D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                2,
+            ),
+            draw(
+                key(1, 1, 11, vertex_buffer_id(5, 0)),
+                one_slot,
+                D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+                2,
+            ),
+            draw(
+                key(2, 1, 20, vertex_buffer_id(7, 0)),
+                two_slots,
+                D3D_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                1,
+            ),
+        ];
+
+        // Same (stable) sort as DrawList::sort
+        let mut sorted = submitted.to_vec();
+        sorted.sort_by_key(|d| d.key);
+        assert_eq!(
+            sorted
+                .iter()
+                .map(|d| d.object_constants)
+                .collect::<Vec<_>>(),
+            [1, 2, 2, 3, 1, 1]
+        );
+
+        let (stats, input_layout_binds) = execute(&sorted);
+        assert_eq!(stats.draw_calls, 6);
+        // VS 1 + PS 1, VS 2, VS 1 + PS 2
+        assert_eq!(stats.shader_binds, 5);
+        // 10, 11, 10 (after the vertex shader switch), 20
+        assert_eq!(stats.material_binds, 4);
+        assert_eq!(stats.vertex_buffer_binds, 3);
+        assert_eq!(stats.constant_buffer_binds, 4);
+        // The last draw shares its shader with the one before it, but has an extra vertex buffer
+        assert_eq!(input_layout_binds, 4);
+
+        // In submission order the pixel shaders and vertex buffers alternate
+        let (unsorted, _) = execute(&submitted);
+        assert_eq!(unsorted.draw_calls, 6);
+        assert_eq!(unsorted.shader_binds, 6);
+        assert_eq!(unsorted.vertex_buffer_binds, 5);
+    }
+
+    #[test]
+    fn reset_state_rebinds_everything() {
+        let d = draw(
+            key(1, 1, 1, vertex_buffer_id(1, 0)),
+            InputSlots::default(),
+            D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+            1,
+        );
+
+        let mut state = BoundState::default();
+        let all = StateChanges {
+            vertex_shader: true,
+            input_layout: true,
+            pixel_shader: true,
+            material: true,
+            vertex_buffer: true,
+            topology: true,
+            object_constants: true,
+        };
+        assert_eq!(state.changes(&d), all);
+        assert_eq!(state.changes(&d), StateChanges::default());
+
+        state.reset();
+        assert_eq!(state.changes(&d), all);
+    }
+}
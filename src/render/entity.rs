@@ -2,27 +2,37 @@ use anyhow::Context;
 use destiny_pkg::TagHash;
 
 use glam::Vec4;
+use std::rc::Rc;
 
-use tracing::warn;
+use tracing::{debug, warn};
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Dxgi::Common::*;
 
+use crate::bounds::Aabb;
 use crate::entity::EPrimitiveType;
 use crate::entity::IndexBufferHeader;
 use crate::entity::Unk808072c5;
 use crate::entity::Unk8080737e;
 use crate::entity::Unk808073a5;
 use crate::entity::VertexBufferHeader;
+use crate::index_buffer::{part_indices, read_indices, triangles};
+use crate::lod::LOD_LEVELS;
+use crate::transform::Transform;
+use crate::vertex_stream::{MaterialShaderInputs, VertexStream, VertexTransform};
 
 use crate::packages::package_manager;
 
+use super::debug_shaders::index_color;
+use super::draw_list::{vertex_buffer_id, DrawItem, PassDrawLists, SortKey};
+use super::scopes::ScopeRigidModel;
+use super::vertex_input::VertexBuffers;
+use super::ConstantBuffer;
 use super::DeviceContextSwapchain;
 use super::RenderData;
 
 pub struct EntityModelBuffer {
-    combined_vertex_buffer: ID3D11Buffer,
-    combined_vertex_stride: u32,
+    vertex_buffers: VertexBuffers,
 
     index_buffer: ID3D11Buffer,
     index_format: DXGI_FORMAT,
@@ -31,8 +41,12 @@ pub struct EntityModelBuffer {
 pub struct EntityRenderer {
     meshes: Vec<(EntityModelBuffer, Vec<Unk8080737e>)>,
 
-    _material_map: Vec<Unk808072c5>,
+    material_map: Vec<Unk808072c5>,
     materials: Vec<TagHash>,
+    /// LOD levels that have at least one part
+    pub available_lods: [bool; LOD_LEVELS],
+    /// Model space bounds of the decoded vertex positions
+    vertex_bounds: Aabb,
 
     model: Unk808073a5,
 }
@@ -67,15 +81,33 @@ impl EntityRenderer {
         .into()
     }
 
+    /// Model space bounds of the vertex data. Falls back to the full range of the quantized vertex positions when
+    /// the vertices couldn't be decoded
+    pub fn bounds(&self) -> Aabb {
+        if !self.vertex_bounds.is_empty() {
+            return self.vertex_bounds;
+        }
+
+        let scale = self.mesh_scale().truncate().abs();
+        let offset = self.mesh_offset().truncate();
+        Aabb {
+            min: offset - scale,
+            max: offset + scale,
+        }
+    }
+
     pub fn load(
         model: Unk808073a5,
         material_map: Vec<Unk808072c5>,
         materials: Vec<TagHash>,
         dcs: &DeviceContextSwapchain,
+        shader_inputs: &MaterialShaderInputs,
     ) -> anyhow::Result<Self> {
         let mut meshes = vec![];
+        let mut vertex_bounds = Aabb::EMPTY;
+        let vertex_transform = VertexTransform::from(&model);
 
-        for mesh in &model.meshes {
+        for (mesh_index, mesh) in model.meshes.iter().enumerate() {
             let pm = package_manager();
             let vertex_header: VertexBufferHeader =
                 pm.read_tag_struct(mesh.position_buffer).unwrap();
@@ -89,8 +121,9 @@ impl EntityRenderer {
 
             let vertex_data = pm.read_tag(t).unwrap();
 
-            let mut vertex2_stride = None;
-            let mut vertex2_data = None;
+            let mut vertex_buffers = VertexBuffers::default();
+            vertex_buffers.push(&dcs.device, &vertex_header, &vertex_data)?;
+            let mut vertex2 = None;
             if mesh.secondary_vertex_buffer.is_valid() {
                 let vertex2_header: VertexBufferHeader =
                     pm.read_tag_struct(mesh.secondary_vertex_buffer).unwrap();
@@ -98,15 +131,54 @@ impl EntityRenderer {
                     .get_entry(mesh.secondary_vertex_buffer)
                     .unwrap()
                     .reference;
+                let vertex2_data = pm.read_tag(t).unwrap();
 
-                vertex2_stride = Some(vertex2_header.stride as u32);
-                vertex2_data = Some(pm.read_tag(t).unwrap());
+                vertex_buffers.push(&dcs.device, &vertex2_header, &vertex2_data)?;
+                vertex2 = Some((vertex2_header, vertex2_data));
             }
 
+            // Vertex data is decoded with the layout of the first material the mesh is drawn with
+            let material = mesh.parts.iter().find_map(|p| {
+                if p.variant_shader_index == u16::MAX {
+                    Some(p.material)
+                } else {
+                    resolve_variant_material(
+                        &material_map,
+                        materials.len(),
+                        p.variant_shader_index,
+                        0,
+                    )
+                    .map(|i| materials[i])
+                }
+            });
+            let mut streams = vec![(&vertex_header, vertex_data.as_slice())];
+            streams.extend(vertex2.as_ref().map(|(h, d)| (h, d.as_slice())));
+            let stream = VertexStream::load(material, shader_inputs, &streams, &vertex_transform);
+            vertex_bounds = vertex_bounds.union(&Aabb::from_points(&stream.positions));
+
             let index_header: IndexBufferHeader = pm.read_tag_struct(mesh.index_buffer).unwrap();
             let t = pm.get_entry(mesh.index_buffer).unwrap().reference;
             let index_data = pm.read_tag(t).unwrap();
 
+            let indices = read_indices(&index_header, &index_data);
+            let triangle_count: usize = mesh
+                .parts
+                .iter()
+                .filter(|p| p.lod_category.is_highest_detail())
+                .map(|p| {
+                    triangles(
+                        part_indices(&indices, p.index_start, p.index_count),
+                        p.primitive_type,
+                    )
+                    .len()
+                })
+                .sum();
+            debug!(
+                "Entity mesh {mesh_index}: {} vertices ({}), {triangle_count} highest detail triangles",
+                stream.positions.len(),
+                stream.attribute_names().join(", ")
+            );
+
             let index_buffer = unsafe {
                 dcs.device
                     .CreateBuffer(
@@ -124,38 +196,9 @@ impl EntityRenderer {
                     .context("Failed to create index buffer")?
             };
 
-            let combined_vertex_data = if let Some(vertex2_data) = vertex2_data {
-                vertex_data
-                    .chunks_exact(vertex_header.stride as _)
-                    .zip(vertex2_data.chunks_exact(vertex2_stride.unwrap() as _))
-                    .flat_map(|(v1, v2)| [v1, v2].concat())
-                    .collect()
-            } else {
-                vertex_data
-            };
-
-            let combined_vertex_buffer = unsafe {
-                dcs.device
-                    .CreateBuffer(
-                        &D3D11_BUFFER_DESC {
-                            ByteWidth: combined_vertex_data.len() as _,
-                            Usage: D3D11_USAGE_IMMUTABLE,
-                            BindFlags: D3D11_BIND_VERTEX_BUFFER,
-                            ..Default::default()
-                        },
-                        Some(&D3D11_SUBRESOURCE_DATA {
-                            pSysMem: combined_vertex_data.as_ptr() as _,
-                            ..Default::default()
-                        }),
-                    )
-                    .context("Failed to create combined vertex buffer")?
-            };
-
             meshes.push((
                 EntityModelBuffer {
-                    combined_vertex_buffer,
-                    combined_vertex_stride: (vertex_header.stride as u32
-                        + vertex2_stride.unwrap_or_default()),
+                    vertex_buffers,
                     index_buffer,
                     index_format: if index_header.is_32bit {
                         DXGI_FORMAT_R32_UINT
@@ -167,83 +210,219 @@ impl EntityRenderer {
             ))
         }
 
+        let mut available_lods = [false; LOD_LEVELS];
+        for (_, parts) in &meshes {
+            for p in parts {
+                available_lods[p.lod_category.lod_level() as usize] = true;
+
+                if p.variant_shader_index != u16::MAX
+                    && resolve_variant_material(
+                        &material_map,
+                        materials.len(),
+                        p.variant_shader_index,
+                        0,
+                    )
+                    .is_none()
+                {
+                    warn!(
+                        "Variant shader index {} could not be resolved (material map has {} entries, {} materials)",
+                        p.variant_shader_index,
+                        material_map.len(),
+                        materials.len()
+                    );
+                }
+            }
+        }
+
         Ok(Self {
             meshes,
-            _material_map: material_map,
+            available_lods,
+            vertex_bounds,
+            material_map,
             materials,
             model,
         })
     }
 
-    fn get_material(&self, _index: u16) -> Option<TagHash> {
-        // let map = self.material_map.iter().find(|&v| {
-        //     (v.material_start as isize..v.material_start as isize + v.material_count as isize)
-        //         .contains(&(index as isize))
-        // })?;
-
-        // None
-        self.materials.first().cloned()
-        // self.material_map
-        //     .get(index as usize)
-        //     .map(|m| self.materials.get((m.material_start) as usize))
-        //     .flatten()
-        //     .cloned()
+    /// Resolves the material of a variant-shaded part to an index into the material list
+    fn get_material_index(&self, variant_shader_index: u16) -> Option<usize> {
+        resolve_variant_material(
+            &self.material_map,
+            self.materials.len(),
+            variant_shader_index,
+            0,
+        )
     }
 
-    pub fn draw(
+    /// Adds the parts of the given LOD level to the draw lists. Parts are colored by their material index when `debug_materials` is set
+    pub fn collect_draws(
         &self,
-        dcs: &DeviceContextSwapchain,
+        draw_lists: &mut PassDrawLists,
         render_data: &RenderData,
-    ) -> anyhow::Result<()> {
-        unsafe {
-            for (buffers, parts) in self.meshes.iter()
-            // .enumerate()
-            // .filter(|(_, u)| u.unk2 == 0)
-            {
-                for p in parts {
-                    if !p.lod_category.is_highest_detail() {
-                        continue;
-                    }
+        instance: &EntityInstance,
+        lod: u8,
+        debug_materials: bool,
+    ) {
+        for (mesh_index, (buffers, parts)) in self.meshes.iter().enumerate() {
+            for p in parts {
+                if p.lod_category.lod_level() != lod {
+                    continue;
+                }
 
-                    let mat_hash = if p.variant_shader_index == u16::MAX {
-                        Some(p.material)
-                    } else {
-                        self.get_material(p.variant_shader_index)
+                let material_index = if p.variant_shader_index == u16::MAX {
+                    None
+                } else {
+                    // Unresolvable parts are reported when loading
+                    let Some(index) = self.get_material_index(p.variant_shader_index) else {
+                        continue;
                     };
+                    Some(index)
+                };
+
+                let mat_hash = match material_index {
+                    Some(i) => self.materials[i],
+                    None => p.material,
+                };
+
+                let Some(mat) = render_data.materials.get(&mat_hash.0) else {
+                    continue;
+                };
+
+                draw_lists.push(
+                    mat.render_stage(render_data),
+                    DrawItem {
+                        sort_key: SortKey::new(
+                            mat,
+                            vertex_buffer_id(instance.entity.0, mesh_index),
+                        ),
+                        vertex_buffers: buffers.vertex_buffers.clone(),
+                        index_buffer: buffers.index_buffer.clone(),
+                        index_format: buffers.index_format,
+                        topology: match p.primitive_type {
+                            EPrimitiveType::Triangles => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+                            EPrimitiveType::TriangleStrip => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                        },
+                        index_start: p.index_start,
+                        index_count: p.index_count,
+                        instance_count: None,
+                        object_constants: instance.cb11.buffer().clone(),
+                        dyemap: None,
+                        debug_color: debug_materials.then(|| match material_index {
+                            Some(i) => index_color(i),
+                            // Parts that reference their material directly
+                            None => Vec4::new(0.5, 0.5, 0.5, 1.0),
+                        }),
+                        lod_category: Some(p.lod_category as u8),
+                        group: instance.entity.0,
+                        center: instance.bounds.center(),
+                    },
+                );
+            }
+        }
+    }
+}
 
-                    if let Some(mat_hash) = mat_hash {
-                        if let Some(mat) = render_data.materials.get(&mat_hash.0) {
-                            if mat.unk8 != 1 {
-                                continue;
-                            }
+/// A placed entity, its object constants are written once when the map is loaded
+pub struct EntityInstance {
+    pub entity: TagHash,
+    /// World space bounds
+    pub bounds: Aabb,
+    cb11: ConstantBuffer<ScopeRigidModel>,
+}
 
-                            mat.bind(dcs, render_data)?;
-                        }
-                    }
+impl EntityInstance {
+    pub fn create(
+        renderer: &EntityRenderer,
+        entity: TagHash,
+        transform: &Transform,
+        dcs: Rc<DeviceContextSwapchain>,
+    ) -> anyhow::Result<Self> {
+        let model_matrix = transform.to_mat4();
+
+        let cb11 = ConstantBuffer::create(
+            dcs,
+            Some(&ScopeRigidModel {
+                mesh_to_world: model_matrix,
+                position_scale: renderer.mesh_scale(),
+                position_offset: renderer.mesh_offset(),
+                texcoord0_scale_offset: renderer.texcoord_transform(),
+                dynamic_sh_ao_values: Vec4::ZERO,
+            }),
+        )
+        .context("Failed to create entity constant buffer")?;
 
-                    dcs.context.IASetVertexBuffers(
-                        0,
-                        1,
-                        Some([Some(buffers.combined_vertex_buffer.clone())].as_ptr()),
-                        Some([buffers.combined_vertex_stride].as_ptr()),
-                        Some(&0),
-                    );
+        Ok(Self {
+            entity,
+            bounds: renderer.bounds().transform(&model_matrix),
+            cb11,
+        })
+    }
+}
 
-                    dcs.context.IASetIndexBuffer(
-                        Some(&buffers.index_buffer),
-                        buffers.index_format,
-                        0,
-                    );
-                    dcs.context.IASetPrimitiveTopology(match p.primitive_type {
-                        EPrimitiveType::Triangles => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
-                        EPrimitiveType::TriangleStrip => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
-                    });
+/// Resolves a variant shader index through the entity material map. The map entry selects a range of
+/// `material_count` variants starting at `material_start`, `variant` picks one of them
+pub fn resolve_variant_material(
+    material_map: &[Unk808072c5],
+    material_count: usize,
+    variant_shader_index: u16,
+    variant: usize,
+) -> Option<usize> {
+    let entry = material_map.get(variant_shader_index as usize)?;
+    if entry.material_start < 0 || entry.material_count == 0 {
+        return None;
+    }
 
-                    dcs.context.DrawIndexed(p.index_count, p.index_start, 0);
-                }
-            }
+    let index = entry.material_start as usize + variant % entry.material_count as usize;
+    (index < material_count).then_some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(material_start: i16, material_count: u16) -> Unk808072c5 {
+        Unk808072c5 {
+            material_count,
+            material_start,
+            unk4: 0,
+            unk6: 0,
         }
+    }
+
+    #[test]
+    fn variant_selects_material_in_range() {
+        let material_map = [entry(0, 1), entry(1, 3), entry(4, 2)];
+
+        assert_eq!(resolve_variant_material(&material_map, 6, 0, 0), Some(0));
+        assert_eq!(resolve_variant_material(&material_map, 6, 1, 0), Some(1));
+        assert_eq!(resolve_variant_material(&material_map, 6, 1, 2), Some(3));
+        assert_eq!(resolve_variant_material(&material_map, 6, 2, 1), Some(5));
+    }
+
+    #[test]
+    fn out_of_range_variants_wrap() {
+        let material_map = [entry(1, 3)];
+
+        assert_eq!(resolve_variant_material(&material_map, 4, 0, 3), Some(1));
+        assert_eq!(resolve_variant_material(&material_map, 4, 0, 7), Some(2));
+    }
 
-        Ok(())
+    #[test]
+    fn invalid_entries_resolve_to_nothing() {
+        let material_map = [entry(-1, 2), entry(0, 0), entry(2, 4)];
+
+        // Shader index past the end of the material map
+        assert_eq!(resolve_variant_material(&material_map, 8, 3, 0), None);
+        assert_eq!(
+            resolve_variant_material(&material_map, 8, u16::MAX, 0),
+            None
+        );
+        // Negative start and empty ranges
+        assert_eq!(resolve_variant_material(&material_map, 8, 0, 0), None);
+        assert_eq!(resolve_variant_material(&material_map, 8, 1, 0), None);
+        // Range extends past the material list
+        assert_eq!(resolve_variant_material(&material_map, 4, 2, 1), Some(3));
+        assert_eq!(resolve_variant_material(&material_map, 4, 2, 2), None);
+        assert_eq!(resolve_variant_material(&[], 4, 0, 0), None);
     }
 }
@@ -10,6 +10,8 @@ pub struct GBuffer {
     pub rt0: RenderTarget,
     pub rt1: RenderTarget,
     pub rt2: RenderTarget,
+    /// Forward shaded (transparent) geometry, premultiplied alpha
+    pub forward: RenderTarget,
     pub depth: DepthState,
     dcs: Rc<DeviceContextSwapchain>,
 }
@@ -23,6 +25,8 @@ impl GBuffer {
                 .context("RT1")?,
             rt2: RenderTarget::create(size, &dcs.device, DxgiFormat::B8G8R8A8_UNORM)
                 .context("RT2")?,
+            forward: RenderTarget::create(size, &dcs.device, DxgiFormat::R16G16B16A16_FLOAT)
+                .context("Forward")?,
             depth: DepthState::create(size, &dcs.device).context("Depth")?,
             dcs,
         })
@@ -36,6 +40,9 @@ impl GBuffer {
         self.rt0.resize(new_size, &self.dcs.device).context("RT0")?;
         self.rt1.resize(new_size, &self.dcs.device).context("RT1")?;
         self.rt2.resize(new_size, &self.dcs.device).context("RT2")?;
+        self.forward
+            .resize(new_size, &self.dcs.device)
+            .context("Forward")?;
         self.depth
             .resize(new_size, &self.dcs.device)
             .context("Depth")?;
@@ -117,6 +124,10 @@ pub struct DepthState {
     pub texture: ID3D11Texture2D,
     // TODO(cohae): Should this be here?
     pub state: ID3D11DepthStencilState,
+    /// Depth test without writes, for blended geometry
+    pub state_read_only: ID3D11DepthStencilState,
+    /// No depth test or writes, for the overdraw view
+    pub state_disabled: ID3D11DepthStencilState,
     pub view: ID3D11DepthStencilView,
     pub texture_view: ID3D11ShaderResourceView,
 }
@@ -171,6 +182,30 @@ impl DepthState {
                 .context("Failed to create depth stencil state")?
         };
 
+        let state_read_only = unsafe {
+            device
+                .CreateDepthStencilState(&D3D11_DEPTH_STENCIL_DESC {
+                    DepthEnable: true.into(),
+                    DepthWriteMask: D3D11_DEPTH_WRITE_MASK_ZERO,
+                    DepthFunc: D3D11_COMPARISON_GREATER_EQUAL,
+                    StencilEnable: false.into(),
+                    ..Default::default()
+                })
+                .context("Failed to create read-only depth stencil state")?
+        };
+
+        let state_disabled = unsafe {
+            device
+                .CreateDepthStencilState(&D3D11_DEPTH_STENCIL_DESC {
+                    DepthEnable: false.into(),
+                    DepthWriteMask: D3D11_DEPTH_WRITE_MASK_ZERO,
+                    DepthFunc: D3D11_COMPARISON_ALWAYS,
+                    StencilEnable: false.into(),
+                    ..Default::default()
+                })
+                .context("Failed to create disabled depth stencil state")?
+        };
+
         let view = unsafe {
             device
                 .CreateDepthStencilView(
@@ -206,6 +241,8 @@ impl DepthState {
         Ok(Self {
             texture,
             state,
+            state_read_only,
+            state_disabled,
             view,
             texture_view,
         })
@@ -0,0 +1,381 @@
+use std::rc::Rc;
+
+use glam::{Mat4, Quat, UVec2, Vec2, Vec3, Vec4};
+use windows::Win32::Graphics::Direct3D11::ID3D11ShaderResourceView;
+
+use crate::bounds::BoundingSphere;
+use crate::culling::ViewCuller;
+use crate::map_resources::{LightShape, PointLight};
+
+use super::structured_buffer::StructuredBuffer;
+use super::DeviceContextSwapchain;
+
+/// Size of a light assignment tile in pixels, must match `LIGHT_TILE_SIZE` in fullscreen.hlsl
+pub const LIGHT_TILE_SIZE: u32 = 16;
+
+/// Light as read by the compositor (`Light` in fullscreen.hlsl)
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderLight {
+    /// xyz = world position, w = radius
+    pub position_radius: Vec4,
+    /// rgb = color * intensity, w = 1 for spot lights
+    pub color: Vec4,
+    /// xyz = spot direction, w = cosine of the outer half angle
+    pub direction: Vec4,
+    /// x = cosine of the inner half angle
+    pub params: Vec4,
+}
+
+impl ShaderLight {
+    pub fn new(light: &PointLight, position: Vec3, rotation: Quat) -> Self {
+        let (is_spot, cos_inner, cos_outer) = match light.shape {
+            LightShape::Point => (0.0, 1.0, 1.0),
+            LightShape::Spot {
+                inner_angle,
+                outer_angle,
+            } => (
+                1.0,
+                (inner_angle * 0.5).to_radians().cos(),
+                (outer_angle * 0.5).to_radians().cos(),
+            ),
+        };
+
+        Self {
+            position_radius: position.extend(light.radius),
+            color: (light.color * light.intensity).extend(is_spot),
+            direction: (rotation * Vec3::X).extend(cos_outer),
+            params: Vec4::new(cos_inner, 0.0, 0.0, 0.0),
+        }
+    }
+
+    /// White point light following the camera
+    pub fn camera(position: Vec3) -> Self {
+        Self {
+            position_radius: position.extend(16.0),
+            color: Vec4::new(3.0, 3.0, 3.0, 0.0),
+            direction: Vec4::new(1.0, 0.0, 0.0, 1.0),
+            params: Vec4::new(1.0, 0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn bounds(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: self.position_radius.truncate(),
+            radius: self.position_radius.w,
+        }
+    }
+}
+
+/// Inclusive range of tiles covered by a light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileRect {
+    pub min: UVec2,
+    pub max: UVec2,
+}
+
+/// Returns the screen tiles covered by the bounding box of `sphere`, or `None` if it is off-screen
+pub fn sphere_tile_rect(
+    sphere: &BoundingSphere,
+    proj_view: &Mat4,
+    screen_size: UVec2,
+    tile_count: UVec2,
+) -> Option<TileRect> {
+    let full_screen = TileRect {
+        min: UVec2::ZERO,
+        max: tile_count - 1,
+    };
+
+    let mut ndc_min = Vec3::splat(f32::MAX);
+    let mut ndc_max = Vec3::splat(f32::MIN);
+    let mut behind = 0;
+    for i in 0..8 {
+        let offset = Vec3::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        );
+        let clip = *proj_view * (sphere.center + offset * sphere.radius).extend(1.0);
+
+        if clip.w <= f32::EPSILON {
+            behind += 1;
+            continue;
+        }
+
+        let ndc = clip.truncate() / clip.w;
+        ndc_min = ndc_min.min(ndc);
+        ndc_max = ndc_max.max(ndc);
+    }
+
+    match behind {
+        0 => {}
+        8 => return None,
+        // The box crosses the camera plane, its projection is unbounded
+        _ => return Some(full_screen),
+    }
+
+    if ndc_max.x < -1.0 || ndc_min.x > 1.0 || ndc_max.y < -1.0 || ndc_min.y > 1.0 {
+        return None;
+    }
+
+    // NDC Y points up, pixel Y points down
+    let to_tile = |x: f32, y: f32| {
+        let pixel = Vec2::new(
+            (x.clamp(-1.0, 1.0) * 0.5 + 0.5) * screen_size.x as f32,
+            (0.5 - y.clamp(-1.0, 1.0) * 0.5) * screen_size.y as f32,
+        );
+        UVec2::new(
+            (pixel.x as u32 / LIGHT_TILE_SIZE).min(tile_count.x - 1),
+            (pixel.y as u32 / LIGHT_TILE_SIZE).min(tile_count.y - 1),
+        )
+    };
+
+    Some(TileRect {
+        min: to_tile(ndc_min.x, ndc_max.y),
+        max: to_tile(ndc_max.x, ndc_min.y),
+    })
+}
+
+/// Per-tile light lists, stored as an `[offset, count]` range into a shared index list
+#[derive(Default)]
+pub struct LightTiles {
+    pub tile_count: UVec2,
+    pub ranges: Vec<[u32; 2]>,
+    pub indices: Vec<u32>,
+    rects: Vec<Option<TileRect>>,
+}
+
+impl LightTiles {
+    pub fn build(&mut self, lights: &[ShaderLight], proj_view: &Mat4, screen_size: UVec2) {
+        let screen_size = screen_size.max(UVec2::ONE);
+        self.tile_count = (screen_size + (LIGHT_TILE_SIZE - 1)) / LIGHT_TILE_SIZE;
+        let tile_count = self.tile_count;
+
+        self.rects.clear();
+        self.rects.extend(
+            lights
+                .iter()
+                .map(|l| sphere_tile_rect(&l.bounds(), proj_view, screen_size, tile_count)),
+        );
+
+        self.ranges.clear();
+        self.ranges
+            .resize((tile_count.x * tile_count.y) as usize, [0, 0]);
+
+        let tiles = |r: TileRect| {
+            (r.min.y..=r.max.y).flat_map(move |y| {
+                (r.min.x..=r.max.x).map(move |x| (y * tile_count.x + x) as usize)
+            })
+        };
+
+        for rect in self.rects.iter().flatten() {
+            for tile in tiles(*rect) {
+                self.ranges[tile][1] += 1;
+            }
+        }
+
+        let mut offset = 0;
+        for range in &mut self.ranges {
+            range[0] = offset;
+            offset += range[1];
+            range[1] = 0;
+        }
+
+        self.indices.clear();
+        self.indices.resize(offset as usize, 0);
+        for (i, rect) in self.rects.iter().enumerate() {
+            let Some(rect) = *rect else {
+                continue;
+            };
+
+            for tile in tiles(rect) {
+                let range = &mut self.ranges[tile];
+                self.indices[(range[0] + range[1]) as usize] = i as u32;
+                range[1] += 1;
+            }
+        }
+    }
+
+    pub fn max_tile_lights(&self) -> usize {
+        self.ranges.iter().map(|r| r[1] as usize).max().unwrap_or(0)
+    }
+}
+
+/// GPU buffers for the lights visible in the current frame and their tile assignment
+pub struct LightBuffers {
+    lights: StructuredBuffer<ShaderLight>,
+    tile_ranges: StructuredBuffer<[u32; 2]>,
+    tile_indices: StructuredBuffer<u32>,
+
+    visible: Vec<ShaderLight>,
+    tiles: LightTiles,
+}
+
+impl LightBuffers {
+    pub fn create(dcs: Rc<DeviceContextSwapchain>) -> anyhow::Result<Self> {
+        Ok(Self {
+            lights: StructuredBuffer::create(dcs.clone(), 1024)?,
+            tile_ranges: StructuredBuffer::create(dcs.clone(), 8192)?,
+            tile_indices: StructuredBuffer::create(dcs, 8192)?,
+            visible: vec![],
+            tiles: LightTiles::default(),
+        })
+    }
+
+    /// Culls `lights` against the view, assigns the visible ones to screen tiles and uploads the result.
+    /// The camera light is always the first light
+    pub fn update(
+        &mut self,
+        lights: &[ShaderLight],
+        camera_light: ShaderLight,
+        culler: &ViewCuller,
+        proj_view: &Mat4,
+        screen_size: UVec2,
+    ) -> anyhow::Result<()> {
+        self.visible.clear();
+        self.visible.push(camera_light);
+        self.visible.extend(
+            lights
+                .iter()
+                .filter(|l| culler.is_sphere_visible(&l.bounds())),
+        );
+
+        self.tiles.build(&self.visible, proj_view, screen_size);
+
+        self.lights.write(&self.visible)?;
+        self.tile_ranges.write(&self.tiles.ranges)?;
+        self.tile_indices.write(&self.tiles.indices)?;
+
+        Ok(())
+    }
+
+    pub fn light_count(&self) -> usize {
+        self.visible.len()
+    }
+
+    pub fn tiles_x(&self) -> u32 {
+        self.tiles.tile_count.x
+    }
+
+    pub fn max_tile_lights(&self) -> usize {
+        self.tiles.max_tile_lights()
+    }
+
+    /// Light, tile range and tile index views, in that order
+    pub fn views(&self) -> [Option<ID3D11ShaderResourceView>; 3] {
+        [
+            Some(self.lights.view().clone()),
+            Some(self.tile_ranges.view().clone()),
+            Some(self.tile_indices.view().clone()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: Vec3::new(x, y, z),
+            radius,
+        }
+    }
+
+    fn light(x: f32, y: f32, radius: f32) -> ShaderLight {
+        ShaderLight {
+            position_radius: Vec4::new(x, y, 0.0, radius),
+            ..ShaderLight::camera(Vec3::ZERO)
+        }
+    }
+
+    fn rect(min: [u32; 2], max: [u32; 2]) -> Option<TileRect> {
+        Some(TileRect {
+            min: min.into(),
+            max: max.into(),
+        })
+    }
+
+    /// With an identity matrix positions are already in NDC, a 64x64 screen has 4x4 tiles
+    fn tile_rect(sphere: &BoundingSphere) -> Option<TileRect> {
+        sphere_tile_rect(sphere, &Mat4::IDENTITY, UVec2::splat(64), UVec2::splat(4))
+    }
+
+    #[test]
+    fn on_screen() {
+        assert_eq!(tile_rect(&sphere(0.0, 0.0, 0.5, 0.1)), rect([1, 1], [2, 2]));
+        // NDC Y points up, so the top left corner is tile 0
+        assert_eq!(
+            tile_rect(&sphere(-0.75, 0.75, 0.5, 0.1)),
+            rect([0, 0], [0, 0])
+        );
+        assert_eq!(
+            tile_rect(&sphere(0.75, -0.75, 0.5, 0.1)),
+            rect([3, 3], [3, 3])
+        );
+    }
+
+    #[test]
+    fn partly_off_screen() {
+        // Clamped to the last column
+        assert_eq!(tile_rect(&sphere(1.0, 0.0, 0.5, 0.2)), rect([3, 1], [3, 2]));
+        assert_eq!(
+            tile_rect(&sphere(-1.0, 1.0, 0.5, 0.2)),
+            rect([0, 0], [0, 0])
+        );
+        // Larger than the screen
+        assert_eq!(tile_rect(&sphere(0.0, 0.0, 0.5, 5.0)), rect([0, 0], [3, 3]));
+
+        assert_eq!(tile_rect(&sphere(2.0, 0.0, 0.5, 0.2)), None);
+        assert_eq!(tile_rect(&sphere(0.0, -1.5, 0.5, 0.2)), None);
+    }
+
+    #[test]
+    fn behind_camera() {
+        let proj = Mat4::perspective_infinite_reverse_rh(90f32.to_radians(), 1.0, 0.1);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::Y, Vec3::Z);
+        let proj_view = proj * view;
+        let tile_rect =
+            |s: &BoundingSphere| sphere_tile_rect(s, &proj_view, UVec2::splat(64), UVec2::splat(4));
+
+        assert_eq!(
+            tile_rect(&sphere(0.0, 10.0, 0.0, 1.0)),
+            rect([1, 1], [2, 2])
+        );
+        assert_eq!(tile_rect(&sphere(0.0, -10.0, 0.0, 1.0)), None);
+        // Around the camera, the projection of the box is unbounded
+        assert_eq!(tile_rect(&sphere(0.0, 0.5, 0.0, 1.0)), rect([0, 0], [3, 3]));
+    }
+
+    #[test]
+    fn tiles_cover_partial_edge_tiles() {
+        let lights = [
+            light(0.0, 0.0, 5.0),
+            // Bottom right corner, only touching the last (partial) tile
+            light(1.0, -1.0, 0.05),
+            light(3.0, 0.0, 0.5),
+        ];
+
+        let mut tiles = LightTiles::default();
+        tiles.build(&lights, &Mat4::IDENTITY, UVec2::new(100, 40));
+        assert_eq!(tiles.tile_count, UVec2::new(7, 3));
+        assert_eq!(tiles.ranges.len(), 21);
+
+        for (i, range) in tiles.ranges.iter().enumerate() {
+            let lights = &tiles.indices[range[0] as usize..(range[0] + range[1]) as usize];
+            if i == 20 {
+                assert_eq!(lights, [0, 1]);
+            } else {
+                assert_eq!(lights, [0], "Tile {i}");
+            }
+        }
+        assert_eq!(tiles.indices.len(), 22);
+        assert_eq!(tiles.max_tile_lights(), 2);
+
+        // Rebuilding replaces the previous assignment
+        tiles.build(&lights[1..], &Mat4::IDENTITY, UVec2::ZERO);
+        assert_eq!(tiles.tile_count, UVec2::ONE);
+        assert_eq!(tiles.ranges, [[0, 1]]);
+        assert_eq!(tiles.indices, [0]);
+    }
+}
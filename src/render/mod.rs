@@ -1,16 +1,29 @@
 mod cbuffer;
+pub mod compositor;
+pub mod cubemaps;
 pub mod data;
 mod dcs;
+pub mod debug_draw;
+pub mod debug_shaders;
+pub mod decals;
+pub mod draw_list;
 pub mod entity;
 mod gbuffer;
+pub mod lights;
 pub mod scopes;
+mod shader;
 pub mod static_instanced;
 pub mod static_render;
+mod structured_buffer;
 pub mod terrain;
+pub mod vertex_input;
 
 pub use cbuffer::ConstantBuffer;
 pub use data::RenderData;
 pub use dcs::DeviceContextSwapchain;
+pub use debug_shaders::DebugShaders;
+pub use decals::DecalRenderer;
+pub use draw_list::{DrawStats, PassDrawLists};
 pub use entity::EntityRenderer;
 pub use gbuffer::GBuffer;
 pub use static_instanced::InstancedRenderer;
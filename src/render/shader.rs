@@ -0,0 +1,110 @@
+use anyhow::Context;
+use tracing::warn;
+use windows::core::{PCSTR, PCWSTR};
+use windows::Win32::Graphics::Direct3D::Fxc::{
+    D3DCompile, D3DCompileFromFile, D3DCOMPILE_DEBUG, D3DCOMPILE_SKIP_OPTIMIZATION,
+};
+use windows::Win32::Graphics::Direct3D::ID3DBlob;
+
+fn compile_flags() -> u32 {
+    if cfg!(debug_assertions) {
+        D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
+    } else {
+        0
+    }
+}
+
+pub(crate) fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+    }
+}
+
+/// Turns the result of a compiler call into bytecode. Compiler output is logged on success and returned as part of
+/// the error on failure
+fn compile_result(
+    result: windows::core::Result<()>,
+    blob: Option<ID3DBlob>,
+    errors: Option<ID3DBlob>,
+    description: impl FnOnce() -> String,
+) -> anyhow::Result<Vec<u8>> {
+    let messages = errors.map(|e| {
+        String::from_utf8_lossy(blob_bytes(&e))
+            .trim_end()
+            .to_string()
+    });
+    if let Err(e) = result {
+        return Err(match messages {
+            Some(messages) => anyhow::anyhow!("{}:\n{messages}", description()),
+            None => anyhow::Error::new(e).context(description()),
+        });
+    }
+
+    if let Some(messages) = messages {
+        warn!("{messages}");
+    }
+
+    let blob = blob.context("Shader compiler returned no bytecode")?;
+    Ok(blob_bytes(&blob).to_vec())
+}
+
+/// Compiles a shader entry point from an HLSL file, returning the bytecode. Compiler warnings are logged
+pub fn compile_hlsl(file: PCWSTR, entry_point: PCSTR, target: PCSTR) -> anyhow::Result<Vec<u8>> {
+    let mut blob = None;
+    let mut errors = None;
+    let result = unsafe {
+        D3DCompileFromFile(
+            file,
+            None,
+            None,
+            entry_point,
+            target,
+            compile_flags(),
+            0,
+            &mut blob,
+            Some(&mut errors),
+        )
+    };
+
+    compile_result(result, blob, errors, || unsafe {
+        format!(
+            "Failed to compile {} from {}",
+            entry_point.display(),
+            file.display()
+        )
+    })
+}
+
+/// Compiles a shader entry point from HLSL source. `name` is used for the file name in compiler messages
+pub fn compile_hlsl_source(
+    source: &str,
+    name: &str,
+    entry_point: &str,
+    target: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let name_c = format!("{name}\0");
+    let entry_point_c = format!("{entry_point}\0");
+    let target_c = format!("{target}\0");
+
+    let mut blob = None;
+    let mut errors = None;
+    let result = unsafe {
+        D3DCompile(
+            source.as_ptr() as _,
+            source.len(),
+            PCSTR(name_c.as_ptr()),
+            None,
+            None,
+            PCSTR(entry_point_c.as_ptr()),
+            PCSTR(target_c.as_ptr()),
+            compile_flags(),
+            0,
+            &mut blob,
+            Some(&mut errors),
+        )
+    };
+
+    compile_result(result, blob, errors, || {
+        format!("Failed to compile {entry_point} from {name}")
+    })
+}
@@ -1,19 +1,44 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::culling::{compact, visible_indices, LayerStats, ViewCuller};
+use crate::lod::{nearest_available, LodSelector, LOD_LEVELS};
+use crate::picking::{ray_aabb, Ray};
 use crate::render::scopes::ScopeStaticInstance;
 use crate::render::{ConstantBuffer, DeviceContextSwapchain, StaticModel};
 use crate::statics::Unk808071a3;
+use crate::transform::Transform;
 
-use glam::{Mat4, Quat, Vec3};
+use glam::Mat4;
 
 use std::rc::Rc;
 use std::sync::Arc;
 
+use super::draw_list::PassDrawLists;
 use super::scopes::MatrixConversion;
 use super::RenderData;
 
+/// Visible instances drawn at a single LOD level
+struct LodBatch {
+    instance_buffer: ConstantBuffer<ScopeStaticInstance>,
+    /// Indices of the instances currently packed into the instance buffer
+    visible_instances: Vec<u32>,
+}
+
 pub struct InstancedRenderer {
     renderer: Arc<StaticModel>,
-    instance_count: usize,
-    instance_buffer: ConstantBuffer<ScopeStaticInstance>,
+    instance_data: Vec<ScopeStaticInstance>,
+
+    /// One batch for each LOD level available in the model. Every batch has its own instance buffer, as
+    /// SV_InstanceID does not include the start instance location
+    lod_batches: [Option<LodBatch>; LOD_LEVELS],
+    culling_scratch: Vec<u32>,
+    lod_scratch: [Vec<u32>; LOD_LEVELS],
+    compacted_instance_data: Vec<ScopeStaticInstance>,
+
+    /// Instance to world matrices
+    transforms: Vec<Mat4>,
+    /// World space bounds of each instance
+    bounds: Vec<Aabb>,
+    bounding_spheres: Vec<BoundingSphere>,
 }
 
 impl InstancedRenderer {
@@ -23,28 +48,16 @@ impl InstancedRenderer {
         dcs: Rc<DeviceContextSwapchain>,
     ) -> anyhow::Result<Self> {
         let mut instance_data: Vec<ScopeStaticInstance> = Vec::with_capacity(instances.len());
+        let mut transforms = Vec::with_capacity(instances.len());
+        let mut bounds = Vec::with_capacity(instances.len());
+        let mut bounding_spheres = Vec::with_capacity(instances.len());
 
         for instance in instances {
-            let mm = Mat4::from_scale_rotation_translation(
-                Vec3::splat(instance.scale.x),
-                Quat::from_xyzw(
-                    instance.rotation.x,
-                    instance.rotation.y,
-                    instance.rotation.z,
-                    instance.rotation.w,
-                )
-                .inverse(),
-                Vec3::ZERO,
-            );
-
-            let model_matrix = Mat4::from_cols(
-                mm.x_axis.truncate().extend(instance.translation.x),
-                mm.y_axis.truncate().extend(instance.translation.y),
-                mm.z_axis.truncate().extend(instance.translation.z),
-                mm.w_axis,
-            );
-
-            let combined_matrix = model.mesh_transform() * model_matrix;
+            let transform = Transform::from(instance).to_mat4();
+            let combined_matrix = model.mesh_transform() * transform.transpose();
+            transforms.push(transform);
+            bounds.push(model.bounds.transform(&transform));
+            bounding_spheres.push(model.bounding_sphere.transform(&transform));
 
             let scope_instance = ScopeStaticInstance {
                 mesh_to_world: combined_matrix.to_3x4(),
@@ -54,25 +67,146 @@ impl InstancedRenderer {
             instance_data.push(scope_instance);
         }
 
-        let instance_buffer = ConstantBuffer::create_array_init(dcs, &instance_data)?;
+        let mut lod_batches: [Option<LodBatch>; LOD_LEVELS] = Default::default();
+        for (batch, available) in lod_batches.iter_mut().zip(model.available_lods) {
+            if available {
+                *batch = Some(LodBatch {
+                    instance_buffer: ConstantBuffer::create_array_init(
+                        dcs.clone(),
+                        &instance_data,
+                    )?,
+                    visible_instances: vec![],
+                });
+            }
+        }
 
         Ok(Self {
             renderer: model,
-            instance_count: instance_data.len(),
-            instance_buffer,
+            lod_batches,
+            culling_scratch: Vec::with_capacity(instance_data.len()),
+            lod_scratch: Default::default(),
+            compacted_instance_data: Vec::with_capacity(instance_data.len()),
+            instance_data,
+            transforms,
+            bounds,
+            bounding_spheres,
         })
     }
 
-    pub fn draw(
+    pub fn model(&self) -> &StaticModel {
+        &self.renderer
+    }
+
+    pub fn transforms(&self) -> &[Mat4] {
+        &self.transforms
+    }
+
+    /// Packs the visible instances into the instance buffer of their LOD level. Buffers are only rewritten when their visible set changes
+    pub fn cull(
+        &mut self,
+        culler: &ViewCuller,
+        lod_selector: &LodSelector,
+    ) -> anyhow::Result<LayerStats> {
+        let bounding_spheres = &self.bounding_spheres;
+        visible_indices(
+            bounding_spheres.len(),
+            |i| culler.is_sphere_visible(&bounding_spheres[i]),
+            &mut self.culling_scratch,
+        );
+
+        for v in self.lod_scratch.iter_mut() {
+            v.clear();
+        }
+
+        for &i in &self.culling_scratch {
+            let lod = lod_selector.select(&bounding_spheres[i as usize]);
+            if let Some(lod) = nearest_available(lod, &self.renderer.available_lods) {
+                self.lod_scratch[lod as usize].push(i);
+            }
+        }
+
+        let mut stats = LayerStats {
+            total: self.instance_data.len(),
+            ..Default::default()
+        };
+        for (lod, batch) in self.lod_batches.iter_mut().enumerate() {
+            let Some(batch) = batch else {
+                continue;
+            };
+
+            if self.lod_scratch[lod] != batch.visible_instances {
+                std::mem::swap(&mut self.lod_scratch[lod], &mut batch.visible_instances);
+                compact(
+                    &self.instance_data,
+                    &batch.visible_instances,
+                    &mut self.compacted_instance_data,
+                );
+
+                if !self.compacted_instance_data.is_empty() {
+                    batch
+                        .instance_buffer
+                        .write_array(&self.compacted_instance_data)?;
+                }
+            }
+
+            stats.visible += batch.visible_instances.len();
+            stats.lods[lod] += batch.visible_instances.len();
+        }
+
+        Ok(stats)
+    }
+
+    /// Returns the distance and index of the closest instance intersecting the (world space) ray
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(f32, usize)> {
+        let mut closest: Option<(f32, usize)> = None;
+        for (i, transform) in self.transforms.iter().enumerate() {
+            match ray_aabb(ray, &self.bounds[i]) {
+                Some(t) if closest.map_or(true, |(ct, _)| t < ct) => {}
+                _ => continue,
+            }
+
+            let local_ray = ray.transform(&transform.inverse());
+            if let Some(t) = self.renderer.intersect_ray(&local_ray) {
+                if closest.map_or(true, |(ct, _)| t < ct) {
+                    closest = Some((t, i));
+                }
+            }
+        }
+
+        closest
+    }
+
+    pub fn collect_draws(
         &self,
-        dcs: &DeviceContextSwapchain,
+        draw_lists: &mut PassDrawLists,
         render_data: &RenderData,
+        group: u32,
     ) -> anyhow::Result<()> {
-        unsafe {
-            dcs.context
-                .VSSetConstantBuffers(11, Some(&[Some(self.instance_buffer.buffer().clone())]));
+        for (lod, batch) in self.lod_batches.iter().enumerate() {
+            let Some(batch) = batch else {
+                continue;
+            };
+
+            if batch.visible_instances.is_empty() {
+                continue;
+            }
+
+            let visible_bounds = batch
+                .visible_instances
+                .iter()
+                .fold(Aabb::EMPTY, |acc, &i| acc.union(&self.bounds[i as usize]));
+
+            self.renderer.collect_draws(
+                draw_lists,
+                render_data,
+                batch.instance_buffer.buffer(),
+                batch.visible_instances.len(),
+                lod as u8,
+                visible_bounds.center(),
+                group,
+            )?;
         }
 
-        self.renderer.draw(dcs, render_data, self.instance_count)
+        Ok(())
     }
 }
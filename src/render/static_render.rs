@@ -1,33 +1,68 @@
+use crate::bounds::{aabb_relative_difference, Aabb, BoundingSphere};
 use crate::entity::{EPrimitiveType, IndexBufferHeader, VertexBufferHeader};
+use crate::index_buffer::{part_indices, read_indices, triangles, vertex_range};
+use crate::lod::LOD_LEVELS;
+use crate::picking::{PickingMesh, Ray};
 use crate::statics::{Unk80807194, Unk8080719a, Unk8080719b, Unk808071a7};
+use crate::vertex_stream::{MaterialShaderInputs, VertexStream, VertexTransform};
 
 use anyhow::{ensure, Context};
+use destiny_pkg::TagHash;
 use glam::{Mat4, Vec3};
 
 use crate::packages::package_manager;
 
-use tracing::warn;
+use tracing::{debug, warn};
 use windows::Win32::Graphics::Direct3D::*;
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_FORMAT, DXGI_FORMAT_R16_UINT, DXGI_FORMAT_R32_UINT,
 };
 
-use super::{DeviceContextSwapchain, RenderData};
+use super::draw_list::{vertex_buffer_id, DrawItem, PassDrawLists, SortKey};
+use super::vertex_input::VertexBuffers;
+use super::RenderData;
 
 pub struct StaticModelBuffer {
-    combined_vertex_buffer: ID3D11Buffer,
-    combined_vertex_stride: u32,
+    vertex_buffers: VertexBuffers,
 
     index_buffer: ID3D11Buffer,
     index_format: DXGI_FORMAT,
 }
 
+/// Vertex and index data of one of the buffers of a static model
+struct StaticBufferData {
+    /// Index in the model's buffer list, buffers that can't be drawn are skipped
+    buffer_index: usize,
+    vertex: (VertexBufferHeader, Vec<u8>),
+    vertex2: Option<(VertexBufferHeader, Vec<u8>)>,
+    index_header: IndexBufferHeader,
+    index_data: Vec<u8>,
+}
+
+/// Tags of a static model read from the packages, ready to be uploaded with [`StaticModel::create`]
+pub struct StaticModelData {
+    hash: TagHash,
+    model: Unk808071a7,
+    header: Unk80807194,
+    buffers: Vec<StaticBufferData>,
+}
+
 pub struct StaticModel {
+    pub hash: TagHash,
+
     buffers: Vec<StaticModelBuffer>,
     parts: Vec<Unk8080719a>,
     mesh_groups: Vec<Unk8080719b>,
 
+    /// CPU-side geometry for each buffer, positions are in model space (after [`StaticModel::mesh_transform`])
+    picking_meshes: Vec<PickingMesh>,
+    /// Bounds of all drawn parts, in model space (after [`StaticModel::mesh_transform`])
+    pub bounds: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    /// LOD levels that have at least one drawable part
+    pub available_lods: [bool; LOD_LEVELS],
+
     model: Unk808071a7,
 }
 
@@ -51,91 +86,135 @@ impl StaticModel {
         )
     }
 
-    pub fn load(model: Unk808071a7, device: &ID3D11Device) -> anyhow::Result<StaticModel> {
+    pub fn materials(&self) -> &[TagHash] {
+        &self.model.materials
+    }
+
+    /// Reads the tags of a static model without touching the device, so it can be called from any thread
+    pub fn read(model: Unk808071a7, hash: TagHash) -> anyhow::Result<StaticModelData> {
         let pm = package_manager();
-        let header: Unk80807194 = pm.read_tag_struct(model.unk8).unwrap();
+        let header: Unk80807194 = pm.read_tag_struct(model.unk8)?;
 
         ensure!(header.unk8.len() == model.materials.len());
 
-        let mut buffers = vec![];
-        for (index_buffer, vertex_buffer_hash, vertex2_buffer_hash, _u3) in header.buffers.iter() {
-            let vertex_header: VertexBufferHeader =
-                pm.read_tag_struct(*vertex_buffer_hash).unwrap();
+        let read_data = |hash: TagHash| -> anyhow::Result<Vec<u8>> {
+            let entry = pm.get_entry(hash)?;
+            pm.read_tag(entry.reference)
+                .with_context(|| format!("Failed to read the data of {hash}"))
+        };
 
+        let mut buffers = vec![];
+        for (buffer_index, (index_buffer, vertex_buffer_hash, vertex2_buffer_hash, _u3)) in
+            header.buffers.iter().enumerate()
+        {
+            let vertex_header: VertexBufferHeader = pm.read_tag_struct(*vertex_buffer_hash)?;
             if vertex_header.stride == 24 || vertex_header.stride == 48 {
                 warn!("Support for 32-bit floats in vertex buffers are disabled");
                 continue;
             }
+            let vertex_data = read_data(*vertex_buffer_hash)?;
 
-            let t = pm.get_entry(*vertex_buffer_hash).unwrap().reference;
+            let vertex2 = if vertex2_buffer_hash.is_valid() {
+                Some((
+                    pm.read_tag_struct(*vertex2_buffer_hash)?,
+                    read_data(*vertex2_buffer_hash)?,
+                ))
+            } else {
+                None
+            };
 
-            let vertex_data = pm.read_tag(t).unwrap();
+            let index_header: IndexBufferHeader = pm.read_tag_struct(*index_buffer)?;
+            let index_data = read_data(*index_buffer)?;
 
-            let mut vertex2_stride = None;
-            let mut vertex2_data = None;
-            if vertex2_buffer_hash.is_valid() {
-                let vertex2_header: VertexBufferHeader =
-                    pm.read_tag_struct(*vertex2_buffer_hash).unwrap();
-                let t = pm.get_entry(*vertex2_buffer_hash).unwrap().reference;
+            buffers.push(StaticBufferData {
+                buffer_index,
+                vertex: (vertex_header, vertex_data),
+                vertex2,
+                index_header,
+                index_data,
+            });
+        }
 
-                vertex2_stride = Some(vertex2_header.stride as u32);
-                vertex2_data = Some(pm.read_tag(t).unwrap());
+        Ok(StaticModelData {
+            hash,
+            model,
+            header,
+            buffers,
+        })
+    }
+
+    /// Decodes the vertex data read by [`StaticModel::read`] and creates the buffers
+    pub fn create(
+        data: StaticModelData,
+        device: &ID3D11Device,
+        shader_inputs: &MaterialShaderInputs,
+    ) -> anyhow::Result<StaticModel> {
+        let StaticModelData {
+            hash,
+            model,
+            header,
+            buffers: buffer_data,
+        } = data;
+
+        // Vertex data is decoded with the layout of the first material that draws from the buffer
+        let buffer_material = |buffer_index: usize| {
+            header
+                .unk8
+                .iter()
+                .zip(model.materials.iter())
+                .find(|(u, _)| {
+                    header
+                        .parts
+                        .get(u.part_index as usize)
+                        .map_or(false, |p| p.buffer_index as usize == buffer_index)
+                })
+                .map(|(_, m)| *m)
+        };
+        let vertex_transform = VertexTransform::from(&model);
+
+        let mut buffers = vec![];
+        let mut picking_meshes = vec![];
+        for b in &buffer_data {
+            let mut vertex_buffers = VertexBuffers::default();
+            let mut streams = vec![];
+            for (vertex_header, vertex_data) in std::iter::once(&b.vertex).chain(&b.vertex2) {
+                vertex_buffers.push(device, vertex_header, vertex_data)?;
+                streams.push((vertex_header, vertex_data.as_slice()));
             }
 
-            let index_header: IndexBufferHeader = pm.read_tag_struct(*index_buffer).unwrap();
-            let t = pm.get_entry(*index_buffer).unwrap().reference;
-            let index_data = pm.read_tag(t).unwrap();
+            let stream = VertexStream::load(
+                buffer_material(b.buffer_index),
+                shader_inputs,
+                &streams,
+                &vertex_transform,
+            );
+
+            picking_meshes.push(PickingMesh {
+                positions: stream.positions,
+                indices: read_indices(&b.index_header, &b.index_data),
+            });
 
             let index_buffer = unsafe {
                 device
                     .CreateBuffer(
                         &D3D11_BUFFER_DESC {
-                            ByteWidth: index_data.len() as _,
+                            ByteWidth: b.index_data.len() as _,
                             Usage: D3D11_USAGE_IMMUTABLE,
                             BindFlags: D3D11_BIND_INDEX_BUFFER,
                             ..Default::default()
                         },
                         Some(&D3D11_SUBRESOURCE_DATA {
-                            pSysMem: index_data.as_ptr() as _,
+                            pSysMem: b.index_data.as_ptr() as _,
                             ..Default::default()
                         }),
                     )
                     .context("Failed to create index buffer")?
             };
 
-            let combined_vertex_data = if let Some(vertex2_data) = vertex2_data {
-                vertex_data
-                    .chunks_exact(vertex_header.stride as _)
-                    .zip(vertex2_data.chunks_exact(vertex2_stride.unwrap() as _))
-                    .flat_map(|(v1, v2)| [v1, v2].concat())
-                    .collect()
-            } else {
-                vertex_data
-            };
-
-            let combined_vertex_buffer = unsafe {
-                device
-                    .CreateBuffer(
-                        &D3D11_BUFFER_DESC {
-                            ByteWidth: combined_vertex_data.len() as _,
-                            Usage: D3D11_USAGE_IMMUTABLE,
-                            BindFlags: D3D11_BIND_VERTEX_BUFFER,
-                            ..Default::default()
-                        },
-                        Some(&D3D11_SUBRESOURCE_DATA {
-                            pSysMem: combined_vertex_data.as_ptr() as _,
-                            ..Default::default()
-                        }),
-                    )
-                    .context("Failed to create combined vertex buffer")?
-            };
-
             buffers.push(StaticModelBuffer {
-                combined_vertex_buffer,
-                combined_vertex_stride: (vertex_header.stride as u32
-                    + vertex2_stride.unwrap_or_default()),
+                vertex_buffers,
                 index_buffer,
-                index_format: if index_header.is_32bit {
+                index_format: if b.index_header.is_32bit {
                     DXGI_FORMAT_R32_UINT
                 } else {
                     DXGI_FORMAT_R16_UINT
@@ -143,77 +222,201 @@ impl StaticModel {
             })
         }
 
-        Ok(StaticModel {
+        let mut model = StaticModel {
+            hash,
             buffers,
+            picking_meshes,
+            bounds: Aabb::EMPTY,
+            bounding_sphere: BoundingSphere::EMPTY,
+            available_lods: [false; LOD_LEVELS],
             model,
             parts: header.parts.to_vec(),
             mesh_groups: header.unk8.to_vec(),
-        })
+        };
+
+        model.bounds = model
+            .drawn_parts()
+            .filter_map(|p| {
+                model
+                    .picking_meshes
+                    .get(p.buffer_index as usize)
+                    .map(|m| m.bounds(p.index_start, p.index_count))
+            })
+            .fold(Aabb::EMPTY, |acc, b| acc.union(&b));
+
+        for u in model.mesh_groups.iter().filter(|u| u.unk2 == 0) {
+            let level = model.parts[u.part_index as usize].lod_category.lod_level();
+            model.available_lods[level as usize] = true;
+        }
+
+        let positions: Vec<Vec3> = model
+            .drawn_parts()
+            .filter_map(|p| {
+                let mesh = model.picking_meshes.get(p.buffer_index as usize)?;
+                Some(
+                    part_indices(&mesh.indices, p.index_start, p.index_count)
+                        .iter()
+                        .filter_map(|&i| mesh.positions.get(i as usize).copied())
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect();
+        model.bounding_sphere = BoundingSphere::from_points(&positions);
+
+        for (i, p) in model.parts.iter().enumerate() {
+            let Some(mesh) = model.picking_meshes.get(p.buffer_index as usize) else {
+                continue;
+            };
+
+            let indices = part_indices(&mesh.indices, p.index_start, p.index_count);
+            match vertex_range(indices) {
+                Some(range) if range.end as usize > mesh.positions.len() => warn!(
+                    "Static {hash} part {i} uses vertices {range:?}, but buffer {} only has {} decoded vertices",
+                    p.buffer_index,
+                    mesh.positions.len()
+                ),
+                _ => {}
+            }
+        }
+
+        let triangle_count: usize = model
+            .drawn_parts()
+            .filter_map(|p| {
+                let mesh = model.picking_meshes.get(p.buffer_index as usize)?;
+                Some(
+                    triangles(
+                        part_indices(&mesh.indices, p.index_start, p.index_count),
+                        p.primitive_type,
+                    )
+                    .len(),
+                )
+            })
+            .sum();
+
+        debug!("Static {hash} has {triangle_count} highest detail triangles");
+        // unk38, unk50 and unk5c are unidentified, these differences are logged to help identify them.
+        // unk38 is compared both in model space and in the space of the quantized vertex data
+        debug!(
+            "Static {hash} unknown fields vs computed bounds: unk38 as min/max {:.3} (model space) / {:.3} (mesh space), unk50 to center {:.3}, unk5c {:.3} vs radius {:.3}",
+            model.unk38_difference(),
+            aabb_relative_difference(
+                &model
+                    .bounds
+                    .transform(&model.mesh_transform().transpose().inverse()),
+                &model.unk38_as_aabb()
+            ),
+            model.bounds.center().distance(Vec3::new(
+                model.model.unk50.x,
+                model.model.unk50.y,
+                model.model.unk50.z
+            )),
+            model.model.unk5c,
+            model.bounding_sphere.radius,
+        );
+
+        Ok(model)
     }
 
-    pub fn draw(
+    /// `unk38` read as a min/max box. Only used for comparing against the computed bounds, it is not known to be one
+    fn unk38_as_aabb(&self) -> Aabb {
+        let u = &self.model.unk38;
+        Aabb {
+            min: Vec3::new(u[0], u[1], u[2]),
+            max: Vec3::new(u[3], u[4], u[5]),
+        }
+    }
+
+    /// Relative difference between `unk38` read as a min/max box and the model space bounds computed from the vertex data
+    pub fn unk38_difference(&self) -> f32 {
+        aabb_relative_difference(&self.bounds, &self.unk38_as_aabb())
+    }
+
+    /// Highest detail parts, used for bounds and picking
+    fn drawn_parts(&self) -> impl Iterator<Item = &Unk8080719a> {
+        self.mesh_groups
+            .iter()
+            .filter(|u| u.unk2 == 0)
+            .map(|u| &self.parts[u.part_index as usize])
+            .filter(|p| p.lod_category.is_highest_detail())
+    }
+
+    /// Intersects a model-space ray with the drawn geometry
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<f32> {
+        self.drawn_parts()
+            .filter_map(|p| {
+                self.picking_meshes.get(p.buffer_index as usize)?.intersect(
+                    ray,
+                    p.index_start,
+                    p.index_count,
+                    p.primitive_type,
+                )
+            })
+            .min_by(|a, b| a.total_cmp(b))
+    }
+
+    /// Adds the parts of the given LOD level to the draw lists. `center` is the world space center of the drawn instances,
+    /// `group` the placement group they belong to
+    #[allow(clippy::too_many_arguments)]
+    pub fn collect_draws(
         &self,
-        dcs: &DeviceContextSwapchain,
+        draw_lists: &mut PassDrawLists,
         render_data: &RenderData,
+        instance_buffer: &ID3D11Buffer,
         instance_count: usize,
+        lod: u8,
+        center: Vec3,
+        group: u32,
     ) -> anyhow::Result<()> {
-        unsafe {
-            for (iu, u) in self
-                .mesh_groups
-                .iter()
-                .enumerate()
-                .filter(|(_, u)| u.unk2 == 0)
-            {
-                let p = &self.parts[u.part_index as usize];
-                if !p.lod_category.is_highest_detail() {
-                    continue;
-                }
-
-                if let Some(buffers) = self.buffers.get(p.buffer_index as usize) {
-                    if let Some(mat) = self
-                        .model
-                        .materials
-                        .get(iu)
-                        .and_then(|m| render_data.materials.get(&m.0))
-                    {
-                        if mat.unk8 != 1 {
-                            continue;
-                        }
-
-                        mat.bind(dcs, render_data)?;
-                    } else {
-                        anyhow::bail!(
-                            "Could not find material {}",
-                            self.model.materials.get(iu).unwrap()
-                        );
-                    }
-
-                    dcs.context.IASetVertexBuffers(
-                        0,
-                        1,
-                        Some([Some(buffers.combined_vertex_buffer.clone())].as_ptr()),
-                        Some([buffers.combined_vertex_stride].as_ptr()),
-                        Some(&0),
-                    );
+        for (iu, u) in self
+            .mesh_groups
+            .iter()
+            .enumerate()
+            .filter(|(_, u)| u.unk2 == 0)
+        {
+            let p = &self.parts[u.part_index as usize];
+            if p.lod_category.lod_level() != lod {
+                continue;
+            }
 
-                    dcs.context.IASetIndexBuffer(
-                        Some(&buffers.index_buffer),
-                        buffers.index_format,
-                        0,
+            if let Some(buffers) = self.buffers.get(p.buffer_index as usize) {
+                let Some(mat) = self
+                    .model
+                    .materials
+                    .get(iu)
+                    .and_then(|m| render_data.materials.get(&m.0))
+                else {
+                    anyhow::bail!(
+                        "Could not find material {}",
+                        self.model.materials.get(iu).unwrap()
                     );
-                    dcs.context.IASetPrimitiveTopology(match p.primitive_type {
-                        EPrimitiveType::Triangles => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
-                        EPrimitiveType::TriangleStrip => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
-                    });
-
-                    dcs.context.DrawIndexedInstanced(
-                        p.index_count,
-                        instance_count as _,
-                        p.index_start,
-                        0,
-                        0,
-                    );
-                }
+                };
+
+                draw_lists.push(
+                    mat.render_stage(render_data),
+                    DrawItem {
+                        sort_key: SortKey::new(
+                            mat,
+                            vertex_buffer_id(self.hash.0, p.buffer_index as usize),
+                        ),
+                        vertex_buffers: buffers.vertex_buffers.clone(),
+                        index_buffer: buffers.index_buffer.clone(),
+                        index_format: buffers.index_format,
+                        topology: match p.primitive_type {
+                            EPrimitiveType::Triangles => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+                            EPrimitiveType::TriangleStrip => D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                        },
+                        index_start: p.index_start,
+                        index_count: p.index_count,
+                        instance_count: Some(instance_count as u32),
+                        object_constants: instance_buffer.clone(),
+                        dyemap: None,
+                        debug_color: None,
+                        lod_category: Some(p.lod_category as u8),
+                        group,
+                        center,
+                    },
+                );
             }
         }
 
@@ -0,0 +1,91 @@
+use crate::render::DeviceContextSwapchain;
+use anyhow::Context;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use windows::Win32::Graphics::Direct3D11::*;
+
+/// Dynamic `StructuredBuffer<T>` shader resource. Grows when written with more elements than it can hold
+pub struct StructuredBuffer<T: Copy> {
+    dcs: Rc<DeviceContextSwapchain>,
+    buffer: ID3D11Buffer,
+    view: ID3D11ShaderResourceView,
+    capacity: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> StructuredBuffer<T> {
+    pub fn create(dcs: Rc<DeviceContextSwapchain>, capacity: usize) -> anyhow::Result<Self> {
+        let capacity = capacity.max(1);
+        let (buffer, view) = Self::create_buffer(&dcs, capacity)?;
+
+        Ok(Self {
+            dcs,
+            buffer,
+            view,
+            capacity,
+            _marker: Default::default(),
+        })
+    }
+
+    fn create_buffer(
+        dcs: &DeviceContextSwapchain,
+        capacity: usize,
+    ) -> anyhow::Result<(ID3D11Buffer, ID3D11ShaderResourceView)> {
+        unsafe {
+            let buffer = dcs
+                .device
+                .CreateBuffer(
+                    &D3D11_BUFFER_DESC {
+                        Usage: D3D11_USAGE_DYNAMIC,
+                        BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                        CPUAccessFlags: D3D11_CPU_ACCESS_WRITE,
+                        MiscFlags: D3D11_RESOURCE_MISC_BUFFER_STRUCTURED,
+                        ByteWidth: (capacity * std::mem::size_of::<T>()) as _,
+                        StructureByteStride: std::mem::size_of::<T>() as _,
+                    },
+                    None,
+                )
+                .context("Failed to create StructuredBuffer")?;
+
+            let view = dcs
+                .device
+                .CreateShaderResourceView(&buffer, None)
+                .context("Failed to create StructuredBuffer view")?;
+
+            Ok((buffer, view))
+        }
+    }
+
+    /// Uploads `data`, recreating the buffer if it doesn't fit
+    pub fn write(&mut self, data: &[T]) -> anyhow::Result<()> {
+        if data.len() > self.capacity {
+            let capacity = data.len().next_power_of_two();
+            (self.buffer, self.view) = Self::create_buffer(&self.dcs, capacity)?;
+            self.capacity = capacity;
+        }
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        unsafe {
+            let memory = self
+                .dcs
+                .context
+                .Map(&self.buffer, 0, D3D11_MAP_WRITE_DISCARD, 0)
+                .context("Failed to map StructuredBuffer for writing")?;
+
+            memory
+                .pData
+                .copy_from_nonoverlapping(data.as_ptr() as _, std::mem::size_of_val(data));
+
+            self.dcs.context.Unmap(&self.buffer, 0);
+        }
+
+        Ok(())
+    }
+
+    pub fn view(&self) -> &ID3D11ShaderResourceView {
+        &self.view
+    }
+}
@@ -1,34 +1,60 @@
-use crate::entity::{IndexBufferHeader, VertexBufferHeader};
-use crate::map::Unk8080714f;
+use crate::bounds::{aabb_relative_difference, Aabb, BoundingSphere};
+use crate::culling::{LayerStats, ViewCuller};
+use crate::entity::{EPrimitiveType, IndexBufferHeader, VertexBufferHeader};
+use crate::index_buffer::{part_indices, read_indices};
+use crate::lod::{nearest_available, LodSelector, LOD_LEVELS};
+use crate::map::{Unk8080714f, Unk80807152};
+use crate::picking::{ray_aabb, PickingMesh, Ray};
+use crate::vertex_stream::{MaterialShaderInputs, VertexStream, VertexTransform};
 
 use crate::packages::package_manager;
 
 use anyhow::Context;
-use glam::{Mat4, Vec4};
+use destiny_pkg::TagHash;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use std::rc::Rc;
+use tracing::debug;
 
 use windows::Win32::Graphics::Direct3D::D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP;
 use windows::Win32::Graphics::Direct3D11::{
-    ID3D11Buffer, ID3D11Device, D3D11_BIND_INDEX_BUFFER, D3D11_BIND_VERTEX_BUFFER,
-    D3D11_BUFFER_DESC, D3D11_MAP_WRITE_DISCARD, D3D11_SUBRESOURCE_DATA, D3D11_USAGE_IMMUTABLE,
+    ID3D11Buffer, D3D11_BIND_INDEX_BUFFER, D3D11_BUFFER_DESC, D3D11_SUBRESOURCE_DATA,
+    D3D11_USAGE_IMMUTABLE,
 };
 use windows::Win32::Graphics::Dxgi::Common::{
     DXGI_FORMAT, DXGI_FORMAT_R16_UINT, DXGI_FORMAT_R32_UINT,
 };
 
-use super::{DeviceContextSwapchain, RenderData};
+use super::draw_list::{vertex_buffer_id, DrawItem, PassDrawLists, SortKey};
+use super::vertex_input::VertexBuffers;
+use super::{ConstantBuffer, DeviceContextSwapchain, RenderData};
 
 pub struct TerrainRenderer {
+    hash: TagHash,
     terrain: Unk8080714f,
 
-    combined_vertex_buffer: ID3D11Buffer,
-    combined_vertex_stride: u32,
+    vertex_buffers: VertexBuffers,
 
     index_buffer: ID3D11Buffer,
     index_format: DXGI_FORMAT,
+
+    /// CPU-side geometry, positions are in world space
+    picking_mesh: PickingMesh,
+    part_bounds: Vec<Aabb>,
+    group_spheres: Vec<BoundingSphere>,
+    /// Detail levels that have at least one part, for each mesh group
+    group_lods: Vec<[bool; LOD_LEVELS]>,
+    /// Per mesh group vertex shader constants (cb11)
+    group_cbuffers: Vec<ConstantBuffer<Mat4>>,
+    pub bounds: Aabb,
 }
 
 impl TerrainRenderer {
-    pub fn load(terrain: Unk8080714f, device: &ID3D11Device) -> anyhow::Result<TerrainRenderer> {
+    pub fn load(
+        terrain: Unk8080714f,
+        hash: TagHash,
+        dcs: Rc<DeviceContextSwapchain>,
+        shader_inputs: &MaterialShaderInputs,
+    ) -> anyhow::Result<TerrainRenderer> {
         let pm = package_manager();
         let vertex_header: VertexBufferHeader = pm.read_tag_struct(terrain.vertex_buffer).unwrap();
 
@@ -36,23 +62,140 @@ impl TerrainRenderer {
 
         let vertex_data = pm.read_tag(t).unwrap();
 
-        let mut vertex2_stride = None;
-        let mut vertex2_data = None;
+        let mut vertex_buffers = VertexBuffers::default();
+        vertex_buffers.push(&dcs.device, &vertex_header, &vertex_data)?;
+        let mut vertex2 = None;
         if terrain.vertex2_buffer.is_valid() {
             let vertex2_header: VertexBufferHeader =
                 pm.read_tag_struct(terrain.vertex2_buffer).unwrap();
             let t = pm.get_entry(terrain.vertex2_buffer).unwrap().reference;
+            let vertex2_data = pm.read_tag(t).unwrap();
 
-            vertex2_stride = Some(vertex2_header.stride as u32);
-            vertex2_data = Some(pm.read_tag(t).unwrap());
+            vertex_buffers.push(&dcs.device, &vertex2_header, &vertex2_data)?;
+            vertex2 = Some((vertex2_header, vertex2_data));
         }
 
         let index_header: IndexBufferHeader = pm.read_tag_struct(terrain.indices).unwrap();
         let t = pm.get_entry(terrain.indices).unwrap().reference;
         let index_data = pm.read_tag(t).unwrap();
 
+        // Terrain positions are offset and scaled by the same vector that's passed to the vertex shader in cb11
+        let vertex_transform = VertexTransform {
+            position_scale: Vec3::splat(terrain.unk30.w),
+            position_offset: Vec3::new(terrain.unk30.x, terrain.unk30.y, terrain.unk30.z),
+            ..Default::default()
+        };
+        let mut streams = vec![(&vertex_header, vertex_data.as_slice())];
+        streams.extend(vertex2.as_ref().map(|(h, d)| (h, d.as_slice())));
+        let mut stream = VertexStream::load(
+            terrain.mesh_parts.first().map(|p| p.material),
+            shader_inputs,
+            &streams,
+            &vertex_transform,
+        );
+        let indices = read_indices(&index_header, &index_data);
+
+        // Texcoords are transformed per mesh group (cb11[1])
+        // ? Guess: unk20 is assumed to hold the scale in xy and the offset in zw, like the entity texcoord transform.
+        // This hasn't been checked against the terrain vertex shaders
+        let mut transformed = vec![false; stream.texcoords.len()];
+        for part in terrain.mesh_parts.iter() {
+            let Some(group) = terrain.mesh_groups.get(part.group_index as usize) else {
+                continue;
+            };
+
+            for &i in part_indices(&indices, part.index_start, part.index_count as u32) {
+                let (Some(texcoord), Some(done)) = (
+                    stream.texcoords.get_mut(i as usize),
+                    transformed.get_mut(i as usize),
+                ) else {
+                    continue;
+                };
+
+                if !*done {
+                    *texcoord = *texcoord * Vec2::new(group.unk20.x, group.unk20.y)
+                        + Vec2::new(group.unk20.z, group.unk20.w);
+                    *done = true;
+                }
+            }
+        }
+
+        let picking_mesh = PickingMesh {
+            positions: stream.positions,
+            indices,
+        };
+        let part_bounds = terrain
+            .mesh_parts
+            .iter()
+            .map(|p| picking_mesh.bounds(p.index_start, p.index_count as u32))
+            .collect::<Vec<_>>();
+
+        let mut group_bounds = vec![Aabb::EMPTY; terrain.mesh_groups.len()];
+        let mut group_lods = vec![[false; LOD_LEVELS]; terrain.mesh_groups.len()];
+        for (part, part_bounds) in terrain.mesh_parts.iter().zip(&part_bounds) {
+            if let Some(b) = group_bounds.get_mut(part.group_index as usize) {
+                *b = b.union(part_bounds);
+            }
+
+            if let Some(lods) = group_lods.get_mut(part.group_index as usize) {
+                if let Some(available) = lods.get_mut(part.detail_level as usize) {
+                    *available = true;
+                }
+            }
+        }
+        let group_spheres = group_bounds.iter().map(BoundingSphere::from_aabb).collect();
+
+        let bounds = part_bounds.iter().fold(Aabb::EMPTY, |acc, b| acc.union(b));
+
+        // The header and mesh group float groups are unidentified. Read them as min/max boxes and log how far they
+        // are from the computed bounds, to help identify them
+        let header_floats = Aabb {
+            min: Vec3::new(terrain.unk10.x, terrain.unk10.y, terrain.unk10.z),
+            max: Vec3::new(terrain.unk20.x, terrain.unk20.y, terrain.unk20.z),
+        };
+        debug!(
+            "Terrain {hash} unknown fields vs computed bounds: unk10/unk20 as min/max {:.3}",
+            aabb_relative_difference(&bounds, &header_floats)
+        );
+        for (i, (group, computed)) in terrain.mesh_groups.iter().zip(&group_bounds).enumerate() {
+            if computed.is_empty() {
+                continue;
+            }
+
+            let group_floats = Aabb {
+                min: Vec3::new(group.unk0, group.unk4, group.unk8),
+                max: Vec3::new(group.unkc, group.unk10, group.unk14),
+            };
+            debug!(
+                "Terrain {hash} group {i} unknown fields vs computed bounds: unk0-unk14 as min/max {:.3}",
+                aabb_relative_difference(computed, &group_floats)
+            );
+        }
+
+        let scope_offset = Vec4::new(
+            terrain.unk30.x,
+            terrain.unk30.y,
+            terrain.unk30.z,
+            terrain.unk30.w,
+        );
+        let group_cbuffers = terrain
+            .mesh_groups
+            .iter()
+            .map(|group| {
+                let texcoord_transform =
+                    Vec4::new(group.unk20.x, group.unk20.y, group.unk20.z, group.unk20.w);
+
+                // * Not scope_instance, same buffer index but not the same format
+                let scope_terrain =
+                    Mat4::from_cols(scope_offset, texcoord_transform, Vec4::ZERO, Vec4::ZERO);
+
+                ConstantBuffer::create(dcs.clone(), Some(&scope_terrain))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("Failed to create mesh group constant buffers")?;
+
         let index_buffer = unsafe {
-            device
+            dcs.device
                 .CreateBuffer(
                     &D3D11_BUFFER_DESC {
                         ByteWidth: index_data.len() as _,
@@ -68,121 +211,126 @@ impl TerrainRenderer {
                 .context("Failed to create index buffer")?
         };
 
-        let combined_vertex_data = if let Some(vertex2_data) = vertex2_data {
-            vertex_data
-                .chunks_exact(vertex_header.stride as _)
-                .zip(vertex2_data.chunks_exact(vertex2_stride.unwrap() as _))
-                .flat_map(|(v1, v2)| [v1, v2].concat())
-                .collect()
-        } else {
-            vertex_data
-        };
-
-        let combined_vertex_buffer = unsafe {
-            device
-                .CreateBuffer(
-                    &D3D11_BUFFER_DESC {
-                        ByteWidth: combined_vertex_data.len() as _,
-                        Usage: D3D11_USAGE_IMMUTABLE,
-                        BindFlags: D3D11_BIND_VERTEX_BUFFER,
-                        ..Default::default()
-                    },
-                    Some(&D3D11_SUBRESOURCE_DATA {
-                        pSysMem: combined_vertex_data.as_ptr() as _,
-                        ..Default::default()
-                    }),
-                )
-                .context("Failed to create combined vertex buffer")?
-        };
-
         Ok(TerrainRenderer {
+            hash,
             terrain,
-            combined_vertex_buffer,
-            combined_vertex_stride: (vertex_header.stride as u32
-                + vertex2_stride.unwrap_or_default()),
+            vertex_buffers,
             index_buffer,
             index_format: if index_header.is_32bit {
                 DXGI_FORMAT_R32_UINT
             } else {
                 DXGI_FORMAT_R16_UINT
             },
+            picking_mesh,
+            part_bounds,
+            group_spheres,
+            group_lods,
+            group_cbuffers,
+            bounds,
         })
     }
 
-    pub fn draw(
+    pub fn parts(&self) -> &[Unk80807152] {
+        &self.terrain.mesh_parts
+    }
+
+    /// Returns the distance and index of the closest drawn part intersecting the (world space) ray
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<(f32, usize)> {
+        let mut closest: Option<(f32, usize)> = None;
+        for (i, part) in self.terrain.mesh_parts.iter().enumerate() {
+            if part.detail_level != 0 {
+                continue;
+            }
+
+            match ray_aabb(ray, &self.part_bounds[i]) {
+                Some(t) if closest.map_or(true, |(ct, _)| t < ct) => {}
+                _ => continue,
+            }
+
+            if let Some(t) = self.picking_mesh.intersect(
+                ray,
+                part.index_start,
+                part.index_count as u32,
+                EPrimitiveType::TriangleStrip,
+            ) {
+                if closest.map_or(true, |(ct, _)| t < ct) {
+                    closest = Some((t, i));
+                }
+            }
+        }
+
+        closest
+    }
+
+    /// Culls parts, selects a LOD per mesh group and adds the remaining parts to the draw lists
+    pub fn collect_draws(
         &self,
-        dcs: &DeviceContextSwapchain,
+        draw_lists: &mut PassDrawLists,
         render_data: &RenderData,
-        cb11: &ID3D11Buffer,
-    ) -> anyhow::Result<()> {
-        unsafe {
-            for part in self
-                .terrain
-                .mesh_parts
-                .iter()
-                .filter(|u| u.detail_level == 0)
-            {
-                // TODO(cohae): Generate these one-time per mesh group, writing these every frame, every part is terribly inefficient
-                if let Some(group) = self.terrain.mesh_groups.get(part.group_index as usize) {
-                    let bmap = dcs
-                        .context
-                        .Map(cb11, 0, D3D11_MAP_WRITE_DISCARD, 0)
-                        .unwrap();
-
-                    let offset = Vec4::new(
-                        self.terrain.unk30.x,
-                        self.terrain.unk30.y,
-                        self.terrain.unk30.z,
-                        self.terrain.unk30.w,
-                    );
-
-                    // let texcoord_transform = Vec4::new(4.0, 4.0, 0.0, 0.0);
-                    let texcoord_transform =
-                        Vec4::new(group.unk20.x, group.unk20.y, group.unk20.z, group.unk20.w);
-
-                    // * Not scope_instance, same buffer index but not the same format
-                    let scope_terrain =
-                        Mat4::from_cols(offset, texcoord_transform, Vec4::ZERO, Vec4::ZERO);
-
-                    bmap.pData.copy_from_nonoverlapping(
-                        &scope_terrain as *const Mat4 as _,
-                        std::mem::size_of::<Mat4>(),
-                    );
-
-                    dcs.context.Unmap(cb11, 0);
-                    dcs.context
-                        .VSSetConstantBuffers(11, Some(&[Some(cb11.clone())]));
-
-                    if let Some(dyemap) = render_data.textures.get(&group.dyemap.0) {
-                        dcs.context
-                            .PSSetShaderResources(14, Some(&[Some(dyemap.view.clone())]));
-                    } else {
-                        dcs.context.PSSetShaderResources(14, Some(&[None]));
-                    }
-                }
+        culler: &ViewCuller,
+        lod_selector: &LodSelector,
+    ) -> anyhow::Result<LayerStats> {
+        // LODs are selected per mesh group, so neighbouring parts of a group never mix detail levels
+        let group_lods: Vec<Option<u8>> = self
+            .group_spheres
+            .iter()
+            .zip(&self.group_lods)
+            .map(|(sphere, available)| nearest_available(lod_selector.select(sphere), available))
+            .collect();
 
-                if let Some(m) = render_data.materials.get(&part.material.0) {
-                    m.bind(dcs, render_data)?;
-                }
+        let mut stats = LayerStats::default();
+        let terrain_visible = culler.is_aabb_visible(&self.bounds);
+
+        for (part, bounds) in self.terrain.mesh_parts.iter().zip(&self.part_bounds) {
+            let lod = part.detail_level;
+            if group_lods.get(part.group_index as usize).copied().flatten() != Some(lod) {
+                continue;
+            }
 
-                dcs.context.IASetVertexBuffers(
-                    0,
-                    1,
-                    Some([Some(self.combined_vertex_buffer.clone())].as_ptr()),
-                    Some([self.combined_vertex_stride].as_ptr()),
-                    Some(&0),
-                );
-
-                dcs.context
-                    .IASetIndexBuffer(Some(&self.index_buffer), self.index_format, 0);
-                dcs.context
-                    .IASetPrimitiveTopology(D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP);
-
-                dcs.context
-                    .DrawIndexed(part.index_count as _, part.index_start, 0);
+            stats.total += 1;
+            if !terrain_visible || !culler.is_aabb_visible(bounds) {
+                continue;
             }
+            stats.visible += 1;
+            stats.lods[lod as usize] += 1;
+
+            let (Some(group), Some(cb11)) = (
+                self.terrain.mesh_groups.get(part.group_index as usize),
+                self.group_cbuffers.get(part.group_index as usize),
+            ) else {
+                continue;
+            };
+
+            let Some(mat) = render_data.materials.get(&part.material.0) else {
+                continue;
+            };
+
+            draw_lists.push(
+                mat.render_stage(render_data),
+                DrawItem {
+                    sort_key: SortKey::new(mat, vertex_buffer_id(self.hash.0, 0)),
+                    vertex_buffers: self.vertex_buffers.clone(),
+                    index_buffer: self.index_buffer.clone(),
+                    index_format: self.index_format,
+                    topology: D3D10_PRIMITIVE_TOPOLOGY_TRIANGLESTRIP,
+                    index_start: part.index_start,
+                    index_count: part.index_count as _,
+                    instance_count: None,
+                    object_constants: cb11.buffer().clone(),
+                    dyemap: Some(
+                        render_data
+                            .textures
+                            .get(&group.dyemap.0)
+                            .map(|t| t.view.clone()),
+                    ),
+                    debug_color: None,
+                    lod_category: None,
+                    group: self.hash.0,
+                    center: bounds.center(),
+                },
+            );
         }
 
-        Ok(())
+        Ok(stats)
     }
 }
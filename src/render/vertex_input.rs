@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use anyhow::Context;
+use itertools::Itertools;
+use tracing::{error, warn};
+use windows::Win32::Graphics::Direct3D11::*;
+
+use crate::entity::VertexBufferHeader;
+use crate::vertex_layout::{InputSlot, InputSlots, ShaderInput, VertexLayout, MAX_INPUT_SLOTS};
+
+use super::DeviceContextSwapchain;
+
+pub struct VertexShader {
+    pub shader: ID3D11VertexShader,
+    /// Needed to validate input layouts, which are created on demand
+    pub bytecode: Vec<u8>,
+    pub inputs: Vec<ShaderInput>,
+    /// Output register of TEXCOORD0, see [`crate::dxbc::texcoord0_register`]
+    pub texcoord0_register: Option<u32>,
+}
+
+/// Vertex buffers of a mesh, bound to consecutive input slots starting at 0
+#[derive(Clone, Default)]
+pub struct VertexBuffers {
+    buffers: [Option<ID3D11Buffer>; MAX_INPUT_SLOTS],
+    pub slots: InputSlots,
+}
+
+impl VertexBuffers {
+    /// Uploads a per-vertex buffer and adds it to the next free slot
+    pub fn push(
+        &mut self,
+        device: &ID3D11Device,
+        header: &VertexBufferHeader,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.push_data(device, InputSlot::per_vertex(header), data)
+    }
+
+    /// Uploads a vertex buffer and adds it to the next free slot
+    pub fn push_data(
+        &mut self,
+        device: &ID3D11Device,
+        slot: InputSlot,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let buffer = unsafe {
+            device
+                .CreateBuffer(
+                    &D3D11_BUFFER_DESC {
+                        ByteWidth: data.len() as _,
+                        Usage: D3D11_USAGE_IMMUTABLE,
+                        BindFlags: D3D11_BIND_VERTEX_BUFFER,
+                        ..Default::default()
+                    },
+                    Some(&D3D11_SUBRESOURCE_DATA {
+                        pSysMem: data.as_ptr() as _,
+                        ..Default::default()
+                    }),
+                )
+                .context("Failed to create vertex buffer")?
+        };
+
+        let index = self.slots.len();
+        self.slots.push(slot).context("Too many vertex buffers")?;
+        self.buffers[index] = Some(buffer);
+        Ok(())
+    }
+
+    pub fn bind(&self, dcs: &DeviceContextSwapchain) {
+        let strides = self.slots.iter().map(|s| s.stride).collect_vec();
+        let offsets = vec![0; strides.len()];
+        unsafe {
+            dcs.context.IASetVertexBuffers(
+                0,
+                strides.len() as u32,
+                Some(self.buffers.as_ptr()),
+                Some(strides.as_ptr()),
+                Some(offsets.as_ptr()),
+            );
+        }
+    }
+}
+
+/// Input layouts for each combination of vertex shader and input slots, resolved on first use
+#[derive(Default)]
+pub struct InputLayoutCache {
+    layouts: RefCell<HashMap<(u32, InputSlots), Option<ID3D11InputLayout>>>,
+}
+
+impl InputLayoutCache {
+    /// Returns `None` if no usable layout could be resolved. Diagnostics are only logged the first time
+    pub fn get(
+        &self,
+        dcs: &DeviceContextSwapchain,
+        shader_hash: u32,
+        shader: &VertexShader,
+        slots: InputSlots,
+    ) -> Option<ID3D11InputLayout> {
+        self.layouts
+            .borrow_mut()
+            .entry((shader_hash, slots))
+            .or_insert_with(|| create_input_layout(dcs, shader_hash, shader, &slots))
+            .clone()
+    }
+}
+
+fn create_input_layout(
+    dcs: &DeviceContextSwapchain,
+    shader_hash: u32,
+    shader: &VertexShader,
+    slots: &[InputSlot],
+) -> Option<ID3D11InputLayout> {
+    let layout = VertexLayout::resolve(&shader.inputs, slots);
+    if !layout.diagnostics.is_empty() {
+        let inputs = shader.inputs.iter().map(|i| format!("\t{i}")).join("\n");
+        let diagnostics = layout
+            .diagnostics
+            .iter()
+            .map(|d| format!("\t{d}"))
+            .join("\n");
+        if layout.has_errors() {
+            error!(
+                "Can't resolve the input layout of VS {shader_hash:08X}, inputs:\n{inputs}\n{diagnostics}"
+            );
+            return None;
+        }
+
+        warn!(
+            "Input layout of VS {shader_hash:08X} might be wrong, inputs:\n{inputs}\n{diagnostics}"
+        );
+    }
+
+    // Shaders that only read system values get an empty layout
+    unsafe {
+        dcs.device
+            .CreateInputLayout(&layout.descs(), &shader.bytecode)
+            .map_err(|e| error!("Failed to create input layout for VS {shader_hash:08X}: {e}"))
+            .ok()
+    }
+}
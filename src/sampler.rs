@@ -0,0 +1,527 @@
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+
+use anyhow::ensure;
+use binrw::{BinRead, BinReaderExt};
+use destiny_pkg::TagHash;
+use windows::Win32::Graphics::Direct3D11::*;
+
+use crate::packages::package_manager;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    Point,
+    Linear,
+}
+
+/// How filtered samples are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterReduction {
+    Standard,
+    /// Samples are compared against a reference value (shadow maps)
+    Comparison,
+    Minimum,
+    Maximum,
+}
+
+/// Decomposed `D3D11_FILTER`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerFilter {
+    pub min: FilterType,
+    pub mag: FilterType,
+    pub mip: FilterType,
+    pub anisotropic: bool,
+    pub reduction: FilterReduction,
+}
+
+impl SamplerFilter {
+    const MIP_BIT: u32 = 1 << 0;
+    const MAG_BIT: u32 = 1 << 2;
+    const MIN_BIT: u32 = 1 << 4;
+    const ANISOTROPIC_BIT: u32 = 1 << 6;
+    const REDUCTION_SHIFT: u32 = 7;
+
+    pub fn from_raw(raw: u32) -> Result<Self, String> {
+        let linear_bits = Self::MIN_BIT | Self::MAG_BIT | Self::MIP_BIT;
+        let valid_bits = linear_bits | Self::ANISOTROPIC_BIT | (0b11 << Self::REDUCTION_SHIFT);
+        if raw & !valid_bits != 0 {
+            return Err(format!("Invalid sampler filter 0x{raw:x}"));
+        }
+
+        let anisotropic = raw & Self::ANISOTROPIC_BIT != 0;
+        // Anisotropic filtering is only defined with every stage set to linear
+        if anisotropic && raw & linear_bits != linear_bits {
+            return Err(format!("Invalid anisotropic sampler filter 0x{raw:x}"));
+        }
+
+        let filter_type = |bit: u32| {
+            if raw & bit != 0 {
+                FilterType::Linear
+            } else {
+                FilterType::Point
+            }
+        };
+
+        Ok(Self {
+            min: filter_type(Self::MIN_BIT),
+            mag: filter_type(Self::MAG_BIT),
+            mip: filter_type(Self::MIP_BIT),
+            anisotropic,
+            reduction: match (raw >> Self::REDUCTION_SHIFT) & 0b11 {
+                0 => FilterReduction::Standard,
+                1 => FilterReduction::Comparison,
+                2 => FilterReduction::Minimum,
+                _ => FilterReduction::Maximum,
+            },
+        })
+    }
+
+    pub fn to_raw(self) -> u32 {
+        let bit = |filter_type: FilterType, bit: u32| match filter_type {
+            FilterType::Point => 0,
+            FilterType::Linear => bit,
+        };
+
+        let reduction = match self.reduction {
+            FilterReduction::Standard => 0,
+            FilterReduction::Comparison => 1,
+            FilterReduction::Minimum => 2,
+            FilterReduction::Maximum => 3,
+        };
+
+        bit(self.min, Self::MIN_BIT)
+            | bit(self.mag, Self::MAG_BIT)
+            | bit(self.mip, Self::MIP_BIT)
+            | if self.anisotropic {
+                Self::ANISOTROPIC_BIT
+            } else {
+                0
+            }
+            | reduction << Self::REDUCTION_SHIFT
+    }
+}
+
+impl Display for SamplerFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.anisotropic {
+            f.write_str("anisotropic")?;
+        } else {
+            write!(
+                f,
+                "min {:?}, mag {:?}, mip {:?}",
+                self.min, self.mag, self.mip
+            )?;
+        }
+
+        match self.reduction {
+            FilterReduction::Standard => Ok(()),
+            r => write!(f, " ({r:?})"),
+        }
+    }
+}
+
+/// `D3D11_TEXTURE_ADDRESS_MODE`
+#[derive(BinRead, Debug, Clone, Copy, PartialEq, Eq)]
+#[br(repr(u32))]
+pub enum AddressMode {
+    Wrap = 1,
+    Mirror = 2,
+    Clamp = 3,
+    Border = 4,
+    MirrorOnce = 5,
+}
+
+/// `D3D11_COMPARISON_FUNC`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonFunc {
+    Never = 1,
+    Less = 2,
+    Equal = 3,
+    LessEqual = 4,
+    Greater = 5,
+    NotEqual = 6,
+    GreaterEqual = 7,
+    Always = 8,
+}
+
+impl ComparisonFunc {
+    /// Samplers that don't compare store 0
+    pub fn from_raw(raw: u32) -> Result<Option<Self>, String> {
+        Ok(Some(match raw {
+            0 => return Ok(None),
+            1 => Self::Never,
+            2 => Self::Less,
+            3 => Self::Equal,
+            4 => Self::LessEqual,
+            5 => Self::Greater,
+            6 => Self::NotEqual,
+            7 => Self::GreaterEqual,
+            8 => Self::Always,
+            _ => return Err(format!("Invalid sampler comparison func {raw}")),
+        }))
+    }
+}
+
+/// Sampler state as stored in sampler tags, laid out like `D3D11_SAMPLER_DESC`
+#[derive(BinRead, Debug, Clone, Copy, PartialEq)]
+pub struct SamplerDesc {
+    #[br(try_map = SamplerFilter::from_raw)]
+    pub filter: SamplerFilter,
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+    pub address_w: AddressMode,
+    pub mip_lod_bias: f32,
+    #[br(assert(max_anisotropy <= 16, "Invalid max anisotropy {}", max_anisotropy))]
+    pub max_anisotropy: u32,
+    #[br(try_map = ComparisonFunc::from_raw)]
+    pub comparison: Option<ComparisonFunc>,
+    pub border_color: [f32; 4],
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl SamplerDesc {
+    pub const SIZE: usize = 52;
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        ensure!(
+            data.len() >= Self::SIZE,
+            "Sampler data is too short ({} bytes, expected {})",
+            data.len(),
+            Self::SIZE
+        );
+
+        Ok(Cursor::new(data).read_le()?)
+    }
+
+    /// Reads the sampler state referenced by a material's sampler tag
+    pub fn load(sampler: TagHash) -> anyhow::Result<Self> {
+        let entry = package_manager().get_entry(sampler)?;
+        let data = package_manager().read_tag(entry.reference)?;
+        Self::parse(&data)
+    }
+}
+
+impl From<&SamplerDesc> for D3D11_SAMPLER_DESC {
+    fn from(desc: &SamplerDesc) -> Self {
+        D3D11_SAMPLER_DESC {
+            Filter: D3D11_FILTER(desc.filter.to_raw() as i32),
+            AddressU: D3D11_TEXTURE_ADDRESS_MODE(desc.address_u as i32),
+            AddressV: D3D11_TEXTURE_ADDRESS_MODE(desc.address_v as i32),
+            AddressW: D3D11_TEXTURE_ADDRESS_MODE(desc.address_w as i32),
+            MipLODBias: desc.mip_lod_bias,
+            MaxAnisotropy: desc.max_anisotropy,
+            // The comparison func is validated even when the filter doesn't compare
+            ComparisonFunc: D3D11_COMPARISON_FUNC(
+                desc.comparison.unwrap_or(ComparisonFunc::Never) as i32
+            ),
+            BorderColor: desc.border_color,
+            MinLOD: desc.min_lod,
+            MaxLOD: desc.max_lod,
+        }
+    }
+}
+
+/// Sampler as written to glTF. Filters and wrap modes use the WebGL enum values from the glTF spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GltfSampler {
+    pub mag_filter: u32,
+    pub min_filter: u32,
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+    /// Parts of the D3D sampler that glTF can't express, and what was used instead
+    pub approximations: Vec<String>,
+}
+
+impl GltfSampler {
+    pub const NEAREST: u32 = 9728;
+    pub const LINEAR: u32 = 9729;
+    pub const NEAREST_MIPMAP_NEAREST: u32 = 9984;
+    pub const LINEAR_MIPMAP_NEAREST: u32 = 9985;
+    pub const NEAREST_MIPMAP_LINEAR: u32 = 9986;
+    pub const LINEAR_MIPMAP_LINEAR: u32 = 9987;
+    pub const CLAMP_TO_EDGE: u32 = 33071;
+    pub const MIRRORED_REPEAT: u32 = 33648;
+    pub const REPEAT: u32 = 10497;
+
+    fn name(value: u32) -> &'static str {
+        match value {
+            Self::NEAREST => "NEAREST",
+            Self::LINEAR => "LINEAR",
+            Self::NEAREST_MIPMAP_NEAREST => "NEAREST_MIPMAP_NEAREST",
+            Self::LINEAR_MIPMAP_NEAREST => "LINEAR_MIPMAP_NEAREST",
+            Self::NEAREST_MIPMAP_LINEAR => "NEAREST_MIPMAP_LINEAR",
+            Self::LINEAR_MIPMAP_LINEAR => "LINEAR_MIPMAP_LINEAR",
+            Self::CLAMP_TO_EDGE => "CLAMP_TO_EDGE",
+            Self::MIRRORED_REPEAT => "MIRRORED_REPEAT",
+            Self::REPEAT => "REPEAT",
+            _ => "?",
+        }
+    }
+
+    fn wrap(axis: &str, mode: AddressMode, approximations: &mut Vec<String>) -> u32 {
+        match mode {
+            AddressMode::Wrap => Self::REPEAT,
+            AddressMode::Mirror => Self::MIRRORED_REPEAT,
+            AddressMode::Clamp => Self::CLAMP_TO_EDGE,
+            // Border samples outside of [0, 1], clamping keeps the edge texels instead
+            AddressMode::Border => {
+                approximations.push(format!("{axis}: border as CLAMP_TO_EDGE"));
+                Self::CLAMP_TO_EDGE
+            }
+            // Same as mirror for [-1, 1], which covers most uses
+            AddressMode::MirrorOnce => {
+                approximations.push(format!("{axis}: mirror once as MIRRORED_REPEAT"));
+                Self::MIRRORED_REPEAT
+            }
+        }
+    }
+}
+
+impl From<&SamplerDesc> for GltfSampler {
+    fn from(desc: &SamplerDesc) -> Self {
+        let mut approximations = vec![];
+        let filter = desc.filter;
+        if filter.anisotropic {
+            approximations.push(format!(
+                "anisotropic x{} as LINEAR_MIPMAP_LINEAR",
+                desc.max_anisotropy
+            ));
+        }
+        if filter.reduction != FilterReduction::Standard {
+            approximations.push(format!("{:?} reduction ignored", filter.reduction));
+        }
+
+        // Anisotropic filters always have every stage set to linear
+        let min_filter = if desc.max_lod <= 0.0 {
+            // Only the top level is sampled
+            match filter.min {
+                FilterType::Point => Self::NEAREST,
+                FilterType::Linear => Self::LINEAR,
+            }
+        } else {
+            match (filter.min, filter.mip) {
+                (FilterType::Point, FilterType::Point) => Self::NEAREST_MIPMAP_NEAREST,
+                (FilterType::Linear, FilterType::Point) => Self::LINEAR_MIPMAP_NEAREST,
+                (FilterType::Point, FilterType::Linear) => Self::NEAREST_MIPMAP_LINEAR,
+                (FilterType::Linear, FilterType::Linear) => Self::LINEAR_MIPMAP_LINEAR,
+            }
+        };
+
+        Self {
+            mag_filter: match filter.mag {
+                FilterType::Point => Self::NEAREST,
+                FilterType::Linear => Self::LINEAR,
+            },
+            min_filter,
+            wrap_s: Self::wrap("wrapS", desc.address_u, &mut approximations),
+            wrap_t: Self::wrap("wrapT", desc.address_v, &mut approximations),
+            approximations,
+        }
+    }
+}
+
+impl Display for GltfSampler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "magFilter {}, minFilter {}, wrapS {}, wrapT {}",
+            Self::name(self.mag_filter),
+            Self::name(self.min_filter),
+            Self::name(self.wrap_s),
+            Self::name(self.wrap_t)
+        )
+    }
+}
+
+impl Display for SamplerDesc {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.filter)?;
+        if self.filter.anisotropic {
+            write!(f, " x{}", self.max_anisotropy)?;
+        }
+        if self.filter.reduction == FilterReduction::Comparison {
+            match self.comparison {
+                Some(c) => write!(f, ", compare {c:?}")?,
+                None => f.write_str(", compare none")?,
+            }
+        }
+        write!(
+            f,
+            ", address {:?}/{:?}/{:?}",
+            self.address_u, self.address_v, self.address_w
+        )?;
+        if [self.address_u, self.address_v, self.address_w].contains(&AddressMode::Border) {
+            write!(f, ", border {:?}", self.border_color)?;
+        }
+        write!(
+            f,
+            ", LOD bias {:.2}, LOD {:.1}..{:.1}",
+            self.mip_lod_bias, self.min_lod, self.max_lod
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler_data(filter: u32, comparison: u32) -> Vec<u8> {
+        sampler_data_with(filter, comparison, [3, 3], 1)
+    }
+
+    fn sampler_data_with(
+        filter: u32,
+        comparison: u32,
+        [address_u, address_v]: [u32; 2],
+        max_anisotropy: u32,
+    ) -> Vec<u8> {
+        let mut data = vec![];
+        for v in [filter, address_u, address_v, 3] {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend(0f32.to_le_bytes());
+        data.extend(max_anisotropy.to_le_bytes());
+        data.extend(comparison.to_le_bytes());
+        for v in [0f32, 0., 0., 0., 0., f32::MAX] {
+            data.extend(v.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn zero_comparison_func_means_no_comparison() {
+        let desc = SamplerDesc::parse(&sampler_data(0x15, 0)).unwrap();
+        assert_eq!(desc.comparison, None);
+        assert_eq!(desc.filter.reduction, FilterReduction::Standard);
+        assert_eq!(desc.address_u, AddressMode::Clamp);
+        assert_eq!(desc.max_lod, f32::MAX);
+    }
+
+    #[test]
+    fn comparison_func() {
+        let desc = SamplerDesc::parse(&sampler_data(0x95, 2)).unwrap();
+        assert_eq!(desc.comparison, Some(ComparisonFunc::Less));
+        assert_eq!(desc.filter.reduction, FilterReduction::Comparison);
+    }
+
+    #[test]
+    fn invalid_comparison_func_is_an_error() {
+        assert!(SamplerDesc::parse(&sampler_data(0x15, 9)).is_err());
+    }
+    fn gltf(filter: u32, address: [u32; 2], max_anisotropy: u32) -> GltfSampler {
+        let desc = SamplerDesc::parse(&sampler_data_with(filter, 0, address, max_anisotropy));
+        GltfSampler::from(&desc.unwrap())
+    }
+
+    #[test]
+    fn gltf_filters() {
+        // (D3D11_FILTER, magFilter, minFilter)
+        let cases = [
+            (
+                0x00,
+                GltfSampler::NEAREST,
+                GltfSampler::NEAREST_MIPMAP_NEAREST,
+            ),
+            (
+                0x01,
+                GltfSampler::NEAREST,
+                GltfSampler::NEAREST_MIPMAP_LINEAR,
+            ),
+            (
+                0x04,
+                GltfSampler::LINEAR,
+                GltfSampler::NEAREST_MIPMAP_NEAREST,
+            ),
+            (
+                0x10,
+                GltfSampler::NEAREST,
+                GltfSampler::LINEAR_MIPMAP_NEAREST,
+            ),
+            (
+                0x14,
+                GltfSampler::LINEAR,
+                GltfSampler::LINEAR_MIPMAP_NEAREST,
+            ),
+            (0x15, GltfSampler::LINEAR, GltfSampler::LINEAR_MIPMAP_LINEAR),
+        ];
+        for (filter, mag, min) in cases {
+            let sampler = gltf(filter, [1, 1], 1);
+            assert_eq!(
+                (sampler.mag_filter, sampler.min_filter),
+                (mag, min),
+                "filter 0x{filter:x}"
+            );
+            assert!(sampler.approximations.is_empty(), "filter 0x{filter:x}");
+        }
+    }
+
+    #[test]
+    fn gltf_without_mips_uses_the_plain_min_filter() {
+        let mut data = sampler_data(0x15, 0);
+        let max_lod = data.len() - 4;
+        data[max_lod..].copy_from_slice(&0f32.to_le_bytes());
+        let sampler = GltfSampler::from(&SamplerDesc::parse(&data).unwrap());
+        assert_eq!(sampler.min_filter, GltfSampler::LINEAR);
+    }
+
+    #[test]
+    fn gltf_wrap_modes() {
+        let sampler = gltf(0x15, [1, 2], 1);
+        assert_eq!(sampler.wrap_s, GltfSampler::REPEAT);
+        assert_eq!(sampler.wrap_t, GltfSampler::MIRRORED_REPEAT);
+        assert!(sampler.approximations.is_empty());
+
+        let sampler = gltf(0x15, [3, 3], 1);
+        assert_eq!(sampler.wrap_s, GltfSampler::CLAMP_TO_EDGE);
+        assert_eq!(sampler.wrap_t, GltfSampler::CLAMP_TO_EDGE);
+    }
+
+    #[test]
+    fn gltf_mirror_once_falls_back_to_mirrored_repeat() {
+        let sampler = gltf(0x15, [5, 1], 1);
+        assert_eq!(sampler.wrap_s, GltfSampler::MIRRORED_REPEAT);
+        assert_eq!(sampler.wrap_t, GltfSampler::REPEAT);
+        assert_eq!(
+            sampler.approximations,
+            ["wrapS: mirror once as MIRRORED_REPEAT"]
+        );
+    }
+
+    #[test]
+    fn gltf_border_falls_back_to_clamp_to_edge() {
+        let sampler = gltf(0x15, [4, 4], 1);
+        assert_eq!(sampler.wrap_s, GltfSampler::CLAMP_TO_EDGE);
+        assert_eq!(sampler.wrap_t, GltfSampler::CLAMP_TO_EDGE);
+        assert_eq!(
+            sampler.approximations,
+            [
+                "wrapS: border as CLAMP_TO_EDGE",
+                "wrapT: border as CLAMP_TO_EDGE"
+            ]
+        );
+    }
+
+    #[test]
+    fn gltf_anisotropic_falls_back_to_trilinear() {
+        let sampler = gltf(0x55, [1, 1], 16);
+        assert_eq!(sampler.mag_filter, GltfSampler::LINEAR);
+        assert_eq!(sampler.min_filter, GltfSampler::LINEAR_MIPMAP_LINEAR);
+        assert_eq!(
+            sampler.approximations,
+            ["anisotropic x16 as LINEAR_MIPMAP_LINEAR"]
+        );
+        assert_eq!(
+            sampler.to_string(),
+            "magFilter LINEAR, minFilter LINEAR_MIPMAP_LINEAR, wrapS REPEAT, wrapT REPEAT"
+        );
+    }
+
+    #[test]
+    fn gltf_comparison_is_dropped() {
+        let desc = SamplerDesc::parse(&sampler_data(0x95, 2)).unwrap();
+        let sampler = GltfSampler::from(&desc);
+        assert_eq!(sampler.min_filter, GltfSampler::LINEAR_MIPMAP_LINEAR);
+        assert_eq!(sampler.approximations, ["Comparison reduction ignored"]);
+    }
+}
@@ -69,6 +69,7 @@ pub struct Unk808071a7 {
     pub materials: TablePointer<TagHash>,
     pub unk20: TablePointer<Unk80807193>,
     pub unk30: [u32; 2],
+    // ? Compared against the computed bounds in StaticModel::create (debug log)
     pub unk38: [f32; 6],
     // ? Similar to model_offset, but not quite right...
     pub unk50: Vector3,
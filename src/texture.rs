@@ -6,9 +6,12 @@ use crate::types::IVector2;
 use anyhow::Context;
 use binrw::BinRead;
 use destiny_pkg::TagHash;
+use std::fs::File;
 use std::io::SeekFrom;
+use std::path::Path;
 use windows::Win32::Graphics::Direct3D::{
     WKPDID_D3DDebugObjectName, D3D11_SRV_DIMENSION_TEXTURE2D, D3D11_SRV_DIMENSION_TEXTURE3D,
+    D3D11_SRV_DIMENSION_TEXTURECUBE,
 };
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::Graphics::Direct3D11::{
@@ -34,6 +37,13 @@ pub struct TextureHeader {
     pub large_buffer: Option<TagHash>,
 }
 
+impl TextureHeader {
+    /// Cubemaps are stored as 6 faces, each with their own mip chain
+    pub fn is_cubemap(&self) -> bool {
+        self.array_size == 6
+    }
+}
+
 /// Ref: 0x80809ebb
 #[derive(BinRead, Debug)]
 pub struct TexturePlate {
@@ -61,7 +71,7 @@ pub struct TexturePlateSet {
 
 pub enum TextureHandle {
     Texture2D(ID3D11Texture2D),
-    // TextureCube(ID3D11Texture2D),
+    TextureCube(ID3D11Texture2D),
     Texture3D(ID3D11Texture3D),
 }
 
@@ -71,8 +81,47 @@ pub struct Texture {
     pub format: DxgiFormat,
 }
 
+/// Texture header and pixel data read from the packages, ready to be uploaded with [`Texture::create`]
+pub struct TextureData {
+    pub hash: TagHash,
+    pub header: TextureHeader,
+    pub data: Vec<u8>,
+    pub mips: usize,
+}
+
+/// Row and slice pitch of mip level `mip`
+fn mip_pitch(format: DxgiFormat, width: usize, height: usize, mip: usize) -> (usize, usize) {
+    calculate_pitch(format, (width >> mip).max(1), (height >> mip).max(1))
+}
+
+/// Size in bytes of the first `mips` levels of a single face
+fn mip_chain_size(format: DxgiFormat, width: usize, height: usize, mips: usize) -> usize {
+    (0..mips)
+        .map(|i| mip_pitch(format, width, height, i).1)
+        .sum()
+}
+
+/// Number of mips (at most `max_mips`) for which every face fits in `available` bytes
+fn fitting_mips(
+    format: DxgiFormat,
+    width: usize,
+    height: usize,
+    faces: usize,
+    max_mips: usize,
+    available: usize,
+) -> usize {
+    (0..max_mips)
+        .take_while(|&i| faces * mip_chain_size(format, width, height, i + 1) <= available)
+        .count()
+}
+
 impl Texture {
     pub fn load(dcs: &DeviceContextSwapchain, hash: TagHash) -> anyhow::Result<Texture> {
+        Self::create(dcs, &Self::read(hash)?)
+    }
+
+    /// Reads a texture tag without touching the device, so it can be called from any thread
+    pub fn read(hash: TagHash) -> anyhow::Result<TextureData> {
         let texture_header_ref = package_manager().get_entry(hash)?.reference;
 
         let texture: TextureHeader = package_manager().read_tag_struct(hash)?;
@@ -87,6 +136,7 @@ impl Texture {
                 .to_vec()
         };
 
+        let faces = if texture.is_cubemap() { 6 } else { 1 };
         let mut mips = 1;
         if texture.large_buffer.is_some() {
             let ab = package_manager()
@@ -103,19 +153,49 @@ impl Texture {
                 mips += 1;
             }
 
-            let mut required_mip_bytes = 0;
-            for i in 0..mips {
-                let width = texture.width >> i;
-                let height = texture.height >> i;
-                let size = calculate_pitch(texture.format, width as usize, height as usize);
-                if (required_mip_bytes + size.1) > texture_data.len() {
-                    mips = i + 1;
-                    break;
-                }
-                required_mip_bytes += size.1;
-            }
+            // Mips that aren't fully present are dropped
+            mips = fitting_mips(
+                texture.format,
+                texture.width as usize,
+                texture.height as usize,
+                faces,
+                mips,
+                texture_data.len(),
+            );
+            anyhow::ensure!(mips > 0, "Texture data is too short for the first mip");
         }
 
+        Ok(TextureData {
+            hash,
+            header: texture,
+            data: texture_data,
+            mips,
+        })
+    }
+
+    pub fn create(dcs: &DeviceContextSwapchain, data: &TextureData) -> anyhow::Result<Texture> {
+        let (hash, texture, texture_data, mips) = (data.hash, &data.header, &data.data, data.mips);
+        let faces = if texture.is_cubemap() { 6 } else { 1 };
+
+        // The subresource pointers below are offsets into the data, so all of it has to be present
+        let required_bytes = if texture.depth > 1 {
+            calculate_pitch(texture.format, texture.width as _, texture.height as _).1
+                * texture.depth as usize
+        } else {
+            faces
+                * mip_chain_size(
+                    texture.format,
+                    texture.width as usize,
+                    texture.height as usize,
+                    mips,
+                )
+        };
+        anyhow::ensure!(
+            mips > 0 && required_bytes <= texture_data.len(),
+            "Texture data is too short ({} bytes, expected {required_bytes})",
+            texture_data.len()
+        );
+
         let (tex, view) = unsafe {
             if texture.depth > 1 {
                 let (pitch, slice_pitch) =
@@ -164,20 +244,21 @@ impl Texture {
 
                 (TextureHandle::Texture3D(tex), view)
             } else {
+                // Subresources are ordered by face, then by mip
                 let mut initial_data = vec![];
                 let mut offset = 0;
-                for i in 0..mips {
-                    let width = texture.width >> i;
-                    let height = texture.height >> i;
-                    let (pitch, slice_pitch) =
-                        calculate_pitch(texture.format, width as usize, height as usize);
-
-                    initial_data.push(D3D11_SUBRESOURCE_DATA {
-                        pSysMem: texture_data.as_ptr().add(offset) as _,
-                        SysMemPitch: pitch as u32,
-                        SysMemSlicePitch: 0,
-                    });
-                    offset += slice_pitch;
+                for _ in 0..faces {
+                    for i in 0..mips {
+                        let (pitch, slice_pitch) =
+                            mip_pitch(texture.format, texture.width as _, texture.height as _, i);
+
+                        initial_data.push(D3D11_SUBRESOURCE_DATA {
+                            pSysMem: texture_data.as_ptr().add(offset) as _,
+                            SysMemPitch: pitch as u32,
+                            SysMemSlicePitch: 0,
+                        });
+                        offset += slice_pitch;
+                    }
                 }
 
                 let tex = dcs
@@ -187,8 +268,7 @@ impl Texture {
                             Width: texture.width as _,
                             Height: texture.height as _,
                             MipLevels: mips as u32,
-                            // TODO(cohae): Cubemaps
-                            ArraySize: 1 as _,
+                            ArraySize: faces as _,
                             Format: texture.format.into(),
                             SampleDesc: DXGI_SAMPLE_DESC {
                                 Count: 1,
@@ -197,7 +277,11 @@ impl Texture {
                             Usage: D3D11_USAGE_DEFAULT,
                             BindFlags: D3D11_BIND_SHADER_RESOURCE,
                             CPUAccessFlags: Default::default(),
-                            MiscFlags: Default::default(),
+                            MiscFlags: if texture.is_cubemap() {
+                                D3D11_RESOURCE_MISC_TEXTURECUBE
+                            } else {
+                                Default::default()
+                            },
                         },
                         Some(initial_data.as_ptr()),
                     )
@@ -212,24 +296,45 @@ impl Texture {
                 )
                 .context("Failed to set VS name")?;
 
-                let view = dcs
-                    .device
-                    .CreateShaderResourceView(
-                        &tex,
-                        Some(&D3D11_SHADER_RESOURCE_VIEW_DESC {
-                            Format: texture.format.into(),
-                            ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
-                            Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
-                                Texture2D: D3D11_TEX2D_SRV {
-                                    MostDetailedMip: 0,
-                                    MipLevels: mips as _,
+                if texture.is_cubemap() {
+                    let view = dcs
+                        .device
+                        .CreateShaderResourceView(
+                            &tex,
+                            Some(&D3D11_SHADER_RESOURCE_VIEW_DESC {
+                                Format: texture.format.into(),
+                                ViewDimension: D3D11_SRV_DIMENSION_TEXTURECUBE,
+                                Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                                    TextureCube: D3D11_TEXCUBE_SRV {
+                                        MostDetailedMip: 0,
+                                        MipLevels: mips as _,
+                                    },
                                 },
-                            },
-                        }),
-                    )
-                    .unwrap();
+                            }),
+                        )
+                        .unwrap();
 
-                (TextureHandle::Texture2D(tex), view)
+                    (TextureHandle::TextureCube(tex), view)
+                } else {
+                    let view = dcs
+                        .device
+                        .CreateShaderResourceView(
+                            &tex,
+                            Some(&D3D11_SHADER_RESOURCE_VIEW_DESC {
+                                Format: texture.format.into(),
+                                ViewDimension: D3D11_SRV_DIMENSION_TEXTURE2D,
+                                Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                                    Texture2D: D3D11_TEX2D_SRV {
+                                        MostDetailedMip: 0,
+                                        MipLevels: mips as _,
+                                    },
+                                },
+                            }),
+                        )
+                        .unwrap();
+
+                    (TextureHandle::Texture2D(tex), view)
+                }
             }
         };
 
@@ -239,4 +344,197 @@ impl Texture {
             format: texture.format,
         })
     }
+
+    /// Loads a 2D texture from a DDS or PNG file. PNGs are uploaded as RGBA8, `srgb` picks the sRGB variant
+    pub fn load_file(
+        dcs: &DeviceContextSwapchain,
+        path: &Path,
+        srgb: bool,
+    ) -> anyhow::Result<Texture> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("dds") => Self::load_dds(dcs, path),
+            Some("png") => Self::load_png(dcs, path, srgb),
+            _ => anyhow::bail!("Unsupported texture file {}", path.display()),
+        }
+    }
+
+    fn load_dds(dcs: &DeviceContextSwapchain, path: &Path) -> anyhow::Result<Texture> {
+        let dds = ddsfile::Dds::read(File::open(path)?)?;
+        anyhow::ensure!(
+            dds.get_depth() <= 1 && dds.get_num_array_layers() <= 1,
+            "Only 2D textures are supported"
+        );
+
+        let format = if let Some(f) = dds.get_dxgi_format() {
+            DxgiFormat::try_from(f as u32)?
+        } else {
+            match dds.get_d3d_format() {
+                Some(ddsfile::D3DFormat::DXT1) => DxgiFormat::BC1_UNORM,
+                Some(ddsfile::D3DFormat::DXT3) => DxgiFormat::BC2_UNORM,
+                Some(ddsfile::D3DFormat::DXT5) => DxgiFormat::BC3_UNORM,
+                Some(ddsfile::D3DFormat::A8R8G8B8) => DxgiFormat::B8G8R8A8_UNORM,
+                Some(ddsfile::D3DFormat::A8B8G8R8) => DxgiFormat::R8G8B8A8_UNORM,
+                f => anyhow::bail!("Unsupported DDS format {f:?}"),
+            }
+        };
+
+        Self::create_2d(
+            dcs,
+            &path.display().to_string(),
+            dds.get_width(),
+            dds.get_height(),
+            dds.get_num_mipmap_levels(),
+            format,
+            &dds.data,
+        )
+    }
+
+    fn load_png(dcs: &DeviceContextSwapchain, path: &Path, srgb: bool) -> anyhow::Result<Texture> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        // Expands palettes and low bit depths, and strips 16-bit channels to 8 bits
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels)?;
+        pixels.truncate(info.buffer_size());
+
+        let rgba: Vec<u8> = match info.color_type {
+            png::ColorType::Rgba => pixels,
+            png::ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .flat_map(|c| [c[0], c[1], c[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|c| [c[0], c[0], c[0], c[1]])
+                .collect(),
+            png::ColorType::Grayscale => pixels.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+            png::ColorType::Indexed => anyhow::bail!("Indexed PNG was not expanded"),
+        };
+
+        Self::create_2d(
+            dcs,
+            &path.display().to_string(),
+            info.width,
+            info.height,
+            1,
+            if srgb {
+                DxgiFormat::R8G8B8A8_UNORM_SRGB
+            } else {
+                DxgiFormat::R8G8B8A8_UNORM
+            },
+            &rgba,
+        )
+    }
+
+    /// Creates a 2D texture from tightly packed mips. Mips that don't fit in `data` are dropped
+    fn create_2d(
+        dcs: &DeviceContextSwapchain,
+        name: &str,
+        width: u32,
+        height: u32,
+        mips: u32,
+        format: DxgiFormat,
+        data: &[u8],
+    ) -> anyhow::Result<Texture> {
+        let mut initial_data = vec![];
+        let mut offset = 0;
+        for i in 0..mips.max(1) {
+            let (pitch, slice_pitch) = calculate_pitch(
+                format,
+                (width >> i).max(1) as usize,
+                (height >> i).max(1) as usize,
+            );
+            if offset + slice_pitch > data.len() {
+                break;
+            }
+
+            initial_data.push(D3D11_SUBRESOURCE_DATA {
+                pSysMem: unsafe { data.as_ptr().add(offset) } as _,
+                SysMemPitch: pitch as u32,
+                SysMemSlicePitch: 0,
+            });
+            offset += slice_pitch;
+        }
+        anyhow::ensure!(!initial_data.is_empty(), "Texture data is too short");
+
+        unsafe {
+            let tex = dcs
+                .device
+                .CreateTexture2D(
+                    &D3D11_TEXTURE2D_DESC {
+                        Width: width,
+                        Height: height,
+                        MipLevels: initial_data.len() as u32,
+                        ArraySize: 1,
+                        Format: format.into(),
+                        SampleDesc: DXGI_SAMPLE_DESC {
+                            Count: 1,
+                            Quality: 0,
+                        },
+                        Usage: D3D11_USAGE_IMMUTABLE,
+                        BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                        CPUAccessFlags: Default::default(),
+                        MiscFlags: Default::default(),
+                    },
+                    Some(initial_data.as_ptr()),
+                )
+                .context("Failed to create texture")?;
+
+            let name = format!("Tex {name}\0");
+            tex.SetPrivateData(
+                &WKPDID_D3DDebugObjectName,
+                name.len() as u32 - 1,
+                Some(name.as_ptr() as _),
+            )
+            .context("Failed to set texture name")?;
+
+            let view = dcs
+                .device
+                .CreateShaderResourceView(&tex, None)
+                .context("Failed to create texture view")?;
+
+            Ok(Texture {
+                handle: TextureHandle::Texture2D(tex),
+                view,
+                format,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_size_clamps_to_one_block() {
+        // 16x16 RGBA8: 1024 + 256 + 64 + 16 + 4
+        assert_eq!(mip_chain_size(DxgiFormat::R8G8B8A8_UNORM, 16, 16, 5), 1364);
+        // BC1 mips below 4x4 still take a full 8 byte block
+        assert_eq!(
+            mip_chain_size(DxgiFormat::BC1_UNORM, 8, 8, 4),
+            32 + 8 + 8 + 8
+        );
+    }
+
+    #[test]
+    fn fitting_mips_keeps_complete_mips() {
+        let format = DxgiFormat::R8G8B8A8_UNORM;
+        assert_eq!(fitting_mips(format, 16, 16, 1, 5, 1364), 5);
+        // The last mip is a byte short, so it's dropped instead of read out of bounds
+        assert_eq!(fitting_mips(format, 16, 16, 1, 5, 1363), 4);
+        assert_eq!(fitting_mips(format, 16, 16, 1, 5, 1024), 1);
+        assert_eq!(fitting_mips(format, 16, 16, 1, 5, 1023), 0);
+    }
+
+    #[test]
+    fn fitting_mips_checks_every_face() {
+        let format = DxgiFormat::R8G8B8A8_UNORM;
+        // Enough data for 6 faces of the first two mips, but not the third
+        let available = 6 * (1024 + 256) + 63;
+        assert_eq!(fitting_mips(format, 16, 16, 6, 5, available), 2);
+        assert_eq!(fitting_mips(format, 16, 16, 6, 5, 6 * 1024 - 1), 0);
+    }
 }
@@ -0,0 +1,246 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+
+use crate::map::Unk808099d8;
+use crate::statics::Unk808071a3;
+use crate::types::Vector4;
+
+/// Placement of an object in the world.
+///
+/// Rotations are stored as-is from the game files (xyzw, rotating the object into world space).
+/// The world is right-handed with Z up, so a positive rotation around Z turns +X towards +Y.
+/// Scale is applied in object space, before rotating.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    /// Creates a transform from a translation with the uniform scale in w, as used by map resources and decals
+    pub fn from_translation_scale(translation_scale: Vec4, rotation: Quat) -> Self {
+        Self::new(
+            translation_scale.truncate(),
+            rotation,
+            Vec3::splat(translation_scale.w),
+        )
+    }
+
+    /// Creates the transform of a decal from its `Unk80806e68` transform (translation, w = scale).
+    /// Decals don't store a rotation, they use the one of the collection that places them
+    pub fn from_decal(transform: &Vector4, collection: &Unk808099d8) -> Self {
+        Self::from_translation_scale(
+            Vec4::new(transform.x, transform.y, transform.z, transform.w),
+            quat(&collection.rotation),
+        )
+    }
+
+    /// Object to world matrix
+    pub fn to_mat4(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    /// Returns true if any scale axis is 0, which makes the transform impossible to invert
+    pub fn is_degenerate(&self) -> bool {
+        self.scale.cmpeq(Vec3::ZERO).any()
+    }
+}
+
+fn quat(v: &Vector4) -> Quat {
+    Quat::from_xyzw(v.x, v.y, v.z, v.w)
+}
+
+impl From<&Unk808071a3> for Transform {
+    fn from(instance: &Unk808071a3) -> Self {
+        Self::new(
+            Vec3::new(
+                instance.translation.x,
+                instance.translation.y,
+                instance.translation.z,
+            ),
+            quat(&instance.rotation),
+            Vec3::new(instance.scale.x, instance.scale.y, instance.scale.z),
+        )
+    }
+}
+
+impl From<&Unk808099d8> for Transform {
+    fn from(data: &Unk808099d8) -> Self {
+        Self::from_translation_scale(
+            Vec4::new(
+                data.translation.x,
+                data.translation.y,
+                data.translation.z,
+                data.translation.w,
+            ),
+            quat(&data.rotation),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structure::ResourcePointer;
+    use crate::types::{DestinyHash, Vector3};
+    use destiny_pkg::TagHash;
+
+    fn vector4(v: Vec4) -> Vector4 {
+        Vector4 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+            w: v.w,
+        }
+    }
+
+    fn resource_point(rotation: Quat, translation_scale: Vec4) -> Unk808099d8 {
+        Unk808099d8 {
+            entity: TagHash(u32::MAX),
+            unk4: [0; 3],
+            rotation: vector4(Vec4::from(rotation)),
+            translation: vector4(translation_scale),
+            unk30: [0; 11],
+            unk5c: 0.0,
+            unk60: 0,
+            unk64: DestinyHash(0),
+            unk68: [0; 4],
+            data_resource: ResourcePointer {
+                offset: 0,
+                resource_type: 0,
+                is_valid: false,
+            },
+            unk80: [0; 4],
+        }
+    }
+
+    /// The object to world matrix as it was built before `Transform` existed: the inverse rotation with the
+    /// translation in the bottom row, transposed
+    fn legacy_matrix(translation: Vec3, rotation: Quat, scale: f32) -> Mat4 {
+        let mm = Mat4::from_scale_rotation_translation(
+            Vec3::splat(scale),
+            rotation.inverse(),
+            Vec3::ZERO,
+        );
+        Mat4::from_cols(
+            mm.x_axis.truncate().extend(translation.x),
+            mm.y_axis.truncate().extend(translation.y),
+            mm.z_axis.truncate().extend(translation.z),
+            mm.w_axis,
+        )
+        .transpose()
+    }
+
+    fn assert_mat_near(a: Mat4, b: Mat4) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{a} != {b}");
+    }
+
+    fn rotation() -> Quat {
+        Quat::from_xyzw(0.1, -0.4, 0.3, 0.8).normalize()
+    }
+
+    #[test]
+    fn to_mat4_is_translate_rotate_scale() {
+        let transform = Transform::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            Quat::from_rotation_z(90f32.to_radians()),
+            Vec3::new(2.0, 3.0, 4.0),
+        );
+        let m = transform.to_mat4();
+
+        assert_mat_near(
+            m,
+            Mat4::from_translation(transform.translation)
+                * Mat4::from_quat(transform.rotation)
+                * Mat4::from_scale(transform.scale),
+        );
+
+        // Scaled in object space first, then +X turns towards +Y
+        assert!(m
+            .transform_point3(Vec3::X)
+            .abs_diff_eq(Vec3::new(1.0, 4.0, 3.0), 1e-5));
+        assert!(m
+            .transform_point3(Vec3::Y)
+            .abs_diff_eq(Vec3::new(-2.0, 2.0, 3.0), 1e-5));
+        assert!(m
+            .transform_point3(Vec3::Z)
+            .abs_diff_eq(Vec3::new(1.0, 2.0, 7.0), 1e-5));
+    }
+
+    #[test]
+    fn resource_point_matches_legacy_matrix() {
+        let translation = Vec3::new(-12.0, 5.5, 100.0);
+        let data = resource_point(rotation(), translation.extend(1.5));
+
+        assert_mat_near(
+            Transform::from(&data).to_mat4(),
+            legacy_matrix(translation, rotation(), 1.5),
+        );
+    }
+
+    #[test]
+    fn decal_uses_the_collection_rotation() {
+        let collection = resource_point(rotation(), Vec4::new(1.0, 1.0, 1.0, 1.0));
+        let translation = Vec3::new(3.0, -2.0, 0.5);
+        let decal = Transform::from_decal(&vector4(translation.extend(0.25)), &collection);
+
+        assert_eq!(decal.scale, Vec3::splat(0.25));
+        assert_mat_near(
+            decal.to_mat4(),
+            legacy_matrix(translation, rotation(), 0.25),
+        );
+    }
+
+    #[test]
+    fn static_instance_matches_legacy_matrix() {
+        let instance = Unk808071a3 {
+            rotation: vector4(Vec4::from(rotation())),
+            translation: Vector3 {
+                x: 7.0,
+                y: 8.0,
+                z: 9.0,
+            },
+            scale: Vector3 {
+                x: 2.0,
+                y: 2.0,
+                z: 2.0,
+            },
+            unk28: 0,
+            unk2c: 0,
+        };
+
+        // Uniform scales match the old matrix, which only used scale.x
+        assert_mat_near(
+            Transform::from(&instance).to_mat4(),
+            legacy_matrix(Vec3::new(7.0, 8.0, 9.0), rotation(), 2.0),
+        );
+    }
+
+    #[test]
+    fn zero_scale_is_degenerate() {
+        assert!(!Transform::IDENTITY.is_degenerate());
+        assert!(
+            Transform::new(Vec3::ZERO, Quat::IDENTITY, Vec3::new(1.0, 0.0, 1.0)).is_degenerate()
+        );
+    }
+}
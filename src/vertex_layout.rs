@@ -1,124 +1,691 @@
-use crate::dxbc::{DxbcInputElement, DxbcInputType, DxbcSemanticType};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::ops::Deref;
+
+use anyhow::ensure;
+
+use crate::dxbc::{DxbcInputType, DxbcSignature};
 use crate::dxgi::DxgiFormat;
-use windows::Win32::Graphics::Direct3D11::{D3D11_INPUT_ELEMENT_DESC, D3D11_INPUT_PER_VERTEX_DATA};
+use crate::entity::VertexBufferHeader;
+use windows::core::PCSTR;
+use windows::Win32::Graphics::Direct3D11::{
+    D3D11_INPUT_ELEMENT_DESC, D3D11_INPUT_PER_INSTANCE_DATA, D3D11_INPUT_PER_VERTEX_DATA,
+};
 use windows::Win32::Graphics::Dxgi::Common::DXGI_FORMAT;
 
-#[derive(PartialEq, Clone, Debug)]
-pub struct InputElement {
-    pub format: DxgiFormat,
+pub const MAX_INPUT_SLOTS: usize = 4;
+
+/// A vertex buffer bound to an input slot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct InputSlot {
+    pub stride: u32,
+    /// `VertexBufferHeader::vtype`, only used for diagnostics
+    pub vtype: u16,
+    /// 0 for per-vertex data, otherwise the number of instances drawn per element
+    pub instance_step_rate: u32,
+}
+
+impl InputSlot {
+    pub fn per_vertex(header: &VertexBufferHeader) -> Self {
+        Self {
+            stride: header.stride as u32,
+            vtype: header.vtype,
+            instance_step_rate: 0,
+        }
+    }
+}
+
+/// The input slots of a draw, in slot order
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct InputSlots {
+    slots: [InputSlot; MAX_INPUT_SLOTS],
+    len: usize,
+}
+
+impl InputSlots {
+    pub fn push(&mut self, slot: InputSlot) -> anyhow::Result<()> {
+        ensure!(
+            self.len < MAX_INPUT_SLOTS,
+            "Too many input slots (max {MAX_INPUT_SLOTS})"
+        );
+        self.slots[self.len] = slot;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl Deref for InputSlots {
+    type Target = [InputSlot];
+
+    fn deref(&self) -> &Self::Target {
+        &self.slots[..self.len]
+    }
+}
+
+/// A vertex shader input, from the input signature
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderInput {
+    pub semantic_name: String,
     pub semantic_index: u32,
-    pub semantic_type: DxbcSemanticType,
-    pub component_count: usize,
+    /// `D3D_NAME`, 0 for regular inputs
+    pub system_value_type: u32,
     pub component_type: DxbcInputType,
+    pub component_count: u32,
 }
 
-impl InputElement {
-    pub fn from_dxbc(e: &DxbcInputElement, interpolated: bool, is_float: bool) -> InputElement {
-        let ty = match e.component_mask.iter().count() {
-            1 => InputType::Scalar,
-            2 => InputType::Scalar2,
-            3 => InputType::Scalar3,
-            4 => InputType::Scalar4,
-            _ => unreachable!(),
-        };
+impl ShaderInput {
+    pub fn from_signature(signature: &DxbcSignature) -> Vec<ShaderInput> {
+        signature
+            .elements
+            .iter()
+            .map(|e| ShaderInput {
+                semantic_name: e.semantic_name.to_string(),
+                semantic_index: e.semantic_index,
+                system_value_type: e.system_value_type,
+                component_type: e.component_type.clone(),
+                // Components are read starting from x, so the highest used component decides the size
+                component_count: 8 - e.component_mask.bits().leading_zeros(),
+            })
+            .collect()
+    }
+
+    /// System values (SV_VertexID, SV_InstanceID) are generated by the input assembler and aren't read from a buffer
+    pub fn is_system_value(&self) -> bool {
+        self.system_value_type != 0 || self.semantic_name.to_ascii_uppercase().starts_with("SV_")
+    }
 
-        InputElement {
-            format: ty.into_dxgi_type(&e.semantic_name.to_string(), interpolated, is_float),
-            semantic_index: e.semantic_index,
-            semantic_type: DxbcSemanticType::from_str(&e.semantic_name.to_string())
-                .unwrap_or_else(|| panic!("Unknown semantic type {}", *e.semantic_name)),
-            component_count: e.component_mask.bits().count_ones() as usize,
-            component_type: e.component_type.clone(),
+    /// Formats the input can be stored as, in order of preference
+    pub fn candidate_formats(&self) -> &'static [DxgiFormat] {
+        let i = self.component_count.clamp(1, 4) as usize - 1;
+        match self.component_type {
+            DxbcInputType::Float if i >= 2 && self.semantic_name.eq_ignore_ascii_case("COLOR") => {
+                COLOR_FORMATS
+            }
+            DxbcInputType::Float => FLOAT_FORMATS[i],
+            DxbcInputType::Int => SINT_FORMATS[i],
+            DxbcInputType::Uint => UINT_FORMATS[i],
         }
     }
 }
 
-pub enum InputType {
-    Scalar,
-    Scalar2,
-    Scalar3,
-    Scalar4,
+impl Display for ShaderInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{} {}{}",
+            self.component_type, self.component_count, self.semantic_name, self.semantic_index
+        )
+    }
+}
+
+// Most geometry is stored as 16-bit normalized integers, with 3-component values padded to 4
+const FLOAT_FORMATS: [&[DxgiFormat]; 4] = [
+    &[DxgiFormat::R16_SNORM, DxgiFormat::R32_FLOAT],
+    &[DxgiFormat::R16G16_SNORM, DxgiFormat::R32G32_FLOAT],
+    &[DxgiFormat::R16G16B16A16_SNORM, DxgiFormat::R32G32B32_FLOAT],
+    &[
+        DxgiFormat::R16G16B16A16_SNORM,
+        DxgiFormat::R32G32B32A32_FLOAT,
+        DxgiFormat::R8G8B8A8_UNORM,
+    ],
+];
+
+const COLOR_FORMATS: &[DxgiFormat] = &[
+    DxgiFormat::R8G8B8A8_UNORM,
+    DxgiFormat::R16G16B16A16_SNORM,
+    DxgiFormat::R32G32B32A32_FLOAT,
+];
+
+const SINT_FORMATS: [&[DxgiFormat]; 4] = [
+    &[DxgiFormat::R16_SINT, DxgiFormat::R32_SINT],
+    &[DxgiFormat::R16G16_SINT, DxgiFormat::R32G32_SINT],
+    &[DxgiFormat::R16G16B16A16_SINT, DxgiFormat::R32G32B32_SINT],
+    &[
+        DxgiFormat::R16G16B16A16_SINT,
+        DxgiFormat::R32G32B32A32_SINT,
+        DxgiFormat::R8G8B8A8_SINT,
+    ],
+];
+
+const UINT_FORMATS: [&[DxgiFormat]; 4] = [
+    &[DxgiFormat::R16_UINT, DxgiFormat::R32_UINT],
+    &[DxgiFormat::R16G16_UINT, DxgiFormat::R32G32_UINT],
+    &[DxgiFormat::R16G16B16A16_UINT, DxgiFormat::R32G32B32_UINT],
+    &[
+        DxgiFormat::R16G16B16A16_UINT,
+        DxgiFormat::R32G32B32A32_UINT,
+        DxgiFormat::R8G8B8A8_UINT,
+    ],
+];
+
+fn format_size(format: DxgiFormat) -> u32 {
+    (format.bpp() / 8) as u32
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutElement {
+    pub semantic_name: CString,
+    pub semantic_index: u32,
+    pub format: DxgiFormat,
+    pub slot: u32,
+    pub offset: u32,
+    pub instance_step_rate: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutDiagnostic {
+    /// The shader reads vertex data, but no buffers are bound
+    NoInputSlots,
+    /// No combination of formats fills every slot exactly, the preferred format of each input was used instead
+    StrideMismatch {
+        slot: u32,
+        stride: u32,
+        vtype: u16,
+        used: u32,
+    },
+    /// The input doesn't fit in the bound slots and reads past the end of the last one
+    Overflow { input: String },
+    /// None of the inputs read from this slot
+    UnusedSlot { slot: u32, stride: u32 },
 }
 
-impl InputType {
-    /// Align type to be usable with 16-bit formats
-    pub fn align_16(self) -> InputType {
+impl LayoutDiagnostic {
+    /// Errors make the layout unusable, other diagnostics only mean that some data might be misinterpreted
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            LayoutDiagnostic::NoInputSlots | LayoutDiagnostic::Overflow { .. }
+        )
+    }
+}
+
+impl Display for LayoutDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            InputType::Scalar => InputType::Scalar,
-            InputType::Scalar2 => InputType::Scalar2,
-            InputType::Scalar3 => InputType::Scalar4,
-            InputType::Scalar4 => InputType::Scalar4,
-        }
-    }
-
-    /// Convert to a compatible DXGI_FORMAT
-    /// This function aligns 16-bit types to 32-bit where necessary
-    pub fn into_dxgi_type(
-        self,
-        semantic_name: &str,
-        interpolated: bool,
-        is_float: bool,
-    ) -> DxgiFormat {
-        match if !is_float { self.align_16() } else { self } {
-            InputType::Scalar => {
-                if is_float {
-                    DxgiFormat::R32_FLOAT
-                } else if interpolated {
-                    DxgiFormat::R16_SNORM
-                } else {
-                    DxgiFormat::R16_SINT
-                }
+            LayoutDiagnostic::NoInputSlots => {
+                f.write_str("The shader reads vertex data, but no vertex buffers are bound")
             }
-            InputType::Scalar2 => {
-                if is_float {
-                    DxgiFormat::R32G32_FLOAT
-                } else if interpolated {
-                    DxgiFormat::R16G16_SNORM
-                } else {
-                    DxgiFormat::R16G16_SINT
-                }
+            LayoutDiagnostic::StrideMismatch {
+                slot,
+                stride,
+                vtype,
+                used,
+            } => write!(
+                f,
+                "Slot {slot} (vtype {vtype}) has a stride of {stride} bytes, inputs use {used} bytes"
+            ),
+            LayoutDiagnostic::Overflow { input } => {
+                write!(f, "{input} reads past the end of the last slot")
             }
-            InputType::Scalar3 => {
-                if is_float {
-                    DxgiFormat::R32G32B32_FLOAT
-                } else {
-                    unreachable!()
-                }
+            LayoutDiagnostic::UnusedSlot { slot, stride } => {
+                write!(f, "Slot {slot} ({stride} bytes) isn't read by the shader")
+            }
+        }
+    }
+}
+
+/// Input layout for a vertex shader and a set of bound vertex buffers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VertexLayout {
+    pub elements: Vec<LayoutElement>,
+    pub diagnostics: Vec<LayoutDiagnostic>,
+}
+
+impl VertexLayout {
+    /// Picks a format for each input, so that the inputs fill the strides of the slots exactly.
+    ///
+    /// Inputs are assigned to slots in signature order. When several assignments fit, the one with the
+    /// most preferred formats (see [`ShaderInput::candidate_formats`]) wins. Trailing slots that none of
+    /// the inputs read are allowed, but reported. If nothing fits, every input gets its preferred format
+    /// and the mismatches are reported as diagnostics
+    pub fn resolve(inputs: &[ShaderInput], slots: &[InputSlot]) -> VertexLayout {
+        let inputs = inputs
+            .iter()
+            .filter(|i| !i.is_system_value())
+            .collect::<Vec<_>>();
+
+        if inputs.is_empty() {
+            return VertexLayout {
+                elements: vec![],
+                diagnostics: unused_slots(slots, 0),
+            };
+        }
+
+        if slots.is_empty() {
+            return VertexLayout {
+                elements: vec![],
+                diagnostics: vec![LayoutDiagnostic::NoInputSlots],
+            };
+        }
+
+        for require_all_slots in [true, false] {
+            let mut search = Search {
+                inputs: &inputs,
+                slots,
+                require_all_slots,
+                placements: Vec::with_capacity(inputs.len()),
+                failed: HashSet::new(),
+            };
+
+            if search.run(0, 0, 0) {
+                let last_slot = search.placements.last().map(|p| p.0).unwrap_or_default();
+                return VertexLayout {
+                    elements: elements(&inputs, slots, &search.placements),
+                    diagnostics: unused_slots(slots, last_slot as usize + 1),
+                };
+            }
+        }
+
+        Self::fallback(&inputs, slots)
+    }
+
+    /// Assigns the preferred format to every input, moving on to the next slot when an input doesn't fit
+    fn fallback(inputs: &[&ShaderInput], slots: &[InputSlot]) -> VertexLayout {
+        let mut diagnostics = vec![];
+        let mut placements = vec![];
+        let mut used = vec![0; slots.len()];
+        let mut slot = 0;
+        for input in inputs {
+            let format = input.candidate_formats()[0];
+            let size = format_size(format);
+            while used[slot] + size > slots[slot].stride && slot + 1 < slots.len() {
+                slot += 1;
             }
-            InputType::Scalar4 => {
-                if semantic_name.starts_with("COLOR") {
-                    DxgiFormat::R8G8B8A8_UNORM
-                } else if is_float {
-                    DxgiFormat::R32G32B32A32_FLOAT
-                } else if interpolated {
-                    DxgiFormat::R16G16B16A16_SNORM
+
+            if used[slot] + size > slots[slot].stride {
+                diagnostics.push(LayoutDiagnostic::Overflow {
+                    input: input.to_string(),
+                });
+            }
+
+            placements.push((slot as u32, used[slot], format));
+            used[slot] += size;
+        }
+
+        for (i, (s, used)) in slots.iter().zip(used).enumerate() {
+            if used == 0 {
+                diagnostics.push(LayoutDiagnostic::UnusedSlot {
+                    slot: i as u32,
+                    stride: s.stride,
+                });
+            } else if used < s.stride {
+                diagnostics.push(LayoutDiagnostic::StrideMismatch {
+                    slot: i as u32,
+                    stride: s.stride,
+                    vtype: s.vtype,
+                    used,
+                });
+            }
+        }
+
+        VertexLayout {
+            elements: elements(inputs, slots, &placements),
+            diagnostics,
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(LayoutDiagnostic::is_error)
+    }
+
+    /// Element descriptions for `CreateInputLayout`. The semantic names point into `self`, so the layout has to
+    /// outlive the returned descriptions
+    pub fn descs(&self) -> Vec<D3D11_INPUT_ELEMENT_DESC> {
+        self.elements
+            .iter()
+            .map(|e| D3D11_INPUT_ELEMENT_DESC {
+                SemanticName: PCSTR(e.semantic_name.as_ptr() as _),
+                SemanticIndex: e.semantic_index,
+                Format: DXGI_FORMAT(e.format.into()),
+                InputSlot: e.slot,
+                AlignedByteOffset: e.offset,
+                InputSlotClass: if e.instance_step_rate == 0 {
+                    D3D11_INPUT_PER_VERTEX_DATA
                 } else {
-                    DxgiFormat::R16G16B16A16_SINT
-                }
+                    D3D11_INPUT_PER_INSTANCE_DATA
+                },
+                InstanceDataStepRate: e.instance_step_rate,
+            })
+            .collect()
+    }
+}
+
+/// Depth-first search over the candidate formats of each input
+struct Search<'a> {
+    inputs: &'a [&'a ShaderInput],
+    slots: &'a [InputSlot],
+    require_all_slots: bool,
+    /// (slot, offset, format) of each placed input
+    placements: Vec<(u32, u32, DxgiFormat)>,
+    /// (input, slot, offset) states that are known to lead nowhere
+    failed: HashSet<(usize, usize, u32)>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, input: usize, slot: usize, offset: u32) -> bool {
+        let stride = self.slots[slot].stride;
+        if offset == stride && slot + 1 < self.slots.len() && input < self.inputs.len() {
+            return self.run(input, slot + 1, 0);
+        }
+
+        if input == self.inputs.len() {
+            return offset == stride && (!self.require_all_slots || slot + 1 == self.slots.len());
+        }
+
+        if self.failed.contains(&(input, slot, offset)) {
+            return false;
+        }
+
+        for &format in self.inputs[input].candidate_formats() {
+            let size = format_size(format);
+            if offset + size > stride {
+                continue;
+            }
+
+            self.placements.push((slot as u32, offset, format));
+            if self.run(input + 1, slot, offset + size) {
+                return true;
             }
+            self.placements.pop();
         }
+
+        self.failed.insert((input, slot, offset));
+        false
     }
 }
 
-pub fn build_input_layout(elements: &[InputElement]) -> Vec<D3D11_INPUT_ELEMENT_DESC> {
-    let mut map = vec![];
-    let mut offset = 0;
-    for e in elements
+fn elements(
+    inputs: &[&ShaderInput],
+    slots: &[InputSlot],
+    placements: &[(u32, u32, DxgiFormat)],
+) -> Vec<LayoutElement> {
+    inputs
+        .iter()
+        .zip(placements)
+        .map(|(input, &(slot, offset, format))| LayoutElement {
+            semantic_name: CString::new(input.semantic_name.as_str()).unwrap_or_default(),
+            semantic_index: input.semantic_index,
+            format,
+            slot,
+            offset,
+            instance_step_rate: slots[slot as usize].instance_step_rate,
+        })
+        .collect()
+}
+
+fn unused_slots(slots: &[InputSlot], first_unused: usize) -> Vec<LayoutDiagnostic> {
+    slots
         .iter()
-        .filter(|e| !e.semantic_type.is_system_value())
-    {
-        map.push(D3D11_INPUT_ELEMENT_DESC {
-            SemanticName: e.semantic_type.to_pcstr(),
-            SemanticIndex: e.semantic_index,
-            Format: DXGI_FORMAT(e.format.into()),
-            InputSlot: 0,
-            AlignedByteOffset: offset,
-            InputSlotClass: D3D11_INPUT_PER_VERTEX_DATA,
-            InstanceDataStepRate: 0,
-        });
-
-        offset += (e.format.bpp() / 8) as u32;
-    }
-
-    map
+        .enumerate()
+        .skip(first_unused)
+        .map(|(i, s)| LayoutDiagnostic::UnusedSlot {
+            slot: i as u32,
+            stride: s.stride,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(semantic: &str, index: u32, component_type: DxbcInputType, count: u32) -> ShaderInput {
+        ShaderInput {
+            semantic_name: semantic.to_string(),
+            semantic_index: index,
+            system_value_type: 0,
+            component_type,
+            component_count: count,
+        }
+    }
+
+    fn float(semantic: &str, index: u32, count: u32) -> ShaderInput {
+        input(semantic, index, DxbcInputType::Float, count)
+    }
+
+    fn vertex(stride: u32) -> InputSlot {
+        InputSlot {
+            stride,
+            vtype: 0,
+            instance_step_rate: 0,
+        }
+    }
+
+    /// Static instances read their transforms from a constant buffer, so nothing streams instance data yet
+    fn instance(stride: u32, instance_step_rate: u32) -> InputSlot {
+        InputSlot {
+            stride,
+            vtype: 0,
+            instance_step_rate,
+        }
+    }
+
+    /// (semantic, index, slot, offset, format, instance step rate)
+    fn summary(layout: &VertexLayout) -> Vec<(String, u32, u32, u32, DxgiFormat, u32)> {
+        layout
+            .elements
+            .iter()
+            .map(|e| {
+                (
+                    e.semantic_name.to_str().unwrap().to_string(),
+                    e.semantic_index,
+                    e.slot,
+                    e.offset,
+                    e.format,
+                    e.instance_step_rate,
+                )
+            })
+            .collect()
+    }
+
+    struct Case {
+        name: &'static str,
+        inputs: Vec<ShaderInput>,
+        slots: Vec<InputSlot>,
+        elements: Vec<(&'static str, u32, u32, u32, DxgiFormat, u32)>,
+        diagnostics: Vec<LayoutDiagnostic>,
+    }
+
+    #[test]
+    fn resolve() {
+        use DxgiFormat::*;
+
+        let cases = [
+            Case {
+                name: "packed 16-bit inputs fill the stride",
+                inputs: vec![float("POSITION", 0, 4), float("NORMAL", 0, 4)],
+                slots: vec![vertex(16)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0),
+                    ("NORMAL", 0, 0, 8, R16G16B16A16_SNORM, 0),
+                ],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "search backtracks to 32-bit floats",
+                inputs: vec![float("POSITION", 0, 3), float("TEXCOORD", 0, 2)],
+                slots: vec![vertex(20)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R32G32B32_FLOAT, 0),
+                    ("TEXCOORD", 0, 0, 12, R32G32_FLOAT, 0),
+                ],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "inputs continue in the next slot",
+                inputs: vec![
+                    float("POSITION", 0, 4),
+                    float("TEXCOORD", 0, 2),
+                    float("NORMAL", 0, 4),
+                ],
+                slots: vec![vertex(8), vertex(12)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0),
+                    ("TEXCOORD", 0, 1, 0, R16G16_SNORM, 0),
+                    ("NORMAL", 0, 1, 4, R16G16B16A16_SNORM, 0),
+                ],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "colors prefer 8-bit unorm",
+                inputs: vec![float("POSITION", 0, 4), float("COLOR", 0, 4)],
+                slots: vec![vertex(12)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0),
+                    ("COLOR", 0, 0, 8, R8G8B8A8_UNORM, 0),
+                ],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "integer inputs",
+                inputs: vec![
+                    float("POSITION", 0, 4),
+                    input("BLENDINDICES", 0, DxbcInputType::Uint, 4),
+                ],
+                slots: vec![vertex(12)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0),
+                    ("BLENDINDICES", 0, 0, 8, R8G8B8A8_UINT, 0),
+                ],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "instance data keeps its step rate",
+                inputs: vec![float("POSITION", 0, 4), float("TEXCOORD", 1, 4)],
+                slots: vec![vertex(8), instance(16, 1)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0),
+                    ("TEXCOORD", 1, 1, 0, R32G32B32A32_FLOAT, 1),
+                ],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "system values aren't read from buffers",
+                inputs: vec![
+                    float("POSITION", 0, 4),
+                    input("SV_VertexID", 0, DxbcInputType::Uint, 1),
+                ],
+                slots: vec![vertex(8)],
+                elements: vec![("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0)],
+                diagnostics: vec![],
+            },
+            Case {
+                name: "trailing slots are reported as unused",
+                inputs: vec![float("POSITION", 0, 4)],
+                slots: vec![vertex(8), vertex(4)],
+                elements: vec![("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0)],
+                diagnostics: vec![LayoutDiagnostic::UnusedSlot { slot: 1, stride: 4 }],
+            },
+            Case {
+                name: "system values only",
+                inputs: vec![input("SV_VertexID", 0, DxbcInputType::Uint, 1)],
+                slots: vec![vertex(8)],
+                elements: vec![],
+                diagnostics: vec![LayoutDiagnostic::UnusedSlot { slot: 0, stride: 8 }],
+            },
+            Case {
+                name: "no buffers bound",
+                inputs: vec![float("POSITION", 0, 4)],
+                slots: vec![],
+                elements: vec![],
+                diagnostics: vec![LayoutDiagnostic::NoInputSlots],
+            },
+            Case {
+                name: "fallback reports a stride mismatch",
+                inputs: vec![float("POSITION", 0, 4)],
+                slots: vec![InputSlot {
+                    stride: 10,
+                    vtype: 2,
+                    instance_step_rate: 0,
+                }],
+                elements: vec![("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0)],
+                diagnostics: vec![LayoutDiagnostic::StrideMismatch {
+                    slot: 0,
+                    stride: 10,
+                    vtype: 2,
+                    used: 8,
+                }],
+            },
+            Case {
+                name: "fallback reports inputs past the last slot",
+                inputs: vec![float("POSITION", 0, 3), float("NORMAL", 0, 3)],
+                slots: vec![vertex(10)],
+                elements: vec![
+                    ("POSITION", 0, 0, 0, R16G16B16A16_SNORM, 0),
+                    ("NORMAL", 0, 0, 8, R16G16B16A16_SNORM, 0),
+                ],
+                diagnostics: vec![LayoutDiagnostic::Overflow {
+                    input: "float3 NORMAL0".to_string(),
+                }],
+            },
+        ];
+
+        for case in cases {
+            let layout = VertexLayout::resolve(&case.inputs, &case.slots);
+            let expected = case
+                .elements
+                .iter()
+                .map(|&(name, index, slot, offset, format, step)| {
+                    (name.to_string(), index, slot, offset, format, step)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(summary(&layout), expected, "{}", case.name);
+            assert_eq!(layout.diagnostics, case.diagnostics, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            (LayoutDiagnostic::NoInputSlots, true),
+            (
+                LayoutDiagnostic::Overflow {
+                    input: "float4 NORMAL0".to_string(),
+                },
+                true,
+            ),
+            (
+                LayoutDiagnostic::StrideMismatch {
+                    slot: 0,
+                    stride: 10,
+                    vtype: 0,
+                    used: 8,
+                },
+                false,
+            ),
+            (LayoutDiagnostic::UnusedSlot { slot: 1, stride: 4 }, false),
+        ];
+
+        for (diagnostic, is_error) in cases {
+            assert_eq!(diagnostic.is_error(), is_error, "{diagnostic}");
+        }
+    }
+
+    #[test]
+    fn per_instance_descs() {
+        let layout = VertexLayout::resolve(
+            &[float("POSITION", 0, 4), float("TEXCOORD", 1, 4)],
+            &[vertex(8), instance(16, 2)],
+        );
+        let descs = layout.descs();
+        assert_eq!(descs[0].InputSlotClass, D3D11_INPUT_PER_VERTEX_DATA);
+        assert_eq!(descs[0].InstanceDataStepRate, 0);
+        assert_eq!(descs[1].InputSlotClass, D3D11_INPUT_PER_INSTANCE_DATA);
+        assert_eq!(descs[1].InstanceDataStepRate, 2);
+        assert_eq!(descs[1].InputSlot, 1);
+    }
+    #[test]
+    fn input_slots_are_limited() {
+        let mut slots = InputSlots::default();
+        for stride in 0..MAX_INPUT_SLOTS as u32 {
+            slots.push(vertex(stride)).unwrap();
+        }
+        assert!(slots.push(vertex(4)).is_err());
+        assert_eq!(slots.len(), MAX_INPUT_SLOTS);
+        assert_eq!(
+            slots[MAX_INPUT_SLOTS - 1].stride,
+            MAX_INPUT_SLOTS as u32 - 1
+        );
+    }
 }
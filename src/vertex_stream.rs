@@ -0,0 +1,527 @@
+use std::ffi::CString;
+
+use destiny_pkg::TagHash;
+use glam::{UVec4, Vec2, Vec3, Vec4};
+use nohash_hasher::IntMap;
+use tracing::debug;
+
+use crate::dxgi::DxgiFormat;
+use crate::entity::{Unk808073a5, VertexBufferHeader};
+use crate::material::Material;
+use crate::render::vertex_input::VertexShader;
+use crate::statics::Unk808071a7;
+use crate::types::DecodeFloat;
+use crate::vertex_layout::{InputSlot, LayoutElement, ShaderInput, VertexLayout};
+
+/// Transformation the vertex shaders apply to the quantized vertex data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexTransform {
+    pub position_scale: Vec3,
+    pub position_offset: Vec3,
+    pub texcoord_scale: Vec2,
+    pub texcoord_offset: Vec2,
+}
+
+impl Default for VertexTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl VertexTransform {
+    pub const IDENTITY: VertexTransform = VertexTransform {
+        position_scale: Vec3::ONE,
+        position_offset: Vec3::ZERO,
+        texcoord_scale: Vec2::ONE,
+        texcoord_offset: Vec2::ZERO,
+    };
+}
+
+impl From<&Unk808071a7> for VertexTransform {
+    /// Same as [`crate::render::StaticModel::mesh_transform`], statics only use the X texcoord scale
+    fn from(model: &Unk808071a7) -> Self {
+        Self {
+            position_scale: Vec3::splat(model.model_scale),
+            position_offset: Vec3::new(
+                model.model_offset.x,
+                model.model_offset.y,
+                model.model_offset.z,
+            ),
+            texcoord_scale: Vec2::splat(model.texture_coordinate_scale.x),
+            texcoord_offset: Vec2::new(
+                model.texture_coordinate_offset.x,
+                model.texture_coordinate_offset.y,
+            ),
+        }
+    }
+}
+
+impl From<&Unk808073a5> for VertexTransform {
+    fn from(model: &Unk808073a5) -> Self {
+        Self {
+            position_scale: Vec3::new(
+                model.model_scale.x,
+                model.model_scale.y,
+                model.model_scale.z,
+            ),
+            position_offset: Vec3::new(
+                model.model_offset.x,
+                model.model_offset.y,
+                model.model_offset.z,
+            ),
+            texcoord_scale: Vec2::new(model.texcoord_scale.x, model.texcoord_scale.y),
+            texcoord_offset: Vec2::new(model.texcoord_offset.x, model.texcoord_offset.y),
+        }
+    }
+}
+
+/// Vertex data decoded on the CPU. Attributes the layout doesn't have are left empty
+#[derive(Clone, Debug, Default)]
+pub struct VertexStream {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    /// w is the bitangent sign
+    pub tangents: Vec<Vec4>,
+    pub texcoords: Vec<Vec2>,
+    pub colors: Vec<Vec4>,
+    pub blend_indices: Vec<UVec4>,
+    pub blend_weights: Vec<Vec4>,
+}
+
+impl VertexStream {
+    /// Decodes the primary and secondary vertex buffers of a mesh.
+    ///
+    /// The layout is resolved from the vertex shader of `material`, the same way it is for drawing. When that fails
+    /// (or the material or its vertex shader isn't loaded), only the positions are decoded, assuming they're stored
+    /// at the start of the primary buffer
+    pub fn load(
+        material: Option<TagHash>,
+        shader_inputs: &MaterialShaderInputs,
+        buffers: &[(&VertexBufferHeader, &[u8])],
+        transform: &VertexTransform,
+    ) -> VertexStream {
+        let slots = buffers
+            .iter()
+            .map(|(header, _)| InputSlot::per_vertex(header))
+            .collect::<Vec<_>>();
+        let data = buffers.iter().map(|(_, data)| *data).collect::<Vec<_>>();
+
+        let layout = match material.and_then(|m| Some((m, shader_inputs.get(m)?))) {
+            Some((material, inputs)) => {
+                let layout = VertexLayout::resolve(inputs, &slots);
+                if layout.has_errors() || find_element(&layout, "POSITION", 0).is_none() {
+                    debug!(
+                        "Vertex layout of material {material} doesn't match its buffers, only decoding positions"
+                    );
+                    position_layout(&slots)
+                } else {
+                    layout
+                }
+            }
+            None => position_layout(&slots),
+        };
+
+        Self::decode(&layout, &slots, &data, transform)
+    }
+
+    /// Decodes every element of `layout` that maps to one of the attributes. `buffers` are the contents of `slots`
+    pub fn decode(
+        layout: &VertexLayout,
+        slots: &[InputSlot],
+        buffers: &[&[u8]],
+        transform: &VertexTransform,
+    ) -> VertexStream {
+        let vertex_count = slots
+            .iter()
+            .zip(buffers)
+            .filter(|(s, _)| s.instance_step_rate == 0 && s.stride != 0)
+            .map(|(s, data)| data.len() / s.stride as usize)
+            .min()
+            .unwrap_or_default();
+
+        let read = |semantic: &str| -> Vec<Vec4> {
+            let Some(e) = find_element(layout, semantic, 0) else {
+                return vec![];
+            };
+            let (Some(slot), Some(data)) =
+                (slots.get(e.slot as usize), buffers.get(e.slot as usize))
+            else {
+                return vec![];
+            };
+
+            let stride = slot.stride as usize;
+            (0..vertex_count)
+                .map_while(|v| {
+                    let start = v * stride + e.offset as usize;
+                    data.get(start..)
+                        .and_then(|d| decode_components(e.format, d))
+                })
+                .collect()
+        };
+
+        VertexStream {
+            positions: read("POSITION")
+                .into_iter()
+                .map(|p| p.truncate() * transform.position_scale + transform.position_offset)
+                .collect(),
+            normals: read("NORMAL")
+                .into_iter()
+                .map(|n| n.truncate().normalize_or_zero())
+                .collect(),
+            tangents: read("TANGENT"),
+            texcoords: read("TEXCOORD")
+                .into_iter()
+                .map(|t| {
+                    t.truncate().truncate() * transform.texcoord_scale + transform.texcoord_offset
+                })
+                .collect(),
+            colors: read("COLOR"),
+            blend_indices: read("BLENDINDICES")
+                .into_iter()
+                .map(|i| i.as_uvec4())
+                .collect(),
+            blend_weights: read("BLENDWEIGHT"),
+        }
+    }
+
+    /// Names of the attributes that were decoded
+    pub fn attribute_names(&self) -> Vec<&'static str> {
+        [
+            ("position", self.positions.is_empty()),
+            ("normal", self.normals.is_empty()),
+            ("tangent", self.tangents.is_empty()),
+            ("texcoord", self.texcoords.is_empty()),
+            ("color", self.colors.is_empty()),
+            ("blend indices", self.blend_indices.is_empty()),
+            ("blend weights", self.blend_weights.is_empty()),
+        ]
+        .into_iter()
+        .filter(|(_, empty)| !empty)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// Looks up the vertex shader inputs of materials in the vertex shaders that were loaded for drawing
+#[derive(Clone, Copy)]
+pub struct MaterialShaderInputs<'a> {
+    pub materials: &'a IntMap<u32, Material>,
+    pub vshaders: &'a IntMap<u32, VertexShader>,
+}
+
+impl<'a> MaterialShaderInputs<'a> {
+    pub fn get(&self, material: TagHash) -> Option<&'a [ShaderInput]> {
+        let material = self.materials.get(&material.0)?;
+        self.vshaders
+            .get(&material.vertex_shader.0)
+            .map(|vs| vs.inputs.as_slice())
+    }
+}
+
+fn find_element<'a>(
+    layout: &'a VertexLayout,
+    semantic: &str,
+    index: u32,
+) -> Option<&'a LayoutElement> {
+    layout.elements.iter().find(|e| {
+        e.semantic_index == index
+            && e.semantic_name
+                .to_str()
+                .map_or(false, |s| s.eq_ignore_ascii_case(semantic))
+    })
+}
+
+/// Position-only layout for buffers without a known shader. Primary buffers with a 24 or 48 byte stride store
+/// 32-bit float positions, all others 16-bit normalized ones
+fn position_layout(slots: &[InputSlot]) -> VertexLayout {
+    let Some(primary) = slots.first() else {
+        return VertexLayout::default();
+    };
+
+    VertexLayout {
+        elements: vec![LayoutElement {
+            semantic_name: CString::new("POSITION").unwrap(),
+            semantic_index: 0,
+            format: if primary.stride == 24 || primary.stride == 48 {
+                DxgiFormat::R32G32B32_FLOAT
+            } else {
+                DxgiFormat::R16G16B16A16_SNORM
+            },
+            slot: 0,
+            offset: 0,
+            instance_step_rate: 0,
+        }],
+        diagnostics: vec![],
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ComponentType {
+    Float32,
+    Snorm16,
+    Unorm16,
+    Sint16,
+    Uint16,
+    Sint32,
+    Uint32,
+    Snorm8,
+    Unorm8,
+    Sint8,
+    Uint8,
+}
+
+impl ComponentType {
+    fn size(self) -> usize {
+        match self {
+            ComponentType::Float32 | ComponentType::Sint32 | ComponentType::Uint32 => 4,
+            ComponentType::Snorm16
+            | ComponentType::Unorm16
+            | ComponentType::Sint16
+            | ComponentType::Uint16 => 2,
+            ComponentType::Snorm8
+            | ComponentType::Unorm8
+            | ComponentType::Sint8
+            | ComponentType::Uint8 => 1,
+        }
+    }
+
+    /// Normalized values are mapped to [-1, 1]/[0, 1], integers are converted as-is
+    fn read(self, d: &[u8]) -> f32 {
+        match self {
+            ComponentType::Float32 => f32::from_le_bytes([d[0], d[1], d[2], d[3]]),
+            ComponentType::Snorm16 => i16::from_le_bytes([d[0], d[1]]).decode_float().max(-1.0),
+            ComponentType::Unorm16 => u16::from_le_bytes([d[0], d[1]]).decode_float(),
+            ComponentType::Sint16 => i16::from_le_bytes([d[0], d[1]]) as f32,
+            ComponentType::Uint16 => u16::from_le_bytes([d[0], d[1]]) as f32,
+            ComponentType::Sint32 => i32::from_le_bytes([d[0], d[1], d[2], d[3]]) as f32,
+            ComponentType::Uint32 => u32::from_le_bytes([d[0], d[1], d[2], d[3]]) as f32,
+            ComponentType::Snorm8 => (d[0] as i8).decode_float().max(-1.0),
+            ComponentType::Unorm8 => d[0].decode_float(),
+            ComponentType::Sint8 => d[0] as i8 as f32,
+            ComponentType::Uint8 => d[0] as f32,
+        }
+    }
+}
+
+fn component_layout(format: DxgiFormat) -> Option<(ComponentType, usize)> {
+    Some(match format {
+        DxgiFormat::R32_FLOAT => (ComponentType::Float32, 1),
+        DxgiFormat::R32G32_FLOAT => (ComponentType::Float32, 2),
+        DxgiFormat::R32G32B32_FLOAT => (ComponentType::Float32, 3),
+        DxgiFormat::R32G32B32A32_FLOAT => (ComponentType::Float32, 4),
+        DxgiFormat::R16_SNORM => (ComponentType::Snorm16, 1),
+        DxgiFormat::R16G16_SNORM => (ComponentType::Snorm16, 2),
+        DxgiFormat::R16G16B16A16_SNORM => (ComponentType::Snorm16, 4),
+        DxgiFormat::R16_UNORM => (ComponentType::Unorm16, 1),
+        DxgiFormat::R16G16_UNORM => (ComponentType::Unorm16, 2),
+        DxgiFormat::R16G16B16A16_UNORM => (ComponentType::Unorm16, 4),
+        DxgiFormat::R16_SINT => (ComponentType::Sint16, 1),
+        DxgiFormat::R16G16_SINT => (ComponentType::Sint16, 2),
+        DxgiFormat::R16G16B16A16_SINT => (ComponentType::Sint16, 4),
+        DxgiFormat::R16_UINT => (ComponentType::Uint16, 1),
+        DxgiFormat::R16G16_UINT => (ComponentType::Uint16, 2),
+        DxgiFormat::R16G16B16A16_UINT => (ComponentType::Uint16, 4),
+        DxgiFormat::R32_SINT => (ComponentType::Sint32, 1),
+        DxgiFormat::R32G32_SINT => (ComponentType::Sint32, 2),
+        DxgiFormat::R32G32B32_SINT => (ComponentType::Sint32, 3),
+        DxgiFormat::R32G32B32A32_SINT => (ComponentType::Sint32, 4),
+        DxgiFormat::R32_UINT => (ComponentType::Uint32, 1),
+        DxgiFormat::R32G32_UINT => (ComponentType::Uint32, 2),
+        DxgiFormat::R32G32B32_UINT => (ComponentType::Uint32, 3),
+        DxgiFormat::R32G32B32A32_UINT => (ComponentType::Uint32, 4),
+        DxgiFormat::R8G8B8A8_SNORM => (ComponentType::Snorm8, 4),
+        DxgiFormat::R8G8B8A8_UNORM => (ComponentType::Unorm8, 4),
+        DxgiFormat::R8G8B8A8_SINT => (ComponentType::Sint8, 4),
+        DxgiFormat::R8G8B8A8_UINT => (ComponentType::Uint8, 4),
+        _ => return None,
+    })
+}
+
+/// Reads a single element. Missing components are 0, except for w, which is 1
+fn decode_components(format: DxgiFormat, data: &[u8]) -> Option<Vec4> {
+    let (ty, count) = component_layout(format)?;
+    let size = ty.size();
+    if data.len() < size * count {
+        return None;
+    }
+
+    let mut v = [0.0, 0.0, 0.0, 1.0];
+    for (i, c) in v.iter_mut().take(count).enumerate() {
+        *c = ty.read(&data[i * size..]);
+    }
+
+    Some(Vec4::from(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes_i16(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn bytes_f32(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn element(semantic: &str, format: DxgiFormat, slot: u32, offset: u32) -> LayoutElement {
+        LayoutElement {
+            semantic_name: CString::new(semantic).unwrap(),
+            semantic_index: 0,
+            format,
+            slot,
+            offset,
+            instance_step_rate: 0,
+        }
+    }
+
+    fn slot(stride: u32) -> InputSlot {
+        InputSlot {
+            stride,
+            vtype: 0,
+            instance_step_rate: 0,
+        }
+    }
+
+    #[test]
+    fn decode_components() {
+        use DxgiFormat::*;
+
+        let cases: [(&str, DxgiFormat, Vec<u8>, Option<Vec4>); 10] = [
+            (
+                "snorm16 maps to [-1, 1], -32768 is clamped",
+                R16G16B16A16_SNORM,
+                bytes_i16(&[32767, -32767, -32768, 0]),
+                Some(Vec4::new(1.0, -1.0, -1.0, 0.0)),
+            ),
+            (
+                "snorm16 pair, w defaults to 1",
+                R16G16_SNORM,
+                bytes_i16(&[16384, -8192]),
+                Some(Vec4::new(16384.0 / 32767.0, -8192.0 / 32767.0, 0.0, 1.0)),
+            ),
+            (
+                "sint16 isn't scaled",
+                R16G16_SINT,
+                bytes_i16(&[-5, 300]),
+                Some(Vec4::new(-5.0, 300.0, 0.0, 1.0)),
+            ),
+            (
+                "unorm16",
+                R16_UNORM,
+                bytes_i16(&[-1]),
+                Some(Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            ),
+            (
+                "unorm8 maps to [0, 1]",
+                R8G8B8A8_UNORM,
+                vec![0, 255, 51, 128],
+                Some(Vec4::new(0.0, 1.0, 0.2, 128.0 / 255.0)),
+            ),
+            (
+                "snorm8 maps to [-1, 1], -128 is clamped",
+                R8G8B8A8_SNORM,
+                vec![127, 0x81, 0x80, 0],
+                Some(Vec4::new(1.0, -1.0, -1.0, 0.0)),
+            ),
+            (
+                "uint8",
+                R8G8B8A8_UINT,
+                vec![1, 2, 3, 255],
+                Some(Vec4::new(1.0, 2.0, 3.0, 255.0)),
+            ),
+            (
+                "float32",
+                R32G32B32_FLOAT,
+                bytes_f32(&[1.5, -2.0, 0.25]),
+                Some(Vec4::new(1.5, -2.0, 0.25, 1.0)),
+            ),
+            ("truncated data", R32G32_FLOAT, bytes_f32(&[1.0]), None),
+            ("unsupported format", R10G10B10A2_UNORM, vec![0; 4], None),
+        ];
+
+        for (name, format, data, expected) in cases {
+            let decoded = super::decode_components(format, &data);
+            match (decoded, expected) {
+                (Some(d), Some(e)) => assert!(d.abs_diff_eq(e, 1e-6), "{name}: {d} != {e}"),
+                (d, e) => assert_eq!(d, e, "{name}"),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_reads_elements_at_their_offsets() {
+        // Slot 0: snorm16 position, snorm16 texcoord (12 bytes). Slot 1: 4 bytes of padding, unorm8 color (8 bytes)
+        let mut primary = vec![];
+        primary.extend(bytes_i16(&[32767, 0, 0, 0, 16384, 0]));
+        primary.extend(bytes_i16(&[0, -32767, 32767, 0, 0, 32767]));
+        let secondary = [0, 0, 0, 0, 255, 0, 0, 255, 0, 0, 0, 0, 0, 255, 0, 51];
+
+        let layout = VertexLayout {
+            elements: vec![
+                element("POSITION", DxgiFormat::R16G16B16A16_SNORM, 0, 0),
+                element("TEXCOORD", DxgiFormat::R16G16_SNORM, 0, 8),
+                element("COLOR", DxgiFormat::R8G8B8A8_UNORM, 1, 4),
+            ],
+            diagnostics: vec![],
+        };
+        let transform = VertexTransform {
+            position_scale: Vec3::splat(2.0),
+            position_offset: Vec3::new(1.0, 0.0, 0.0),
+            texcoord_scale: Vec2::new(0.5, 2.0),
+            texcoord_offset: Vec2::new(0.25, -1.0),
+        };
+
+        let stream = VertexStream::decode(
+            &layout,
+            &[slot(12), slot(8)],
+            &[&primary, &secondary],
+            &transform,
+        );
+
+        assert_eq!(stream.attribute_names(), ["position", "texcoord", "color"]);
+        assert_eq!(
+            stream.positions,
+            [Vec3::new(3.0, 0.0, 0.0), Vec3::new(1.0, -2.0, 2.0)]
+        );
+        let expected_texcoords = [
+            Vec2::new(0.25 + 0.5 * 16384.0 / 32767.0, -1.0),
+            Vec2::new(0.25, 1.0),
+        ];
+        for (t, e) in stream.texcoords.iter().zip(expected_texcoords) {
+            assert!(t.abs_diff_eq(e, 1e-6), "{t} != {e}");
+        }
+        assert_eq!(stream.texcoords.len(), 2);
+        let expected_colors = [Vec4::new(1.0, 0.0, 0.0, 1.0), Vec4::new(0.0, 1.0, 0.0, 0.2)];
+        for (c, e) in stream.colors.iter().zip(expected_colors) {
+            assert!(c.abs_diff_eq(e, 1e-6), "{c} != {e}");
+        }
+        assert_eq!(stream.colors.len(), 2);
+    }
+
+    #[test]
+    fn decode_stops_at_the_shortest_buffer() {
+        let layout = position_layout(&[slot(8), slot(4)]);
+        let stream = VertexStream::decode(
+            &layout,
+            &[slot(8), slot(4)],
+            &[&bytes_i16(&[0; 12]), &[0; 4]],
+            &VertexTransform::IDENTITY,
+        );
+        assert_eq!(stream.positions.len(), 1);
+    }
+
+    #[test]
+    fn position_layout_guesses_from_the_stride() {
+        for (stride, format) in [
+            (24, DxgiFormat::R32G32B32_FLOAT),
+            (48, DxgiFormat::R32G32B32_FLOAT),
+            (16, DxgiFormat::R16G16B16A16_SNORM),
+        ] {
+            let layout = position_layout(&[slot(stride)]);
+            assert_eq!(layout.elements[0].format, format, "stride {stride}");
+        }
+        assert!(position_layout(&[]).elements.is_empty());
+    }
+}